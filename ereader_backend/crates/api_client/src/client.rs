@@ -3,10 +3,13 @@
 use crate::auth::AuthProvider;
 use crate::error::{Error, Result};
 use crate::models::{
-    Book, Collection, CreateBookRequest, CreateCollectionRequest,
-    ListBooksQuery, PaginatedResponse, SearchBooksQuery, SyncRequest,
-    SyncResponse, UpdateBookRequest, UpdateCollectionRequest,
+    Author, AuthorDetail, AutocompleteQuery, Book, BookCompletion, BookIdsRequest, BulkBooksResponse, Collection, CollectionTreeNode, CursorPage, ConflictChoice, CreateBookRequest, CreateCollectionRequest,
+    ListBooksQuery, MergeAuthorsRequest, MergeSeriesRequest, MoveBooksRequest, NextInSeries, PaginatedResponse, ResolveConflictRequest, SearchBooksQuery, SearchBooksResponse,
+    Series, SeriesDetail, SetParentRequest, SyncConflictRecord, SyncRequest, SyncResponse, TextSearchHit, UpdateBookRequest,
+    UpdateAuthorRequest, UpdateCollectionRequest, UpdateSeriesRequest,
 };
+use common::types::Paginated;
+use futures_util::stream::{self, Stream, TryStreamExt};
 use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
 use std::sync::Arc;
 use std::time::Duration;
@@ -236,6 +239,26 @@ impl Client {
         self.get(&path).await
     }
 
+    /// List a page of books after a cursor, or the first page if the query has none
+    pub async fn list_books_by_cursor(&self, mut query: ListBooksQuery) -> Result<CursorPage<Book>> {
+        query.cursor.get_or_insert_with(String::new);
+        let params = serde_urlencoded::to_string(&query).unwrap_or_default();
+        self.get(&format!("/api/v1/books?{}", params)).await
+    }
+
+    /// Stream every book matching a query, fetching pages by cursor as they are consumed
+    pub fn list_all_books(&self, query: ListBooksQuery) -> impl Stream<Item = Result<Book>> + '_ {
+        stream::try_unfold(Some(query), move |query| async move {
+            let Some(query) = query else {
+                return Ok::<_, Error>(None);
+            };
+            let page = self.list_books_by_cursor(query.clone()).await?;
+            let next = page.next_cursor.map(|cursor| query.cursor(cursor));
+            Ok(Some((stream::iter(page.items.into_iter().map(Ok)), next)))
+        })
+        .try_flatten()
+    }
+
     /// Get a book by ID
     pub async fn get_book(&self, id: Uuid) -> Result<Book> {
         self.get(&format!("/api/v1/books/{}", id)).await
@@ -256,12 +279,24 @@ impl Client {
         self.delete(&format!("/api/v1/books/{}", id)).await
     }
 
-    /// Search books
-    pub async fn search_books(&self, query: SearchBooksQuery) -> Result<PaginatedResponse<Book>> {
+    /// Search books with field filters such as `author:"le guin" -tag:read`, with facet counts
+    pub async fn search_books(&self, query: SearchBooksQuery) -> Result<SearchBooksResponse> {
         let params = serde_urlencoded::to_string(&query).unwrap_or_default();
         self.get(&format!("/api/v1/books/search?{}", params)).await
     }
 
+    /// Complete typed text with titles, authors and series, tolerating typos
+    pub async fn autocomplete(&self, query: AutocompleteQuery) -> Result<Vec<BookCompletion>> {
+        let params = serde_urlencoded::to_string(&query).unwrap_or_default();
+        self.get(&format!("/api/v1/books/autocomplete?{}", params)).await
+    }
+
+    /// Search the text of books, with snippets of the matching passages
+    pub async fn search_text(&self, query: SearchBooksQuery) -> Result<PaginatedResponse<TextSearchHit>> {
+        let params = serde_urlencoded::to_string(&query).unwrap_or_default();
+        self.get(&format!("/api/v1/books/search/text?{}", params)).await
+    }
+
     // ==================== Collection Endpoints ====================
 
     /// List collections
@@ -269,6 +304,20 @@ impl Client {
         self.get("/api/v1/collections").await
     }
 
+    /// List collections as a tree, top-level collections first
+    pub async fn collection_tree(&self) -> Result<Vec<CollectionTreeNode>> {
+        self.get("/api/v1/collections/tree").await
+    }
+
+    /// Move a collection into another, or to the top level if `parent_id` is `None`
+    pub async fn move_collection(&self, id: Uuid, parent_id: Option<Uuid>) -> Result<Collection> {
+        self.put(
+            &format!("/api/v1/collections/{}/parent", id),
+            Some(SetParentRequest { parent_id }),
+        )
+        .await
+    }
+
     /// Get a collection by ID
     pub async fn get_collection(&self, id: Uuid) -> Result<Collection> {
         self.get(&format!("/api/v1/collections/{}", id)).await
@@ -289,6 +338,100 @@ impl Client {
         self.delete(&format!("/api/v1/collections/{}", id)).await
     }
 
+    /// Add books to the end of a collection, skipping books already in it
+    pub async fn add_books_to_collection(&self, id: Uuid, book_ids: Vec<Uuid>) -> Result<BulkBooksResponse> {
+        self.post(
+            &format!("/api/v1/collections/{}/books/bulk", id),
+            Some(BookIdsRequest { book_ids }),
+        )
+        .await
+    }
+
+    /// Remove books from a collection
+    pub async fn remove_books_from_collection(&self, id: Uuid, book_ids: Vec<Uuid>) -> Result<BulkBooksResponse> {
+        self.post(
+            &format!("/api/v1/collections/{}/books/bulk-remove", id),
+            Some(BookIdsRequest { book_ids }),
+        )
+        .await
+    }
+
+    /// Move books from one collection to the end of another
+    pub async fn move_books_to_collection(
+        &self,
+        from: Uuid,
+        to: Uuid,
+        book_ids: Vec<Uuid>,
+    ) -> Result<BulkBooksResponse> {
+        self.post(
+            &format!("/api/v1/collections/{}/books/bulk-move", from),
+            Some(MoveBooksRequest { book_ids, to_collection_id: to }),
+        )
+        .await
+    }
+
+    // ==================== Author Endpoints ====================
+
+    /// List the people books credit by sort name, with their number of books
+    pub async fn list_authors(&self, limit: i64, offset: i64) -> Result<Paginated<Author>> {
+        self.get(&format!("/api/v1/authors?limit={}&offset={}", limit, offset)).await
+    }
+
+    /// List the people credited in a role, by MARC relator code such as `trl`
+    pub async fn list_contributors(&self, role: &str, limit: i64, offset: i64) -> Result<Paginated<Author>> {
+        self.get(&format!("/api/v1/authors?role={}&limit={}&offset={}", role, limit, offset)).await
+    }
+
+    /// Get an author with their books
+    pub async fn get_author(&self, id: Uuid) -> Result<AuthorDetail> {
+        self.get(&format!("/api/v1/authors/{}", id)).await
+    }
+
+    /// Set an author's sort name or aliases
+    pub async fn update_author(&self, id: Uuid, request: UpdateAuthorRequest) -> Result<Author> {
+        self.put(&format!("/api/v1/authors/{}", id), Some(request)).await
+    }
+
+    /// Merge duplicate authors into one, whose aliases their names become
+    pub async fn merge_authors(&self, into: Uuid, author_ids: Vec<Uuid>) -> Result<Author> {
+        self.post(
+            "/api/v1/admin/authors/merge",
+            Some(MergeAuthorsRequest { into_id: into, author_ids }),
+        )
+        .await
+    }
+
+    // ==================== Series Endpoints ====================
+
+    /// List series with the user's progress through each
+    pub async fn list_series(&self, limit: i64, offset: i64) -> Result<Paginated<Series>> {
+        self.get(&format!("/api/v1/series?limit={}&offset={}", limit, offset)).await
+    }
+
+    /// Get a series with its books in reading order
+    pub async fn get_series(&self, id: Uuid) -> Result<SeriesDetail> {
+        self.get(&format!("/api/v1/series/{}", id)).await
+    }
+
+    /// Get the book to read next in a series
+    pub async fn next_in_series(&self, id: Uuid) -> Result<NextInSeries> {
+        self.get(&format!("/api/v1/series/{}/next", id)).await
+    }
+
+    /// Rename or describe a series
+    pub async fn update_series(&self, id: Uuid, request: UpdateSeriesRequest) -> Result<Series> {
+        self.put(&format!("/api/v1/series/{}", id), Some(request)).await
+    }
+
+    /// Merge series into another, moving their books to it
+    pub async fn merge_series(&self, into: Uuid, series_ids: Vec<Uuid>) -> Result<Series> {
+        self.post(
+            &format!("/api/v1/series/{}/merge", into),
+            Some(MergeSeriesRequest { series_ids }),
+        )
+        .await
+    }
+
     // ==================== Sync Endpoints ====================
 
     /// Sync reading progress and annotations
@@ -296,6 +439,20 @@ impl Client {
         self.post("/api/v1/sync", Some(request)).await
     }
 
+    /// List unresolved sync conflicts, newest first
+    pub async fn list_conflicts(&self, limit: i64, offset: i64) -> Result<Paginated<SyncConflictRecord>> {
+        self.get(&format!("/api/v1/sync/conflicts?limit={}&offset={}", limit, offset)).await
+    }
+
+    /// Resolve a sync conflict by picking the version to keep
+    pub async fn resolve_conflict(&self, id: Uuid, choice: ConflictChoice) -> Result<SyncConflictRecord> {
+        self.post(
+            &format!("/api/v1/sync/conflicts/{}/resolve", id),
+            Some(ResolveConflictRequest { choice }),
+        )
+        .await
+    }
+
     // ==================== Health Endpoints ====================
 
     /// Check if the API is healthy
@@ -0,0 +1,49 @@
+//! Author-related API models.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::Book;
+
+/// Author response from the API
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Author {
+    pub id: Uuid,
+    pub name: String,
+    /// Name the author files under, such as "Le Guin, Ursula K."
+    pub sort_name: String,
+    /// Other names books list the author by
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    /// Number of books by the author, in listings
+    #[serde(default)]
+    pub book_count: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Author with their books
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorDetail {
+    #[serde(flatten)]
+    pub author: Author,
+    pub books: Vec<Book>,
+}
+
+/// Request to set an author's sort name or aliases
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateAuthorRequest {
+    /// New sort name; an empty one derives it from the name again
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aliases: Option<Vec<String>>,
+}
+
+/// Request to merge duplicate authors into one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeAuthorsRequest {
+    pub into_id: Uuid,
+    pub author_ids: Vec<Uuid>,
+}
@@ -1,7 +1,9 @@
 //! Book-related API models.
 
 use chrono::{DateTime, Utc};
+use common::Contributor;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
 /// Book response from the API
@@ -10,14 +12,27 @@ pub struct Book {
     pub id: Uuid,
     pub title: String,
     pub authors: Vec<String>,
+    /// Everyone the book credits, authors included, by MARC relator code of their role
+    #[serde(default)]
+    pub contributors: BTreeMap<String, Vec<String>>,
     pub description: Option<String>,
     pub language: Option<String>,
     pub publisher: Option<String>,
     pub published_date: Option<String>,
     pub isbn: Option<String>,
+    /// Series the book belongs to, linked by its series name
+    #[serde(default)]
+    pub series_id: Option<Uuid>,
     pub series_name: Option<String>,
     pub series_index: Option<f32>,
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub author_sort: Option<String>,
+    /// Rating in half stars from 1 to 10
+    #[serde(default)]
+    pub rating: Option<i16>,
+    #[serde(default)]
+    pub identifiers: BTreeMap<String, String>,
     // File information
     pub format: Option<String>,
     pub file_size: Option<i64>,
@@ -27,12 +42,36 @@ pub struct Book {
     pub updated_at: DateTime<Utc>,
 }
 
+/// Book matching a full-text search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextSearchHit {
+    #[serde(flatten)]
+    pub book: Book,
+    pub rank: f32,
+    pub matching_chapters: i64,
+    pub snippets: Vec<TextSnippet>,
+}
+
+/// Passage of a chapter matching a full-text search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextSnippet {
+    pub spine_index: i32,
+    pub chapter_title: Option<String>,
+    /// CFI of the start of the chapter
+    pub locator: String,
+    /// HTML-escaped text with matched words wrapped in `<mark>`
+    pub snippet: String,
+}
+
 /// Request to create a new book
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct CreateBookRequest {
     pub title: String,
     #[serde(default)]
     pub authors: Vec<String>,
+    /// People credited in other roles, by MARC relator code such as `trl`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub contributors: Vec<Contributor>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -64,6 +103,12 @@ impl CreateBookRequest {
         self
     }
 
+    /// Credit someone in a role other than author, by MARC relator code
+    pub fn with_contributor(mut self, name: impl Into<String>, role: impl Into<String>) -> Self {
+        self.contributors.push(Contributor::new(name, role));
+        self
+    }
+
     pub fn with_description(mut self, description: impl Into<String>) -> Self {
         self.description = Some(description.into());
         self
@@ -88,6 +133,9 @@ pub struct UpdateBookRequest {
     pub title: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub authors: Option<Vec<String>>,
+    /// People credited in other roles, replacing those credited before
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contributors: Option<Vec<Contributor>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -121,6 +169,11 @@ impl UpdateBookRequest {
         self
     }
 
+    pub fn contributors(mut self, contributors: Vec<Contributor>) -> Self {
+        self.contributors = Some(contributors);
+        self
+    }
+
     pub fn description(mut self, description: impl Into<String>) -> Self {
         self.description = Some(description.into());
         self
@@ -137,6 +190,56 @@ pub struct PaginatedResponse<T> {
     pub has_more: bool,
 }
 
+/// Page of a list paginated by cursor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CursorPage<T> {
+    pub items: Vec<T>,
+    pub per_page: i64,
+    pub has_more: bool,
+    /// Cursor of the next page, or `None` on the last page
+    pub next_cursor: Option<String>,
+    /// Number of items in the whole list, if requested
+    #[serde(default)]
+    pub total: Option<i64>,
+}
+
+/// Page of book search results with facet counts over every matching book
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchBooksResponse {
+    #[serde(flatten)]
+    pub results: PaginatedResponse<Book>,
+    #[serde(default)]
+    pub facets: BookFacets,
+    /// Corrected query, when nothing matched and a misspelling was likely
+    #[serde(default)]
+    pub did_you_mean: Option<String>,
+}
+
+/// Most common values among the books matching a search, by count
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookFacets {
+    pub tags: Vec<FacetCount>,
+    pub authors: Vec<FacetCount>,
+    pub languages: Vec<FacetCount>,
+    pub series: Vec<FacetCount>,
+}
+
+/// Number of books in a search result sharing a value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: i64,
+}
+
+/// Title, author or series completing typed text
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookCompletion {
+    /// `title`, `author` or `series`
+    pub kind: String,
+    pub value: String,
+    pub score: f32,
+}
+
 /// Query parameters for listing books
 #[derive(Debug, Clone, Default, Serialize)]
 pub struct ListBooksQuery {
@@ -152,8 +255,17 @@ pub struct ListBooksQuery {
     pub tag: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub series: Option<String>,
+    /// Books crediting this person as an author
     #[serde(skip_serializing_if = "Option::is_none")]
     pub author: Option<String>,
+    /// Books crediting this person in any role
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub contributor: Option<String>,
+    /// Paginate by cursor: empty for the first page, then the previous page's `next_cursor`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_total: Option<bool>,
 }
 
 impl ListBooksQuery {
@@ -191,6 +303,21 @@ impl ListBooksQuery {
         self.author = Some(author.into());
         self
     }
+
+    pub fn contributor(mut self, contributor: impl Into<String>) -> Self {
+        self.contributor = Some(contributor.into());
+        self
+    }
+
+    pub fn cursor(mut self, cursor: impl Into<String>) -> Self {
+        self.cursor = Some(cursor.into());
+        self
+    }
+
+    pub fn include_total(mut self, include_total: bool) -> Self {
+        self.include_total = Some(include_total);
+        self
+    }
 }
 
 /// Query parameters for searching books
@@ -221,3 +348,25 @@ impl SearchBooksQuery {
         self
     }
 }
+
+/// Query parameters for autocompleting typed text
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AutocompleteQuery {
+    pub q: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+}
+
+impl AutocompleteQuery {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            q: text.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+}
@@ -8,19 +8,39 @@ use uuid::Uuid;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Collection {
     pub id: Uuid,
+    /// Collection this one is nested in, or `None` at the top level
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
     pub name: String,
     pub description: Option<String>,
+    /// Book search query selecting the books of a smart collection
+    #[serde(default)]
+    pub query: Option<String>,
     pub book_count: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+/// Collection in the tree listing, with the collections nested in it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionTreeNode {
+    #[serde(flatten)]
+    pub collection: Collection,
+    pub children: Vec<CollectionTreeNode>,
+}
+
 /// Request to create a new collection
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateCollectionRequest {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub collection_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<Uuid>,
 }
 
 impl CreateCollectionRequest {
@@ -28,6 +48,19 @@ impl CreateCollectionRequest {
         Self {
             name: name.into(),
             description: None,
+            collection_type: None,
+            query: None,
+            parent_id: None,
+        }
+    }
+
+    /// Smart collection containing the books matching a search query, such as
+    /// `tag:unread lang:de added:30d`
+    pub fn smart(name: impl Into<String>, query: impl Into<String>) -> Self {
+        Self {
+            collection_type: Some("smart".to_string()),
+            query: Some(query.into()),
+            ..Self::new(name)
         }
     }
 
@@ -35,6 +68,18 @@ impl CreateCollectionRequest {
         self.description = Some(description.into());
         self
     }
+
+    /// Nest the new collection in another
+    pub fn with_parent(mut self, parent_id: Uuid) -> Self {
+        self.parent_id = Some(parent_id);
+        self
+    }
+}
+
+/// Request to move a collection into another, or to the top level
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SetParentRequest {
+    pub parent_id: Option<Uuid>,
 }
 
 /// Request to update a collection
@@ -44,6 +89,8 @@ pub struct UpdateCollectionRequest {
     pub name: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
 }
 
 impl UpdateCollectionRequest {
@@ -60,6 +107,12 @@ impl UpdateCollectionRequest {
         self.description = Some(description.into());
         self
     }
+
+    /// New query of a smart collection
+    pub fn query(mut self, query: impl Into<String>) -> Self {
+        self.query = Some(query.into());
+        self
+    }
 }
 
 /// Request to add a book to a collection
@@ -73,3 +126,22 @@ impl AddBookRequest {
         Self { book_id }
     }
 }
+
+/// Request listing books to add to or remove from a collection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookIdsRequest {
+    pub book_ids: Vec<Uuid>,
+}
+
+/// Request to move books into another collection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveBooksRequest {
+    pub book_ids: Vec<Uuid>,
+    pub to_collection_id: Uuid,
+}
+
+/// Number of books a bulk operation added, removed or moved
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkBooksResponse {
+    pub count: u64,
+}
@@ -1,9 +1,13 @@
 //! API response models.
 
+pub mod author;
 pub mod book;
 pub mod collection;
+pub mod series;
 pub mod sync;
 
+pub use author::*;
 pub use book::*;
 pub use collection::*;
+pub use series::*;
 pub use sync::*;
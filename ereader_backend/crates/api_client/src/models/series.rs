@@ -0,0 +1,60 @@
+//! Series-related API models.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::Book;
+
+/// Series response from the API, with the user's progress through it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Series {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    /// Other names books list the series by
+    #[serde(default)]
+    pub aliases: Vec<String>,
+    pub book_count: i64,
+    pub finished_count: i64,
+    pub in_progress_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Series with its books in reading order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesDetail {
+    #[serde(flatten)]
+    pub series: Series,
+    pub books: Vec<SeriesBook>,
+}
+
+/// Book of a series, with the user's progress through it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SeriesBook {
+    #[serde(flatten)]
+    pub book: Book,
+    pub progress: f32,
+}
+
+/// Book to read next in a series, or `None` once the whole series is finished
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NextInSeries {
+    pub book: Option<SeriesBook>,
+}
+
+/// Request to rename or describe a series
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateSeriesRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Request to merge series into another
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MergeSeriesRequest {
+    pub series_ids: Vec<Uuid>,
+}
@@ -1,6 +1,7 @@
 //! Sync-related API models.
 
 use chrono::{DateTime, Utc};
+use common::{AnnotationAnchorStatus, TextQuoteSelector};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -9,25 +10,45 @@ use uuid::Uuid;
 pub struct SyncRequest {
     pub device_id: Uuid,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub last_sync_at: Option<DateTime<Utc>>,
+    pub sync_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
     #[serde(default)]
     pub reading_states: Vec<ReadingStateSync>,
     #[serde(default)]
     pub annotations: Vec<AnnotationSync>,
+    #[serde(default)]
+    pub reading_sessions: Vec<ReadingSessionSync>,
 }
 
 impl SyncRequest {
     pub fn new(device_id: Uuid) -> Self {
         Self {
             device_id,
-            last_sync_at: None,
+            sync_token: None,
+            limit: None,
+            idempotency_key: None,
             reading_states: vec![],
             annotations: vec![],
+            reading_sessions: vec![],
         }
     }
 
-    pub fn last_sync_at(mut self, time: DateTime<Utc>) -> Self {
-        self.last_sync_at = Some(time);
+    pub fn sync_token(mut self, token: impl Into<String>) -> Self {
+        self.sync_token = Some(token.into());
+        self
+    }
+
+    pub fn limit(mut self, limit: i64) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Key that lets the request be retried safely after a network failure
+    pub fn idempotency_key(mut self, key: impl Into<String>) -> Self {
+        self.idempotency_key = Some(key.into());
         self
     }
 
@@ -40,6 +61,24 @@ impl SyncRequest {
         self.annotations = annotations;
         self
     }
+
+    pub fn with_reading_sessions(mut self, sessions: Vec<ReadingSessionSync>) -> Self {
+        self.reading_sessions = sessions;
+        self
+    }
+}
+
+/// A finished stretch of reading, uploaded for reading statistics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadingSessionSync {
+    /// Generated on the device; resending a session is harmless
+    pub id: Uuid,
+    pub book_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub start_locator: String,
+    pub end_locator: String,
+    pub progress_delta: f32,
 }
 
 /// Reading state for sync
@@ -64,6 +103,11 @@ pub struct AnnotationSync {
     pub text_content: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub color: Option<String>,
+    /// Annotated text with its surroundings, used to re-anchor if the book's file changes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<TextQuoteSelector>,
+    #[serde(default)]
+    pub anchor_status: AnnotationAnchorStatus,
     pub deleted: bool,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
@@ -83,11 +127,28 @@ pub enum AnnotationType {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncResponse {
     pub server_time: DateTime<Utc>,
+    pub sync_token: String,
+    pub has_more: bool,
+    /// The sync token was too old; local library state should be rebuilt from this response
+    #[serde(default)]
+    pub reset: bool,
     pub reading_states: Vec<ReadingStateSync>,
     pub annotations: Vec<AnnotationSync>,
+    #[serde(default)]
+    pub tombstones: Vec<TombstoneSync>,
     pub conflicts: Vec<SyncConflict>,
 }
 
+/// A deleted library entity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TombstoneSync {
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+    pub deleted_at: DateTime<Utc>,
+}
+
 /// Sync conflict information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncConflict {
@@ -96,6 +157,9 @@ pub struct SyncConflict {
     pub resolution: ConflictResolution,
     pub server_version: i64,
     pub client_version: i64,
+    /// Logged copy of the conflict, to review or resolve later
+    #[serde(default)]
+    pub conflict_id: Option<Uuid>,
 }
 
 /// How the conflict was resolved
@@ -105,6 +169,41 @@ pub enum ConflictResolution {
     ServerWins,
     ClientWins,
     Merged,
+    KeptBoth,
+}
+
+/// A logged sync conflict awaiting review
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncConflictRecord {
+    pub id: Uuid,
+    pub device_id: Option<Uuid>,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub resolution: ConflictResolution,
+    pub local_version: serde_json::Value,
+    pub server_version: serde_json::Value,
+    pub copy_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolved_with: Option<ConflictChoice>,
+}
+
+/// Version to keep when resolving a logged conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictChoice {
+    /// Keep the server's version
+    Server,
+    /// Replace the server's version with the device's
+    Local,
+    /// Keep the server's version and restore the device's as a separate annotation
+    Both,
+}
+
+/// Request to resolve a logged conflict
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResolveConflictRequest {
+    pub choice: ConflictChoice,
 }
 
 /// Device registration request
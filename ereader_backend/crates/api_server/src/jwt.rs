@@ -63,9 +63,9 @@ impl JwtValidator {
     /// Validate a JWT token and extract claims
     pub async fn validate(&self, token: &str) -> Result<ClerkClaims, ApiError> {
         // For development tokens (dev_<user_id>), skip validation
-        if token.starts_with("dev_") {
+        if let Some(dev_user_id) = token.strip_prefix("dev_") {
             return Ok(ClerkClaims {
-                id: token[4..].to_string(),
+                id: dev_user_id.to_string(),
                 image: None,
                 username: None,
                 last_name: None,
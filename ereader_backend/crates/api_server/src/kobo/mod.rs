@@ -0,0 +1,753 @@
+//! Kobo store sync emulation.
+//!
+//! Kobo devices keep their library in step with the Kobo store through a small JSON API.
+//! Pointing a device's `api_endpoint` at `/kobo/{app token}` makes it sync with this
+//! library instead: books arrive as entitlements with their metadata and download links,
+//! and reading positions travel both ways as reading states. This module holds the shapes
+//! of the protocol and builds them from library records; the handlers are in
+//! `routes::kobo`. Store features such as purchases and recommendations aren't emulated.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use db_layer::models::{SyncEntityType, FINISHED_PROGRESS};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use sync_engine::kobo::KoboLocation;
+use sync_engine::{BookSync, ReadingStateSync, SyncResponse, SyncToken};
+use uuid::Uuid;
+
+/// Path the emulated store is served under, followed by the app token
+pub const KOBO_ROOT: &str = "/kobo";
+
+/// Header carrying the sync token in both directions
+pub const SYNC_TOKEN_HEADER: &str = "x-kobo-synctoken";
+
+/// Header telling the device whether to request the next page
+pub const SYNC_HEADER: &str = "x-kobo-sync";
+
+/// Value of [`SYNC_HEADER`] when more changes are pending
+pub const SYNC_CONTINUE: &str = "continue";
+
+/// Category and genre every book is filed under
+const DEFAULT_CATEGORY: &str = "00000000-0000-0000-0000-000000000001";
+
+/// Language reported for books without one
+const DEFAULT_LANGUAGE: &str = "en";
+
+/// Position in the library change feed, as handed to a Kobo device
+///
+/// Kobo devices treat the token as opaque and send back whatever they were given last.
+/// Besides the change sequence number it carries the time the last sync completed, since
+/// devices expect books added after it as new entitlements rather than changed ones.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KoboSyncToken {
+    pub seq: i64,
+    /// Unix time the last complete sync finished
+    pub synced_at: i64,
+    /// Tombstone pruning horizon the position was issued under; see [`SyncToken`]
+    pub horizon: i64,
+}
+
+impl KoboSyncToken {
+    const PREFIX: &'static str = "kobo1.";
+
+    /// Parse a token sent by a device; tokens from elsewhere, such as the real Kobo store,
+    /// start a full sync
+    pub fn parse(token: &str) -> Self {
+        token
+            .strip_prefix(Self::PREFIX)
+            .and_then(|rest| {
+                let mut parts = rest.split('.').map(|part| i64::from_str_radix(part, 16).ok());
+                let token = Self {
+                    seq: parts.next()??,
+                    synced_at: parts.next()??,
+                    horizon: parts.next().unwrap_or(Some(0))?,
+                };
+                parts.next().is_none().then_some(token)
+            })
+            .unwrap_or_default()
+    }
+
+    /// Position in the change feed this token resumes after
+    pub fn sync_token(&self) -> SyncToken {
+        SyncToken::new(self.seq).with_horizon(self.horizon)
+    }
+
+    /// Token for the page after a sync response
+    ///
+    /// The completion time only moves once the last page has been sent, so books created
+    /// during a multi-page sync are still new on the pages that follow.
+    pub fn next(&self, response: &SyncResponse) -> Self {
+        Self {
+            seq: response.sync_token.seq(),
+            horizon: response.sync_token.horizon(),
+            synced_at: if response.has_more {
+                self.synced_at
+            } else {
+                response.server_time.timestamp()
+            },
+        }
+    }
+
+    /// Whether a book created at this time is new to the device
+    fn is_new(&self, created_at: DateTime<Utc>, reset: bool) -> bool {
+        reset || self.seq == 0 || created_at.timestamp() >= self.synced_at
+    }
+}
+
+impl std::fmt::Display for KoboSyncToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{:x}.{:x}", Self::PREFIX, self.seq, self.synced_at)?;
+        if self.horizon > 0 {
+            write!(f, ".{:x}", self.horizon)?;
+        }
+        Ok(())
+    }
+}
+
+/// URLs handed to a device, all under its token's route tree
+#[derive(Debug, Clone)]
+pub struct KoboUrls {
+    base: String,
+}
+
+impl KoboUrls {
+    /// `base` is the absolute URL of the token's route tree, without a trailing slash
+    pub fn new(base: impl Into<String>) -> Self {
+        Self { base: base.into() }
+    }
+
+    /// Download URL for a book, converted to KEPUB or as the original EPUB
+    pub fn download(&self, book_id: Uuid, kepub: bool) -> String {
+        let format = if kepub { "kepub" } else { "epub" };
+        format!("{}/v1/download/{}/{}", self.base, book_id, format)
+    }
+
+    /// Resources announced at initialization, pointing the device's library calls here
+    pub fn resources(&self) -> Value {
+        let base = &self.base;
+        json!({
+            "image_host": base,
+            "image_url_template":
+                format!("{}/{{ImageId}}/{{width}}/{{height}}/false/image.jpg", base),
+            "image_url_quality_template":
+                format!("{}/{{ImageId}}/{{width}}/{{height}}/{{Quality}}/{{IsGreyscale}}/image.jpg", base),
+            "library_sync": format!("{}/v1/library/sync", base),
+            "library_metadata": format!("{}/v1/library/{{Ids}}/metadata", base),
+            "reading_state": format!("{}/v1/library/{{Ids}}/state", base),
+            "delete_entry": format!("{}/v1/library/{{Ids}}", base),
+        })
+    }
+}
+
+/// Format a time the way Kobo devices expect
+pub fn timestamp(time: DateTime<Utc>) -> String {
+    time.format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+/// One change in a library sync response
+#[derive(Debug, Clone, Serialize)]
+pub enum SyncItem {
+    NewEntitlement(Entitlement),
+    ChangedEntitlement(Entitlement),
+    ChangedReadingState(ChangedReadingState),
+}
+
+/// A book in the device's library, with its metadata and reading state
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Entitlement {
+    pub book_entitlement: BookEntitlement,
+    /// Left out for removed books
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub book_metadata: Option<BookMetadata>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reading_state: Option<ReadingState>,
+}
+
+/// Reading state of a book whose entitlement didn't change
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ChangedReadingState {
+    pub reading_state: ReadingState,
+}
+
+/// The device's right to a book
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BookEntitlement {
+    pub accessibility: &'static str,
+    pub active_period: ActivePeriod,
+    pub created: String,
+    pub cross_revision_id: Uuid,
+    pub id: Uuid,
+    pub is_removed: bool,
+    pub is_hidden_from_archive: bool,
+    pub is_locked: bool,
+    pub last_modified: String,
+    pub origin_category: &'static str,
+    pub revision_id: Uuid,
+    pub status: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ActivePeriod {
+    pub from: String,
+}
+
+impl BookEntitlement {
+    fn new(book_id: Uuid, created: DateTime<Utc>, modified: DateTime<Utc>, removed: bool) -> Self {
+        Self {
+            accessibility: "Full",
+            active_period: ActivePeriod {
+                from: timestamp(created),
+            },
+            created: timestamp(created),
+            cross_revision_id: book_id,
+            id: book_id,
+            is_removed: removed,
+            is_hidden_from_archive: false,
+            is_locked: false,
+            last_modified: timestamp(modified),
+            origin_category: "Imported",
+            revision_id: book_id,
+            status: "Active",
+        }
+    }
+}
+
+/// Book metadata as the Kobo store describes it
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BookMetadata {
+    pub categories: Vec<&'static str>,
+    pub cover_image_id: Uuid,
+    pub cross_revision_id: Uuid,
+    pub current_display_price: Value,
+    pub current_love_display_price: Value,
+    pub description: Option<String>,
+    pub download_urls: Vec<DownloadUrl>,
+    pub entitlement_id: Uuid,
+    pub external_ids: Vec<String>,
+    pub genre: &'static str,
+    pub is_eligible_for_kobo_love: bool,
+    pub is_internet_archive: bool,
+    pub is_pre_order: bool,
+    pub is_social_enabled: bool,
+    pub isbn: Option<String>,
+    pub language: String,
+    pub phonetic_pronunciations: Value,
+    pub publication_date: Option<String>,
+    pub publisher: Publisher,
+    pub revision_id: Uuid,
+    pub title: String,
+    pub work_id: Uuid,
+    pub contributor_roles: Vec<ContributorRole>,
+    pub contributors: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub series: Option<Series>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DownloadUrl {
+    pub format: &'static str,
+    pub size: i64,
+    pub url: String,
+    pub platform: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Publisher {
+    pub imprint: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContributorRole {
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Series {
+    pub name: String,
+    pub number: String,
+    pub number_float: f32,
+    /// The store uses an id per series; the name serves as one here
+    pub id: String,
+}
+
+impl BookMetadata {
+    /// Describe a book; `kepub` picks which download the device is offered
+    pub fn new(book: &BookSync, urls: &KoboUrls, kepub: bool) -> Self {
+        let download_urls = book
+            .format
+            .map(|_| DownloadUrl {
+                format: if kepub { "KEPUB" } else { "EPUB3" },
+                size: book.file_size.unwrap_or_default(),
+                url: urls.download(book.id, kepub),
+                platform: "Generic",
+            })
+            .into_iter()
+            .collect();
+
+        Self {
+            categories: vec![DEFAULT_CATEGORY],
+            cover_image_id: book.id,
+            cross_revision_id: book.id,
+            current_display_price: json!({ "CurrencyCode": "USD", "TotalAmount": 0 }),
+            current_love_display_price: json!({ "TotalAmount": 0 }),
+            description: book.description.clone(),
+            download_urls,
+            entitlement_id: book.id,
+            external_ids: Vec::new(),
+            genre: DEFAULT_CATEGORY,
+            is_eligible_for_kobo_love: false,
+            is_internet_archive: false,
+            is_pre_order: false,
+            is_social_enabled: true,
+            isbn: book.isbn.clone(),
+            language: book
+                .language
+                .clone()
+                .unwrap_or_else(|| DEFAULT_LANGUAGE.to_string()),
+            phonetic_pronunciations: json!({}),
+            publication_date: book.published_date.as_deref().and_then(publication_date),
+            publisher: Publisher {
+                imprint: String::new(),
+                name: book.publisher.clone().unwrap_or_default(),
+            },
+            revision_id: book.id,
+            title: book.title.clone(),
+            work_id: book.id,
+            contributor_roles: book
+                .authors
+                .iter()
+                .map(|name| ContributorRole { name: name.clone() })
+                .collect(),
+            contributors: book.authors.clone(),
+            series: book.series_name.clone().map(|name| {
+                let index = book.series_index.unwrap_or(1.0);
+                Series {
+                    id: name.clone(),
+                    name,
+                    number: index.to_string(),
+                    number_float: index,
+                }
+            }),
+        }
+    }
+}
+
+/// Turn a publication date such as `2004`, `2004-05` or `2004-05-17` into a timestamp
+fn publication_date(date: &str) -> Option<String> {
+    let date = date.trim();
+    let padded = match date.len() {
+        4 => format!("{}-01-01", date),
+        7 => format!("{}-01", date),
+        _ => date.get(..10)?.to_string(),
+    };
+    let day = NaiveDate::parse_from_str(&padded, "%Y-%m-%d").ok()?;
+    Some(timestamp(day.and_hms_opt(0, 0, 0)?.and_utc()))
+}
+
+/// Where the reader is in a book
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReadStatus {
+    ReadyToRead,
+    Reading,
+    Finished,
+}
+
+impl ReadStatus {
+    fn from_progress(progress: f32) -> Self {
+        if progress >= FINISHED_PROGRESS {
+            ReadStatus::Finished
+        } else if progress > 0.0 {
+            ReadStatus::Reading
+        } else {
+            ReadStatus::ReadyToRead
+        }
+    }
+}
+
+/// Reading state of a book as sent to a device
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReadingState {
+    pub entitlement_id: Uuid,
+    pub created: String,
+    pub last_modified: String,
+    pub priority_timestamp: String,
+    pub status_info: StatusInfo,
+    pub statistics: Statistics,
+    pub current_bookmark: Bookmark,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StatusInfo {
+    pub last_modified: String,
+    pub status: ReadStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Statistics {
+    pub last_modified: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Bookmark {
+    pub last_modified: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress_percent: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub location: Option<KoboLocation>,
+}
+
+impl ReadingState {
+    /// Reading state for the current position in a book
+    ///
+    /// `location` is the bookmark the position translates to, if any.
+    pub fn new(state: &ReadingStateSync, location: Option<KoboLocation>) -> Self {
+        let modified = timestamp(state.updated_at);
+        let progress = state.location.progress;
+        Self {
+            entitlement_id: state.book_id,
+            created: modified.clone(),
+            last_modified: modified.clone(),
+            priority_timestamp: modified.clone(),
+            status_info: StatusInfo {
+                last_modified: modified.clone(),
+                status: ReadStatus::from_progress(progress),
+            },
+            statistics: Statistics {
+                last_modified: modified.clone(),
+            },
+            current_bookmark: Bookmark {
+                last_modified: modified,
+                progress_percent: Some((progress * 100.0).round()),
+                location,
+            },
+        }
+    }
+
+    /// Reading state for a book that hasn't been opened yet
+    pub fn unread(book_id: Uuid, created: DateTime<Utc>) -> Self {
+        let created = timestamp(created);
+        Self {
+            entitlement_id: book_id,
+            created: created.clone(),
+            last_modified: created.clone(),
+            priority_timestamp: created.clone(),
+            status_info: StatusInfo {
+                last_modified: created.clone(),
+                status: ReadStatus::ReadyToRead,
+            },
+            statistics: Statistics {
+                last_modified: created.clone(),
+            },
+            current_bookmark: Bookmark {
+                last_modified: created,
+                progress_percent: None,
+                location: None,
+            },
+        }
+    }
+}
+
+/// Build the items of a library sync response
+///
+/// `reading_states` holds the Kobo form of each reading state in the response, by book.
+/// Books without a file can't be downloaded by the device and are left out.
+pub fn library_sync_items(
+    response: &SyncResponse,
+    since: &KoboSyncToken,
+    urls: &KoboUrls,
+    kepub: bool,
+    mut reading_states: HashMap<Uuid, ReadingState>,
+) -> Vec<SyncItem> {
+    let mut items = Vec::new();
+
+    for book in response.books.iter().filter(|b| b.format.is_some()) {
+        let entitlement = Entitlement {
+            book_entitlement: BookEntitlement::new(book.id, book.created_at, book.updated_at, false),
+            book_metadata: Some(BookMetadata::new(book, urls, kepub)),
+            reading_state: Some(
+                reading_states
+                    .remove(&book.id)
+                    .unwrap_or_else(|| ReadingState::unread(book.id, book.created_at)),
+            ),
+        };
+        items.push(if since.is_new(book.created_at, response.reset) {
+            SyncItem::NewEntitlement(entitlement)
+        } else {
+            SyncItem::ChangedEntitlement(entitlement)
+        });
+    }
+
+    for tombstone in response
+        .tombstones
+        .iter()
+        .filter(|t| t.entity_type == SyncEntityType::Book)
+    {
+        let deleted_at = tombstone.deleted_at;
+        items.push(SyncItem::ChangedEntitlement(Entitlement {
+            book_entitlement: BookEntitlement::new(tombstone.entity_id, deleted_at, deleted_at, true),
+            book_metadata: None,
+            reading_state: None,
+        }));
+    }
+
+    // Reading states of books whose entitlement didn't change this page
+    for state in &response.reading_states {
+        if let Some(reading_state) = reading_states.remove(&state.book_id) {
+            items.push(SyncItem::ChangedReadingState(ChangedReadingState { reading_state }));
+        }
+    }
+
+    items
+}
+
+/// Reading state updates sent by a device
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReadingStatesRequest {
+    pub reading_states: Vec<ReadingStateUpdate>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReadingStateUpdate {
+    #[serde(default)]
+    pub current_bookmark: Option<BookmarkUpdate>,
+    #[serde(default)]
+    pub status_info: Option<StatusInfoUpdate>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct BookmarkUpdate {
+    /// Progress through the whole book, 0-100
+    #[serde(default)]
+    pub progress_percent: Option<f32>,
+    #[serde(default)]
+    pub location: Option<KoboLocation>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StatusInfoUpdate {
+    #[serde(default)]
+    pub status: Option<ReadStatus>,
+}
+
+impl ReadingStateUpdate {
+    /// Progress (0.0-1.0) the update moves the book to, if it moves it at all
+    ///
+    /// Marking a book finished on the device finishes it even without a bookmark.
+    pub fn progress(&self) -> Option<f32> {
+        let bookmark = self
+            .current_bookmark
+            .as_ref()
+            .and_then(|b| b.progress_percent)
+            .map(|percent| (percent / 100.0).clamp(0.0, 1.0));
+        let finished = self
+            .status_info
+            .as_ref()
+            .and_then(|s| s.status)
+            .is_some_and(|s| s == ReadStatus::Finished);
+
+        match bookmark {
+            Some(progress) if finished => Some(progress.max(FINISHED_PROGRESS)),
+            Some(progress) => Some(progress),
+            None => finished.then_some(1.0),
+        }
+    }
+
+    pub fn location(&self) -> Option<&KoboLocation> {
+        self.current_bookmark.as_ref()?.location.as_ref()
+    }
+}
+
+/// Reply to a reading state update
+pub fn update_result(book_id: Uuid, applied: bool) -> Value {
+    let result = if applied { "Success" } else { "Ignored" };
+    json!({
+        "RequestResult": "Success",
+        "UpdateResults": [{
+            "EntitlementId": book_id,
+            "CurrentBookmarkResult": { "Result": result },
+            "StatisticsResult": { "Result": "Ignored" },
+            "StatusInfoResult": { "Result": result },
+        }],
+    })
+}
+
+/// `Content-Disposition` value for downloading a book as `{stem}.kepub.epub`
+///
+/// Titles can hold quotes and non-ASCII characters, which a quoted filename can't carry, so
+/// the plain `filename` replaces them with `_` and `filename*` carries the exact name
+/// percent-encoded as UTF-8 (RFC 5987).
+pub fn kepub_disposition(stem: &str) -> String {
+    let name = format!("{}.kepub.epub", stem);
+    let fallback: String = name
+        .chars()
+        .map(|c| match c {
+            '"' | '\\' => '_',
+            ' ' => c,
+            c if c.is_ascii_graphic() => c,
+            _ => '_',
+        })
+        .collect();
+    let encoded: String = name
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'!' | b'#' | b'$' | b'&' | b'+' | b'-'
+            | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect();
+
+    format!("attachment; filename=\"{}\"; filename*=UTF-8''{}", fallback, encoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use common::{HybridTimestamp, ReadingLocation};
+    use sync_engine::TombstoneSync;
+
+    fn time(day: u32, hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 2, day, hour, 0, 0).unwrap()
+    }
+
+    fn book(id: &str, created: DateTime<Utc>) -> BookSync {
+        BookSync {
+            id: id.parse().unwrap(),
+            title: "The Left Hand of Darkness".to_string(),
+            authors: vec!["Ursula K. Le Guin".to_string()],
+            description: Some("A story of Winter.".to_string()),
+            language: None,
+            publisher: Some("Ace".to_string()),
+            published_date: Some("1969".to_string()),
+            isbn: None,
+            series_name: Some("Hainish Cycle".to_string()),
+            series_index: Some(4.0),
+            tags: vec![],
+            format: Some(common::BookFormat::Epub),
+            content_hash: None,
+            file_size: Some(524288),
+            created_at: created,
+            updated_at: time(12, 9),
+        }
+    }
+
+    #[test]
+    fn test_sync_token_round_trip() {
+        let token = KoboSyncToken {
+            seq: 300,
+            synced_at: 1_739_000_000,
+            horizon: 120,
+        };
+        assert_eq!(KoboSyncToken::parse(&token.to_string()), token);
+        assert_eq!(KoboSyncToken::parse("eyJhbGciOi.store.token"), KoboSyncToken::default());
+
+        let mut response = SyncResponse::empty();
+        response.sync_token = SyncToken::new(400);
+        response.has_more = true;
+        assert_eq!(token.next(&response).synced_at, token.synced_at);
+        response.has_more = false;
+        assert_eq!(token.next(&response).synced_at, response.server_time.timestamp());
+        assert_eq!(token.next(&response).seq, 400);
+        assert_eq!(KoboSyncToken::parse("kobo1.12c.67a5a000").horizon, 0);
+    }
+
+    #[test]
+    fn test_publication_date() {
+        assert_eq!(publication_date("1969").as_deref(), Some("1969-01-01T00:00:00Z"));
+        assert_eq!(publication_date("2004-05").as_deref(), Some("2004-05-01T00:00:00Z"));
+        assert_eq!(
+            publication_date("2004-05-17T10:00:00+02:00").as_deref(),
+            Some("2004-05-17T00:00:00Z")
+        );
+        assert_eq!(publication_date("spring"), None);
+    }
+
+    #[test]
+    fn test_recorded_state_updates() {
+        let request: ReadingStatesRequest =
+            serde_json::from_str(include_str!("fixtures/state_update.json")).unwrap();
+        let update = &request.reading_states[0];
+        assert_eq!(update.progress(), Some(0.42));
+        let location = update.location().unwrap();
+        assert_eq!(location.value, "kobo.27.3");
+        assert_eq!(location.source, "OEBPS/Text/chapter05.xhtml");
+
+        let request: ReadingStatesRequest =
+            serde_json::from_str(include_str!("fixtures/state_finished.json")).unwrap();
+        assert_eq!(request.reading_states[0].progress(), Some(1.0));
+        assert!(request.reading_states[0].location().is_none());
+    }
+
+    #[test]
+    fn test_library_sync_matches_recorded_response() {
+        let urls = KoboUrls::new("http://books.local/kobo/ert_token");
+        let since = KoboSyncToken {
+            seq: 10,
+            synced_at: time(10, 0).timestamp(),
+            horizon: 0,
+        };
+
+        let added = book("01950a3e-7c1a-7000-8000-000000000001", time(11, 8));
+        let changed = book("01950a3e-7c1a-7000-8000-000000000002", time(1, 8));
+        let read_elsewhere: Uuid = "01950a3e-7c1a-7000-8000-000000000003".parse().unwrap();
+        let state = ReadingStateSync {
+            book_id: read_elsewhere,
+            location: ReadingLocation::new("epubcfi(/6/10!/4/2/1:0)", 0.25),
+            hlc: HybridTimestamp::new(time(12, 10).timestamp_millis(), 0, Uuid::nil()),
+            base_hlc: None,
+            updated_at: time(12, 10),
+        };
+
+        let mut response = SyncResponse::empty();
+        response.books = vec![added, changed];
+        response.reading_states = vec![state.clone()];
+        response.tombstones = vec![TombstoneSync {
+            entity_type: SyncEntityType::Book,
+            entity_id: "01950a3e-7c1a-7000-8000-000000000004".parse().unwrap(),
+            parent_id: None,
+            deleted_at: time(12, 11),
+        }];
+
+        let location = KoboLocation::chapter_start("OEBPS/Text/chapter05.xhtml");
+        let reading_states =
+            HashMap::from([(read_elsewhere, ReadingState::new(&state, Some(location)))]);
+        let items = library_sync_items(&response, &since, &urls, true, reading_states);
+
+        let expected: Value =
+            serde_json::from_str(include_str!("fixtures/library_sync.json")).unwrap();
+        assert_eq!(serde_json::to_value(&items).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_kepub_disposition() {
+        assert_eq!(
+            kepub_disposition("Dune"),
+            "attachment; filename=\"Dune.kepub.epub\"; filename*=UTF-8''Dune.kepub.epub"
+        );
+        assert_eq!(
+            kepub_disposition("L'Étranger \"Camus\""),
+            "attachment; filename=\"L'_tranger _Camus_.kepub.epub\"; \
+             filename*=UTF-8''L%27%C3%89tranger%20%22Camus%22.kepub.epub"
+        );
+        assert!(axum::http::HeaderValue::from_str(&kepub_disposition("東京\r\n")).is_ok());
+    }
+}
@@ -4,12 +4,16 @@
 //! - HTTP API server using Axum
 //! - Authentication middleware
 //! - Route handlers for books, sync, and health checks
+//! - OPDS catalog feeds for third-party reading apps
+//! - Kobo store sync emulation for Kobo devices
 //! - Application state management
 
 pub mod error;
 pub mod extractors;
 pub mod jwt;
+pub mod kobo;
 pub mod middleware;
+pub mod opds;
 pub mod routes;
 pub mod state;
 
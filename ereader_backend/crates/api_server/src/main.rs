@@ -45,6 +45,7 @@ async fn main() -> anyhow::Result<()> {
     let state_config = AppStateConfig {
         clerk_secret_key: config.clerk.secret_key.clone(),
         clerk_jwks_url: config.clerk.jwks_url.clone(),
+        kobo: config.kobo.clone(),
     };
     let state = AppState::new(pool, storage, state_config);
 
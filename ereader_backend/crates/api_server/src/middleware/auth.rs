@@ -1,15 +1,33 @@
 //! Authentication middleware using Clerk JWT verification.
+//!
+//! Clients that can't sign in with Clerk, such as OPDS readers, authenticate with an app
+//! token instead, sent as the password of HTTP Basic auth or as a bearer token. KOReader's
+//! progress sync signs in with its own account headers, and Kobo devices carry an app token
+//! in the path of every request.
 
 use axum::{
     body::Body,
-    extract::State,
-    http::{header, Request, StatusCode},
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
     middleware::Next,
-    response::Response,
+    response::{IntoResponse, Response},
 };
+use axum_extra::headers::{
+    authorization::{Basic, Bearer},
+    Authorization, HeaderMapExt,
+};
+use common::config::KoboConfig;
+use db_layer::models::{hash_app_token, hash_kosync_key, is_app_token};
+use db_layer::queries::{AppTokenQueries, KosyncAccountQueries};
+use std::collections::HashMap;
 use crate::extractors::AuthUser;
+use crate::kobo::{KoboUrls, KOBO_ROOT};
+use crate::routes::kosync::KosyncError;
 use crate::state::AppState;
 
+/// Realm offered to clients that should prompt for Basic credentials
+const BASIC_REALM: &str = "Basic realm=\"ereader\", charset=\"UTF-8\"";
+
 /// Middleware that validates JWT tokens and extracts user info
 pub async fn auth_middleware(
     State(state): State<AppState>,
@@ -50,3 +68,163 @@ pub async fn auth_middleware(
 
     Ok(next.run(request).await)
 }
+
+/// Middleware that accepts an app token or a Clerk JWT
+///
+/// App tokens are accepted as the Basic auth password (the username is ignored) or as a
+/// bearer token. Rejections ask for Basic credentials so reading apps prompt the user.
+pub async fn app_auth_middleware(
+    State(state): State<AppState>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let headers = request.headers();
+    let user_id = if let Some(Authorization(basic)) = headers.typed_get::<Authorization<Basic>>() {
+        app_token_user(&state, basic.password()).await
+    } else if let Some(Authorization(bearer)) = headers.typed_get::<Authorization<Bearer>>() {
+        if is_app_token(bearer.token()) {
+            app_token_user(&state, bearer.token()).await
+        } else {
+            match state.jwt_validator.validate(bearer.token()).await {
+                Ok(claims) => Ok(Some(claims.id).filter(|id| !id.is_empty())),
+                Err(e) => {
+                    tracing::warn!(error = %e, "JWT validation failed");
+                    Ok(None)
+                }
+            }
+        }
+    } else {
+        Ok(None)
+    };
+
+    let user_id = match user_id {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => {
+            let mut response = StatusCode::UNAUTHORIZED.into_response();
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static(BASIC_REALM));
+            return response;
+        }
+        Err(e) => return e.into_response(),
+    };
+
+    request.extensions_mut().insert(AuthUser::new(user_id));
+    next.run(request).await
+}
+
+/// Middleware that checks KOReader progress sync credentials
+///
+/// KOReader sends the account's username in `x-auth-user` and the MD5 of its password in
+/// `x-auth-key`.
+pub async fn kosync_auth_middleware(
+    State(state): State<AppState>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let header_str = |headers: &header::HeaderMap, name: &str| {
+        headers
+            .get(name)
+            .and_then(|h| h.to_str().ok())
+            .filter(|v| !v.is_empty())
+            .map(str::to_string)
+    };
+    let username = header_str(request.headers(), "x-auth-user");
+    let key = header_str(request.headers(), "x-auth-key");
+    let (Some(username), Some(key)) = (username, key) else {
+        return KosyncError::unauthorized().into_response();
+    };
+
+    let key_hash = hash_kosync_key(&key);
+    let account =
+        match KosyncAccountQueries::authenticate(&state.pool, &username, &key_hash).await {
+            Ok(Some(account)) => account,
+            Ok(None) => return KosyncError::unauthorized().into_response(),
+            Err(e) => return KosyncError::from(e).into_response(),
+        };
+
+    request.extensions_mut().insert(AuthUser::new(account.user_id));
+    next.run(request).await
+}
+
+/// Middleware that checks the app token in the path of Kobo store sync requests
+///
+/// Kobo devices can only be configured with a base URL, so the token is its first path
+/// segment. The token and the URLs handed to the device are added to the request.
+pub async fn kobo_auth_middleware(
+    State(state): State<AppState>,
+    Path(params): Path<HashMap<String, String>>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(token) = params.get("token") else {
+        return StatusCode::UNAUTHORIZED.into_response();
+    };
+
+    let base = format!(
+        "{}{}/{}",
+        public_base_url(&state.config.kobo, request.headers()),
+        KOBO_ROOT,
+        token
+    );
+
+    let app_token = match AppTokenQueries::authenticate(&state.pool, &hash_app_token(token)).await {
+        Ok(Some(app_token)) => app_token,
+        Ok(None) => return StatusCode::UNAUTHORIZED.into_response(),
+        Err(e) => return e.into_response(),
+    };
+
+    request.extensions_mut().insert(AuthUser::new(app_token.user_id.clone()));
+    request.extensions_mut().insert(app_token);
+    request.extensions_mut().insert(KoboUrls::new(base));
+    next.run(request).await
+}
+
+/// Scheme and host device links are built on, without a trailing slash
+///
+/// The configured public URL wins. Forwarded headers are client-supplied unless a proxy
+/// overwrites them, so they are only read when the config says to trust them.
+fn public_base_url(config: &KoboConfig, headers: &HeaderMap) -> String {
+    if let Some(url) = &config.public_url {
+        return url.trim_end_matches('/').to_string();
+    }
+
+    let header = |name: &str| headers.get(name).and_then(|h| h.to_str().ok());
+    let forwarded = |name: &str| header(name).filter(|_| config.trust_proxy_headers);
+    let host = forwarded("x-forwarded-host")
+        .or_else(|| header(header::HOST.as_str()))
+        .unwrap_or("localhost");
+    let scheme = forwarded("x-forwarded-proto").unwrap_or("http");
+    format!("{}://{}", scheme, host)
+}
+
+/// Look up the user an app token belongs to
+async fn app_token_user(state: &AppState, token: &str) -> common::Result<Option<String>> {
+    let found = AppTokenQueries::authenticate(&state.pool, &hash_app_token(token)).await?;
+    Ok(found.map(|t| t.user_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::HOST, HeaderValue::from_static("books.local:8080"));
+        headers.insert("x-forwarded-host", HeaderValue::from_static("proxy.example"));
+        headers.insert("x-forwarded-proto", HeaderValue::from_static("https"));
+        headers
+    }
+
+    #[test]
+    fn test_public_base_url() {
+        let mut config = KoboConfig::default();
+        assert_eq!(public_base_url(&config, &headers()), "http://books.local:8080");
+
+        config.trust_proxy_headers = true;
+        assert_eq!(public_base_url(&config, &headers()), "https://proxy.example");
+
+        config.public_url = Some("https://books.example.com/".into());
+        assert_eq!(public_base_url(&config, &headers()), "https://books.example.com");
+    }
+}
@@ -2,4 +2,4 @@
 
 pub mod auth;
 
-pub use auth::auth_middleware;
+pub use auth::{app_auth_middleware, auth_middleware, kobo_auth_middleware, kosync_auth_middleware};
@@ -0,0 +1,182 @@
+//! OPDS 1.2 (Atom) rendering.
+
+use std::borrow::Cow;
+
+use common::types::relator;
+
+use super::{Feed, FeedKind, NavigationEntry, OpdsVersion, Publication};
+
+pub const NAVIGATION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+pub const ACQUISITION_TYPE: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+pub const OPENSEARCH_TYPE: &str = "application/opensearchdescription+xml";
+
+const ACQUISITION_REL: &str = "http://opds-spec.org/acquisition";
+const IMAGE_REL: &str = "http://opds-spec.org/image";
+const THUMBNAIL_REL: &str = "http://opds-spec.org/image/thumbnail";
+
+/// Media type of a feed
+pub fn feed_type(kind: FeedKind) -> &'static str {
+    match kind {
+        FeedKind::Navigation => NAVIGATION_TYPE,
+        FeedKind::Acquisition => ACQUISITION_TYPE,
+    }
+}
+
+/// Render a feed as an Atom document
+pub fn render(feed: &Feed) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(
+        "<feed xmlns=\"http://www.w3.org/2005/Atom\" \
+         xmlns:dc=\"http://purl.org/dc/terms/\" \
+         xmlns:opds=\"http://opds-spec.org/2010/catalog\" \
+         xmlns:opensearch=\"http://a9.com/-/spec/opensearch/1.1/\">\n",
+    );
+
+    element(&mut xml, "id", &feed.id);
+    element(&mut xml, "title", &feed.title);
+    element(&mut xml, "updated", &feed.updated.to_rfc3339());
+    link(&mut xml, "self", &feed.self_href, feed_type(feed.kind), None);
+    link(&mut xml, "start", &OpdsVersion::V1.href("/"), NAVIGATION_TYPE, None);
+    link(&mut xml, "search", &OpdsVersion::V1.href("/opensearch.xml"), OPENSEARCH_TYPE, None);
+    for l in &feed.links {
+        link(&mut xml, l.rel, &l.href, feed_type(l.kind), None);
+    }
+
+    if let Some(page) = &feed.page {
+        element(&mut xml, "opensearch:totalResults", &page.total.to_string());
+        element(&mut xml, "opensearch:itemsPerPage", &page.per_page.to_string());
+        element(
+            &mut xml,
+            "opensearch:startIndex",
+            &(page.pagination().offset + 1).to_string(),
+        );
+    }
+
+    for entry in &feed.navigation {
+        navigation_entry(&mut xml, entry);
+    }
+    for publication in &feed.publications {
+        publication_entry(&mut xml, publication);
+    }
+
+    xml.push_str("</feed>\n");
+    xml
+}
+
+/// OpenSearch description pointing clients at the catalog's search feed
+pub fn opensearch_description() -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <OpenSearchDescription xmlns=\"http://a9.com/-/spec/opensearch/1.1/\">\n\
+         <ShortName>Library</ShortName>\n\
+         <Description>Search the library by title, author, or description</Description>\n\
+         <InputEncoding>UTF-8</InputEncoding>\n\
+         <OutputEncoding>UTF-8</OutputEncoding>\n\
+         <Url type=\"{}\" template=\"{}\"/>\n\
+         </OpenSearchDescription>\n",
+        escape(ACQUISITION_TYPE),
+        escape(&OpdsVersion::V1.href("/search?q={searchTerms}")),
+    )
+}
+
+fn navigation_entry(xml: &mut String, entry: &NavigationEntry) {
+    xml.push_str("<entry>\n");
+    element(xml, "title", &entry.title);
+    element(xml, "id", &entry.id);
+    element(xml, "updated", &entry.updated.to_rfc3339());
+    if let Some(summary) = &entry.summary {
+        xml.push_str(&format!("<content type=\"text\">{}</content>\n", escape(summary)));
+    }
+    link(xml, "subsection", &entry.href, feed_type(entry.kind), None);
+    xml.push_str("</entry>\n");
+}
+
+fn publication_entry(xml: &mut String, publication: &Publication) {
+    let book = &publication.book;
+
+    xml.push_str("<entry>\n");
+    element(xml, "title", &book.title);
+    element(xml, "id", &format!("urn:uuid:{}", book.id));
+    element(xml, "updated", &book.updated_at.to_rfc3339());
+    for author in &book.authors {
+        xml.push_str(&format!("<author><name>{}</name></author>\n", escape(author)));
+    }
+    for contributor in book.contributors.iter().filter(|c| c.role != relator::AUTHOR) {
+        xml.push_str(&format!("<contributor><name>{}</name></contributor>\n", escape(&contributor.name)));
+    }
+    if let Some(language) = &book.language {
+        element(xml, "dc:language", language);
+    }
+    if let Some(publisher) = &book.publisher {
+        element(xml, "dc:publisher", publisher);
+    }
+    if let Some(published) = &book.published_date {
+        element(xml, "dc:issued", published);
+    }
+    if let Some(isbn) = &book.isbn {
+        element(xml, "dc:identifier", &format!("urn:isbn:{}", isbn));
+    }
+    for tag in &book.tags {
+        xml.push_str(&format!(
+            "<category term=\"{0}\" label=\"{0}\"/>\n",
+            escape(tag)
+        ));
+    }
+    if let Some(description) = &book.description {
+        xml.push_str(&format!("<summary type=\"text\">{}</summary>\n", escape(description)));
+    }
+
+    if let Some(href) = &publication.image_href {
+        link(xml, IMAGE_REL, href, "image/jpeg", None);
+    }
+    if let Some(href) = &publication.thumbnail_href {
+        link(xml, THUMBNAIL_REL, href, "image/jpeg", None);
+    }
+    if let Some(format) = book.format {
+        link(
+            xml,
+            ACQUISITION_REL,
+            &publication.acquisition_href,
+            format.mime_type(),
+            book.file_size,
+        );
+    }
+    xml.push_str("</entry>\n");
+}
+
+fn element(xml: &mut String, name: &str, text: &str) {
+    xml.push_str(&format!("<{0}>{1}</{0}>\n", name, escape(text)));
+}
+
+fn link(xml: &mut String, rel: &str, href: &str, media_type: &str, length: Option<i64>) {
+    xml.push_str(&format!(
+        "<link rel=\"{}\" href=\"{}\" type=\"{}\"",
+        escape(rel),
+        escape(href),
+        escape(media_type)
+    ));
+    if let Some(length) = length {
+        xml.push_str(&format!(" length=\"{}\"", length));
+    }
+    xml.push_str("/>\n");
+}
+
+/// Escape text for use in element content or attribute values
+fn escape(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
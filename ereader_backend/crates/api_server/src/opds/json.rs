@@ -0,0 +1,137 @@
+//! OPDS 2.0 (JSON) rendering.
+
+use common::types::relator;
+use serde_json::{json, Map, Value};
+
+use super::{Feed, FeedKind, NavigationEntry, OpdsVersion, Publication};
+
+pub const OPDS_JSON_TYPE: &str = "application/opds+json";
+
+const ACQUISITION_REL: &str = "http://opds-spec.org/acquisition";
+
+/// Render a feed as an OPDS 2.0 document
+pub fn render(feed: &Feed) -> Value {
+    let mut metadata = Map::new();
+    metadata.insert("title".into(), json!(feed.title));
+    metadata.insert("modified".into(), json!(feed.updated));
+    if let Some(page) = &feed.page {
+        metadata.insert("numberOfItems".into(), json!(page.total));
+        metadata.insert("itemsPerPage".into(), json!(page.per_page));
+        metadata.insert("currentPage".into(), json!(page.number));
+    }
+
+    let mut links = vec![
+        link("self", &feed.self_href, OPDS_JSON_TYPE),
+        link("start", &OpdsVersion::V2.href("/"), OPDS_JSON_TYPE),
+        json!({
+            "rel": "search",
+            "href": OpdsVersion::V2.href("/search{?query}"),
+            "type": OPDS_JSON_TYPE,
+            "templated": true,
+        }),
+    ];
+    links.extend(
+        feed.links
+            .iter()
+            .map(|l| link(l.rel, &l.href, OPDS_JSON_TYPE)),
+    );
+
+    let mut document = json!({
+        "metadata": metadata,
+        "links": links,
+    });
+    // Acquisition feeds may lead with links to subsections, such as nested collections
+    if feed.kind == FeedKind::Navigation || !feed.navigation.is_empty() {
+        document["navigation"] = feed.navigation.iter().map(navigation_link).collect();
+    }
+    if feed.kind == FeedKind::Acquisition {
+        document["publications"] = feed.publications.iter().map(publication).collect();
+    }
+    document
+}
+
+fn navigation_link(entry: &NavigationEntry) -> Value {
+    json!({
+        "href": entry.href,
+        "title": entry.title,
+        "type": OPDS_JSON_TYPE,
+        "rel": "subsection",
+    })
+}
+
+fn publication(publication: &Publication) -> Value {
+    let book = &publication.book;
+
+    let mut metadata = Map::new();
+    metadata.insert("@type".into(), json!("http://schema.org/Book"));
+    metadata.insert("identifier".into(), json!(format!("urn:uuid:{}", book.id)));
+    metadata.insert("title".into(), json!(book.title));
+    metadata.insert("modified".into(), json!(book.updated_at));
+    if !book.authors.is_empty() {
+        let authors: Vec<_> = book.authors.iter().map(|a| json!({ "name": a })).collect();
+        metadata.insert("author".into(), json!(authors));
+    }
+    // Other roles under their OPDS names, or as contributors with the relator code
+    for contributor in book.contributors.iter().filter(|c| c.role != relator::AUTHOR) {
+        let (key, entry) = match relator::term(&contributor.role) {
+            Some(term) => (term, json!({ "name": contributor.name })),
+            None => ("contributor", json!({ "name": contributor.name, "role": contributor.role })),
+        };
+        if let Value::Array(entries) = metadata.entry(key).or_insert_with(|| json!([])) {
+            entries.push(entry);
+        }
+    }
+    if let Some(language) = &book.language {
+        metadata.insert("language".into(), json!(language));
+    }
+    if let Some(publisher) = &book.publisher {
+        metadata.insert("publisher".into(), json!(publisher));
+    }
+    if let Some(published) = &book.published_date {
+        metadata.insert("published".into(), json!(published));
+    }
+    if let Some(description) = &book.description {
+        metadata.insert("description".into(), json!(description));
+    }
+    if !book.tags.is_empty() {
+        metadata.insert("subject".into(), json!(book.tags));
+    }
+    if let Some(series) = &book.series_name {
+        let mut entry = json!({ "name": series });
+        if let Some(position) = book.series_index {
+            entry["position"] = json!(position);
+        }
+        metadata.insert("belongsTo".into(), json!({ "series": [entry] }));
+    }
+
+    let mut acquisition = json!({
+        "rel": ACQUISITION_REL,
+        "href": publication.acquisition_href,
+    });
+    if let Some(format) = book.format {
+        acquisition["type"] = json!(format.mime_type());
+    }
+    if let Some(size) = book.file_size {
+        acquisition["length"] = json!(size);
+    }
+
+    let images: Vec<_> = [&publication.image_href, &publication.thumbnail_href]
+        .into_iter()
+        .flatten()
+        .map(|href| json!({ "href": href, "type": "image/jpeg" }))
+        .collect();
+
+    json!({
+        "metadata": metadata,
+        "links": [acquisition],
+        "images": images,
+    })
+}
+
+fn link(rel: &str, href: &str, media_type: &str) -> Value {
+    json!({
+        "rel": rel,
+        "href": href,
+        "type": media_type,
+    })
+}
@@ -0,0 +1,235 @@
+//! OPDS catalog feeds.
+//!
+//! Handlers describe each feed once as a [`Feed`], which is rendered as an OPDS 1.2 Atom
+//! document or an OPDS 2.0 JSON document depending on the catalog version requested.
+
+pub mod atom;
+pub mod json;
+
+use axum::{
+    http::header,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use common::Pagination;
+use db_layer::models::Book;
+
+/// Path the catalog is served under
+pub const OPDS_ROOT: &str = "/api/v1/opds";
+
+/// OPDS catalog version, chosen by the path a feed is requested under
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpdsVersion {
+    /// OPDS 1.2 (Atom), served at the catalog root
+    V1,
+    /// OPDS 2.0 (JSON), served under `/v2`
+    V2,
+}
+
+impl OpdsVersion {
+    /// Absolute path of a feed in this version of the catalog
+    pub fn href(&self, path: &str) -> String {
+        match self {
+            OpdsVersion::V1 => format!("{}{}", OPDS_ROOT, path),
+            OpdsVersion::V2 => format!("{}/v2{}", OPDS_ROOT, path),
+        }
+    }
+
+    /// Render a feed with this version's media type
+    pub fn render(&self, feed: &Feed) -> Response {
+        match self {
+            OpdsVersion::V1 => {
+                ([(header::CONTENT_TYPE, atom::feed_type(feed.kind))], atom::render(feed))
+                    .into_response()
+            }
+            OpdsVersion::V2 => {
+                ([(header::CONTENT_TYPE, json::OPDS_JSON_TYPE)], json::render(feed).to_string())
+                    .into_response()
+            }
+        }
+    }
+}
+
+/// Whether a feed lists other feeds or books
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedKind {
+    Navigation,
+    Acquisition,
+}
+
+/// Link from a feed to a related feed
+#[derive(Debug, Clone)]
+pub struct FeedLink {
+    pub rel: &'static str,
+    pub href: String,
+    pub kind: FeedKind,
+}
+
+/// Position of a feed within a paginated listing
+#[derive(Debug, Clone, Copy)]
+pub struct FeedPage {
+    /// Page number, starting at 1
+    pub number: i64,
+    pub per_page: i64,
+    pub total: i64,
+}
+
+impl FeedPage {
+    pub fn new(number: Option<i64>, per_page: i64) -> Self {
+        Self {
+            number: number.unwrap_or(1).max(1),
+            per_page,
+            total: 0,
+        }
+    }
+
+    pub fn with_total(mut self, total: i64) -> Self {
+        self.total = total;
+        self
+    }
+
+    pub fn pagination(&self) -> Pagination {
+        Pagination::new(self.per_page, (self.number - 1) * self.per_page)
+    }
+
+    /// Number of the last page, which is 1 for an empty listing
+    pub fn last(&self) -> i64 {
+        ((self.total + self.per_page - 1) / self.per_page).max(1)
+    }
+}
+
+/// Entry of a navigation feed, pointing at another feed
+#[derive(Debug, Clone)]
+pub struct NavigationEntry {
+    pub id: String,
+    pub title: String,
+    pub href: String,
+    pub summary: Option<String>,
+    pub updated: DateTime<Utc>,
+    pub kind: FeedKind,
+}
+
+/// Entry of an acquisition feed, describing a book that can be downloaded
+#[derive(Debug, Clone)]
+pub struct Publication {
+    pub book: Book,
+    pub acquisition_href: String,
+    pub image_href: Option<String>,
+    pub thumbnail_href: Option<String>,
+}
+
+impl Publication {
+    /// Describe a book, or `None` if it has no file to download
+    pub fn new(book: Book, has_cover: bool) -> Option<Self> {
+        if !book.has_file() || book.format.is_none() {
+            return None;
+        }
+
+        let cover_href = |size: &str| format!("{}/books/{}/cover/{}", OPDS_ROOT, book.id, size);
+        Some(Self {
+            acquisition_href: format!("{}/books/{}/download", OPDS_ROOT, book.id),
+            image_href: has_cover.then(|| cover_href("large")),
+            thumbnail_href: has_cover.then(|| cover_href("small")),
+            book,
+        })
+    }
+}
+
+/// A catalog feed, independent of the OPDS version it is rendered as
+#[derive(Debug, Clone)]
+pub struct Feed {
+    /// Permanent identifier of the feed
+    pub id: String,
+    pub title: String,
+    pub updated: DateTime<Utc>,
+    pub kind: FeedKind,
+    pub self_href: String,
+    pub links: Vec<FeedLink>,
+    pub page: Option<FeedPage>,
+    pub navigation: Vec<NavigationEntry>,
+    pub publications: Vec<Publication>,
+}
+
+impl Feed {
+    pub fn new(
+        id: impl Into<String>,
+        title: impl Into<String>,
+        kind: FeedKind,
+        self_href: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: id.into(),
+            title: title.into(),
+            updated: Utc::now(),
+            kind,
+            self_href: self_href.into(),
+            links: vec![],
+            page: None,
+            navigation: vec![],
+            publications: vec![],
+        }
+    }
+
+    pub fn with_link(mut self, rel: &'static str, href: impl Into<String>, kind: FeedKind) -> Self {
+        self.links.push(FeedLink {
+            rel,
+            href: href.into(),
+            kind,
+        });
+        self
+    }
+
+    pub fn with_navigation(mut self, entries: Vec<NavigationEntry>) -> Self {
+        self.navigation = entries;
+        self
+    }
+
+    pub fn with_publications(mut self, publications: Vec<Publication>) -> Self {
+        self.publications = publications;
+        self
+    }
+
+    /// Mark the feed as one page of a listing, linking to its first, previous, next, and
+    /// last pages
+    pub fn with_page(mut self, page: FeedPage, href: impl Fn(i64) -> String) -> Self {
+        let kind = self.kind;
+        let last = page.last();
+        self = self.with_link("first", href(1), kind);
+        if page.number > 1 {
+            self = self.with_link("previous", href((page.number - 1).min(last)), kind);
+        }
+        if page.number < last {
+            self = self.with_link("next", href(page.number + 1), kind);
+        }
+        self = self.with_link("last", href(last), kind);
+        self.page = Some(page);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_links() {
+        let page = FeedPage::new(Some(2), 50).with_total(120);
+        assert_eq!(page.last(), 3);
+        assert_eq!(page.pagination().offset, 50);
+
+        let feed = Feed::new("urn:test", "Test", FeedKind::Acquisition, "/books?page=2")
+            .with_page(page, |n| format!("/books?page={}", n));
+        let rels: Vec<_> = feed.links.iter().map(|l| (l.rel, l.href.as_str())).collect();
+        assert_eq!(
+            rels,
+            [
+                ("first", "/books?page=1"),
+                ("previous", "/books?page=1"),
+                ("next", "/books?page=3"),
+                ("last", "/books?page=3"),
+            ]
+        );
+
+        assert_eq!(FeedPage::new(None, 50).last(), 1);
+    }
+}
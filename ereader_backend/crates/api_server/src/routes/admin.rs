@@ -8,7 +8,9 @@ use axum::{
 use serde::{Deserialize, Serialize};
 
 use crate::extractors::AuthUser;
+use crate::routes::authors::AuthorResponse;
 use crate::state::AppState;
+use sync_engine::{ChangeAction, SyncChange};
 
 /// Statistics response
 #[derive(Debug, Serialize)]
@@ -54,6 +56,14 @@ pub struct BackupResponse {
     pub message: String,
 }
 
+/// Author merge request
+#[derive(Debug, Deserialize)]
+pub struct MergeAuthorsRequest {
+    /// Author the others are merged into
+    pub into_id: uuid::Uuid,
+    pub author_ids: Vec<uuid::Uuid>,
+}
+
 /// Trigger reindexing of books
 /// This extracts metadata from uploaded files and regenerates covers
 pub async fn trigger_reindex(
@@ -135,6 +145,42 @@ pub async fn create_backup(
     ))
 }
 
+/// Merge duplicate authors into one
+/// Books by the merged authors list the remaining author instead, and their names become
+/// its aliases
+pub async fn merge_authors(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(req): Json<MergeAuthorsRequest>,
+) -> Result<Json<AuthorResponse>, StatusCode> {
+    // TODO: Verify user has admin role
+
+    let author = db_layer::queries::AuthorQueries::merge(&state.pool, &user.user_id, req.into_id, &req.author_ids)
+        .await
+        .map_err(|e| match e {
+            common::Error::NotFound(_) => StatusCode::NOT_FOUND,
+            e => {
+                tracing::error!(error = %e, "Failed to merge authors");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+
+    // The merged authors' books now list this author by name
+    match db_layer::queries::AuthorQueries::get_books(&state.pool, author.id).await {
+        Ok(books) => {
+            for book in books {
+                state.publish_change(
+                    &user.user_id,
+                    SyncChange::Book { book_id: book.id, action: ChangeAction::Updated },
+                );
+            }
+        }
+        Err(e) => tracing::warn!(error = %e, "Failed to list merged author books"),
+    }
+
+    Ok(Json(author.into()))
+}
+
 /// Get system statistics
 pub async fn get_stats(
     State(_state): State<AppState>,
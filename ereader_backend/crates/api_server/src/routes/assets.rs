@@ -7,10 +7,12 @@ use axum::{
     response::Response,
     Json,
 };
-use common::{BookFormat, ContentHash, Error, Result};
-use db_layer::queries::BookQueries;
+use common::{BookFormat, ContentHash, Error, PartialMd5, Result};
+use db_layer::models::{Book, CreateTask, task_types};
+use db_layer::queries::{AuthorQueries, BookQueries, TaskQueries};
 use serde::Serialize;
 use storage_layer::{CoverStorage, Storage};
+use sync_engine::{ChangeAction, SyncChange};
 use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
@@ -25,11 +27,11 @@ pub async fn upload_file(
     mut multipart: Multipart,
 ) -> Result<Json<UploadResponse>> {
     // Verify book exists and belongs to user
-    let _book = BookQueries::get_by_id_for_user(&state.pool, id, &auth.user_id)
+    let existing = BookQueries::get_by_id_for_user(&state.pool, id, &auth.user_id)
         .await?
         .ok_or_else(|| Error::NotFound("Book not found".into()))?;
 
-    while let Some(field) = multipart
+    if let Some(field) = multipart
         .next_field()
         .await
         .map_err(|e| {
@@ -72,28 +74,38 @@ pub async fn upload_file(
         let storage_path = state.storage.store(&content_hash, &data).await?;
 
         // Extract metadata and update book if available
-        if let Ok(metadata) = indexer::extract_metadata(format, &data) {
-            if metadata.has_data() {
-                // Update book metadata if we have meaningful data
-                let update = db_layer::models::UpdateBook {
-                    title: metadata.title,
-                    authors: if metadata.authors.is_empty() {
-                        None
-                    } else {
-                        Some(metadata.authors)
-                    },
-                    description: metadata.description,
-                    language: metadata.language,
-                    publisher: metadata.publisher,
-                    published_date: metadata.published_date,
-                    isbn: metadata.isbn,
-                    series_name: metadata.series_name,
-                    series_index: metadata.series_index,
-                    tags: None,
-                };
-
-                let _ = BookQueries::update_metadata(&state.pool, id, &update).await;
-            }
+        if let Ok(metadata) = indexer::extract_metadata(format, &data)
+            && metadata.has_data()
+        {
+            // Update book metadata if we have meaningful data
+            let author_sort = metadata.author_sort();
+            let update = db_layer::models::UpdateBook {
+                title: metadata.title,
+                authors: if metadata.authors.is_empty() {
+                    None
+                } else {
+                    Some(metadata.authors)
+                },
+                contributors: if metadata.contributors.is_empty() {
+                    None
+                } else {
+                    Some(metadata.contributors)
+                },
+                description: metadata.description,
+                language: metadata.language,
+                publisher: metadata.publisher,
+                published_date: metadata.published_date,
+                isbn: metadata.isbn,
+                series_name: metadata.series_name,
+                series_index: metadata.series_index,
+                tags: None,
+                author_sort,
+                rating: None,
+                identifiers: None,
+            };
+
+            let _ = BookQueries::update_metadata(&state.pool, id, &update).await;
+            let _ = AuthorQueries::fill_sort_names(&state.pool, &auth.user_id, &metadata.sort_names).await;
         }
 
         // Extract and store cover if present
@@ -119,6 +131,28 @@ pub async fn upload_file(
             &storage_path,
             &filename,
         ).await?;
+        BookQueries::set_partial_md5(&state.pool, id, PartialMd5::from_bytes(&data).as_str()).await?;
+
+        // Index the new file's text for full-text search
+        let task = CreateTask::new(
+            task_types::INDEX_BOOK_TEXT,
+            serde_json::json!({ "book_id": id }),
+        );
+        TaskQueries::create(&state.pool, &task).await?;
+
+        // Annotations point into the old file; move them onto the new one
+        if let Some(previous_storage_path) = existing.storage_path
+            && existing.content_hash.as_deref() != Some(content_hash.as_str())
+        {
+            let task = CreateTask::new(
+                task_types::REANCHOR_ANNOTATIONS,
+                serde_json::json!({
+                    "book_id": id,
+                    "previous_storage_path": previous_storage_path,
+                }),
+            );
+            TaskQueries::create(&state.pool, &task).await?;
+        }
 
         tracing::info!(
             book_id = %id,
@@ -126,6 +160,11 @@ pub async fn upload_file(
             size_bytes = file_size,
             "File uploaded successfully"
         );
+        state.publish_change(
+            &auth.user_id,
+            SyncChange::Book { book_id: book.id, action: ChangeAction::Updated },
+        );
+        state.queue_smart_refresh(&auth.user_id).await;
 
         return Ok(Json(UploadResponse {
             book_id: book.id,
@@ -157,6 +196,11 @@ pub async fn download_file(
         .await?
         .ok_or_else(|| Error::NotFound("Book not found".into()))?;
 
+    file_response(&state, book).await
+}
+
+/// Stream a book's file as an attachment
+pub(crate) async fn file_response(state: &AppState, book: Book) -> Result<Response> {
     // Check if book has a file
     let storage_path = book.storage_path
         .ok_or_else(|| Error::NotFound("No file available for this book".into()))?;
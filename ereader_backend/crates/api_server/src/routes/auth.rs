@@ -1,9 +1,13 @@
 //! Authentication and device management endpoints.
 
 use axum::{
-    extract::{Json, State},
+    extract::{Json, Path, State},
     http::StatusCode,
 };
+use common::{Error, Result};
+use db_layer::models::{hash_kosync_key, AppToken, CreateAppToken, KosyncAccount};
+use db_layer::queries::{AppTokenQueries, KosyncAccountQueries};
+use md5::{Digest, Md5};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -33,7 +37,7 @@ pub async fn register_device(
     State(state): State<AppState>,
     user: AuthUser,
     Json(req): Json<RegisterDeviceRequest>,
-) -> Result<Json<DeviceResponse>, StatusCode> {
+) -> std::result::Result<Json<DeviceResponse>, StatusCode> {
     let mut create_device = db_layer::models::CreateDevice::new(
         user.user_id.clone(),
         req.name.clone(),
@@ -71,7 +75,7 @@ pub struct ClerkWebhookEvent {
 pub async fn clerk_webhook(
     State(state): State<AppState>,
     Json(event): Json<ClerkWebhookEvent>,
-) -> Result<StatusCode, StatusCode> {
+) -> std::result::Result<StatusCode, StatusCode> {
     tracing::info!(event_type = %event.event_type, "Received Clerk webhook");
 
     match event.event_type.as_str() {
@@ -139,3 +143,155 @@ pub async fn clerk_webhook(
         }
     }
 }
+
+/// Request body for creating an app token
+#[derive(Debug, Deserialize)]
+pub struct CreateAppTokenRequest {
+    /// Label to recognise the token by, e.g. the reading app it's for
+    pub name: String,
+}
+
+/// App token response structure
+#[derive(Debug, Serialize)]
+pub struct AppTokenResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_used_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl From<AppToken> for AppTokenResponse {
+    fn from(token: AppToken) -> Self {
+        Self {
+            id: token.id,
+            name: token.name,
+            created_at: token.created_at,
+            last_used_at: token.last_used_at,
+        }
+    }
+}
+
+/// Response for a newly created app token
+#[derive(Debug, Serialize)]
+pub struct CreatedAppTokenResponse {
+    #[serde(flatten)]
+    pub app_token: AppTokenResponse,
+    /// The token itself; it can't be retrieved again
+    pub token: String,
+}
+
+/// Create an app token for clients that can't sign in with Clerk
+pub async fn create_app_token(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(req): Json<CreateAppTokenRequest>,
+) -> Result<Json<CreatedAppTokenResponse>> {
+    let name = req.name.trim();
+    if name.is_empty() {
+        return Err(Error::validation_field("name", "must not be empty"));
+    }
+
+    let (data, token) = CreateAppToken::generate(user.user_id, name);
+    let app_token = AppTokenQueries::create(&state.pool, &data).await?;
+
+    Ok(Json(CreatedAppTokenResponse {
+        app_token: app_token.into(),
+        token,
+    }))
+}
+
+/// List the authenticated user's app tokens
+pub async fn list_app_tokens(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<AppTokenResponse>>> {
+    let tokens = AppTokenQueries::list_for_user(&state.pool, &user.user_id).await?;
+
+    Ok(Json(tokens.into_iter().map(AppTokenResponse::from).collect()))
+}
+
+/// Revoke an app token
+pub async fn revoke_app_token(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode> {
+    if !AppTokenQueries::delete_for_user(&state.pool, id, &user.user_id).await? {
+        return Err(Error::not_found_resource("app token", id));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Request body for setting up KOReader progress sync
+#[derive(Debug, Deserialize)]
+pub struct KosyncAccountRequest {
+    pub username: String,
+    /// Password to enter in KOReader
+    pub password: String,
+}
+
+/// KOReader progress sync account response structure
+#[derive(Debug, Serialize)]
+pub struct KosyncAccountResponse {
+    pub username: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<KosyncAccount> for KosyncAccountResponse {
+    fn from(account: KosyncAccount) -> Self {
+        Self {
+            username: account.username,
+            created_at: account.created_at,
+            updated_at: account.updated_at,
+        }
+    }
+}
+
+/// Get the authenticated user's KOReader progress sync account
+pub async fn get_kosync_account(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<KosyncAccountResponse>> {
+    let account = KosyncAccountQueries::get_for_user(&state.pool, &user.user_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("No KOReader sync account set up".into()))?;
+
+    Ok(Json(account.into()))
+}
+
+/// Set the username and password KOReader's progress sync signs in with
+pub async fn set_kosync_account(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(req): Json<KosyncAccountRequest>,
+) -> Result<Json<KosyncAccountResponse>> {
+    let username = req.username.trim();
+    if username.is_empty() {
+        return Err(Error::validation_field("username", "must not be empty"));
+    }
+    if req.password.is_empty() {
+        return Err(Error::validation_field("password", "must not be empty"));
+    }
+
+    // KOReader only ever sends the MD5 of the password
+    let key = format!("{:x}", Md5::digest(req.password.as_bytes()));
+    let account =
+        KosyncAccountQueries::upsert(&state.pool, &user.user_id, username, &hash_kosync_key(&key))
+            .await?;
+
+    Ok(Json(account.into()))
+}
+
+/// Remove the authenticated user's KOReader progress sync account
+pub async fn delete_kosync_account(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<StatusCode> {
+    if !KosyncAccountQueries::delete_for_user(&state.pool, &user.user_id).await? {
+        return Err(Error::NotFound("No KOReader sync account set up".into()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
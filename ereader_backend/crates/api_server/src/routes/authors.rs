@@ -0,0 +1,160 @@
+//! Author endpoints.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use common::types::{relator, Paginated, Pagination};
+use common::Error;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::extractors::AuthUser;
+use crate::routes::library::BookResponse;
+use crate::state::AppState;
+
+/// Query parameters for listing authors
+#[derive(Debug, Deserialize)]
+pub struct ListAuthorsQuery {
+    /// Only people credited in this role, a MARC relator code such as `trl`
+    #[serde(default)]
+    pub role: Option<String>,
+}
+
+/// Request body for updating an author
+#[derive(Debug, Deserialize)]
+pub struct UpdateAuthorRequest {
+    /// Name the author files under; an empty one derives it from the name again
+    #[serde(default)]
+    pub sort_name: Option<String>,
+    /// Other names books list the author by
+    #[serde(default)]
+    pub aliases: Option<Vec<String>>,
+}
+
+/// Author response structure
+#[derive(Debug, Serialize)]
+pub struct AuthorResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub sort_name: String,
+    pub aliases: Vec<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<db_layer::models::Author> for AuthorResponse {
+    fn from(a: db_layer::models::Author) -> Self {
+        Self {
+            id: a.id,
+            name: a.name,
+            sort_name: a.sort_name,
+            aliases: a.aliases,
+            created_at: a.created_at,
+            updated_at: a.updated_at,
+        }
+    }
+}
+
+/// Author in a listing, with the number of books by them
+#[derive(Debug, Serialize)]
+pub struct AuthorSummaryResponse {
+    #[serde(flatten)]
+    pub author: AuthorResponse,
+    pub book_count: i64,
+}
+
+/// Author with their books
+#[derive(Debug, Serialize)]
+pub struct AuthorDetailResponse {
+    #[serde(flatten)]
+    pub author: AuthorResponse,
+    pub books: Vec<BookResponse>,
+}
+
+/// List the people the user's books credit by sort name, or only those in a role
+pub async fn list_authors(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(query): Query<ListAuthorsQuery>,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<Paginated<AuthorSummaryResponse>>, StatusCode> {
+    let role = match query.role.as_deref() {
+        Some(role) => Some(relator::normalize(role).ok_or(StatusCode::BAD_REQUEST)?),
+        None => None,
+    };
+
+    let authors = db_layer::queries::AuthorQueries::list_for_user(&state.pool, &user.user_id, role.as_deref(), &pagination)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to list authors");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(Paginated {
+        items: authors
+            .items
+            .into_iter()
+            .map(|a| AuthorSummaryResponse {
+                author: a.author.into(),
+                book_count: a.book_count,
+            })
+            .collect(),
+        total: authors.total,
+        limit: authors.limit,
+        offset: authors.offset,
+    }))
+}
+
+/// Get an author with their books
+pub async fn get_author(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<AuthorDetailResponse>, StatusCode> {
+    let author = db_layer::queries::AuthorQueries::get_by_id_for_user(&state.pool, id, &user.user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to get author");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let books = db_layer::queries::AuthorQueries::get_books(&state.pool, id)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to list author books");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(AuthorDetailResponse {
+        author: author.into(),
+        books: books.into_iter().map(BookResponse::from).collect(),
+    }))
+}
+
+/// Set an author's sort name or aliases
+pub async fn update_author(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateAuthorRequest>,
+) -> Result<Json<AuthorResponse>, StatusCode> {
+    let update_data = db_layer::models::UpdateAuthor {
+        sort_name: req.sort_name,
+        aliases: req.aliases,
+    };
+
+    let author = db_layer::queries::AuthorQueries::update(&state.pool, id, &user.user_id, &update_data)
+        .await
+        .map_err(|e| match e {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            e => {
+                tracing::error!(error = %e, "Failed to update author");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+
+    Ok(Json(author.into()))
+}
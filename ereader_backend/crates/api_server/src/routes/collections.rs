@@ -5,12 +5,17 @@ use axum::{
     http::StatusCode,
     Json,
 };
-use common::types::Pagination;
+use common::types::{Paginated, Pagination};
+use common::Error;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
 use uuid::Uuid;
 
 use crate::extractors::AuthUser;
+use crate::routes::library::BookResponse;
 use crate::state::AppState;
+use db_layer::queries::collections::MAX_BULK_BOOKS;
+use sync_engine::{ChangeAction, SyncChange};
 
 /// Request body for creating a collection
 #[derive(Debug, Deserialize)]
@@ -20,6 +25,12 @@ pub struct CreateCollectionRequest {
     pub description: Option<String>,
     #[serde(default = "default_collection_type")]
     pub collection_type: String,
+    /// Book search query selecting the books of a `smart` collection
+    #[serde(default)]
+    pub query: Option<String>,
+    /// Collection to nest the new one in
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
 }
 
 fn default_collection_type() -> String {
@@ -33,6 +44,24 @@ pub struct UpdateCollectionRequest {
     pub name: Option<String>,
     #[serde(default)]
     pub description: Option<String>,
+    /// New query of a smart collection
+    #[serde(default)]
+    pub query: Option<String>,
+}
+
+/// Request body for moving a collection into another, or to the top level
+#[derive(Debug, Deserialize)]
+pub struct SetParentRequest {
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+}
+
+/// Query parameters for listing a collection's books
+#[derive(Debug, Deserialize)]
+pub struct CollectionBooksQuery {
+    /// Also list the books of collections nested in this one
+    #[serde(default)]
+    pub include_descendants: bool,
 }
 
 /// Request body for adding a book to a collection
@@ -43,44 +72,110 @@ pub struct AddBookRequest {
     pub sort_order: Option<i32>,
 }
 
+/// Request body listing books to add, remove or reorder
+#[derive(Debug, Deserialize)]
+pub struct BookIdsRequest {
+    pub book_ids: Vec<Uuid>,
+}
+
+/// Request body for moving books into another collection
+#[derive(Debug, Deserialize)]
+pub struct MoveBooksRequest {
+    pub book_ids: Vec<Uuid>,
+    pub to_collection_id: Uuid,
+}
+
+/// Request body for moving a book within a collection's order
+#[derive(Debug, Deserialize)]
+pub struct SetPositionRequest {
+    /// Zero-based position; positions past the end move the book to the end
+    pub position: usize,
+}
+
+/// Number of books a bulk operation added, removed or moved
+#[derive(Debug, Serialize)]
+pub struct BulkBooksResponse {
+    pub count: u64,
+}
+
 /// Collection response structure
 #[derive(Debug, Serialize)]
 pub struct CollectionResponse {
     pub id: Uuid,
+    pub parent_id: Option<Uuid>,
     pub name: String,
     pub description: Option<String>,
     pub collection_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub query: Option<String>,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+impl From<db_layer::models::Collection> for CollectionResponse {
+    fn from(c: db_layer::models::Collection) -> Self {
+        Self {
+            id: c.id,
+            parent_id: c.parent_id,
+            name: c.name,
+            description: c.description,
+            collection_type: c.collection_type,
+            query: c.query,
+            created_at: c.created_at,
+            updated_at: c.updated_at,
+        }
+    }
+}
+
+/// Collection in a listing, with the number of books it holds
+#[derive(Debug, Serialize)]
+pub struct CollectionSummaryResponse {
+    #[serde(flatten)]
+    pub collection: CollectionResponse,
+    pub book_count: i64,
+}
+
+/// Collection in the tree listing, with the collections nested in it
+#[derive(Debug, Serialize)]
+pub struct CollectionTreeNode {
+    #[serde(flatten)]
+    pub collection: CollectionSummaryResponse,
+    pub children: Vec<CollectionTreeNode>,
+}
+
 /// Collection detail with book count
 #[derive(Debug, Serialize)]
 pub struct CollectionDetailResponse {
     #[serde(flatten)]
     pub collection: CollectionResponse,
+    /// Number of books in the collection, of which `books` is one page in the
+    /// collection's order
     pub book_count: i64,
-    pub books: Vec<CollectionBookResponse>,
+    pub books: Vec<BookResponse>,
 }
 
-#[derive(Debug, Serialize)]
-pub struct CollectionBookResponse {
-    pub id: Uuid,
-    pub title: String,
-    pub authors: Vec<String>,
-    pub added_at: chrono::DateTime<chrono::Utc>,
-    pub sort_order: Option<i32>,
+/// Check that a smart collection has a valid query and other collections have none
+fn validate_query(collection_type: &str, query: Option<&str>) -> Result<(), StatusCode> {
+    let smart = collection_type == db_layer::models::CollectionType::Smart.as_str();
+    match query {
+        Some(query) if smart => db_layer::queries::SearchQuery::parse(query)
+            .map(drop)
+            .map_err(|_| StatusCode::BAD_REQUEST),
+        None if !smart => Ok(()),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
 }
 
-/// List all collections for the authenticated user
+/// List all collections for the authenticated user, with the number of books in each
 pub async fn list_collections(
     State(state): State<AppState>,
     user: AuthUser,
     Query(pagination): Query<Pagination>,
-) -> Result<Json<common::types::Paginated<CollectionResponse>>, StatusCode> {
-    let collections = db_layer::queries::CollectionQueries::list_for_user(
+) -> Result<Json<common::types::Paginated<CollectionSummaryResponse>>, StatusCode> {
+    let collections = db_layer::queries::CollectionQueries::list_page_with_counts(
         &state.pool,
         &user.user_id,
+        &pagination,
     )
     .await
     .map_err(|e| {
@@ -88,30 +183,88 @@ pub async fn list_collections(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    // Apply pagination manually (TODO: implement in db_layer)
-    let total = collections.len() as i64;
-    let offset = pagination.offset;
-    let limit = pagination.limit;
-    let items: Vec<_> = collections
+    Ok(Json(common::types::Paginated {
+        items: collections
+            .items
+            .into_iter()
+            .map(|c| CollectionSummaryResponse {
+                collection: c.collection.into(),
+                book_count: c.book_count,
+            })
+            .collect(),
+        total: collections.total,
+        limit: collections.limit,
+        offset: collections.offset,
+    }))
+}
+
+/// Tree nodes of the collections directly inside a parent, taking them and their
+/// descendants out of the map of collections by parent
+fn tree_nodes(
+    parent_id: Option<Uuid>,
+    by_parent: &mut HashMap<Option<Uuid>, Vec<db_layer::models::CollectionWithCount>>,
+) -> Vec<CollectionTreeNode> {
+    let collections = by_parent.remove(&parent_id).unwrap_or_default();
+    collections
         .into_iter()
-        .skip(offset as usize)
-        .take(limit as usize)
-        .map(|c| CollectionResponse {
-            id: c.id,
-            name: c.name,
-            description: c.description,
-            collection_type: c.collection_type,
-            created_at: c.created_at,
-            updated_at: c.updated_at,
+        .map(|c| {
+            let children = tree_nodes(Some(c.collection.id), by_parent);
+            CollectionTreeNode {
+                collection: CollectionSummaryResponse {
+                    collection: c.collection.into(),
+                    book_count: c.book_count,
+                },
+                children,
+            }
         })
-        .collect();
+        .collect()
+}
 
-    Ok(Json(common::types::Paginated {
-        items,
-        total,
-        limit,
-        offset,
-    }))
+/// List all collections for the authenticated user as a tree, top-level collections
+/// first, each with the number of books in it
+pub async fn collection_tree(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<CollectionTreeNode>>, StatusCode> {
+    let collections = db_layer::queries::CollectionQueries::list_with_counts(
+        &state.pool,
+        &user.user_id,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!(error = %e, "Failed to list collections");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(collection_forest(collections)))
+}
+
+/// Arrange collections into trees, keeping their order among siblings
+///
+/// A collection whose parent isn't in the list is shown at the top level rather than
+/// dropped.
+fn collection_forest(collections: Vec<db_layer::models::CollectionWithCount>) -> Vec<CollectionTreeNode> {
+    let ids: HashSet<Uuid> = collections.iter().map(|c| c.collection.id).collect();
+
+    let mut by_parent: HashMap<Option<Uuid>, Vec<db_layer::models::CollectionWithCount>> = HashMap::new();
+    for c in collections {
+        let parent_id = c.collection.parent_id.filter(|id| ids.contains(id));
+        by_parent.entry(parent_id).or_default().push(c);
+    }
+
+    tree_nodes(None, &mut by_parent)
+}
+
+/// Fill a user's smart collections with the books their queries match
+async fn refresh_smart_memberships(state: &AppState, user_id: &str) -> Result<(), StatusCode> {
+    let refreshed = async {
+        let mut conn = state.pool.acquire().await?;
+        db_layer::queries::CollectionQueries::refresh_smart_memberships(&mut conn, user_id).await
+    };
+    refreshed.await.map_err(|e| {
+        tracing::error!(error = %e, "Failed to refresh smart collections");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
 }
 
 /// Create a new collection
@@ -120,8 +273,22 @@ pub async fn create_collection(
     user: AuthUser,
     Json(req): Json<CreateCollectionRequest>,
 ) -> Result<(StatusCode, Json<CollectionResponse>), StatusCode> {
+    validate_query(&req.collection_type, req.query.as_deref())?;
+
     let mut create_collection = db_layer::models::CreateCollection::new(user.user_id.clone(), req.name.clone())
         .with_type(req.collection_type.clone());
+    create_collection.query = req.query.clone();
+
+    if let Some(parent_id) = req.parent_id {
+        db_layer::queries::CollectionQueries::get_by_id_for_user(&state.pool, parent_id, &user.user_id)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "Failed to get parent collection");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::NOT_FOUND)?;
+        create_collection = create_collection.with_parent(parent_id);
+    }
 
     if let Some(desc) = &req.description {
         create_collection = create_collection.with_description(desc.clone());
@@ -133,25 +300,24 @@ pub async fn create_collection(
             tracing::error!(error = %e, "Failed to create collection");
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
+    if collection.is_smart() {
+        refresh_smart_memberships(&state, &user.user_id).await?;
+    }
+
+    state.publish_change(
+        &user.user_id,
+        SyncChange::Collection { collection_id: collection.id, action: ChangeAction::Created },
+    );
 
-    Ok((
-        StatusCode::CREATED,
-        Json(CollectionResponse {
-            id: collection.id,
-            name: collection.name,
-            description: collection.description,
-            collection_type: collection.collection_type,
-            created_at: collection.created_at,
-            updated_at: collection.updated_at,
-        }),
-    ))
+    Ok((StatusCode::CREATED, Json(collection.into())))
 }
 
-/// Get a collection by ID with its books
+/// Get a collection by ID with a page of its books
 pub async fn get_collection(
     State(state): State<AppState>,
     user: AuthUser,
     Path(id): Path<Uuid>,
+    Query(pagination): Query<Pagination>,
 ) -> Result<Json<CollectionDetailResponse>, StatusCode> {
     let collection = db_layer::queries::CollectionQueries::get_by_id_for_user(
         &state.pool,
@@ -165,20 +331,17 @@ pub async fn get_collection(
     })?
     .ok_or(StatusCode::NOT_FOUND)?;
 
-    // TODO: Implement CollectionQueries::get_books in db_layer
-    let books = vec![];
+    let books = db_layer::queries::CollectionQueries::get_books(&state.pool, &collection, &pagination)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to list collection books");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
 
     Ok(Json(CollectionDetailResponse {
-        collection: CollectionResponse {
-            id: collection.id,
-            name: collection.name,
-            description: collection.description,
-            collection_type: collection.collection_type,
-            created_at: collection.created_at,
-            updated_at: collection.updated_at,
-        },
-        book_count: books.len() as i64,
-        books,
+        collection: collection.into(),
+        book_count: books.total,
+        books: books.items.into_iter().map(BookResponse::from).collect(),
     }))
 }
 
@@ -190,7 +353,7 @@ pub async fn update_collection(
     Json(req): Json<UpdateCollectionRequest>,
 ) -> Result<Json<CollectionResponse>, StatusCode> {
     // Verify ownership first
-    let _existing = db_layer::queries::CollectionQueries::get_by_id_for_user(
+    let existing = db_layer::queries::CollectionQueries::get_by_id_for_user(
         &state.pool,
         id,
         &user.user_id,
@@ -202,9 +365,14 @@ pub async fn update_collection(
     })?
     .ok_or(StatusCode::NOT_FOUND)?;
 
+    if let Some(query) = &req.query {
+        validate_query(&existing.collection_type, Some(query))?;
+    }
+
     let update_data = db_layer::models::UpdateCollection {
         name: req.name,
         description: req.description,
+        query: req.query,
     };
 
     let collection = db_layer::queries::CollectionQueries::update(&state.pool, id, &update_data)
@@ -213,14 +381,84 @@ pub async fn update_collection(
             tracing::error!(error = %e, "Failed to update collection");
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
+    if collection.is_smart() {
+        refresh_smart_memberships(&state, &user.user_id).await?;
+    }
+
+    state.publish_change(
+        &user.user_id,
+        SyncChange::Collection { collection_id: id, action: ChangeAction::Updated },
+    );
+
+    Ok(Json(collection.into()))
+}
+
+/// Move a collection into another collection, or to the top level
+pub async fn set_parent(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<SetParentRequest>,
+) -> Result<Json<CollectionResponse>, StatusCode> {
+    let collection = db_layer::queries::CollectionQueries::set_parent(
+        &state.pool,
+        &user.user_id,
+        id,
+        req.parent_id,
+    )
+    .await
+    .map_err(|e| match e {
+        Error::NotFound(_) => StatusCode::NOT_FOUND,
+        Error::Validation(_) => StatusCode::BAD_REQUEST,
+        e => {
+            tracing::error!(error = %e, "Failed to move collection");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+
+    state.publish_change(
+        &user.user_id,
+        SyncChange::Collection { collection_id: id, action: ChangeAction::Updated },
+    );
 
-    Ok(Json(CollectionResponse {
-        id: collection.id,
-        name: collection.name,
-        description: collection.description,
-        collection_type: collection.collection_type,
-        created_at: collection.created_at,
-        updated_at: collection.updated_at,
+    Ok(Json(collection.into()))
+}
+
+/// List the books in a collection, optionally with those of collections nested in it
+pub async fn list_books(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Query(pagination): Query<Pagination>,
+    Query(query): Query<CollectionBooksQuery>,
+) -> Result<Json<Paginated<BookResponse>>, StatusCode> {
+    let collection = db_layer::queries::CollectionQueries::get_by_id_for_user(
+        &state.pool,
+        id,
+        &user.user_id,
+    )
+    .await
+    .map_err(|e| {
+        tracing::error!(error = %e, "Failed to get collection");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?
+    .ok_or(StatusCode::NOT_FOUND)?;
+
+    let books = if query.include_descendants {
+        db_layer::queries::CollectionQueries::get_subtree_books(&state.pool, &collection, &pagination).await
+    } else {
+        db_layer::queries::CollectionQueries::get_books(&state.pool, &collection, &pagination).await
+    }
+    .map_err(|e| {
+        tracing::error!(error = %e, "Failed to list collection books");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+
+    Ok(Json(Paginated {
+        items: books.items.into_iter().map(BookResponse::from).collect(),
+        total: books.total,
+        limit: books.limit,
+        offset: books.offset,
     }))
 }
 
@@ -251,6 +489,10 @@ pub async fn delete_collection(
         })?;
 
     if deleted {
+        state.publish_change(
+            &user.user_id,
+            SyncChange::Collection { collection_id: id, action: ChangeAction::Deleted },
+        );
         Ok(StatusCode::NO_CONTENT)
     } else {
         Ok(StatusCode::NOT_FOUND)
@@ -265,7 +507,7 @@ pub async fn add_book(
     Json(req): Json<AddBookRequest>,
 ) -> Result<StatusCode, StatusCode> {
     // Verify collection ownership
-    let _collection = db_layer::queries::CollectionQueries::get_by_id_for_user(
+    let collection = db_layer::queries::CollectionQueries::get_by_id_for_user(
         &state.pool,
         id,
         &user.user_id,
@@ -276,8 +518,15 @@ pub async fn add_book(
         StatusCode::INTERNAL_SERVER_ERROR
     })?
     .ok_or(StatusCode::NOT_FOUND)?;
+    require_manual(collection)?;
 
-    // TODO: Verify book ownership with BookQueries::get_by_id_for_user
+    db_layer::queries::BookQueries::get_by_id_for_user(&state.pool, req.book_id, &user.user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to get book");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
 
     // Add book to collection
     db_layer::queries::CollectionQueries::add_book(&state.pool, id, req.book_id, req.sort_order)
@@ -292,6 +541,11 @@ pub async fn add_book(
             }
         })?;
 
+    state.publish_change(
+        &user.user_id,
+        SyncChange::Collection { collection_id: id, action: ChangeAction::Updated },
+    );
+
     Ok(StatusCode::CREATED)
 }
 
@@ -302,7 +556,7 @@ pub async fn remove_book(
     Path((collection_id, book_id)): Path<(Uuid, Uuid)>,
 ) -> Result<StatusCode, StatusCode> {
     // Verify collection ownership
-    let _collection = db_layer::queries::CollectionQueries::get_by_id_for_user(
+    let collection = db_layer::queries::CollectionQueries::get_by_id_for_user(
         &state.pool,
         collection_id,
         &user.user_id,
@@ -313,6 +567,7 @@ pub async fn remove_book(
         StatusCode::INTERNAL_SERVER_ERROR
     })?
     .ok_or(StatusCode::NOT_FOUND)?;
+    require_manual(collection)?;
 
     let deleted = db_layer::queries::CollectionQueries::remove_book(&state.pool, collection_id, book_id)
         .await
@@ -322,8 +577,283 @@ pub async fn remove_book(
         })?;
 
     if deleted {
+        state.publish_change(
+            &user.user_id,
+            SyncChange::Collection { collection_id, action: ChangeAction::Updated },
+        );
         Ok(StatusCode::NO_CONTENT)
     } else {
         Ok(StatusCode::NOT_FOUND)
     }
 }
+
+/// Get a collection the user owns whose books are chosen by hand
+async fn manual_collection(
+    state: &AppState,
+    id: Uuid,
+    user_id: &str,
+) -> Result<db_layer::models::Collection, StatusCode> {
+    let collection = db_layer::queries::CollectionQueries::get_by_id_for_user(&state.pool, id, user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to get collection");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    require_manual(collection)
+}
+
+/// Refuse to change the books of a smart collection by hand, since its books are the ones
+/// matching its query
+fn require_manual(
+    collection: db_layer::models::Collection,
+) -> Result<db_layer::models::Collection, StatusCode> {
+    if collection.is_smart() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    Ok(collection)
+}
+
+/// Status for a failed bulk or ordering operation
+fn bulk_error(e: Error, action: &str) -> StatusCode {
+    match e {
+        Error::NotFound(_) => StatusCode::NOT_FOUND,
+        Error::Validation(_) => StatusCode::BAD_REQUEST,
+        e => {
+            tracing::error!(error = %e, action, "Failed to update collection books");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Add books to the end of a collection in one transaction
+pub async fn add_books(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<BookIdsRequest>,
+) -> Result<Json<BulkBooksResponse>, StatusCode> {
+    if req.book_ids.len() > MAX_BULK_BOOKS {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    manual_collection(&state, id, &user.user_id).await?;
+
+    let count = db_layer::queries::CollectionQueries::add_books(&state.pool, &user.user_id, id, &req.book_ids)
+        .await
+        .map_err(|e| bulk_error(e, "add"))?;
+
+    if count > 0 {
+        state.publish_change(
+            &user.user_id,
+            SyncChange::Collection { collection_id: id, action: ChangeAction::Updated },
+        );
+    }
+
+    Ok(Json(BulkBooksResponse { count }))
+}
+
+/// Remove books from a collection in one transaction
+pub async fn remove_books(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<BookIdsRequest>,
+) -> Result<Json<BulkBooksResponse>, StatusCode> {
+    if req.book_ids.len() > MAX_BULK_BOOKS {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    manual_collection(&state, id, &user.user_id).await?;
+
+    let count = db_layer::queries::CollectionQueries::remove_books(&state.pool, id, &req.book_ids)
+        .await
+        .map_err(|e| bulk_error(e, "remove"))?;
+
+    if count > 0 {
+        state.publish_change(
+            &user.user_id,
+            SyncChange::Collection { collection_id: id, action: ChangeAction::Updated },
+        );
+    }
+
+    Ok(Json(BulkBooksResponse { count }))
+}
+
+/// Move books from a collection to the end of another in one transaction
+pub async fn move_books(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<MoveBooksRequest>,
+) -> Result<Json<BulkBooksResponse>, StatusCode> {
+    if req.book_ids.len() > MAX_BULK_BOOKS {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    if req.to_collection_id == id {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    manual_collection(&state, id, &user.user_id).await?;
+    manual_collection(&state, req.to_collection_id, &user.user_id).await?;
+
+    let count = db_layer::queries::CollectionQueries::move_books(
+        &state.pool,
+        &user.user_id,
+        id,
+        req.to_collection_id,
+        &req.book_ids,
+    )
+    .await
+    .map_err(|e| bulk_error(e, "move"))?;
+
+    if count > 0 {
+        for collection_id in [id, req.to_collection_id] {
+            state.publish_change(
+                &user.user_id,
+                SyncChange::Collection { collection_id, action: ChangeAction::Updated },
+            );
+        }
+    }
+
+    Ok(Json(BulkBooksResponse { count }))
+}
+
+/// Rewrite a collection's order: the listed books first, then the rest as they were
+pub async fn set_order(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<BookIdsRequest>,
+) -> Result<StatusCode, StatusCode> {
+    if req.book_ids.len() > MAX_BULK_BOOKS {
+        return Err(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+    manual_collection(&state, id, &user.user_id).await?;
+
+    db_layer::queries::CollectionQueries::set_order(&state.pool, id, &req.book_ids)
+        .await
+        .map_err(|e| bulk_error(e, "reorder"))?;
+
+    state.publish_change(
+        &user.user_id,
+        SyncChange::Collection { collection_id: id, action: ChangeAction::Updated },
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Move a book to another position in a collection's order
+pub async fn set_position(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path((collection_id, book_id)): Path<(Uuid, Uuid)>,
+    Json(req): Json<SetPositionRequest>,
+) -> Result<StatusCode, StatusCode> {
+    manual_collection(&state, collection_id, &user.user_id).await?;
+
+    let moved = db_layer::queries::CollectionQueries::move_book(
+        &state.pool,
+        collection_id,
+        book_id,
+        req.position,
+    )
+    .await
+    .map_err(|e| bulk_error(e, "reorder"))?;
+
+    if !moved {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    state.publish_change(
+        &user.user_id,
+        SyncChange::Collection { collection_id, action: ChangeAction::Updated },
+    );
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db_layer::models::{Collection, CollectionType};
+
+    fn collection(query: Option<&str>) -> Collection {
+        Collection {
+            id: Uuid::now_v7(),
+            user_id: "user".into(),
+            parent_id: None,
+            name: "Shelf".into(),
+            description: None,
+            collection_type: if query.is_some() { CollectionType::Smart } else { CollectionType::Shelf }
+                .as_str()
+                .into(),
+            query: query.map(str::to_string),
+            created_at: chrono::Utc::now(),
+            updated_at: chrono::Utc::now(),
+        }
+    }
+
+    fn counted(name: &str, parent_id: Option<Uuid>) -> db_layer::models::CollectionWithCount {
+        let mut collection = collection(None);
+        collection.name = name.into();
+        collection.parent_id = parent_id;
+        db_layer::models::CollectionWithCount { collection, book_count: 1 }
+    }
+
+    /// Names of a forest with each collection's children in brackets
+    fn outline(nodes: &[CollectionTreeNode]) -> String {
+        nodes
+            .iter()
+            .map(|node| match node.children.as_slice() {
+                [] => node.collection.collection.name.clone(),
+                children => format!("{}[{}]", node.collection.collection.name, outline(children)),
+            })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    #[test]
+    fn test_collection_forest() {
+        let fiction = counted("Fiction", None);
+        let fantasy = counted("Fantasy", Some(fiction.collection.id));
+        let epic = counted("Epic", Some(fantasy.collection.id));
+        let quests = counted("Quests", Some(epic.collection.id));
+        let scifi = counted("Sci-fi", Some(fiction.collection.id));
+        let history = counted("History", None);
+
+        // Listed by name, as the database returns them
+        let forest = collection_forest(vec![epic, fantasy, fiction, history, quests, scifi]);
+        assert_eq!(outline(&forest), "Fiction[Fantasy[Epic[Quests]],Sci-fi],History");
+        assert_eq!(forest[0].children[0].children[0].children[0].collection.book_count, 1);
+    }
+
+    #[test]
+    fn test_collection_forest_keeps_orphans() {
+        let orphan = counted("Orphan", Some(Uuid::now_v7()));
+        let child = counted("Child", Some(orphan.collection.id));
+        let root = counted("Root", None);
+
+        let forest = collection_forest(vec![child, orphan, root]);
+        assert_eq!(outline(&forest), "Orphan[Child],Root");
+    }
+
+    #[test]
+    fn test_require_manual() {
+        assert!(require_manual(collection(None)).is_ok());
+        assert_eq!(
+            require_manual(collection(Some("tag:scifi"))).unwrap_err(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn test_validate_query() {
+        let smart = CollectionType::Smart.as_str();
+        let shelf = CollectionType::Shelf.as_str();
+
+        assert!(validate_query(smart, Some("tag:scifi")).is_ok());
+        assert!(validate_query(shelf, None).is_ok());
+        assert_eq!(validate_query(smart, None), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(validate_query(smart, Some("(tag:scifi")), Err(StatusCode::BAD_REQUEST));
+        assert_eq!(validate_query(shelf, Some("tag:scifi")), Err(StatusCode::BAD_REQUEST));
+    }
+}
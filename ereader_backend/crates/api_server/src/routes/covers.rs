@@ -40,7 +40,7 @@ pub async fn get_cover_size(
     get_cover_impl(state, auth, id, cover_size).await
 }
 
-async fn get_cover_impl(
+pub(crate) async fn get_cover_impl(
     state: AppState,
     auth: AuthUser,
     id: Uuid,
@@ -0,0 +1,375 @@
+//! Kobo store sync endpoints.
+//!
+//! Served under `/kobo/{app token}` when the `kobo` config section enables them; see
+//! [`crate::kobo`] for the protocol. Bookmarks are translated against the spine paths
+//! recorded when a book's text is indexed.
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use common::{BookFormat, Error, Result};
+use db_layer::models::{AppToken, Book, CreateDevice, DeviceReadingState};
+use db_layer::queries::{BookQueries, DeviceQueries, ReadingStateQueries};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use storage_layer::{CoverSize, Storage};
+use sync_engine::kobo::{kobo_location, KoboLocation, KOBO_DEVICE_TYPE};
+use sync_engine::{BookSync, ReadingStateSync, SyncMerger};
+use uuid::Uuid;
+
+use crate::extractors::AuthUser;
+use crate::kobo::{
+    self, library_sync_items, update_result, KoboSyncToken, KoboUrls, ReadingStatesRequest,
+    SYNC_CONTINUE, SYNC_HEADER, SYNC_TOKEN_HEADER,
+};
+use crate::routes::{assets, covers};
+use crate::state::AppState;
+
+/// Changes sent per library sync page
+const SYNC_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct BookPath {
+    pub book_id: Uuid,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DownloadPath {
+    pub book_id: Uuid,
+    /// `epub` or `kepub`
+    pub format: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImagePath {
+    pub image_id: Uuid,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Tell the device where its library calls go
+pub async fn initialization(Extension(urls): Extension<KoboUrls>) -> Json<Value> {
+    Json(json!({ "Resources": urls.resources() }))
+}
+
+/// Accept the device's sign-in
+///
+/// The app token in the path is what authenticates the device; the tokens handed out here
+/// only satisfy the device and are never checked.
+pub async fn auth_device(Json(req): Json<Value>) -> Json<Value> {
+    Json(json!({
+        "AccessToken": Uuid::new_v4().simple().to_string(),
+        "RefreshToken": Uuid::new_v4().simple().to_string(),
+        "TokenType": "Bearer",
+        "TrackingId": Uuid::new_v4(),
+        "UserKey": req.get("UserKey").cloned().unwrap_or_else(|| json!("")),
+    }))
+}
+
+/// Send a page of library changes after the device's sync token
+pub async fn library_sync(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Extension(urls): Extension<KoboUrls>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let since = headers
+        .get(SYNC_TOKEN_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(KoboSyncToken::parse)
+        .unwrap_or_default();
+
+    let response = SyncMerger::new(&state.pool)
+        .changes_since(&user.user_id, since.sync_token(), SYNC_PAGE_SIZE)
+        .await?;
+    let reading_states = kobo_reading_states(&state, &user.user_id, &response.reading_states).await?;
+    let items = library_sync_items(&response, &since, &urls, state.config.kobo.kepub, reading_states);
+
+    let next = HeaderValue::from_str(&since.next(&response).to_string())
+        .map_err(|e| Error::Internal(e.to_string()))?;
+    let mut http_response = Json(items).into_response();
+    http_response.headers_mut().insert(SYNC_TOKEN_HEADER, next);
+    if response.has_more {
+        http_response
+            .headers_mut()
+            .insert(SYNC_HEADER, HeaderValue::from_static(SYNC_CONTINUE));
+    }
+
+    Ok(http_response)
+}
+
+/// Get a book's metadata
+pub async fn get_metadata(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Extension(urls): Extension<KoboUrls>,
+    Path(path): Path<BookPath>,
+) -> Result<Json<Vec<kobo::BookMetadata>>> {
+    let book = get_book(&state, &user, path.book_id).await?;
+
+    Ok(Json(vec![kobo::BookMetadata::new(
+        &BookSync::from(book),
+        &urls,
+        state.config.kobo.kepub,
+    )]))
+}
+
+/// Get a book's reading state
+pub async fn get_reading_state(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(path): Path<BookPath>,
+) -> Result<Json<Vec<kobo::ReadingState>>> {
+    let book = get_book(&state, &user, path.book_id).await?;
+
+    let Some(reading_state) =
+        ReadingStateQueries::get_for_book(&state.pool, &user.user_id, book.id).await?
+    else {
+        return Ok(Json(vec![kobo::ReadingState::unread(book.id, book.created_at)]));
+    };
+    let reading_state = ReadingStateSync::from(reading_state);
+    let positions =
+        ReadingStateQueries::get_device_positions(&state.pool, &user.user_id, book.id).await?;
+    let reported = positions
+        .iter()
+        .find(|p| p.device_id == reading_state.hlc.device_id);
+    let location = bookmark(&state, &user.user_id, &reading_state, reported).await?;
+
+    Ok(Json(vec![kobo::ReadingState::new(&reading_state, location)]))
+}
+
+/// Record the device's bookmark for a book
+pub async fn update_reading_state(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Extension(app_token): Extension<AppToken>,
+    Path(path): Path<BookPath>,
+    Json(req): Json<ReadingStatesRequest>,
+) -> Result<Json<Value>> {
+    let book = get_book(&state, &user, path.book_id).await?;
+
+    let Some((update, progress)) = req
+        .reading_states
+        .iter()
+        .find_map(|u| u.progress().map(|progress| (u, progress)))
+    else {
+        return Ok(Json(update_result(book.id, false)));
+    };
+
+    // Each app token a Kobo syncs with counts as one device
+    let device = DeviceQueries::upsert_external(
+        &state.pool,
+        &CreateDevice::new(&user.user_id, &app_token.name, KOBO_DEVICE_TYPE),
+        &format!("kobo:{}", app_token.id),
+    )
+    .await?;
+    let spine = spine_paths(&state, &user.user_id, &[book.id])
+        .await?
+        .remove(&book.id)
+        .unwrap_or_default();
+
+    SyncMerger::new(&state.pool)
+        .with_events(&state.events)
+        .update_from_kobo(&user.user_id, device.id, book.id, update.location(), progress, &spine)
+        .await?;
+
+    Ok(Json(update_result(book.id, true)))
+}
+
+/// Acknowledge a book removed on the device
+///
+/// Removing a book from a Kobo only archives it there; the library keeps it.
+pub async fn delete_entry(Path(_path): Path<BookPath>) -> StatusCode {
+    StatusCode::NO_CONTENT
+}
+
+/// Download a book as the original EPUB or converted to KEPUB
+pub async fn download(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(path): Path<DownloadPath>,
+) -> Result<Response> {
+    let book = get_book(&state, &user, path.book_id).await?;
+
+    match path.format.as_str() {
+        "epub" => assets::file_response(&state, book).await,
+        "kepub" => kepub_response(&state, book).await,
+        other => Err(Error::Validation(format!(
+            "Invalid download format: {}. Must be epub or kepub",
+            other
+        ))),
+    }
+}
+
+/// Get a book's cover at the smallest stored size that covers the requested one
+pub async fn cover_image(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(path): Path<ImagePath>,
+) -> Result<Response> {
+    let size = CoverSize::all()
+        .into_iter()
+        .find(|size| {
+            let (width, height) = size.dimensions();
+            width >= path.width && height >= path.height
+        })
+        .unwrap_or(CoverSize::Large);
+
+    covers::get_cover_impl(state, user, path.image_id, size).await
+}
+
+async fn get_book(state: &AppState, user: &AuthUser, book_id: Uuid) -> Result<Book> {
+    BookQueries::get_by_id_for_user(&state.pool, book_id, &user.user_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Book not found".into()))
+}
+
+/// Kobo reading states for reading states in a sync response, by book
+async fn kobo_reading_states(
+    state: &AppState,
+    user_id: &str,
+    reading_states: &[ReadingStateSync],
+) -> Result<HashMap<Uuid, kobo::ReadingState>> {
+    if reading_states.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let book_ids: Vec<Uuid> = reading_states.iter().map(|s| s.book_id).collect();
+    let positions = {
+        let mut conn = state.pool.acquire().await?;
+        ReadingStateQueries::get_device_positions_for_books(&mut conn, user_id, &book_ids).await?
+    };
+
+    let reported = |reading_state: &ReadingStateSync| {
+        positions.iter().find(|p| {
+            p.book_id == reading_state.book_id && p.device_id == reading_state.hlc.device_id
+        })
+    };
+
+    // Spines only for positions that aren't a Kobo's own bookmark, looked up together
+    let foreign: Vec<Uuid> = reading_states
+        .iter()
+        .filter(|s| kobo_location(s, reported(s), &[]).is_none())
+        .map(|s| s.book_id)
+        .collect();
+    let spines = spine_paths(state, user_id, &foreign).await?;
+
+    let mut result = HashMap::with_capacity(reading_states.len());
+    for reading_state in reading_states {
+        let spine = spines.get(&reading_state.book_id).map_or(&[][..], Vec::as_slice);
+        let location = kobo_location(reading_state, reported(reading_state), spine);
+        result.insert(
+            reading_state.book_id,
+            kobo::ReadingState::new(reading_state, location),
+        );
+    }
+
+    Ok(result)
+}
+
+/// Bookmark for a reading state
+///
+/// A Kobo's own bookmark needs no spine, so it is only looked up when the position came from
+/// another device.
+async fn bookmark(
+    state: &AppState,
+    user_id: &str,
+    reading_state: &ReadingStateSync,
+    reported: Option<&DeviceReadingState>,
+) -> Result<Option<KoboLocation>> {
+    if let Some(location) = kobo_location(reading_state, reported, &[]) {
+        return Ok(Some(location));
+    }
+
+    let spine = spine_paths(state, user_id, &[reading_state.book_id])
+        .await?
+        .remove(&reading_state.book_id)
+        .unwrap_or_default();
+    Ok(kobo_location(reading_state, reported, &spine))
+}
+
+/// Archive paths of the content documents of a user's books in reading order, by book
+///
+/// Recorded when a book's text is indexed; a book indexed before they were is read once,
+/// off the async runtime, and its paths recorded. Books without a file, or whose file can't
+/// be read, get none, and positions in them are translated from progress alone.
+async fn spine_paths(state: &AppState, user_id: &str, book_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<String>>> {
+    if book_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut spines = HashMap::with_capacity(book_ids.len());
+    for (book_id, paths) in BookQueries::get_spine_paths(&state.pool, user_id, book_ids).await? {
+        let paths = match paths {
+            Some(paths) => paths,
+            None => record_spine_paths(state, user_id, book_id).await?,
+        };
+        spines.insert(book_id, paths);
+    }
+
+    Ok(spines)
+}
+
+/// Read and record the spine paths of a book indexed before they were recorded
+async fn record_spine_paths(state: &AppState, user_id: &str, book_id: Uuid) -> Result<Vec<String>> {
+    let Some(book) = BookQueries::get_by_id_for_user(&state.pool, book_id, user_id).await? else {
+        return Ok(Vec::new());
+    };
+    let (Some(storage_path), Some(format)) = (book.storage_path.as_deref(), book.format) else {
+        return Ok(Vec::new());
+    };
+
+    let data = match state.storage.retrieve(storage_path).await {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::warn!(book_id = %book.id, error = %e, "Failed to read file for Kobo bookmark");
+            return Ok(Vec::new());
+        }
+    };
+    let paths = tokio::task::spawn_blocking(move || indexer::spine_paths(format, &data))
+        .await
+        .map_err(|e| Error::Internal(e.to_string()))?
+        .unwrap_or_else(|e| {
+            // Recorded empty, so a broken file isn't read again on every sync
+            tracing::warn!(book_id = %book.id, error = %e, "Failed to read spine for Kobo bookmark");
+            Vec::new()
+        });
+    BookQueries::set_spine_paths(&state.pool, book.id, &paths).await?;
+
+    Ok(paths)
+}
+
+/// Convert a book's EPUB to KEPUB and send it as an attachment
+async fn kepub_response(state: &AppState, book: Book) -> Result<Response> {
+    let (Some(storage_path), Some(format)) = (book.storage_path.as_deref(), book.format) else {
+        return Err(Error::NotFound("No file available for this book".into()));
+    };
+    // Only EPUBs convert to KEPUB
+    if format != BookFormat::Epub {
+        return Err(Error::NotFound(format!("No EPUB file available for this book, only {}", format)));
+    }
+
+    let data = state.storage.retrieve(storage_path).await?;
+    let kepub = tokio::task::spawn_blocking(move || indexer::convert_to_kepub(&data))
+        .await
+        .map_err(|e| Error::Internal(e.to_string()))??;
+
+    let stem = book
+        .original_filename
+        .as_deref()
+        .and_then(|name| name.rsplit_once('.'))
+        .map_or(book.title.as_str(), |(stem, _)| stem);
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, format.mime_type())
+        .header(header::CONTENT_LENGTH, kepub.len())
+        .header(header::CONTENT_DISPOSITION, kobo::kepub_disposition(stem))
+        .body(Body::from(kepub))
+        .map_err(|e| Error::Internal(e.to_string()))
+}
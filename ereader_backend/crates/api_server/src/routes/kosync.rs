@@ -0,0 +1,236 @@
+//! KOReader progress sync (kosync) endpoints.
+//!
+//! Implements the protocol spoken by KOReader's progress sync plugin, so KOReader devices
+//! share one reading position with the rest of the library. Accounts are set up through
+//! `/auth/kosync`; KOReader then signs in with the `x-auth-user` and `x-auth-key` headers.
+//! Documents are matched to books by the partial MD5 stored with each file. Progress for
+//! documents that aren't in the library is acknowledged but not stored.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::Utc;
+use db_layer::models::{hash_kosync_key, CreateDevice};
+use db_layer::queries::{BookQueries, DeviceQueries, KosyncAccountQueries, ReadingStateQueries};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sync_engine::kosync::{kosync_progress, KOREADER_DEVICE_TYPE};
+use sync_engine::SyncMerger;
+
+use crate::extractors::AuthUser;
+use crate::state::AppState;
+
+/// Unexpected server error
+const ERROR_UNKNOWN: u32 = 1000;
+/// Missing or wrong credentials
+const ERROR_UNAUTHORIZED: u32 = 2001;
+/// Missing or malformed request fields
+const ERROR_INVALID_FIELDS: u32 = 2003;
+/// Progress update without a document
+const ERROR_DOCUMENT_FIELD_MISSING: u32 = 2004;
+/// Registration attempted from KOReader; not part of the original protocol, but KOReader
+/// shows the message to the user
+const ERROR_REGISTRATION_DISABLED: u32 = 2005;
+
+/// Error in the shape KOReader expects
+#[derive(Debug)]
+pub struct KosyncError {
+    status: StatusCode,
+    code: u32,
+    message: String,
+}
+
+impl KosyncError {
+    fn new(status: StatusCode, code: u32, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, ERROR_UNAUTHORIZED, "Unauthorized")
+    }
+}
+
+impl IntoResponse for KosyncError {
+    fn into_response(self) -> Response {
+        let body = json!({
+            "code": self.code,
+            "message": self.message,
+        });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+impl From<common::Error> for KosyncError {
+    fn from(e: common::Error) -> Self {
+        if let common::Error::Validation(message) = &e {
+            tracing::debug!(error = %message, "Rejected kosync request");
+            return Self::new(StatusCode::FORBIDDEN, ERROR_INVALID_FIELDS, "Invalid request");
+        }
+        tracing::error!(error = %e, "kosync request failed");
+        Self::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            ERROR_UNKNOWN,
+            "Unknown server error",
+        )
+    }
+}
+
+type KosyncResult<T> = std::result::Result<T, KosyncError>;
+
+/// Request body KOReader sends to register
+#[derive(Debug, Deserialize)]
+pub struct CreateUserRequest {
+    pub username: String,
+    /// MD5 hex of the password
+    pub password: String,
+}
+
+/// Progress update from KOReader
+#[derive(Debug, Deserialize)]
+pub struct UpdateProgressRequest {
+    #[serde(default)]
+    pub document: Option<String>,
+    /// XPointer for reflowable documents, page number for fixed layouts
+    #[serde(default)]
+    pub progress: Option<String>,
+    #[serde(default)]
+    pub percentage: Option<f32>,
+    /// Device model name
+    #[serde(default)]
+    pub device: Option<String>,
+    #[serde(default)]
+    pub device_id: Option<String>,
+}
+
+/// Progress of a document as KOReader expects it
+#[derive(Debug, Serialize)]
+pub struct ProgressResponse {
+    pub document: String,
+    pub progress: String,
+    pub percentage: f32,
+    pub device: String,
+    pub device_id: String,
+    /// Unix time of the update
+    pub timestamp: i64,
+}
+
+/// Handle registration from KOReader
+///
+/// Accounts can only be set up in the library app. Credentials that already match an
+/// account are accepted, so registering from KOReader after setting up the account works.
+pub async fn create_user(
+    State(state): State<AppState>,
+    Json(req): Json<CreateUserRequest>,
+) -> KosyncResult<(StatusCode, Json<serde_json::Value>)> {
+    let account =
+        KosyncAccountQueries::authenticate(&state.pool, &req.username, &hash_kosync_key(&req.password))
+            .await?;
+
+    match account {
+        Some(account) => Ok((StatusCode::CREATED, Json(json!({ "username": account.username })))),
+        None => Err(KosyncError::new(
+            StatusCode::FORBIDDEN,
+            ERROR_REGISTRATION_DISABLED,
+            "Set up a KOReader sync account in the library app first",
+        )),
+    }
+}
+
+/// Confirm the credentials KOReader signed in with
+pub async fn authorize(_user: AuthUser) -> Json<serde_json::Value> {
+    Json(json!({ "authorized": "OK" }))
+}
+
+/// Record KOReader's position in a document
+pub async fn update_progress(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(req): Json<UpdateProgressRequest>,
+) -> KosyncResult<Json<serde_json::Value>> {
+    let document = req
+        .document
+        .filter(|d| !d.is_empty())
+        .ok_or_else(|| {
+            KosyncError::new(
+                StatusCode::FORBIDDEN,
+                ERROR_DOCUMENT_FIELD_MISSING,
+                "Field 'document' not provided.",
+            )
+        })?;
+    let (Some(progress), Some(percentage), Some(device)) = (req.progress, req.percentage, req.device)
+    else {
+        return Err(KosyncError::new(
+            StatusCode::FORBIDDEN,
+            ERROR_INVALID_FIELDS,
+            "Invalid request",
+        ));
+    };
+    let device_id = req.device_id.unwrap_or_else(|| device.clone());
+
+    let Some(book) = BookQueries::find_by_kosync_document(&state.pool, &user.user_id, &document).await?
+    else {
+        tracing::debug!(document = %document, "Ignoring kosync progress for unknown document");
+        return Ok(Json(json!({ "document": document, "timestamp": Utc::now().timestamp() })));
+    };
+
+    let device = DeviceQueries::upsert_external(
+        &state.pool,
+        &CreateDevice::new(&user.user_id, device, KOREADER_DEVICE_TYPE),
+        &device_id,
+    )
+    .await?;
+
+    let reading_state = SyncMerger::new(&state.pool)
+        .with_events(&state.events)
+        .update_from_kosync(&user.user_id, device.id, book.id, &progress, percentage)
+        .await?;
+
+    Ok(Json(json!({
+        "document": document,
+        "timestamp": reading_state.updated_at.timestamp(),
+    })))
+}
+
+/// Get the shared position in a document, or an empty object if there is none
+pub async fn get_progress(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(document): Path<String>,
+) -> KosyncResult<Response> {
+    let Some(book) = BookQueries::find_by_kosync_document(&state.pool, &user.user_id, &document).await?
+    else {
+        return Ok(Json(json!({})).into_response());
+    };
+    let Some(reading_state) =
+        ReadingStateQueries::get_for_book(&state.pool, &user.user_id, book.id).await?
+    else {
+        return Ok(Json(json!({})).into_response());
+    };
+
+    let positions =
+        ReadingStateQueries::get_device_positions(&state.pool, &user.user_id, book.id).await?;
+    let writer = positions
+        .iter()
+        .find(|p| p.device_id == reading_state.device_id);
+
+    // A KOReader that reported the current position gets its own XPointer back
+    let external_id = DeviceQueries::get_external_id(&state.pool, reading_state.device_id).await?;
+    let reported = writer.filter(|_| external_id.is_some());
+
+    Ok(Json(ProgressResponse {
+        document,
+        progress: kosync_progress(&reading_state, reported),
+        percentage: reading_state.location().progress,
+        device: writer.map(|p| p.device_name.clone()).unwrap_or_default(),
+        device_id: external_id.unwrap_or_else(|| reading_state.device_id.simple().to_string()),
+        timestamp: reading_state.updated_at.timestamp(),
+    })
+    .into_response())
+}
@@ -6,12 +6,25 @@ use axum::{
     response::IntoResponse,
     Json,
 };
-use common::{types::Pagination, BookFormat};
+use common::{types::{relator, Pagination}, BookFormat, Contributor, CursorPaginated, Error};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 use crate::extractors::AuthUser;
 use crate::state::AppState;
-use db_layer::queries::{BookQueries, BookSortOptions, BookFilterOptions};
+use db_layer::models::{BookFacets, BookIdentifiers};
+use db_layer::queries::{BookQueries, BookSortOptions, BookFilterOptions, BookTextQueries, SearchQuery};
+use sync_engine::kosync::chapter_cfi;
+use sync_engine::{ChangeAction, SyncChange};
+
+/// Chapters a full-text search returns snippets from for each book
+const SNIPPETS_PER_BOOK: i64 = 3;
+
+/// Values returned for each facet of a book search
+const FACET_LIMIT: i64 = 20;
+
+/// Completions returned by autocomplete unless the request asks for fewer
+const MAX_COMPLETIONS: i64 = 10;
 
 /// Query parameters for listing books
 #[derive(Debug, Deserialize)]
@@ -28,8 +41,19 @@ pub struct ListBooksQuery {
     pub tag: Option<String>,
     #[serde(default)]
     pub series: Option<String>,
+    /// Books crediting this person as an author
     #[serde(default)]
     pub author: Option<String>,
+    /// Books crediting this person in any role, such as translator
+    #[serde(default)]
+    pub contributor: Option<String>,
+    /// Paginate by cursor instead of page number: empty for the first page, then the
+    /// `next_cursor` of the previous page
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Count all matching books when paginating by cursor
+    #[serde(default)]
+    pub include_total: Option<bool>,
 }
 
 /// Query parameters for searching books
@@ -40,6 +64,21 @@ pub struct SearchBooksQuery {
     pub page: Option<i64>,
     #[serde(default)]
     pub per_page: Option<i64>,
+    /// Paginate by cursor instead of page number: empty for the first page, then the
+    /// `next_cursor` of the previous page
+    #[serde(default)]
+    pub cursor: Option<String>,
+    /// Count all matching books when paginating by cursor
+    #[serde(default)]
+    pub include_total: Option<bool>,
+}
+
+/// Query parameters for autocompleting typed text
+#[derive(Debug, Deserialize)]
+pub struct AutocompleteQuery {
+    pub q: String,
+    #[serde(default)]
+    pub limit: Option<i64>,
 }
 
 /// Request body for creating a book
@@ -48,6 +87,9 @@ pub struct CreateBookRequest {
     pub title: String,
     #[serde(default)]
     pub authors: Vec<String>,
+    /// People credited in other roles, by MARC relator code such as `trl`
+    #[serde(default)]
+    pub contributors: Vec<Contributor>,
     #[serde(default)]
     pub description: Option<String>,
     #[serde(default)]
@@ -64,6 +106,12 @@ pub struct CreateBookRequest {
     pub series_index: Option<f32>,
     #[serde(default)]
     pub tags: Vec<String>,
+    #[serde(default)]
+    pub author_sort: Option<String>,
+    #[serde(default)]
+    pub rating: Option<i16>,
+    #[serde(default)]
+    pub identifiers: BookIdentifiers,
 }
 
 /// Request body for updating a book
@@ -73,6 +121,9 @@ pub struct UpdateBookRequest {
     pub title: Option<String>,
     #[serde(default)]
     pub authors: Option<Vec<String>>,
+    /// People credited in other roles, by MARC relator code such as `trl`
+    #[serde(default)]
+    pub contributors: Option<Vec<Contributor>>,
     #[serde(default)]
     pub description: Option<String>,
     #[serde(default)]
@@ -89,6 +140,12 @@ pub struct UpdateBookRequest {
     pub series_index: Option<f32>,
     #[serde(default)]
     pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub author_sort: Option<String>,
+    #[serde(default)]
+    pub rating: Option<i16>,
+    #[serde(default)]
+    pub identifiers: Option<BookIdentifiers>,
 }
 
 /// Book response structure
@@ -97,14 +154,21 @@ pub struct BookResponse {
     pub id: Uuid,
     pub title: String,
     pub authors: Vec<String>,
+    /// Everyone the book credits, authors included, by MARC relator code of their role
+    pub contributors: BTreeMap<String, Vec<String>>,
     pub description: Option<String>,
     pub language: Option<String>,
     pub publisher: Option<String>,
     pub published_date: Option<String>,
     pub isbn: Option<String>,
+    pub series_id: Option<Uuid>,
     pub series_name: Option<String>,
     pub series_index: Option<f32>,
     pub tags: Vec<String>,
+    pub author_sort: Option<String>,
+    /// Rating in half stars from 1 to 10
+    pub rating: Option<i16>,
+    pub identifiers: BookIdentifiers,
     // File information
     pub format: Option<BookFormat>,
     pub file_size: Option<i64>,
@@ -117,18 +181,24 @@ pub struct BookResponse {
 impl From<db_layer::models::Book> for BookResponse {
     fn from(book: db_layer::models::Book) -> Self {
         let has_file = book.has_file();
+        let contributors = book.contributors_by_role();
         Self {
             id: book.id,
             title: book.title,
             authors: book.authors,
+            contributors,
             description: book.description,
             language: book.language,
             publisher: book.publisher,
             published_date: book.published_date,
             isbn: book.isbn,
+            series_id: book.series_id,
             series_name: book.series_name,
             series_index: book.series_index,
             tags: book.tags,
+            author_sort: book.author_sort,
+            rating: book.rating,
+            identifiers: book.identifiers.0,
             format: book.format,
             file_size: book.file_size,
             content_hash: book.content_hash,
@@ -139,6 +209,27 @@ impl From<db_layer::models::Book> for BookResponse {
     }
 }
 
+/// Book matching a full-text search, with passages from its best-matching chapters
+#[derive(Debug, Serialize)]
+pub struct TextSearchHit {
+    #[serde(flatten)]
+    pub book: BookResponse,
+    pub rank: f32,
+    pub matching_chapters: i64,
+    pub snippets: Vec<TextSnippetResponse>,
+}
+
+/// Passage of a chapter matching a full-text search
+#[derive(Debug, Serialize)]
+pub struct TextSnippetResponse {
+    pub spine_index: i32,
+    pub chapter_title: Option<String>,
+    /// CFI of the start of the chapter
+    pub locator: String,
+    /// HTML-escaped text with matched words wrapped in `<mark>`
+    pub snippet: String,
+}
+
 /// Paginated response wrapper
 #[derive(Debug, Serialize)]
 pub struct PaginatedResponse<T> {
@@ -149,7 +240,55 @@ pub struct PaginatedResponse<T> {
     pub has_more: bool,
 }
 
+/// Page of a list paginated by cursor
+#[derive(Debug, Serialize)]
+pub struct CursorPaginatedResponse<T> {
+    pub items: Vec<T>,
+    pub per_page: i64,
+    pub has_more: bool,
+    /// Cursor of the next page, or null on the last page
+    pub next_cursor: Option<String>,
+    /// Number of items in the whole list, if requested with `include_total`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total: Option<i64>,
+}
+
+/// Page of books, paginated by page number or by cursor as requested
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum BookPageResponse {
+    Offset(PaginatedResponse<BookResponse>),
+    Cursor(CursorPaginatedResponse<BookResponse>),
+}
+
+impl BookPageResponse {
+    fn from_cursor_page(books: CursorPaginated<db_layer::models::Book>, per_page: i64) -> Self {
+        BookPageResponse::Cursor(CursorPaginatedResponse {
+            has_more: books.has_more(),
+            items: books.items.into_iter().map(BookResponse::from).collect(),
+            per_page,
+            next_cursor: books.next_cursor,
+            total: books.total,
+        })
+    }
+}
+
+/// Page of search results with facet counts over every matching book
+#[derive(Debug, Serialize)]
+pub struct SearchBooksResponse {
+    #[serde(flatten)]
+    pub results: BookPageResponse,
+    /// Facet counts, left out of cursor pages after the first
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub facets: Option<BookFacets>,
+    /// Corrected query, when nothing matched and a misspelling was likely
+    pub did_you_mean: Option<String>,
+}
+
 /// List books for the authenticated user
+///
+/// Pages are numbered unless the request has a `cursor`, which pages through the books
+/// after the position it encodes so that books added meanwhile don't shift later pages.
 pub async fn list_books(
     State(state): State<AppState>,
     auth: AuthUser,
@@ -173,8 +312,31 @@ pub async fn list_books(
         tag: query.tag,
         series: query.series,
         author: query.author,
+        contributor: query.contributor,
     };
 
+    if let Some(cursor) = query.cursor.as_deref() {
+        let books = BookQueries::list_for_user_by_cursor(
+            &state.pool,
+            &auth.user_id,
+            per_page,
+            Some(cursor).filter(|c| !c.is_empty()),
+            query.include_total.unwrap_or(false),
+            &sort,
+            &filter,
+        )
+        .await
+        .map_err(|e| match e {
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            e => {
+                tracing::error!(error = %e, "Failed to list books");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+
+        return Ok(Json(BookPageResponse::from_cursor_page(books, per_page)));
+    }
+
     let books = BookQueries::list_for_user(
         &state.pool,
         &auth.user_id,
@@ -188,13 +350,13 @@ pub async fn list_books(
         StatusCode::INTERNAL_SERVER_ERROR
     })?;
 
-    let response = PaginatedResponse {
+    let response = BookPageResponse::Offset(PaginatedResponse {
         has_more: books.has_more(),
         items: books.items.into_iter().map(BookResponse::from).collect(),
         total: books.total,
         page,
         per_page,
-    };
+    });
 
     Ok(Json(response))
 }
@@ -227,6 +389,7 @@ pub async fn create_book(
         .with_tags(req.tags);
 
     let mut book_data = create_book;
+    book_data.contributors = valid_contributors(req.contributors)?;
     book_data.description = req.description;
     book_data.isbn = req.isbn;
     book_data.publisher = req.publisher;
@@ -234,6 +397,9 @@ pub async fn create_book(
     book_data.published_date = req.published_date;
     book_data.series_name = req.series_name;
     book_data.series_index = req.series_index;
+    book_data.author_sort = req.author_sort;
+    book_data.rating = valid_rating(req.rating)?;
+    book_data.identifiers = req.identifiers;
 
     let book = BookQueries::create(&state.pool, &book_data)
         .await
@@ -243,6 +409,11 @@ pub async fn create_book(
         })?;
 
     tracing::info!(book_id = %book.id, user_id = %auth.user_id, "Created book");
+    state.publish_change(
+        &auth.user_id,
+        SyncChange::Book { book_id: book.id, action: ChangeAction::Created },
+    );
+    state.queue_smart_refresh(&auth.user_id).await;
 
     Ok((StatusCode::CREATED, Json(BookResponse::from(book))))
 }
@@ -266,6 +437,7 @@ pub async fn update_book(
     let update = db_layer::models::UpdateBook {
         title: req.title,
         authors: req.authors,
+        contributors: req.contributors.map(valid_contributors).transpose()?,
         description: req.description,
         isbn: req.isbn,
         publisher: req.publisher,
@@ -274,6 +446,9 @@ pub async fn update_book(
         series_name: req.series_name,
         series_index: req.series_index,
         tags: req.tags,
+        author_sort: req.author_sort,
+        rating: valid_rating(req.rating)?,
+        identifiers: req.identifiers,
     };
 
     let book = BookQueries::update_metadata(&state.pool, existing.id, &update)
@@ -284,6 +459,11 @@ pub async fn update_book(
         })?;
 
     tracing::info!(book_id = %book.id, user_id = %auth.user_id, "Updated book");
+    state.publish_change(
+        &auth.user_id,
+        SyncChange::Book { book_id: book.id, action: ChangeAction::Updated },
+    );
+    state.queue_smart_refresh(&auth.user_id).await;
 
     Ok(Json(BookResponse::from(book)))
 }
@@ -312,13 +492,23 @@ pub async fn delete_book(
 
     if deleted {
         tracing::info!(book_id = %id, user_id = %auth.user_id, "Deleted book");
+        state.publish_change(
+            &auth.user_id,
+            SyncChange::Book { book_id: id, action: ChangeAction::Deleted },
+        );
+        state.queue_smart_refresh(&auth.user_id).await;
         Ok(StatusCode::NO_CONTENT)
     } else {
         Err(StatusCode::NOT_FOUND)
     }
 }
 
-/// Search books by title or author
+/// Search books with the query syntax of [`db_layer::queries::book_search`]
+///
+/// Along with a page of matches, the response counts the most common tags, authors,
+/// languages and series among all matching books. A search with no matches suggests a
+/// query with misspelled words corrected. Pages are numbered or follow cursors as in
+/// [`list_books`].
 pub async fn search_books(
     State(state): State<AppState>,
     auth: AuthUser,
@@ -333,20 +523,189 @@ pub async fn search_books(
         offset,
     };
 
-    let books = BookQueries::search(&state.pool, &auth.user_id, &query.q, &pagination)
+    let search = SearchQuery::parse(&query.q).map_err(|e| {
+        tracing::debug!(error = %e, query = %query.q, "Invalid search query");
+        StatusCode::BAD_REQUEST
+    })?;
+
+    // Later cursor pages continue a search, so they skip the facets and suggestion
+    let (results, first_page, no_matches) = if let Some(cursor) = query.cursor.as_deref() {
+        let books = BookQueries::search_by_cursor(
+            &state.pool,
+            &auth.user_id,
+            &search,
+            per_page,
+            Some(cursor).filter(|c| !c.is_empty()),
+            query.include_total.unwrap_or(false),
+        )
+        .await
+        .map_err(|e| match e {
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            e => {
+                tracing::error!(error = %e, query = %query.q, "Failed to search books");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+
+        let first_page = cursor.is_empty();
+        let no_matches = first_page && books.items.is_empty();
+        (BookPageResponse::from_cursor_page(books, per_page), first_page, no_matches)
+    } else {
+        let books = BookQueries::search(&state.pool, &auth.user_id, &search, &pagination)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, query = %query.q, "Failed to search books");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+
+        let no_matches = books.total == 0;
+        let results = BookPageResponse::Offset(PaginatedResponse {
+            has_more: books.has_more(),
+            items: books.items.into_iter().map(BookResponse::from).collect(),
+            total: books.total,
+            page,
+            per_page,
+        });
+        (results, true, no_matches)
+    };
+
+    let facets = if first_page {
+        let facets = BookQueries::search_facets(&state.pool, &auth.user_id, &search, FACET_LIMIT)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, query = %query.q, "Failed to count search facets");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?;
+        Some(facets)
+    } else {
+        None
+    };
+
+    let did_you_mean = if no_matches {
+        BookQueries::did_you_mean(&state.pool, &auth.user_id, &query.q, &search)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, query = %query.q, "Failed to suggest a search correction");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+    } else {
+        None
+    };
+
+    let response = SearchBooksResponse {
+        results,
+        facets,
+        did_you_mean,
+    };
+
+    Ok(Json(response))
+}
+
+/// Complete text typed into a search box with titles, authors and series of the user's
+/// books, tolerating typos
+pub async fn autocomplete(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<AutocompleteQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if query.q.trim().is_empty() {
+        return Ok(Json(Vec::new()));
+    }
+    let limit = query.limit.unwrap_or(MAX_COMPLETIONS).clamp(1, MAX_COMPLETIONS);
+
+    let completions = BookQueries::autocomplete(&state.pool, &auth.user_id, &query.q, limit)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, query = %query.q, "Failed to autocomplete");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(completions))
+}
+
+/// Search the text of the user's books, best match first
+///
+/// The query uses web search syntax: quoted phrases, `or`, and `-` to exclude words. Each
+/// book comes with snippets from up to [`SNIPPETS_PER_BOOK`] of its best-matching chapters.
+pub async fn search_text(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Query(query): Query<SearchBooksQuery>,
+) -> Result<impl IntoResponse, StatusCode> {
+    if query.q.trim().is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(20).clamp(1, 100);
+    let offset = (page - 1) * per_page;
+
+    let pagination = Pagination {
+        limit: per_page,
+        offset,
+    };
+
+    let hits = BookTextQueries::search(&state.pool, &auth.user_id, &query.q, &pagination)
         .await
         .map_err(|e| {
-            tracing::error!(error = %e, query = %query.q, "Failed to search books");
+            tracing::error!(error = %e, query = %query.q, "Failed to search book text");
             StatusCode::INTERNAL_SERVER_ERROR
         })?;
 
-    let response = PaginatedResponse {
-        has_more: books.has_more(),
-        items: books.items.into_iter().map(BookResponse::from).collect(),
-        total: books.total,
+    let book_ids: Vec<Uuid> = hits.items.iter().map(|hit| hit.book.id).collect();
+    let snippets = BookTextQueries::snippets(&state.pool, &book_ids, &query.q, SNIPPETS_PER_BOOK)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, query = %query.q, "Failed to get search snippets");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let has_more = hits.has_more();
+    let items = hits
+        .items
+        .into_iter()
+        .map(|hit| TextSearchHit {
+            snippets: snippets
+                .iter()
+                .filter(|s| s.book_id == hit.book.id)
+                .map(|s| TextSnippetResponse {
+                    spine_index: s.spine_index,
+                    chapter_title: s.title.clone(),
+                    locator: chapter_cfi(s.spine_index as usize + 1),
+                    snippet: s.snippet.clone(),
+                })
+                .collect(),
+            rank: hit.rank,
+            matching_chapters: hit.matching_chapters,
+            book: BookResponse::from(hit.book),
+        })
+        .collect();
+
+    Ok(Json(PaginatedResponse {
+        has_more,
+        items,
+        total: hits.total,
         page,
         per_page,
-    };
+    }))
+}
 
-    Ok(Json(response))
+/// Trim contributors' names, dropping blank ones, and check their roles are relator codes
+fn valid_contributors(contributors: Vec<Contributor>) -> Result<Vec<Contributor>, StatusCode> {
+    contributors
+        .into_iter()
+        .filter(|c| !c.name.trim().is_empty())
+        .map(|c| {
+            let role = relator::normalize(&c.role).ok_or(StatusCode::BAD_REQUEST)?;
+            Ok(Contributor::new(c.name.trim(), role))
+        })
+        .collect()
+}
+
+/// Check a rating is in half stars from 1 to 10
+fn valid_rating(rating: Option<i16>) -> Result<Option<i16>, StatusCode> {
+    match rating {
+        Some(rating) if !(1..=10).contains(&rating) => Err(StatusCode::BAD_REQUEST),
+        rating => Ok(rating),
+    }
 }
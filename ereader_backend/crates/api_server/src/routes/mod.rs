@@ -3,28 +3,51 @@
 pub mod admin;
 pub mod assets;
 pub mod auth;
+pub mod authors;
 pub mod collections;
 pub mod covers;
 pub mod health;
+pub mod kobo;
+pub mod kosync;
 pub mod library;
+pub mod opds;
+pub mod series;
+pub mod stats;
 pub mod sync;
 
 use axum::{
     extract::DefaultBodyLimit,
     middleware,
-    routing::{delete, get, post},
-    Router,
+    routing::{delete, get, post, put},
+    Extension, Router,
 };
 use tower_http::cors::{CorsLayer, Any};
-use crate::middleware::auth_middleware;
+use crate::middleware::{
+    app_auth_middleware, auth_middleware, kobo_auth_middleware, kosync_auth_middleware,
+};
+use crate::opds::OpdsVersion;
 use crate::state::AppState;
 
+/// OPDS feeds for one version of the catalog
+fn opds_feed_routes(version: OpdsVersion) -> Router<AppState> {
+    Router::new()
+        .route("/", get(opds::root))
+        .route("/books", get(opds::all_books))
+        .route("/new", get(opds::recent_books))
+        .route("/search", get(opds::search))
+        .route("/collections", get(opds::collections))
+        .route("/collections/{id}", get(opds::collection_books))
+        .layer(Extension(version))
+}
+
 /// Create the main application router
 pub fn create_router(state: AppState) -> Router {
     // Library routes with auth middleware
     let library_routes = Router::new()
         .route("/", get(library::list_books).post(library::create_book))
         .route("/search", get(library::search_books))
+        .route("/search/text", get(library::search_text))
+        .route("/autocomplete", get(library::autocomplete))
         .route(
             "/{id}",
             get(library::get_book)
@@ -44,14 +67,41 @@ pub fn create_router(state: AppState) -> Router {
     // Collections routes with auth middleware
     let collection_routes = Router::new()
         .route("/", get(collections::list_collections).post(collections::create_collection))
+        .route("/tree", get(collections::collection_tree))
         .route(
             "/{id}",
             get(collections::get_collection)
                 .put(collections::update_collection)
                 .delete(collections::delete_collection),
         )
-        .route("/{id}/books", post(collections::add_book))
+        .route("/{id}/parent", put(collections::set_parent))
+        .route("/{id}/books", get(collections::list_books).post(collections::add_book))
+        .route("/{id}/books/bulk", post(collections::add_books))
+        .route("/{id}/books/bulk-remove", post(collections::remove_books))
+        .route("/{id}/books/bulk-move", post(collections::move_books))
+        .route("/{id}/books/order", put(collections::set_order))
         .route("/{id}/books/{book_id}", delete(collections::remove_book))
+        .route("/{id}/books/{book_id}/position", put(collections::set_position))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth_middleware,
+        ));
+
+    // Authors routes with auth middleware
+    let author_routes = Router::new()
+        .route("/", get(authors::list_authors))
+        .route("/{id}", get(authors::get_author).put(authors::update_author))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth_middleware,
+        ));
+
+    // Series routes with auth middleware
+    let series_routes = Router::new()
+        .route("/", get(series::list_series))
+        .route("/{id}", get(series::get_series).put(series::update_series))
+        .route("/{id}/next", get(series::next_in_series))
+        .route("/{id}/merge", post(series::merge_series))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
@@ -61,8 +111,25 @@ pub fn create_router(state: AppState) -> Router {
     let sync_routes = Router::new()
         .route("/", post(sync::sync_batch))
         .route("/reading-state/{book_id}", get(sync::get_reading_state).put(sync::update_reading_state))
+        .route("/reading-state/{book_id}/devices", get(sync::get_device_positions))
+        .route("/settings", get(sync::get_settings).put(sync::update_settings))
+        .route("/stream", get(sync::stream))
         .route("/annotations", get(sync::list_annotations))
         .route("/annotations/{book_id}", get(sync::get_book_annotations))
+        .route("/conflicts", get(sync::list_conflicts))
+        .route("/conflicts/{id}/resolve", post(sync::resolve_conflict))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth_middleware,
+        ));
+
+    // Reading statistics routes with auth middleware
+    let stats_routes = Router::new()
+        .route("/reading-time", get(stats::reading_time))
+        .route("/finished", get(stats::finished_books))
+        .route("/streaks", get(stats::streaks))
+        .route("/books", get(stats::list_book_stats))
+        .route("/books/{book_id}", get(stats::get_book_stats))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
@@ -75,13 +142,79 @@ pub fn create_router(state: AppState) -> Router {
                 state.clone(),
                 auth_middleware,
             )))
-        .route("/webhook", post(auth::clerk_webhook)); // No auth middleware - validated via webhook secret
+        .route("/webhook", post(auth::clerk_webhook)) // No auth middleware - validated via webhook secret
+        .route(
+            "/app-tokens",
+            get(auth::list_app_tokens)
+                .post(auth::create_app_token)
+                .layer(middleware::from_fn_with_state(state.clone(), auth_middleware)),
+        )
+        .route(
+            "/app-tokens/{id}",
+            delete(auth::revoke_app_token)
+                .layer(middleware::from_fn_with_state(state.clone(), auth_middleware)),
+        )
+        .route(
+            "/kosync",
+            get(auth::get_kosync_account)
+                .put(auth::set_kosync_account)
+                .delete(auth::delete_kosync_account)
+                .layer(middleware::from_fn_with_state(state.clone(), auth_middleware)),
+        );
+
+    // KOReader progress sync routes, authenticated with kosync account headers except for
+    // registration
+    let kosync_routes = Router::new()
+        .route("/users/auth", get(kosync::authorize))
+        .route("/syncs/progress", put(kosync::update_progress))
+        .route("/syncs/progress/{document}", get(kosync::get_progress))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            kosync_auth_middleware,
+        ))
+        .route("/users/create", post(kosync::create_user));
+
+    // OPDS catalog routes, which also accept app tokens; covers and downloads are served
+    // here too so reading apps can fetch them with the same credentials
+    let opds_routes = Router::new()
+        .merge(opds_feed_routes(OpdsVersion::V1))
+        .nest("/v2", opds_feed_routes(OpdsVersion::V2))
+        .route("/opensearch.xml", get(opds::opensearch))
+        .route("/books/{id}/download", get(assets::download_file))
+        .route("/books/{id}/cover/{size}", get(covers::get_cover_size))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            app_auth_middleware,
+        ));
+
+    // Kobo store sync emulation, authenticated by the app token in the path
+    let kobo_routes = Router::new()
+        .route("/v1/initialization", get(kobo::initialization))
+        .route("/v1/auth/device", post(kobo::auth_device))
+        .route("/v1/library/sync", get(kobo::library_sync))
+        .route("/v1/library/{book_id}", delete(kobo::delete_entry))
+        .route("/v1/library/{book_id}/metadata", get(kobo::get_metadata))
+        .route(
+            "/v1/library/{book_id}/state",
+            get(kobo::get_reading_state).put(kobo::update_reading_state),
+        )
+        .route("/v1/download/{book_id}/{format}", get(kobo::download))
+        .route("/{image_id}/{width}/{height}/{greyscale}/image.jpg", get(kobo::cover_image))
+        .route(
+            "/{image_id}/{width}/{height}/{quality}/{greyscale}/image.jpg",
+            get(kobo::cover_image),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            kobo_auth_middleware,
+        ));
 
     // Admin routes with auth middleware
     let admin_routes = Router::new()
         .route("/reindex", post(admin::trigger_reindex))
         .route("/backup", post(admin::create_backup))
         .route("/stats", get(admin::get_stats))
+        .route("/authors/merge", post(admin::merge_authors))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth_middleware,
@@ -97,8 +230,18 @@ pub fn create_router(state: AppState) -> Router {
         .nest("/books", library_routes)
         // Collections endpoints (with auth)
         .nest("/collections", collection_routes)
+        // Authors endpoints (with auth)
+        .nest("/authors", author_routes)
+        // Series endpoints (with auth)
+        .nest("/series", series_routes)
         // Sync endpoints (with auth)
         .nest("/sync", sync_routes)
+        // Reading statistics endpoints (with auth)
+        .nest("/stats", stats_routes)
+        // OPDS catalog (with app token or JWT auth)
+        .nest("/opds", opds_routes)
+        // KOReader progress sync (with kosync account auth)
+        .nest("/kosync", kosync_routes)
         // Admin endpoints (with auth)
         .nest("/admin", admin_routes);
 
@@ -108,8 +251,11 @@ pub fn create_router(state: AppState) -> Router {
         .allow_methods(Any)
         .allow_headers(Any);
 
-    Router::new()
-        .nest("/api/v1", api_routes)
-        .layer(cors)
-        .with_state(state)
+    let mut router = Router::new().nest("/api/v1", api_routes);
+    if state.config.kobo.enabled {
+        // Kobo devices take a base URL of their own, so the emulated store sits beside the API
+        router = router.nest("/kobo/{token}", kobo_routes);
+    }
+
+    router.layer(cors).with_state(state)
 }
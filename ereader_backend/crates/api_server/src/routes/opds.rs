@@ -0,0 +1,260 @@
+//! OPDS catalog endpoints.
+//!
+//! The same feeds are served as OPDS 1.2 at the catalog root and as OPDS 2.0 under `/v2`.
+//! Books without a file are left out of acquisition feeds, since there is nothing to
+//! download.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::Utc;
+use common::{Error, Paginated, Result};
+use db_layer::models::{Book, Collection};
+use db_layer::queries::{BookFilterOptions, BookQueries, BookSortOptions, CollectionQueries, SearchQuery};
+use serde::Deserialize;
+use storage_layer::CoverStorage;
+use uuid::Uuid;
+
+use crate::extractors::AuthUser;
+use crate::opds::{
+    atom, Feed, FeedKind, FeedPage, NavigationEntry, OpdsVersion, Publication,
+};
+use crate::state::AppState;
+
+/// Books per page of an acquisition feed
+const PAGE_SIZE: i64 = 50;
+
+/// Query parameters for paginated feeds
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    #[serde(default)]
+    pub page: Option<i64>,
+}
+
+/// Query parameters for the search feed
+#[derive(Debug, Deserialize)]
+pub struct SearchFeedQuery {
+    /// Search terms; OPDS 2.0 clients send them as `query`
+    #[serde(alias = "query")]
+    pub q: String,
+    #[serde(default)]
+    pub page: Option<i64>,
+}
+
+/// Catalog root, linking to the other feeds
+pub async fn root(Extension(version): Extension<OpdsVersion>) -> Response {
+    let now = Utc::now();
+    let entry = |path: &str, title: &str, summary: &str, kind: FeedKind| NavigationEntry {
+        id: format!("urn:ereader:opds{}", path),
+        title: title.to_string(),
+        href: version.href(path),
+        summary: Some(summary.to_string()),
+        updated: now,
+        kind,
+    };
+
+    let feed = Feed::new("urn:ereader:opds", "Library", FeedKind::Navigation, version.href("/"))
+        .with_navigation(vec![
+            entry("/books", "All books", "Every book, by title", FeedKind::Acquisition),
+            entry("/new", "Recently added", "Newest books first", FeedKind::Acquisition),
+            entry("/collections", "Collections", "Books by collection", FeedKind::Navigation),
+        ]);
+
+    version.render(&feed)
+}
+
+/// OpenSearch description for OPDS 1.2 clients
+pub async fn opensearch() -> Response {
+    (
+        [(header::CONTENT_TYPE, atom::OPENSEARCH_TYPE)],
+        atom::opensearch_description(),
+    )
+        .into_response()
+}
+
+/// All books, by title
+pub async fn all_books(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Extension(version): Extension<OpdsVersion>,
+    Query(query): Query<FeedQuery>,
+) -> Result<Response> {
+    book_list_feed(&state, &auth, version, query, "/books", "All books", BookSortOptions::by_title())
+        .await
+}
+
+/// Books, newest first
+pub async fn recent_books(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Extension(version): Extension<OpdsVersion>,
+    Query(query): Query<FeedQuery>,
+) -> Result<Response> {
+    book_list_feed(
+        &state,
+        &auth,
+        version,
+        query,
+        "/new",
+        "Recently added",
+        BookSortOptions::by_created_at_desc(),
+    )
+    .await
+}
+
+/// Books matching a search
+pub async fn search(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Extension(version): Extension<OpdsVersion>,
+    Query(query): Query<SearchFeedQuery>,
+) -> Result<Response> {
+    let terms = query.q.trim();
+    if terms.is_empty() {
+        return Err(Error::validation_field("q", "must not be empty"));
+    }
+
+    let page = FeedPage::new(query.page, PAGE_SIZE);
+    let search = SearchQuery::parse(terms)?;
+    let books = BookQueries::search(&state.pool, &auth.user_id, &search, &page.pagination()).await?;
+
+    let encoded: String = form_urlencoded::byte_serialize(terms.as_bytes()).collect();
+    let href = |n: i64| version.href(&format!("/search?q={}&page={}", encoded, n));
+    let feed = Feed::new(
+        format!("urn:ereader:opds/search?q={}", encoded),
+        format!("Search: {}", terms),
+        FeedKind::Acquisition,
+        href(page.number),
+    );
+
+    Ok(version.render(&acquisition_feed(&state, feed, page, books, href).await?))
+}
+
+/// Entry linking to a collection's feed
+fn collection_entry(version: OpdsVersion, collection: Collection) -> NavigationEntry {
+    NavigationEntry {
+        id: format!("urn:uuid:{}", collection.id),
+        href: version.href(&format!("/collections/{}", collection.id)),
+        title: collection.name,
+        summary: collection.description,
+        updated: collection.updated_at,
+        kind: FeedKind::Acquisition,
+    }
+}
+
+/// The user's top-level collections
+pub async fn collections(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Extension(version): Extension<OpdsVersion>,
+) -> Result<Response> {
+    let collections = CollectionQueries::list_children(&state.pool, &auth.user_id, None).await?;
+
+    let entries = collections
+        .into_iter()
+        .map(|c| collection_entry(version, c))
+        .collect();
+
+    let feed = Feed::new(
+        "urn:ereader:opds/collections",
+        "Collections",
+        FeedKind::Navigation,
+        version.href("/collections"),
+    )
+    .with_link("up", version.href("/"), FeedKind::Navigation)
+    .with_navigation(entries);
+
+    Ok(version.render(&feed))
+}
+
+/// Books in a collection, after the collections nested in it on the first page
+pub async fn collection_books(
+    State(state): State<AppState>,
+    auth: AuthUser,
+    Extension(version): Extension<OpdsVersion>,
+    Path(id): Path<Uuid>,
+    Query(query): Query<FeedQuery>,
+) -> Result<Response> {
+    let collection = CollectionQueries::get_by_id_for_user(&state.pool, id, &auth.user_id)
+        .await?
+        .ok_or_else(|| Error::not_found_resource("collection", id))?;
+
+    let page = FeedPage::new(query.page, PAGE_SIZE);
+    let books = CollectionQueries::get_books(&state.pool, &collection, &page.pagination()).await?;
+
+    let children = if page.number == 1 {
+        CollectionQueries::list_children(&state.pool, &auth.user_id, Some(id)).await?
+    } else {
+        Vec::new()
+    };
+    let up = match collection.parent_id {
+        Some(parent_id) => version.href(&format!("/collections/{}", parent_id)),
+        None => version.href("/collections"),
+    };
+
+    let href = |n: i64| version.href(&format!("/collections/{}?page={}", id, n));
+    let feed = Feed::new(
+        format!("urn:uuid:{}", collection.id),
+        collection.name,
+        FeedKind::Acquisition,
+        href(page.number),
+    )
+    .with_link("up", up, FeedKind::Navigation)
+    .with_navigation(children.into_iter().map(|c| collection_entry(version, c)).collect());
+
+    Ok(version.render(&acquisition_feed(&state, feed, page, books, href).await?))
+}
+
+/// Feed of all the user's books in the given order
+async fn book_list_feed(
+    state: &AppState,
+    auth: &AuthUser,
+    version: OpdsVersion,
+    query: FeedQuery,
+    path: &str,
+    title: &str,
+    sort: BookSortOptions,
+) -> Result<Response> {
+    let page = FeedPage::new(query.page, PAGE_SIZE);
+    let books = BookQueries::list_for_user(
+        &state.pool,
+        &auth.user_id,
+        &page.pagination(),
+        &sort,
+        &BookFilterOptions::default(),
+    )
+    .await?;
+
+    let href = |n: i64| version.href(&format!("{}?page={}", path, n));
+    let feed = Feed::new(
+        format!("urn:ereader:opds{}", path),
+        title,
+        FeedKind::Acquisition,
+        href(page.number),
+    )
+    .with_link("up", version.href("/"), FeedKind::Navigation);
+
+    Ok(version.render(&acquisition_feed(state, feed, page, books, href).await?))
+}
+
+/// Fill an acquisition feed with one page of books
+async fn acquisition_feed(
+    state: &AppState,
+    feed: Feed,
+    page: FeedPage,
+    books: Paginated<Book>,
+    href: impl Fn(i64) -> String,
+) -> Result<Feed> {
+    let mut publications = Vec::with_capacity(books.items.len());
+    for book in books.items {
+        let has_cover = state.storage.cover_exists(book.id).await?;
+        publications.extend(Publication::new(book, has_cover));
+    }
+
+    Ok(feed
+        .with_page(page.with_total(books.total), href)
+        .with_publications(publications))
+}
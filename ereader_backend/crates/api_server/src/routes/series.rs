@@ -0,0 +1,256 @@
+//! Series endpoints.
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use common::types::{Paginated, Pagination};
+use common::Error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::extractors::AuthUser;
+use crate::routes::library::BookResponse;
+use crate::state::AppState;
+use sync_engine::{ChangeAction, SyncChange};
+
+/// Request body for updating a series
+#[derive(Debug, Deserialize)]
+pub struct UpdateSeriesRequest {
+    /// New name, which the series' books take as their series name
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Request body for merging series into another
+#[derive(Debug, Deserialize)]
+pub struct MergeSeriesRequest {
+    pub series_ids: Vec<Uuid>,
+}
+
+/// Series response structure, with the user's progress through it
+#[derive(Debug, Serialize)]
+pub struct SeriesResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub aliases: Vec<String>,
+    pub book_count: i64,
+    pub finished_count: i64,
+    pub in_progress_count: i64,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl From<db_layer::models::SeriesWithProgress> for SeriesResponse {
+    fn from(s: db_layer::models::SeriesWithProgress) -> Self {
+        Self {
+            id: s.series.id,
+            name: s.series.name,
+            description: s.series.description,
+            aliases: s.series.aliases,
+            book_count: s.book_count,
+            finished_count: s.finished_count,
+            in_progress_count: s.in_progress_count,
+            created_at: s.series.created_at,
+            updated_at: s.series.updated_at,
+        }
+    }
+}
+
+/// Series with its books in reading order
+#[derive(Debug, Serialize)]
+pub struct SeriesDetailResponse {
+    #[serde(flatten)]
+    pub series: SeriesResponse,
+    pub books: Vec<SeriesBookResponse>,
+}
+
+/// Book of a series, with the user's progress through it
+#[derive(Debug, Serialize)]
+pub struct SeriesBookResponse {
+    #[serde(flatten)]
+    pub book: BookResponse,
+    pub progress: f32,
+}
+
+/// Book to read next in a series, or `None` once the whole series is finished
+#[derive(Debug, Serialize)]
+pub struct NextInSeriesResponse {
+    pub book: Option<SeriesBookResponse>,
+}
+
+/// List the user's series with their progress through each
+pub async fn list_series(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<Paginated<SeriesResponse>>, StatusCode> {
+    let series = db_layer::queries::SeriesQueries::list_for_user(&state.pool, &user.user_id, &pagination)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to list series");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok(Json(Paginated {
+        items: series.items.into_iter().map(SeriesResponse::from).collect(),
+        total: series.total,
+        limit: series.limit,
+        offset: series.offset,
+    }))
+}
+
+/// Get a series with its books in reading order
+pub async fn get_series(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SeriesDetailResponse>, StatusCode> {
+    let series = series_with_progress(&state, id, &user.user_id).await?;
+    let books = series_books(&state, id, &user.user_id).await?;
+
+    Ok(Json(SeriesDetailResponse { series, books }))
+}
+
+/// Get the book to read next in a series
+///
+/// That's the last book started if it isn't finished yet, or else the first unfinished
+/// book after it in reading order.
+pub async fn next_in_series(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+) -> Result<Json<NextInSeriesResponse>, StatusCode> {
+    db_layer::queries::SeriesQueries::get_by_id_for_user(&state.pool, id, &user.user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to get series");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let books = series_books(&state, id, &user.user_id).await?;
+    let order: Vec<Uuid> = books.iter().map(|b| b.book.id).collect();
+    let progress: HashMap<Uuid, f32> = books.iter().map(|b| (b.book.id, b.progress)).collect();
+    let next = db_layer::models::next_to_read(&order, &progress);
+
+    Ok(Json(NextInSeriesResponse {
+        book: books.into_iter().find(|b| Some(b.book.id) == next),
+    }))
+}
+
+/// Rename or describe a series
+pub async fn update_series(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<UpdateSeriesRequest>,
+) -> Result<Json<SeriesResponse>, StatusCode> {
+    let renamed = req.name.is_some();
+    let update_data = db_layer::models::UpdateSeries {
+        name: req.name,
+        description: req.description,
+    };
+
+    db_layer::queries::SeriesQueries::update(&state.pool, id, &user.user_id, &update_data)
+        .await
+        .map_err(|e| match e {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            Error::Validation(_) => StatusCode::BAD_REQUEST,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            e => {
+                tracing::error!(error = %e, "Failed to update series");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+
+    if renamed {
+        publish_book_changes(&state, id, &user.user_id).await;
+    }
+
+    Ok(Json(series_with_progress(&state, id, &user.user_id).await?))
+}
+
+/// Merge other series into this one, moving their books to it
+pub async fn merge_series(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<MergeSeriesRequest>,
+) -> Result<Json<SeriesResponse>, StatusCode> {
+    db_layer::queries::SeriesQueries::merge(&state.pool, &user.user_id, id, &req.series_ids)
+        .await
+        .map_err(|e| match e {
+            Error::NotFound(_) => StatusCode::NOT_FOUND,
+            e => {
+                tracing::error!(error = %e, "Failed to merge series");
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        })?;
+
+    publish_book_changes(&state, id, &user.user_id).await;
+
+    Ok(Json(series_with_progress(&state, id, &user.user_id).await?))
+}
+
+async fn series_with_progress(state: &AppState, id: Uuid, user_id: &str) -> Result<SeriesResponse, StatusCode> {
+    let series = db_layer::queries::SeriesQueries::get_with_progress(&state.pool, id, user_id)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to get series");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    Ok(series.into())
+}
+
+/// Books of a series in reading order, with the user's progress through each
+async fn series_books(state: &AppState, id: Uuid, user_id: &str) -> Result<Vec<SeriesBookResponse>, StatusCode> {
+    let books = db_layer::queries::SeriesQueries::get_books(&state.pool, id)
+        .await
+        .map_err(|e| {
+            tracing::error!(error = %e, "Failed to list series books");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    let book_ids: Vec<Uuid> = books.iter().map(|b| b.id).collect();
+    let progress: HashMap<Uuid, f32> =
+        db_layer::queries::ReadingStateQueries::get_for_books(&state.pool, user_id, &book_ids)
+            .await
+            .map_err(|e| {
+                tracing::error!(error = %e, "Failed to get reading states");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .into_iter()
+            .map(|rs| (rs.book_id, rs.location().progress))
+            .collect();
+
+    Ok(books
+        .into_iter()
+        .map(|book| SeriesBookResponse {
+            progress: progress.get(&book.id).copied().unwrap_or_default(),
+            book: book.into(),
+        })
+        .collect())
+}
+
+/// Let the user's devices know the books of a series changed their series name
+async fn publish_book_changes(state: &AppState, id: Uuid, user_id: &str) {
+    match db_layer::queries::SeriesQueries::get_books(&state.pool, id).await {
+        Ok(books) => {
+            for book in books {
+                state.publish_change(
+                    user_id,
+                    SyncChange::Book { book_id: book.id, action: ChangeAction::Updated },
+                );
+            }
+        }
+        Err(e) => tracing::warn!(error = %e, "Failed to list renamed series books"),
+    }
+}
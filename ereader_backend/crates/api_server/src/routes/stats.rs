@@ -0,0 +1,238 @@
+//! Reading statistics endpoints.
+//!
+//! Statistics are computed from the reading sessions devices upload during sync. Days are
+//! local to the client, given as `utc_offset_minutes`.
+
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Utc};
+use common::{Error, Result};
+use db_layer::models::{
+    BookReadingTotals, ReadingStreak, ReadingTimeBucket, StatsPeriod, FINISHED_PROGRESS,
+};
+use db_layer::queries::{BookQueries, ReadingSessionQueries, ReadingStateQueries};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+use crate::extractors::AuthUser;
+use crate::state::AppState;
+
+/// Largest offset from UTC of any time zone
+const MAX_UTC_OFFSET_MINUTES: i32 = 14 * 60;
+
+/// Days covered by default when asking for daily reading time
+const DEFAULT_DAYS: i64 = 30;
+
+/// Weeks covered by default when asking for weekly reading time
+const DEFAULT_WEEKS: i64 = 12;
+
+#[derive(Debug, Deserialize)]
+pub struct ReadingTimeQuery {
+    #[serde(default)]
+    pub period: StatsPeriod,
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LocalTimeQuery {
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadingTimeResponse {
+    pub period: StatsPeriod,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub total_seconds: i64,
+    /// Periods with no reading are left out
+    pub buckets: Vec<ReadingTimeBucket>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FinishedYearResponse {
+    pub year: i32,
+    pub books: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BookStatsResponse {
+    pub book_id: Uuid,
+    pub seconds: i64,
+    pub sessions: i64,
+    /// Current progress from the book's reading state
+    pub current_progress: Option<f32>,
+    /// Average progress per hour of reading (1.0 is the whole book)
+    pub progress_per_hour: Option<f64>,
+    /// Reading time left at the average speed
+    pub estimated_remaining_secs: Option<i64>,
+    pub last_read_at: Option<DateTime<Utc>>,
+}
+
+impl BookStatsResponse {
+    fn new(book_id: Uuid, totals: Option<&BookReadingTotals>, current_progress: Option<f32>) -> Self {
+        Self {
+            book_id,
+            seconds: totals.map_or(0, |t| t.seconds),
+            sessions: totals.map_or(0, |t| t.sessions),
+            current_progress,
+            progress_per_hour: totals.and_then(BookReadingTotals::progress_per_hour),
+            estimated_remaining_secs: totals
+                .zip(current_progress)
+                .and_then(|(t, progress)| t.estimated_remaining_secs(progress)),
+            last_read_at: totals.map(|t| t.last_read_at),
+        }
+    }
+}
+
+/// Get reading time per day or week
+pub async fn reading_time(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(query): Query<ReadingTimeQuery>,
+) -> Result<Json<ReadingTimeResponse>> {
+    utc_offset(query.utc_offset_minutes)?;
+
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query.from.unwrap_or_else(|| match query.period {
+        StatsPeriod::Day => to - Duration::days(DEFAULT_DAYS),
+        StatsPeriod::Week => to - Duration::weeks(DEFAULT_WEEKS),
+    });
+    if from >= to {
+        return Err(Error::validation_field("from", "must be before `to`"));
+    }
+
+    let buckets = ReadingSessionQueries::reading_time(
+        &state.pool,
+        &user.user_id,
+        query.period,
+        from,
+        to,
+        query.utc_offset_minutes,
+    )
+    .await?;
+
+    Ok(Json(ReadingTimeResponse {
+        period: query.period,
+        from,
+        to,
+        total_seconds: buckets.iter().map(|b| b.seconds).sum(),
+        buckets,
+    }))
+}
+
+/// Count books finished in each year, oldest year first
+///
+/// A book is finished once its reading state reaches [`FINISHED_PROGRESS`]; it counts
+/// towards the year of its last reading session.
+pub async fn finished_books(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(query): Query<LocalTimeQuery>,
+) -> Result<Json<Vec<FinishedYearResponse>>> {
+    let offset = utc_offset(query.utc_offset_minutes)?;
+
+    let last_read: HashMap<Uuid, DateTime<Utc>> =
+        ReadingSessionQueries::book_totals(&state.pool, &user.user_id)
+            .await?
+            .into_iter()
+            .map(|t| (t.book_id, t.last_read_at))
+            .collect();
+
+    let mut per_year: BTreeMap<i32, i64> = BTreeMap::new();
+    for reading_state in ReadingStateQueries::get_for_user(&state.pool, &user.user_id).await? {
+        if reading_state.location().progress < FINISHED_PROGRESS {
+            continue;
+        }
+        let finished_at = last_read
+            .get(&reading_state.book_id)
+            .copied()
+            .unwrap_or(reading_state.updated_at);
+        *per_year
+            .entry(finished_at.with_timezone(&offset).year())
+            .or_default() += 1;
+    }
+
+    Ok(Json(
+        per_year
+            .into_iter()
+            .map(|(year, books)| FinishedYearResponse { year, books })
+            .collect(),
+    ))
+}
+
+/// Get the current and longest streaks of consecutive reading days
+pub async fn streaks(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(query): Query<LocalTimeQuery>,
+) -> Result<Json<ReadingStreak>> {
+    let offset = utc_offset(query.utc_offset_minutes)?;
+
+    let days =
+        ReadingSessionQueries::reading_days(&state.pool, &user.user_id, query.utc_offset_minutes)
+            .await?;
+    let today = Utc::now().with_timezone(&offset).date_naive();
+
+    Ok(Json(ReadingStreak::from_days(&days, today)))
+}
+
+/// Get reading time and average speed for each book read, most recent first
+pub async fn list_book_stats(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<Vec<BookStatsResponse>>> {
+    let progress: HashMap<Uuid, f32> = ReadingStateQueries::get_for_user(&state.pool, &user.user_id)
+        .await?
+        .into_iter()
+        .map(|s| (s.book_id, s.location().progress))
+        .collect();
+
+    let stats = ReadingSessionQueries::book_totals(&state.pool, &user.user_id)
+        .await?
+        .iter()
+        .map(|t| BookStatsResponse::new(t.book_id, Some(t), progress.get(&t.book_id).copied()))
+        .collect();
+
+    Ok(Json(stats))
+}
+
+/// Get reading time and average speed for a book
+pub async fn get_book_stats(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(book_id): Path<Uuid>,
+) -> Result<Json<BookStatsResponse>> {
+    BookQueries::get_by_id_for_user(&state.pool, book_id, &user.user_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Book not found".into()))?;
+
+    let totals =
+        ReadingSessionQueries::totals_for_book(&state.pool, &user.user_id, book_id).await?;
+    let progress = ReadingStateQueries::get_for_book(&state.pool, &user.user_id, book_id)
+        .await?
+        .map(|s| s.location().progress);
+
+    Ok(Json(BookStatsResponse::new(book_id, totals.as_ref(), progress)))
+}
+
+/// Validate the client's offset from UTC
+fn utc_offset(minutes: i32) -> Result<FixedOffset> {
+    if minutes.abs() > MAX_UTC_OFFSET_MINUTES {
+        return Err(Error::validation_field(
+            "utc_offset_minutes",
+            &format!("must be between -{0} and {0}", MAX_UTC_OFFSET_MINUTES),
+        ));
+    }
+
+    FixedOffset::east_opt(minutes * 60)
+        .ok_or_else(|| Error::validation_field("utc_offset_minutes", "invalid offset"))
+}
@@ -1,169 +1,352 @@
-//! Sync endpoints - STUB IMPLEMENTATION
-//! These are placeholder implementations until ReadingStateQueries and AnnotationQueries
-//! are implemented in db_layer.
+//! Sync endpoints for reading progress and annotations.
 
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
+    extract::{Path, Query, State},
+    response::sse::{Event, KeepAlive, Sse},
     Json,
 };
-use common::types::ReadingLocation;
+use common::types::{
+    AnnotationAnchorStatus, Paginated, Pagination, ReadingLocation, ReadingPositionPolicy,
+    TextQuoteSelector,
+};
+use common::{Error, Result};
+use db_layer::models::SyncConflictEntry;
+use db_layer::queries::{
+    AnnotationQueries, BookQueries, DeviceQueries, ReadingStateQueries, SyncConflictQueries,
+    SyncSettingsQueries,
+};
 use serde::{Deserialize, Serialize};
+use sync_engine::{
+    AnnotationTypeSync, ConflictChoice, ConflictResolution, DeviceReadingPosition,
+    ReadingStateSync, SyncMerger, SyncRequest, SyncResponse,
+};
+use tokio_stream::{
+    wrappers::errors::BroadcastStreamRecvError,
+    Stream, StreamExt,
+};
 use uuid::Uuid;
 
 use crate::extractors::AuthUser;
 use crate::state::AppState;
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ReadingStateSync {
-    pub book_id: Uuid,
+#[derive(Debug, Deserialize)]
+pub struct UpdateReadingStateRequest {
+    pub device_id: Uuid,
     pub location: ReadingLocation,
-    pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct AnnotationSync {
-    #[serde(default)]
-    pub id: Option<Uuid>,
-    pub book_id: Uuid,
-    pub annotation_type: AnnotationType,
-    pub location_start: String,
-    #[serde(default)]
-    pub location_end: Option<String>,
-    #[serde(default)]
-    pub content: Option<String>,
-    #[serde(default)]
-    pub style: Option<String>,
-    pub updated_at: chrono::DateTime<chrono::Utc>,
+#[derive(Debug, Deserialize)]
+pub struct StreamQuery {
+    /// Device opening the stream; its own changes are not echoed back
     #[serde(default)]
-    pub deleted: bool,
+    pub device_id: Option<Uuid>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-pub enum AnnotationType {
-    Highlight,
-    Note,
-    Bookmark,
+pub struct SyncSettingsBody {
+    pub reading_position_policy: ReadingPositionPolicy,
 }
 
 #[derive(Debug, Deserialize)]
-pub struct SyncRequest {
-    pub device_id: Uuid,
-    #[serde(default)]
-    pub last_sync_at: Option<chrono::DateTime<chrono::Utc>>,
-    #[serde(default)]
-    pub reading_states: Vec<ReadingStateSync>,
-    #[serde(default)]
-    pub annotations: Vec<AnnotationSync>,
+pub struct ResolveConflictRequest {
+    pub choice: ConflictChoice,
 }
 
 #[derive(Debug, Serialize)]
-pub struct SyncResponse {
-    pub server_time: chrono::DateTime<chrono::Utc>,
-    pub reading_states: Vec<ReadingStateSync>,
-    pub annotations: Vec<AnnotationSync>,
-    pub conflicts: Vec<SyncConflict>,
-}
-
-#[derive(Debug, Serialize)]
-pub struct SyncConflict {
+pub struct SyncConflictResponse {
+    pub id: Uuid,
+    pub device_id: Option<Uuid>,
     pub entity_type: String,
-    pub entity_id: String,
-    pub local_updated_at: chrono::DateTime<chrono::Utc>,
-    pub server_updated_at: chrono::DateTime<chrono::Utc>,
+    pub entity_id: Uuid,
+    /// How the conflict was resolved automatically during sync
     pub resolution: ConflictResolution,
+    /// The device's version that lost or was merged
+    pub local_version: serde_json::Value,
+    /// The server's version when the conflict happened
+    pub server_version: serde_json::Value,
+    /// Annotation holding the device's version, when both versions were kept
+    pub copy_id: Option<Uuid>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub resolved_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub resolved_with: Option<ConflictChoice>,
 }
 
-#[derive(Debug, Serialize)]
-#[serde(rename_all = "snake_case")]
-pub enum ConflictResolution {
-    ServerWins,
-    ClientWins,
-    Merged,
-}
-
-#[derive(Debug, Serialize)]
-pub struct ReadingStateResponse {
-    pub book_id: Uuid,
-    pub location: ReadingLocation,
-    pub updated_at: chrono::DateTime<chrono::Utc>,
-}
-
-#[derive(Debug, Deserialize)]
-pub struct UpdateReadingStateRequest {
-    pub device_id: Uuid,
-    pub location: ReadingLocation,
+impl From<SyncConflictEntry> for SyncConflictResponse {
+    fn from(conflict: SyncConflictEntry) -> Self {
+        Self {
+            id: conflict.id,
+            device_id: conflict.device_id,
+            entity_type: conflict.entity_type,
+            entity_id: conflict.entity_id,
+            resolution: conflict.resolution,
+            local_version: conflict.local_version,
+            server_version: conflict.server_version,
+            copy_id: conflict.copy_id,
+            created_at: conflict.created_at,
+            resolved_at: conflict.resolved_at,
+            resolved_with: conflict.resolved_with,
+        }
+    }
 }
 
 #[derive(Debug, Serialize)]
 pub struct AnnotationResponse {
     pub id: Uuid,
     pub book_id: Uuid,
-    pub annotation_type: AnnotationType,
+    pub annotation_type: AnnotationTypeSync,
     pub location_start: String,
     pub location_end: Option<String>,
     pub content: Option<String>,
     pub style: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quote: Option<TextQuoteSelector>,
+    pub anchor_status: AnnotationAnchorStatus,
+    pub revision: i32,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
-/// Perform batch sync - STUB
+impl From<db_layer::models::Annotation> for AnnotationResponse {
+    fn from(annotation: db_layer::models::Annotation) -> Self {
+        let quote = annotation.quote();
+        Self {
+            id: annotation.id,
+            book_id: annotation.book_id,
+            annotation_type: annotation.annotation_type.into(),
+            location_start: annotation.location_start,
+            location_end: annotation.location_end,
+            content: annotation.content,
+            style: annotation.style,
+            quote,
+            anchor_status: annotation.anchor_status,
+            revision: annotation.revision,
+            created_at: annotation.created_at,
+            updated_at: annotation.updated_at,
+        }
+    }
+}
+
+/// Perform batch sync of reading states and annotations
 pub async fn sync_batch(
-    State(_state): State<AppState>,
-    _user: AuthUser,
-    Json(_request): Json<SyncRequest>,
-) -> Result<Json<SyncResponse>, StatusCode> {
-    // TODO: Implement with ReadingStateQueries and AnnotationQueries
-    Ok(Json(SyncResponse {
-        server_time: chrono::Utc::now(),
-        reading_states: vec![],
-        annotations: vec![],
-        conflicts: vec![],
-    }))
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(request): Json<SyncRequest>,
+) -> Result<Json<SyncResponse>> {
+    let response = SyncMerger::new(&state.pool)
+        .with_events(&state.events)
+        .process_sync(&user.user_id, request)
+        .await?;
+
+    tracing::debug!(
+        user_id = %user.user_id,
+        reading_states = response.reading_states.len(),
+        annotations = response.annotations.len(),
+        conflicts = response.conflicts.len(),
+        "Processed sync batch"
+    );
+
+    Ok(Json(response))
 }
 
-/// Get reading state - STUB
+/// Stream changes made on the user's other devices as Server-Sent Events
+///
+/// Each event is named after the changed entity (`reading_state`, `annotation`, `book`,
+/// `collection`). A `resync` event means events were dropped and the client should run a
+/// regular sync.
+pub async fn stream(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(query): Query<StreamQuery>,
+) -> Result<Sse<impl Stream<Item = std::result::Result<Event, axum::Error>>>> {
+    if let Some(device_id) = query.device_id {
+        DeviceQueries::get_by_id_for_user(&state.pool, device_id, &user.user_id)
+            .await?
+            .ok_or_else(|| Error::not_found_resource("device", device_id))?;
+    }
+
+    let device_id = query.device_id;
+    let events = state.events.subscribe(&user.user_id).filter_map(move |received| {
+        match received {
+            Ok(event) => {
+                let from_other_device =
+                    device_id.is_none() || event.origin_device_id != device_id;
+                from_other_device.then(|| {
+                    Event::default()
+                        .event(event.change.name())
+                        .json_data(&event)
+                })
+            }
+            Err(BroadcastStreamRecvError::Lagged(missed)) => {
+                tracing::debug!(missed, "Sync stream subscriber lagged");
+                Some(Ok(Event::default().event("resync").data(missed.to_string())))
+            }
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Get the reading state for a book
 pub async fn get_reading_state(
-    State(_state): State<AppState>,
-    _user: AuthUser,
-    Path(_book_id): Path<Uuid>,
-) -> Result<Json<Option<ReadingStateResponse>>, StatusCode> {
-    // TODO: Implement with ReadingStateQueries
-    Ok(Json(None))
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(book_id): Path<Uuid>,
+) -> Result<Json<Option<ReadingStateSync>>> {
+    verify_book(&state, &user, book_id).await?;
+
+    let reading_state = ReadingStateQueries::get_for_book(&state.pool, &user.user_id, book_id)
+        .await?
+        .map(ReadingStateSync::from);
+
+    Ok(Json(reading_state))
 }
 
-/// Update reading state - STUB
+/// Update the reading state for a book
 pub async fn update_reading_state(
-    State(_state): State<AppState>,
-    _user: AuthUser,
+    State(state): State<AppState>,
+    user: AuthUser,
     Path(book_id): Path<Uuid>,
     Json(req): Json<UpdateReadingStateRequest>,
-) -> Result<Json<ReadingStateResponse>, StatusCode> {
-    // TODO: Implement with ReadingStateQueries
-    Ok(Json(ReadingStateResponse {
-        book_id,
-        location: req.location,
-        updated_at: chrono::Utc::now(),
+) -> Result<Json<ReadingStateSync>> {
+    let reading_state = SyncMerger::new(&state.pool)
+        .with_events(&state.events)
+        .update_reading_state(&user.user_id, req.device_id, book_id, req.location)
+        .await?;
+
+    Ok(Json(reading_state))
+}
+
+/// Get the last position reported by each device for a book
+pub async fn get_device_positions(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(book_id): Path<Uuid>,
+) -> Result<Json<Vec<DeviceReadingPosition>>> {
+    verify_book(&state, &user, book_id).await?;
+
+    let positions = ReadingStateQueries::get_device_positions(&state.pool, &user.user_id, book_id)
+        .await?
+        .into_iter()
+        .map(DeviceReadingPosition::from)
+        .collect();
+
+    Ok(Json(positions))
+}
+
+/// Get the sync settings for the authenticated user
+pub async fn get_settings(
+    State(state): State<AppState>,
+    user: AuthUser,
+) -> Result<Json<SyncSettingsBody>> {
+    let settings = SyncSettingsQueries::get_for_user(&state.pool, &user.user_id).await?;
+
+    Ok(Json(SyncSettingsBody {
+        reading_position_policy: settings.reading_position_policy,
+    }))
+}
+
+/// Update the sync settings for the authenticated user
+pub async fn update_settings(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Json(req): Json<SyncSettingsBody>,
+) -> Result<Json<SyncSettingsBody>> {
+    let settings = SyncSettingsQueries::set_reading_position_policy(
+        &state.pool,
+        &user.user_id,
+        req.reading_position_policy,
+    )
+    .await?;
+
+    Ok(Json(SyncSettingsBody {
+        reading_position_policy: settings.reading_position_policy,
     }))
 }
 
-/// List annotations - STUB
+/// List all annotations for the authenticated user
 pub async fn list_annotations(
-    State(_state): State<AppState>,
-    _user: AuthUser,
-) -> Result<Json<Vec<AnnotationResponse>>, StatusCode> {
-    // TODO: Implement with AnnotationQueries
-    Ok(Json(vec![]))
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<Paginated<AnnotationResponse>>> {
+    let annotations =
+        AnnotationQueries::list_for_user(&state.pool, &user.user_id, &pagination).await?;
+
+    Ok(Json(Paginated {
+        items: annotations
+            .items
+            .into_iter()
+            .map(AnnotationResponse::from)
+            .collect(),
+        total: annotations.total,
+        limit: annotations.limit,
+        offset: annotations.offset,
+    }))
 }
 
-/// Get book annotations - STUB
+/// Get all annotations for a book
 pub async fn get_book_annotations(
-    State(_state): State<AppState>,
-    _user: AuthUser,
-    Path(_book_id): Path<Uuid>,
-) -> Result<Json<Vec<AnnotationResponse>>, StatusCode> {
-    // TODO: Implement with AnnotationQueries
-    Ok(Json(vec![]))
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(book_id): Path<Uuid>,
+) -> Result<Json<Vec<AnnotationResponse>>> {
+    verify_book(&state, &user, book_id).await?;
+
+    let annotations = AnnotationQueries::get_for_book(&state.pool, &user.user_id, book_id).await?;
+
+    Ok(Json(
+        annotations.into_iter().map(AnnotationResponse::from).collect(),
+    ))
+}
+
+/// List unresolved sync conflicts for the authenticated user, newest first
+pub async fn list_conflicts(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Query(pagination): Query<Pagination>,
+) -> Result<Json<Paginated<SyncConflictResponse>>> {
+    let conflicts =
+        SyncConflictQueries::list_unresolved(&state.pool, &user.user_id, &pagination).await?;
+
+    Ok(Json(Paginated {
+        items: conflicts
+            .items
+            .into_iter()
+            .map(SyncConflictResponse::from)
+            .collect(),
+        total: conflicts.total,
+        limit: conflicts.limit,
+        offset: conflicts.offset,
+    }))
+}
+
+/// Resolve a sync conflict with the version the user picked
+pub async fn resolve_conflict(
+    State(state): State<AppState>,
+    user: AuthUser,
+    Path(id): Path<Uuid>,
+    Json(req): Json<ResolveConflictRequest>,
+) -> Result<Json<SyncConflictResponse>> {
+    let conflict = SyncMerger::new(&state.pool)
+        .with_events(&state.events)
+        .resolve_conflict(&user.user_id, id, req.choice)
+        .await?;
+
+    tracing::info!(
+        user_id = %user.user_id,
+        conflict_id = %id,
+        choice = %req.choice,
+        "Resolved sync conflict"
+    );
+
+    Ok(Json(SyncConflictResponse::from(conflict)))
+}
+
+/// Ensure the book exists and belongs to the user
+async fn verify_book(state: &AppState, user: &AuthUser, book_id: Uuid) -> Result<()> {
+    BookQueries::get_by_id_for_user(&state.pool, book_id, &user.user_id)
+        .await?
+        .ok_or_else(|| Error::NotFound("Book not found".into()))?;
+
+    Ok(())
 }
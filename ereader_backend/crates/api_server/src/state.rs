@@ -1,9 +1,13 @@
 //! Application state shared across handlers.
 
 use crate::jwt::JwtValidator;
+use common::config::KoboConfig;
+use db_layer::models::{CreateTask, task_types};
+use db_layer::queries::{CollectionQueries, TaskQueries};
 use db_layer::DbPool;
 use std::sync::Arc;
 use storage_layer::LocalStorage;
+use sync_engine::{SyncChange, SyncEvent, SyncEventBus};
 
 /// Application state shared across all handlers
 #[derive(Clone)]
@@ -12,6 +16,7 @@ pub struct AppState {
     pub storage: Arc<LocalStorage>,
     pub config: AppStateConfig,
     pub jwt_validator: JwtValidator,
+    pub events: SyncEventBus,
 }
 
 /// Configuration subset needed by handlers
@@ -19,6 +24,7 @@ pub struct AppState {
 pub struct AppStateConfig {
     pub clerk_secret_key: String,
     pub clerk_jwks_url: String,
+    pub kobo: KoboConfig,
 }
 
 impl AppState {
@@ -33,6 +39,33 @@ impl AppState {
             storage: Arc::new(storage),
             config,
             jwt_validator,
+            events: SyncEventBus::default(),
+        }
+    }
+
+    /// Notify the user's connected devices of a change made outside of sync
+    pub fn publish_change(&self, user_id: &str, change: SyncChange) {
+        self.events.publish(SyncEvent::new(user_id, change));
+    }
+
+    /// Queue a refresh of the user's smart collections after their books changed
+    ///
+    /// Failures are only logged; the worker's periodic refresh catches up.
+    pub async fn queue_smart_refresh(&self, user_id: &str) {
+        let result = async {
+            if CollectionQueries::has_smart_collections(&self.pool, user_id).await? {
+                let task = CreateTask::new(
+                    task_types::REFRESH_SMART_COLLECTIONS,
+                    serde_json::json!({ "user_id": user_id }),
+                );
+                TaskQueries::create(&self.pool, &task).await?;
+            }
+            common::Result::Ok(())
+        }
+        .await;
+
+        if let Err(e) = result {
+            tracing::warn!(error = %e, user_id, "Failed to queue smart collection refresh");
         }
     }
 }
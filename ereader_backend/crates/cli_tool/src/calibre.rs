@@ -0,0 +1,604 @@
+//! Calibre library import.
+//!
+//! A Calibre library is a folder per book, holding its files and `cover.jpg`, indexed by a
+//! `metadata.db` SQLite database. Books are read from the database and imported with their
+//! EPUB and cover; a book whose EPUB is already in the library (by content hash) is not
+//! imported again. Every book handled is recorded by its Calibre id, so running the import
+//! again resumes after the books already done and retries the ones that failed.
+//!
+//! Calibre custom columns are mapped where there is a matching field: tag-like columns add
+//! tags, and series, rating and comments columns fill the book's series, rating and
+//! description when Calibre's own fields leave them empty. Other columns are skipped.
+
+use anyhow::Context;
+use common::config::AppConfig;
+use common::BookFormat;
+use db_layer::models::{Book, BookIdentifiers, CreateBook, CreateCover};
+use db_layer::queries::{BookImportQueries, BookQueries, CoverQueries};
+use db_layer::DbPool;
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use storage_layer::{CoverSize, CoverStorage, LocalStorage, Storage};
+
+/// Name of the database in a Calibre library folder
+const METADATA_DB: &str = "metadata.db";
+
+/// Name of the cover image in a Calibre book folder
+const COVER_FILE: &str = "cover.jpg";
+
+/// Calibre's placeholder for an unknown publication date falls in this year
+const UNDEFINED_YEAR: &str = "0101";
+
+/// A Calibre library opened read-only
+pub struct CalibreLibrary {
+    root: PathBuf,
+    db: SqlitePool,
+}
+
+/// Book metadata read from a Calibre library
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CalibreBook {
+    pub id: i64,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub author_sort: Option<String>,
+    /// Folder of the book's files, relative to the library
+    pub path: String,
+    pub has_cover: bool,
+    pub series: Option<(String, f32)>,
+    pub tags: Vec<String>,
+    pub identifiers: BookIdentifiers,
+    pub publisher: Option<String>,
+    pub published_date: Option<String>,
+    pub description: Option<String>,
+    /// Rating in half stars from 1 to 10
+    pub rating: Option<i16>,
+    pub language: Option<String>,
+    /// File name of the EPUB in the book's folder, without extension
+    pub epub: Option<String>,
+}
+
+/// Calibre custom column
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct CustomColumn {
+    pub id: i64,
+    pub label: String,
+    pub name: String,
+    pub datatype: String,
+    pub is_multiple: bool,
+    pub normalized: bool,
+}
+
+/// Book field a custom column is imported into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnMapping {
+    Tags,
+    Series,
+    Rating,
+    Description,
+}
+
+impl CustomColumn {
+    /// Field the column's values are imported into, if any
+    pub fn mapping(&self) -> Option<ColumnMapping> {
+        match self.datatype.as_str() {
+            "text" | "enumeration" => Some(ColumnMapping::Tags),
+            "series" => Some(ColumnMapping::Series),
+            "rating" => Some(ColumnMapping::Rating),
+            "comments" => Some(ColumnMapping::Description),
+            _ => None,
+        }
+    }
+
+    /// Tag for one of the column's values
+    ///
+    /// Values of multi-valued columns are tags in their own right; single values are
+    /// prefixed with the column name, as in `Read status: Finished`.
+    fn tag(&self, value: &str) -> String {
+        if self.is_multiple {
+            value.to_string()
+        } else {
+            format!("{}: {}", self.name, value)
+        }
+    }
+}
+
+impl CalibreLibrary {
+    /// Open the library in a Calibre library folder
+    pub async fn open(root: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let root = root.into();
+        let db_path = root.join(METADATA_DB);
+        if !db_path.is_file() {
+            anyhow::bail!("No Calibre library at {}: {} not found", root.display(), METADATA_DB);
+        }
+
+        let options = SqliteConnectOptions::new().filename(&db_path).read_only(true);
+        let db = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .with_context(|| format!("Failed to open {}", db_path.display()))?;
+
+        Ok(Self { root, db })
+    }
+
+    /// Calibre's id for the library, which names it as an import source
+    pub async fn uuid(&self) -> anyhow::Result<String> {
+        let uuid = sqlx::query_scalar::<_, String>("SELECT uuid FROM library_id LIMIT 1")
+            .fetch_one(&self.db)
+            .await?;
+
+        Ok(uuid)
+    }
+
+    /// Ids of all books, in the order they were added
+    pub async fn book_ids(&self) -> anyhow::Result<Vec<i64>> {
+        let ids = sqlx::query_scalar::<_, i64>("SELECT id FROM books ORDER BY id")
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(ids)
+    }
+
+    /// Custom columns that haven't been deleted
+    pub async fn custom_columns(&self) -> anyhow::Result<Vec<CustomColumn>> {
+        let columns = sqlx::query_as::<_, CustomColumn>(
+            r#"
+            SELECT id, label, name, datatype, is_multiple, normalized
+            FROM custom_columns
+            WHERE mark_for_delete = 0
+            ORDER BY id
+            "#,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        Ok(columns)
+    }
+
+    /// Read a book's metadata, including mapped custom columns
+    pub async fn read_book(&self, id: i64, columns: &[CustomColumn]) -> anyhow::Result<CalibreBook> {
+        let (title, author_sort, path, has_cover, series_index, pubdate) =
+            sqlx::query_as::<_, (String, Option<String>, String, bool, f64, Option<String>)>(
+                "SELECT title, author_sort, path, has_cover, series_index, pubdate FROM books WHERE id = ?",
+            )
+            .bind(id)
+            .fetch_one(&self.db)
+            .await?;
+
+        let authors = self
+            .linked_values(id, "SELECT a.name FROM books_authors_link l JOIN authors a ON a.id = l.author WHERE l.book = ? ORDER BY l.id")
+            .await?;
+        let tags = self
+            .linked_values(id, "SELECT t.name FROM books_tags_link l JOIN tags t ON t.id = l.tag WHERE l.book = ? ORDER BY t.name")
+            .await?;
+        let series = self
+            .linked_values(id, "SELECT s.name FROM books_series_link l JOIN series s ON s.id = l.series WHERE l.book = ?")
+            .await?;
+        let publisher = self
+            .linked_values(id, "SELECT p.name FROM books_publishers_link l JOIN publishers p ON p.id = l.publisher WHERE l.book = ?")
+            .await?;
+        let language = self
+            .linked_values(id, "SELECT g.lang_code FROM books_languages_link l JOIN languages g ON g.id = l.lang_code WHERE l.book = ? ORDER BY l.item_order")
+            .await?;
+        let comments = self
+            .linked_values(id, "SELECT text FROM comments WHERE book = ?")
+            .await?;
+        let epub = self
+            .linked_values(id, "SELECT name FROM data WHERE book = ? AND format = 'EPUB'")
+            .await?;
+        let rating = sqlx::query_scalar::<_, i64>(
+            "SELECT r.rating FROM books_ratings_link l JOIN ratings r ON r.id = l.rating WHERE l.book = ?",
+        )
+        .bind(id)
+        .fetch_optional(&self.db)
+        .await?;
+        let identifiers = sqlx::query_as::<_, (String, String)>(
+            "SELECT type, val FROM identifiers WHERE book = ?",
+        )
+        .bind(id)
+        .fetch_all(&self.db)
+        .await?
+        .into_iter()
+        .map(|(scheme, value)| (scheme.to_lowercase(), value))
+        .collect();
+
+        let mut book = CalibreBook {
+            id,
+            title,
+            authors,
+            author_sort: author_sort.filter(|s| !s.is_empty()),
+            path,
+            has_cover,
+            series: series.into_iter().next().map(|name| (name, series_index as f32)),
+            tags,
+            identifiers,
+            publisher: publisher.into_iter().next(),
+            published_date: pubdate.as_deref().and_then(published_date),
+            description: comments.into_iter().next().filter(|c| !c.trim().is_empty()),
+            rating: rating.and_then(half_stars),
+            language: language.into_iter().next(),
+            epub: epub.into_iter().next(),
+        };
+
+        for column in columns {
+            self.apply_custom_column(&mut book, column).await?;
+        }
+
+        Ok(book)
+    }
+
+    /// Path of a book's EPUB, if it has one
+    pub fn epub_path(&self, book: &CalibreBook) -> Option<PathBuf> {
+        book.epub
+            .as_ref()
+            .map(|name| self.root.join(&book.path).join(format!("{}.epub", name)))
+    }
+
+    /// Path of a book's cover, if it has one
+    pub fn cover_path(&self, book: &CalibreBook) -> Option<PathBuf> {
+        book.has_cover
+            .then(|| self.root.join(&book.path).join(COVER_FILE))
+    }
+
+    async fn linked_values(&self, id: i64, query: &str) -> anyhow::Result<Vec<String>> {
+        let values = sqlx::query_scalar::<_, String>(query)
+            .bind(id)
+            .fetch_all(&self.db)
+            .await?;
+
+        Ok(values)
+    }
+
+    /// Merge a custom column's values for a book into the mapped field
+    async fn apply_custom_column(
+        &self,
+        book: &mut CalibreBook,
+        column: &CustomColumn,
+    ) -> anyhow::Result<()> {
+        let Some(mapping) = column.mapping() else {
+            return Ok(());
+        };
+        // Normalized columns keep each distinct value once and link books to them; the
+        // others keep a value per book
+        let values = format!("custom_column_{}", column.id);
+        let link = format!("books_custom_column_{}_link", column.id);
+
+        match mapping {
+            ColumnMapping::Tags => {
+                let query = if column.normalized {
+                    format!("SELECT v.value FROM {link} l JOIN {values} v ON v.id = l.value WHERE l.book = ? ORDER BY v.value")
+                } else {
+                    format!("SELECT value FROM {values} WHERE book = ?")
+                };
+                for value in self.linked_values(book.id, &query).await? {
+                    let tag = column.tag(&value);
+                    if !book.tags.contains(&tag) {
+                        book.tags.push(tag);
+                    }
+                }
+            }
+            ColumnMapping::Series if book.series.is_none() && column.normalized => {
+                book.series = sqlx::query_as::<_, (String, f64)>(&format!(
+                    "SELECT v.value, COALESCE(l.extra, 1.0) FROM {link} l JOIN {values} v ON v.id = l.value WHERE l.book = ?"
+                ))
+                .bind(book.id)
+                .fetch_optional(&self.db)
+                .await?
+                .map(|(name, index)| (name, index as f32));
+            }
+            ColumnMapping::Rating if book.rating.is_none() && column.normalized => {
+                book.rating = sqlx::query_scalar::<_, i64>(&format!(
+                    "SELECT v.value FROM {link} l JOIN {values} v ON v.id = l.value WHERE l.book = ?"
+                ))
+                .bind(book.id)
+                .fetch_optional(&self.db)
+                .await?
+                .and_then(half_stars);
+            }
+            ColumnMapping::Description if book.description.is_none() => {
+                book.description = self
+                    .linked_values(book.id, &format!("SELECT value FROM {values} WHERE book = ?"))
+                    .await?
+                    .into_iter()
+                    .next()
+                    .filter(|c| !c.trim().is_empty());
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+/// How a Calibre book was handled
+enum Outcome {
+    Imported(Book),
+    /// Its EPUB is already in the library as this book
+    Duplicate(Book),
+    NoEpub,
+}
+
+/// Counts of books handled by an import
+#[derive(Debug, Default)]
+struct ImportReport {
+    imported: usize,
+    duplicates: usize,
+    no_epub: usize,
+    failed: usize,
+    previously_imported: usize,
+}
+
+/// Import a Calibre library for a user, resuming after books already imported
+pub async fn import_library(
+    library_dir: &Path,
+    user_id: &str,
+    config: &AppConfig,
+) -> anyhow::Result<()> {
+    let library = CalibreLibrary::open(library_dir).await?;
+    let pool = db_layer::create_pool(&config.database).await?;
+    let storage = LocalStorage::from_config(&config.storage).await?;
+
+    let source = format!("calibre:{}", library.uuid().await?);
+    let done: HashSet<String> = BookImportQueries::imported_ids(&pool, user_id, &source)
+        .await?
+        .into_iter()
+        .collect();
+
+    let columns = library.custom_columns().await?;
+    for column in &columns {
+        match column.mapping() {
+            Some(mapping) => println!("Custom column #{} ({}) -> {:?}", column.label, column.name, mapping),
+            None => println!("Custom column #{} ({}) skipped: {} columns have no matching field", column.label, column.name, column.datatype),
+        }
+    }
+
+    let ids = library.book_ids().await?;
+    let total = ids.len();
+    let mut report = ImportReport::default();
+    println!(
+        "Importing {} books from {} ({} already imported)",
+        total,
+        library_dir.display(),
+        ids.iter().filter(|id| done.contains(&id.to_string())).count()
+    );
+
+    for (position, id) in ids.into_iter().enumerate() {
+        let progress = format!("[{:>width$}/{}]", position + 1, total, width = total.to_string().len());
+        if done.contains(&id.to_string()) {
+            report.previously_imported += 1;
+            continue;
+        }
+
+        let book = match library.read_book(id, &columns).await {
+            Ok(book) => book,
+            Err(e) => {
+                report.failed += 1;
+                println!("{} failed     Calibre book {}: {:#}", progress, id, e);
+                continue;
+            }
+        };
+
+        match import_book(&library, &book, user_id, &pool, &storage).await {
+            Ok(Outcome::Imported(imported)) => {
+                BookImportQueries::record(&pool, user_id, &source, &id.to_string(), imported.id).await?;
+                report.imported += 1;
+                println!("{} imported   {}", progress, book.title);
+            }
+            Ok(Outcome::Duplicate(existing)) => {
+                BookImportQueries::record(&pool, user_id, &source, &id.to_string(), existing.id).await?;
+                report.duplicates += 1;
+                println!("{} duplicate  {} (already in library as {})", progress, book.title, existing.id);
+            }
+            Ok(Outcome::NoEpub) => {
+                report.no_epub += 1;
+                println!("{} skipped    {} (no EPUB)", progress, book.title);
+            }
+            Err(e) => {
+                report.failed += 1;
+                println!("{} failed     {}: {:#}", progress, book.title, e);
+            }
+        }
+    }
+
+    println!("Import finished:");
+    println!("  Imported: {}", report.imported);
+    println!("  Duplicates: {}", report.duplicates);
+    println!("  Skipped without EPUB: {}", report.no_epub);
+    println!("  Failed: {}", report.failed);
+    println!("  Imported by earlier runs: {}", report.previously_imported);
+    if report.failed > 0 {
+        println!("Run the import again to retry the failed books.");
+    }
+
+    Ok(())
+}
+
+async fn import_book(
+    library: &CalibreLibrary,
+    book: &CalibreBook,
+    user_id: &str,
+    pool: &DbPool,
+    storage: &LocalStorage,
+) -> anyhow::Result<Outcome> {
+    let Some(epub_path) = library.epub_path(book) else {
+        return Ok(Outcome::NoEpub);
+    };
+    let data = tokio::fs::read(&epub_path)
+        .await
+        .with_context(|| format!("Failed to read {}", epub_path.display()))?;
+
+    let hash = LocalStorage::compute_hash(&data);
+    if let Some(existing) = BookQueries::find_by_content_hash(pool, user_id, hash.as_str()).await? {
+        return Ok(Outcome::Duplicate(existing));
+    }
+
+    let storage_path = storage.store(&hash, &data).await?;
+    let file_name = epub_path
+        .file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("unknown.epub");
+
+    let mut create_book = CreateBook::new(user_id, &book.title)
+        .with_authors(book.authors.clone())
+        .with_tags(book.tags.clone())
+        .with_file(BookFormat::Epub, hash.as_str(), data.len() as i64, &storage_path, file_name)
+        .with_partial_md5(common::PartialMd5::from_bytes(&data).as_str());
+    if let Some((name, index)) = &book.series {
+        create_book = create_book.with_series(name, *index);
+    }
+    if let Some(author_sort) = &book.author_sort {
+        create_book = create_book.with_author_sort(author_sort);
+    }
+    if let Some(rating) = book.rating {
+        create_book = create_book.with_rating(rating);
+    }
+    if let Some(description) = &book.description {
+        create_book = create_book.with_description(description);
+    }
+    create_book.isbn = book.identifiers.get("isbn").cloned();
+    create_book.identifiers = book.identifiers.clone();
+    create_book.publisher = book.publisher.clone();
+    create_book.published_date = book.published_date.clone();
+    create_book.language = book.language.clone();
+
+    let created = BookQueries::create(pool, &create_book).await?;
+    crate::queue_text_index(pool, created.id).await?;
+
+    if let Some(cover_path) = library.cover_path(book)
+        && let Err(e) = import_cover(&created, &cover_path, pool, storage).await
+    {
+        tracing::warn!(book_id = %created.id, error = %e, "Failed to import cover");
+    }
+
+    Ok(Outcome::Imported(created))
+}
+
+async fn import_cover(
+    book: &Book,
+    cover_path: &Path,
+    pool: &DbPool,
+    storage: &LocalStorage,
+) -> anyhow::Result<()> {
+    let data = tokio::fs::read(cover_path).await?;
+    let cover_paths = storage.store_cover(book.id, &data).await?;
+
+    for size in CoverSize::all() {
+        let (width, height) = size.dimensions();
+        let cover = CreateCover::new(
+            book.id,
+            size.as_str(),
+            width as i32,
+            height as i32,
+            cover_paths.get(size),
+        );
+        CoverQueries::create(pool, &cover).await?;
+    }
+
+    Ok(())
+}
+
+/// Publication date as `YYYY-MM-DD` from a Calibre timestamp
+///
+/// Calibre marks unknown dates with a placeholder in the year 101.
+fn published_date(pubdate: &str) -> Option<String> {
+    let date = pubdate.get(..10)?;
+    if date.starts_with(UNDEFINED_YEAR) {
+        return None;
+    }
+
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .ok()
+        .map(|d| d.format("%Y-%m-%d").to_string())
+}
+
+/// Rating in half stars from a Calibre rating, where 0 means unrated
+fn half_stars(rating: i64) -> Option<i16> {
+    (1..=10).contains(&rating).then_some(rating as i16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The parts of Calibre's schema the importer reads
+    const SCHEMA: &str = include_str!("calibre/fixture.sql");
+
+    async fn library() -> (CalibreLibrary, PathBuf) {
+        let root = std::env::temp_dir().join(format!("calibre-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let options = SqliteConnectOptions::new()
+            .filename(root.join(METADATA_DB))
+            .create_if_missing(true);
+        let db = SqlitePool::connect_with(options).await.unwrap();
+        sqlx::raw_sql(SCHEMA).execute(&db).await.unwrap();
+        db.close().await;
+
+        (CalibreLibrary::open(&root).await.unwrap(), root)
+    }
+
+    #[test]
+    fn test_published_date() {
+        assert_eq!(published_date("2010-05-01 00:00:00+00:00").as_deref(), Some("2010-05-01"));
+        assert_eq!(published_date("2010-05-01T00:00:00+00:00").as_deref(), Some("2010-05-01"));
+        assert_eq!(published_date("0101-01-01 00:00:00+00:00"), None);
+        assert_eq!(published_date("unknown"), None);
+    }
+
+    #[test]
+    fn test_half_stars() {
+        assert_eq!(half_stars(0), None);
+        assert_eq!(half_stars(8), Some(8));
+        assert_eq!(half_stars(11), None);
+    }
+
+    #[tokio::test]
+    async fn test_read_book() {
+        let (library, root) = library().await;
+        assert_eq!(library.uuid().await.unwrap(), "5d7f3a2e-8c1b-4e6a-9f0d-2b4c6e8a0f13");
+        assert_eq!(library.book_ids().await.unwrap(), vec![1, 2]);
+
+        let columns = library.custom_columns().await.unwrap();
+        let mappings: Vec<_> = columns.iter().map(CustomColumn::mapping).collect();
+        assert_eq!(
+            mappings,
+            vec![Some(ColumnMapping::Tags), Some(ColumnMapping::Tags), Some(ColumnMapping::Series), None]
+        );
+
+        let book = library.read_book(1, &columns).await.unwrap();
+        assert_eq!(book.title, "The Fellowship of the Ring");
+        assert_eq!(book.authors, vec!["J. R. R. Tolkien"]);
+        assert_eq!(book.author_sort.as_deref(), Some("Tolkien, J. R. R."));
+        assert_eq!(book.series, Some(("The Lord of the Rings".to_string(), 1.0)));
+        assert_eq!(
+            book.tags,
+            vec!["Classics", "Fantasy", "High fantasy", "Read status: Finished"]
+        );
+        assert_eq!(book.identifiers.get("isbn").map(String::as_str), Some("9780261103573"));
+        assert_eq!(book.identifiers.get("goodreads").map(String::as_str), Some("34"));
+        assert_eq!(book.publisher.as_deref(), Some("Allen & Unwin"));
+        assert_eq!(book.published_date.as_deref(), Some("1954-07-29"));
+        assert_eq!(book.description.as_deref(), Some("<p>The first volume.</p>"));
+        assert_eq!(book.rating, Some(10));
+        assert_eq!(book.language.as_deref(), Some("eng"));
+        assert_eq!(
+            library.epub_path(&book),
+            Some(root.join("J. R. R. Tolkien/The Fellowship of the Ring (1)/The Fellowship of the Ring - J. R. R. Tolkien.epub"))
+        );
+        assert_eq!(
+            library.cover_path(&book),
+            Some(root.join("J. R. R. Tolkien/The Fellowship of the Ring (1)/cover.jpg"))
+        );
+
+        // The custom series column fills in for a missing series
+        let book = library.read_book(2, &columns).await.unwrap();
+        assert_eq!(book.series, Some(("Middle-earth".to_string(), 2.5)));
+        assert_eq!(book.published_date, None);
+        assert_eq!(book.rating, None);
+        assert_eq!(library.epub_path(&book), None);
+        assert_eq!(library.cover_path(&book), None);
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
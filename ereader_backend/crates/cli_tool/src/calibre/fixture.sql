@@ -0,0 +1,94 @@
+-- Subset of a Calibre metadata.db with two books
+
+CREATE TABLE library_id (id INTEGER PRIMARY KEY, uuid TEXT NOT NULL);
+CREATE TABLE books (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    title TEXT NOT NULL DEFAULT 'Unknown',
+    sort TEXT,
+    timestamp TIMESTAMP,
+    pubdate TIMESTAMP,
+    series_index REAL NOT NULL DEFAULT 1.0,
+    author_sort TEXT,
+    isbn TEXT DEFAULT '',
+    lccn TEXT DEFAULT '',
+    path TEXT NOT NULL DEFAULT '',
+    flags INTEGER NOT NULL DEFAULT 1,
+    uuid TEXT,
+    has_cover BOOL DEFAULT 0,
+    last_modified TIMESTAMP
+);
+CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT, link TEXT NOT NULL DEFAULT '');
+CREATE TABLE books_authors_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, author INTEGER NOT NULL);
+CREATE TABLE tags (id INTEGER PRIMARY KEY, name TEXT NOT NULL);
+CREATE TABLE books_tags_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, tag INTEGER NOT NULL);
+CREATE TABLE series (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT);
+CREATE TABLE books_series_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, series INTEGER NOT NULL);
+CREATE TABLE publishers (id INTEGER PRIMARY KEY, name TEXT NOT NULL, sort TEXT);
+CREATE TABLE books_publishers_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, publisher INTEGER NOT NULL);
+CREATE TABLE languages (id INTEGER PRIMARY KEY, lang_code TEXT NOT NULL);
+CREATE TABLE books_languages_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, lang_code INTEGER NOT NULL, item_order INTEGER NOT NULL DEFAULT 0);
+CREATE TABLE ratings (id INTEGER PRIMARY KEY, rating INTEGER CHECK(rating > -1 AND rating < 11));
+CREATE TABLE books_ratings_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, rating INTEGER NOT NULL);
+CREATE TABLE comments (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, text TEXT NOT NULL);
+CREATE TABLE identifiers (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, type TEXT NOT NULL DEFAULT 'isbn', val TEXT NOT NULL);
+CREATE TABLE data (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, format TEXT NOT NULL, uncompressed_size INTEGER NOT NULL, name TEXT NOT NULL);
+CREATE TABLE custom_columns (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    label TEXT NOT NULL,
+    name TEXT NOT NULL,
+    datatype TEXT NOT NULL,
+    mark_for_delete BOOL DEFAULT 0 NOT NULL,
+    editable BOOL DEFAULT 1 NOT NULL,
+    display TEXT DEFAULT '{}' NOT NULL,
+    is_multiple BOOL DEFAULT 0 NOT NULL,
+    normalized BOOL NOT NULL
+);
+CREATE TABLE custom_column_1 (id INTEGER PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE books_custom_column_1_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, value INTEGER NOT NULL);
+CREATE TABLE custom_column_2 (id INTEGER PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE books_custom_column_2_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, value INTEGER NOT NULL);
+CREATE TABLE custom_column_3 (id INTEGER PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE books_custom_column_3_link (id INTEGER PRIMARY KEY, book INTEGER NOT NULL, value INTEGER NOT NULL, extra REAL);
+CREATE TABLE custom_column_4 (id INTEGER PRIMARY KEY, book INTEGER, value INTEGER);
+CREATE TABLE custom_column_5 (id INTEGER PRIMARY KEY, value TEXT NOT NULL);
+
+INSERT INTO library_id (uuid) VALUES ('5d7f3a2e-8c1b-4e6a-9f0d-2b4c6e8a0f13');
+
+INSERT INTO books (id, title, pubdate, series_index, author_sort, path, has_cover) VALUES
+    (1, 'The Fellowship of the Ring', '1954-07-29 00:00:00+00:00', 1.0, 'Tolkien, J. R. R.',
+     'J. R. R. Tolkien/The Fellowship of the Ring (1)', 1),
+    (2, 'The Hobbit', '0101-01-01 00:00:00+00:00', 1.0, 'Tolkien, J. R. R.',
+     'J. R. R. Tolkien/The Hobbit (2)', 0);
+INSERT INTO authors (id, name, sort) VALUES (1, 'J. R. R. Tolkien', 'Tolkien, J. R. R.');
+INSERT INTO books_authors_link (book, author) VALUES (1, 1), (2, 1);
+INSERT INTO tags (id, name) VALUES (1, 'Fantasy'), (2, 'Classics');
+INSERT INTO books_tags_link (book, tag) VALUES (1, 1), (1, 2), (2, 1);
+INSERT INTO series (id, name) VALUES (1, 'The Lord of the Rings');
+INSERT INTO books_series_link (book, series) VALUES (1, 1);
+INSERT INTO publishers (id, name) VALUES (1, 'Allen & Unwin');
+INSERT INTO books_publishers_link (book, publisher) VALUES (1, 1);
+INSERT INTO languages (id, lang_code) VALUES (1, 'eng');
+INSERT INTO books_languages_link (book, lang_code) VALUES (1, 1);
+INSERT INTO ratings (id, rating) VALUES (1, 10), (2, 0);
+INSERT INTO books_ratings_link (book, rating) VALUES (1, 1), (2, 2);
+INSERT INTO comments (book, text) VALUES (1, '<p>The first volume.</p>');
+INSERT INTO identifiers (book, type, val) VALUES (1, 'isbn', '9780261103573'), (1, 'goodreads', '34');
+INSERT INTO data (book, format, uncompressed_size, name) VALUES
+    (1, 'EPUB', 1024, 'The Fellowship of the Ring - J. R. R. Tolkien'),
+    (2, 'MOBI', 1024, 'The Hobbit - J. R. R. Tolkien');
+
+-- Genre (tags), read status (enumeration), universe (series), pages (integer) and a
+-- deleted column
+INSERT INTO custom_columns (id, label, name, datatype, is_multiple, normalized, mark_for_delete) VALUES
+    (1, 'genre', 'Genre', 'text', 1, 1, 0),
+    (2, 'read_status', 'Read status', 'enumeration', 0, 1, 0),
+    (3, 'universe', 'Universe', 'series', 0, 1, 0),
+    (4, 'pages', 'Pages', 'int', 0, 0, 0),
+    (5, 'old', 'Old', 'text', 1, 1, 1);
+INSERT INTO custom_column_1 (id, value) VALUES (1, 'High fantasy'), (2, 'Fantasy');
+INSERT INTO books_custom_column_1_link (book, value) VALUES (1, 1);
+INSERT INTO custom_column_2 (id, value) VALUES (1, 'Finished');
+INSERT INTO books_custom_column_2_link (book, value) VALUES (1, 1);
+INSERT INTO custom_column_3 (id, value) VALUES (1, 'Middle-earth');
+INSERT INTO books_custom_column_3_link (book, value, extra) VALUES (1, 1, 1.0), (2, 1, 2.5);
+INSERT INTO custom_column_4 (book, value) VALUES (1, 423);
@@ -1,7 +1,10 @@
 //! E-reader CLI tool for administration.
 
+mod calibre;
+
 use clap::{Parser, Subcommand};
 use common::config::AppConfig;
+use std::path::PathBuf;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[derive(Parser)]
@@ -24,6 +27,11 @@ enum Commands {
         #[command(subcommand)]
         action: BookCommands,
     },
+    /// Import books from another library
+    Import {
+        #[command(subcommand)]
+        source: ImportCommands,
+    },
     /// Database migration commands
     Migrate {
         #[command(subcommand)]
@@ -72,6 +80,17 @@ enum BookCommands {
     },
 }
 
+#[derive(Subcommand)]
+enum ImportCommands {
+    /// Import a Calibre library, resuming after books already imported
+    Calibre {
+        #[arg(help = "Path to the Calibre library folder (containing metadata.db)")]
+        library_dir: PathBuf,
+        #[arg(help = "User ID")]
+        user_id: String,
+    },
+}
+
 #[derive(Subcommand)]
 enum MigrateCommands {
     /// Run pending migrations
@@ -97,6 +116,7 @@ async fn main() -> anyhow::Result<()> {
     match cli.command {
         Commands::User { action } => handle_user_command(action, &config).await,
         Commands::Book { action } => handle_book_command(action, &config).await,
+        Commands::Import { source } => handle_import_command(source, &config).await,
         Commands::Migrate { action } => handle_migrate_command(action, &config).await,
         Commands::Health => handle_health(&config).await,
     }
@@ -203,9 +223,11 @@ async fn handle_book_command(action: BookCommands, config: &AppConfig) -> anyhow
             // Create book with file info included
             let create_book = db_layer::models::CreateBook::new(&user_id, metadata.title.unwrap_or_else(|| "Unknown".to_string()))
                 .with_authors(metadata.authors)
-                .with_file(format, hash.as_str(), data.len() as i64, &storage_path, file_name);
+                .with_file(format, hash.as_str(), data.len() as i64, &storage_path, file_name)
+                .with_partial_md5(common::PartialMd5::from_bytes(&data).as_str());
 
             let book = db_layer::queries::BookQueries::create(&pool, &create_book).await?;
+            queue_text_index(&pool, book.id).await?;
 
             println!("Book imported successfully!");
             println!("  ID: {}", book.id);
@@ -225,6 +247,25 @@ async fn handle_book_command(action: BookCommands, config: &AppConfig) -> anyhow
     Ok(())
 }
 
+async fn handle_import_command(source: ImportCommands, config: &AppConfig) -> anyhow::Result<()> {
+    match source {
+        ImportCommands::Calibre { library_dir, user_id } => {
+            calibre::import_library(&library_dir, &user_id, config).await
+        }
+    }
+}
+
+/// Queue indexing a new book's text for full-text search, which the worker daemon does
+async fn queue_text_index(pool: &db_layer::DbPool, book_id: uuid::Uuid) -> anyhow::Result<()> {
+    let task = db_layer::models::CreateTask::new(
+        db_layer::models::task_types::INDEX_BOOK_TEXT,
+        serde_json::json!({ "book_id": book_id }),
+    );
+    db_layer::queries::TaskQueries::create(pool, &task).await?;
+
+    Ok(())
+}
+
 async fn handle_migrate_command(action: MigrateCommands, config: &AppConfig) -> anyhow::Result<()> {
     let pool = db_layer::create_pool(&config.database).await?;
 
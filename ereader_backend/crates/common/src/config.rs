@@ -10,6 +10,8 @@ pub struct AppConfig {
     pub storage: StorageConfig,
     pub clerk: ClerkConfig,
     pub worker: WorkerConfig,
+    #[serde(default)]
+    pub kobo: KoboConfig,
 }
 
 /// HTTP server configuration
@@ -147,6 +149,9 @@ pub struct WorkerConfig {
     pub poll_interval_secs: u32,
     #[serde(default = "default_task_timeout_secs")]
     pub task_timeout_secs: u32,
+    /// How long sync tombstones for deleted books and collections are kept
+    #[serde(default = "default_tombstone_retention_days")]
+    pub tombstone_retention_days: u32,
 }
 
 fn default_max_concurrent_tasks() -> u32 {
@@ -161,12 +166,51 @@ fn default_task_timeout_secs() -> u32 {
     300
 }
 
+fn default_tombstone_retention_days() -> u32 {
+    90
+}
+
 impl Default for WorkerConfig {
     fn default() -> Self {
         Self {
             max_concurrent_tasks: default_max_concurrent_tasks(),
             poll_interval_secs: default_poll_interval_secs(),
             task_timeout_secs: default_task_timeout_secs(),
+            tombstone_retention_days: default_tombstone_retention_days(),
+        }
+    }
+}
+
+/// Kobo store sync emulation
+#[derive(Debug, Clone, Deserialize)]
+pub struct KoboConfig {
+    /// Serve the `/kobo/{token}` routes Kobo devices sync with
+    #[serde(default)]
+    pub enabled: bool,
+    /// Deliver EPUBs converted to KEPUB, which Kobo devices render with their own engine
+    #[serde(default = "default_kepub")]
+    pub kepub: bool,
+    /// Public base URL of the server, such as `https://books.example.com`, that download,
+    /// cover and sync links handed to devices are built on
+    #[serde(default)]
+    pub public_url: Option<String>,
+    /// Build device links from `X-Forwarded-Host` and `X-Forwarded-Proto` when no public
+    /// URL is set; only enable behind a proxy that overwrites those headers
+    #[serde(default)]
+    pub trust_proxy_headers: bool,
+}
+
+fn default_kepub() -> bool {
+    true
+}
+
+impl Default for KoboConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kepub: default_kepub(),
+            public_url: None,
+            trust_proxy_headers: false,
         }
     }
 }
@@ -242,5 +286,13 @@ mod tests {
         assert_eq!(config.max_concurrent_tasks, 4);
         assert_eq!(config.poll_interval_secs, 5);
         assert_eq!(config.task_timeout_secs, 300);
+        assert_eq!(config.tombstone_retention_days, 90);
+    }
+
+    #[test]
+    fn test_kobo_config_defaults() {
+        let config = KoboConfig::default();
+        assert!(!config.enabled);
+        assert!(config.kepub);
     }
 }
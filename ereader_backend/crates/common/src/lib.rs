@@ -14,6 +14,7 @@ pub mod types;
 pub use config::AppConfig;
 pub use error::{Error, Result};
 pub use types::{
-    AnnotationId, AnnotationType, BookFormat, BookId, CollectionId, ContentHash, DeviceId,
-    Paginated, Pagination, ReadingLocation, UserId,
+    AnnotationAnchorStatus, AnnotationId, AnnotationType, BookFormat, BookId, CollectionId,
+    Contributor, ContentHash, CursorPaginated, DeviceId, HybridTimestamp, Paginated, Pagination, PartialMd5, ReadingLocation,
+    ReadingPositionPolicy, TextQuoteSelector, UserId,
 };
@@ -1,6 +1,8 @@
 //! Core types shared across all crates.
 
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use md5::Md5;
 use sha2::{Digest, Sha256};
 use uuid::Uuid;
 
@@ -184,6 +186,43 @@ impl std::fmt::Display for ContentHash {
     }
 }
 
+/// KOReader's partial MD5 document fingerprint (hex string)
+///
+/// Only 1 KiB samples at offsets 0 and 1024 * 4^i (i = 0..=10) are hashed, so large files
+/// are fingerprinted quickly. KOReader's progress sync identifies documents by it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(transparent)]
+pub struct PartialMd5(pub String);
+
+impl PartialMd5 {
+    const SAMPLE_SIZE: usize = 1024;
+
+    /// Fingerprint a document's raw bytes
+    pub fn from_bytes(data: &[u8]) -> Self {
+        let mut hasher = Md5::new();
+        let offsets = std::iter::once(0).chain((0..=10).map(|i| Self::SAMPLE_SIZE << (2 * i)));
+        for offset in offsets {
+            if offset >= data.len() {
+                break;
+            }
+            let end = (offset + Self::SAMPLE_SIZE).min(data.len());
+            hasher.update(&data[offset..end]);
+        }
+        Self(hex::encode(hasher.finalize()))
+    }
+
+    /// Get the hex string representation
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for PartialMd5 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// Reading position - format-agnostic location
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReadingLocation {
@@ -193,6 +232,10 @@ pub struct ReadingLocation {
     pub progress: f32,
     /// Optional chapter/section info
     pub chapter: Option<String>,
+    /// Position as KOReader reported it (an XPointer or page number), when it couldn't be
+    /// translated to a locator; the locator is then empty and progress is all there is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kosync: Option<String>,
 }
 
 impl ReadingLocation {
@@ -201,6 +244,7 @@ impl ReadingLocation {
             locator: locator.into(),
             progress: progress.clamp(0.0, 1.0),
             chapter: None,
+            kosync: None,
         }
     }
 
@@ -210,6 +254,69 @@ impl ReadingLocation {
     }
 }
 
+/// Hybrid logical clock timestamp for ordering sync writes across devices
+///
+/// Timestamps compare by physical time, then logical counter, then device ID,
+/// so writes from different devices never compare equal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct HybridTimestamp {
+    /// Wall-clock component in milliseconds since the Unix epoch
+    pub physical: i64,
+    /// Counter distinguishing events within the same millisecond
+    pub logical: u32,
+    /// Device that issued the timestamp
+    pub device_id: Uuid,
+}
+
+impl HybridTimestamp {
+    /// Largest logical counter the database stores, since its column is a signed `INT`
+    pub const MAX_LOGICAL: u32 = i32::MAX as u32;
+
+    pub fn new(physical: i64, logical: u32, device_id: Uuid) -> Self {
+        Self {
+            physical,
+            logical,
+            device_id,
+        }
+    }
+
+    /// Create a timestamp from a wall-clock time with a zero logical counter
+    pub fn from_datetime(time: DateTime<Utc>, device_id: Uuid) -> Self {
+        Self::new(time.timestamp_millis(), 0, device_id)
+    }
+
+    /// Create a timestamp for the current time
+    pub fn now(device_id: Uuid) -> Self {
+        Self::from_datetime(Utc::now(), device_id)
+    }
+
+    /// Next timestamp for a write that happens after this one was observed
+    pub fn successor(&self, now: DateTime<Utc>, device_id: Uuid) -> Self {
+        let now = now.timestamp_millis();
+        if now > self.physical {
+            Self::new(now, 0, device_id)
+        } else {
+            Self::new(self.physical, self.logical.saturating_add(1).min(Self::MAX_LOGICAL), device_id)
+        }
+    }
+
+    /// Get the physical component as a date
+    pub fn physical_time(&self) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(self.physical).unwrap_or_default()
+    }
+
+    /// Check whether the physical component is further ahead of `now` than allowed
+    pub fn is_ahead_of(&self, now: DateTime<Utc>, max_drift: chrono::Duration) -> bool {
+        self.physical > now.timestamp_millis() + max_drift.num_milliseconds()
+    }
+}
+
+impl std::fmt::Display for HybridTimestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.physical, self.logical, self.device_id)
+    }
+}
+
 /// Supported ebook formats
 ///
 /// Currently only EPUB is supported. To add new formats in the future:
@@ -276,6 +383,133 @@ impl std::fmt::Display for AnnotationType {
     }
 }
 
+/// Whether an annotation's locators still point at its text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "annotation_anchor_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationAnchorStatus {
+    /// Locators were set by a client against the current file
+    #[default]
+    Anchored,
+    /// Locators were moved to match a replaced file
+    Reanchored,
+    /// The annotated text could not be found after the file was replaced
+    Orphaned,
+}
+
+impl std::fmt::Display for AnnotationAnchorStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Anchored => write!(f, "anchored"),
+            Self::Reanchored => write!(f, "reanchored"),
+            Self::Orphaned => write!(f, "orphaned"),
+        }
+    }
+}
+
+/// Person credited for a book in a given role
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Contributor {
+    pub name: String,
+    /// MARC relator code of the role, such as `trl` for translator; see [`relator`]
+    pub role: String,
+}
+
+impl Contributor {
+    pub fn new(name: impl Into<String>, role: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            role: role.into(),
+        }
+    }
+}
+
+/// MARC relator codes of contributor roles
+///
+/// See <https://id.loc.gov/vocabulary/relators.html> for the full list.
+pub mod relator {
+    pub const AUTHOR: &str = "aut";
+    pub const EDITOR: &str = "edt";
+    pub const TRANSLATOR: &str = "trl";
+    pub const ILLUSTRATOR: &str = "ill";
+    pub const NARRATOR: &str = "nrt";
+    /// Contributor whose role isn't given
+    pub const CONTRIBUTOR: &str = "ctb";
+
+    /// Relator code as stored, lowercase, if `code` looks like one
+    pub fn normalize(code: &str) -> Option<String> {
+        let code = code.trim();
+        (code.len() == 3 && code.chars().all(|c| c.is_ascii_alphabetic()))
+            .then(|| code.to_ascii_lowercase())
+    }
+
+    /// Name of a common role, as used by OPDS 2 for contributor lists
+    pub fn term(code: &str) -> Option<&'static str> {
+        match code {
+            AUTHOR => Some("author"),
+            EDITOR => Some("editor"),
+            TRANSLATOR => Some("translator"),
+            ILLUSTRATOR => Some("illustrator"),
+            NARRATOR => Some("narrator"),
+            CONTRIBUTOR => Some("contributor"),
+            _ => None,
+        }
+    }
+}
+
+/// Text-quote selector identifying annotated text independently of its location
+///
+/// Follows the W3C Web Annotation `TextQuoteSelector`: the exact text plus a little
+/// surrounding context to tell repeated passages apart.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextQuoteSelector {
+    pub exact: String,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+}
+
+impl TextQuoteSelector {
+    pub fn new(exact: impl Into<String>) -> Self {
+        Self {
+            exact: exact.into(),
+            prefix: String::new(),
+            suffix: String::new(),
+        }
+    }
+
+    pub fn with_context(mut self, prefix: impl Into<String>, suffix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self.suffix = suffix.into();
+        self
+    }
+}
+
+/// How the canonical reading position is chosen when devices disagree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "reading_position_policy", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ReadingPositionPolicy {
+    /// The most recent write wins
+    #[default]
+    LatestWrite,
+    /// The position furthest into the book wins
+    FurthestProgress,
+    /// Competing positions are returned to the client to choose from
+    AskUser,
+}
+
+impl std::fmt::Display for ReadingPositionPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::LatestWrite => write!(f, "latest_write"),
+            Self::FurthestProgress => write!(f, "furthest_progress"),
+            Self::AskUser => write!(f, "ask_user"),
+        }
+    }
+}
+
 /// Pagination parameters
 #[derive(Debug, Clone, Deserialize)]
 pub struct Pagination {
@@ -332,10 +566,35 @@ impl<T> Paginated<T> {
     }
 }
 
+/// Page of a list paginated by opaque cursors rather than offsets
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CursorPaginated<T> {
+    pub items: Vec<T>,
+    /// Cursor of the next page, or `None` on the last page
+    pub next_cursor: Option<String>,
+    /// Number of items in the whole list, if it was counted
+    pub total: Option<i64>,
+}
+
+impl<T> CursorPaginated<T> {
+    pub fn has_more(&self) -> bool {
+        self.next_cursor.is_some()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_relator_normalize() {
+        assert_eq!(relator::normalize(" TRL ").as_deref(), Some(relator::TRANSLATOR));
+        assert_eq!(relator::normalize("translator"), None);
+        assert_eq!(relator::normalize("a1b"), None);
+        assert_eq!(relator::term("nrt"), Some("narrator"));
+        assert_eq!(relator::term("pbl"), None);
+    }
+
     #[test]
     fn test_content_hash() {
         let data = b"hello world";
@@ -344,6 +603,24 @@ mod tests {
         assert_eq!(hash.prefix(4), &hash.0[..4]);
     }
 
+    #[test]
+    fn test_partial_md5_samples() {
+        // Small files are hashed whole
+        assert_eq!(
+            PartialMd5::from_bytes(b"hello").as_str(),
+            "5d41402abc4b2a76b9719d911017c592"
+        );
+
+        // Bytes between the samples at 2 KiB and 4 KiB aren't part of the fingerprint
+        let data = vec![7u8; 5000];
+        let mut between = data.clone();
+        between[3000] = 0;
+        let mut sampled = data.clone();
+        sampled[4500] = 0;
+        assert_eq!(PartialMd5::from_bytes(&data), PartialMd5::from_bytes(&between));
+        assert_ne!(PartialMd5::from_bytes(&data), PartialMd5::from_bytes(&sampled));
+    }
+
     #[test]
     fn test_book_format_from_extension() {
         assert_eq!(BookFormat::from_extension("epub"), Some(BookFormat::Epub));
@@ -372,6 +649,43 @@ mod tests {
         assert_eq!(loc.progress, 0.0);
     }
 
+    #[test]
+    fn test_hybrid_timestamp_ordering() {
+        let a = Uuid::from_u128(1);
+        let b = Uuid::from_u128(2);
+        assert!(HybridTimestamp::new(10, 0, b) < HybridTimestamp::new(11, 0, a));
+        assert!(HybridTimestamp::new(10, 0, b) < HybridTimestamp::new(10, 1, a));
+        assert!(HybridTimestamp::new(10, 1, a) < HybridTimestamp::new(10, 1, b));
+    }
+
+    #[test]
+    fn test_hybrid_timestamp_successor() {
+        let device = Uuid::from_u128(1);
+        let last = HybridTimestamp::new(5_000, 3, Uuid::from_u128(2));
+
+        // Clock behind the last observed write: bump the logical counter
+        let behind = DateTime::from_timestamp_millis(4_000).unwrap();
+        let next = last.successor(behind, device);
+        assert_eq!(next, HybridTimestamp::new(5_000, 4, device));
+        assert!(next > last);
+
+        // Clock ahead: adopt the physical time and reset the counter
+        let ahead = DateTime::from_timestamp_millis(6_000).unwrap();
+        assert_eq!(last.successor(ahead, device), HybridTimestamp::new(6_000, 0, device));
+
+        // The counter stops at what the database can store
+        let full = HybridTimestamp::new(5_000, HybridTimestamp::MAX_LOGICAL, device);
+        assert_eq!(full.successor(behind, device).logical, HybridTimestamp::MAX_LOGICAL);
+    }
+
+    #[test]
+    fn test_hybrid_timestamp_drift() {
+        let now = DateTime::from_timestamp_millis(100_000).unwrap();
+        let max_drift = chrono::Duration::seconds(60);
+        assert!(!HybridTimestamp::new(160_000, 0, Uuid::nil()).is_ahead_of(now, max_drift));
+        assert!(HybridTimestamp::new(160_001, 0, Uuid::nil()).is_ahead_of(now, max_drift));
+    }
+
     #[test]
     fn test_pagination_defaults() {
         let p = Pagination::default();
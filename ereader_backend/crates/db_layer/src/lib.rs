@@ -12,5 +12,5 @@ pub mod queries;
 
 // Re-export commonly used items
 pub use models::*;
-pub use pool::{create_pool, create_pool_from_url, health_check, run_migrations, DbPool};
+pub use pool::{create_pool, create_pool_from_url, health_check, run_migrations, DbConn, DbPool};
 pub use queries::*;
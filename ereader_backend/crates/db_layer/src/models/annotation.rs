@@ -1,7 +1,7 @@
 //! Annotation model.
 
 use chrono::{DateTime, Utc};
-use common::AnnotationType;
+use common::{AnnotationAnchorStatus, AnnotationType, HybridTimestamp, TextQuoteSelector};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -16,6 +16,16 @@ pub struct Annotation {
     pub location_end: Option<String>,
     pub content: Option<String>,
     pub style: Option<String>,
+    /// Text-quote selector for the annotated text, if known
+    pub quote_exact: Option<String>,
+    pub quote_prefix: Option<String>,
+    pub quote_suffix: Option<String>,
+    pub anchor_status: AnnotationAnchorStatus,
+    pub hlc_physical: i64,
+    pub hlc_logical: i32,
+    pub hlc_device_id: Uuid,
+    pub revision: i32,
+    pub change_seq: i64,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
@@ -26,6 +36,32 @@ impl Annotation {
     pub fn is_deleted(&self) -> bool {
         self.deleted_at.is_some()
     }
+
+    /// Get the hybrid logical clock of the write that produced this annotation
+    pub fn hlc(&self) -> HybridTimestamp {
+        HybridTimestamp::new(self.hlc_physical, self.hlc_logical as u32, self.hlc_device_id)
+    }
+
+    /// Get the text-quote selector for the annotated text
+    pub fn quote(&self) -> Option<TextQuoteSelector> {
+        self.quote_exact.as_ref().map(|exact| {
+            TextQuoteSelector::new(exact.clone()).with_context(
+                self.quote_prefix.clone().unwrap_or_default(),
+                self.quote_suffix.clone().unwrap_or_default(),
+            )
+        })
+    }
+}
+
+/// Snapshot of an annotation's mergeable fields at a given revision
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AnnotationRevision {
+    pub annotation_id: Uuid,
+    pub revision: i32,
+    pub location_end: Option<String>,
+    pub content: Option<String>,
+    pub style: Option<String>,
+    pub created_at: DateTime<Utc>,
 }
 
 /// Data for creating or updating an annotation
@@ -39,6 +75,10 @@ pub struct UpsertAnnotation {
     pub location_end: Option<String>,
     pub content: Option<String>,
     pub style: Option<String>,
+    /// Selector captured by the client; kept from the previous write if omitted and the
+    /// location is unchanged
+    pub quote: Option<TextQuoteSelector>,
+    pub hlc: HybridTimestamp,
 }
 
 impl UpsertAnnotation {
@@ -57,6 +97,8 @@ impl UpsertAnnotation {
             location_end: Some(location_end.into()),
             content: None,
             style: Some("yellow".to_string()),
+            quote: None,
+            hlc: HybridTimestamp::now(Uuid::nil()),
         }
     }
 
@@ -75,6 +117,8 @@ impl UpsertAnnotation {
             location_end: None,
             content: Some(content.into()),
             style: None,
+            quote: None,
+            hlc: HybridTimestamp::now(Uuid::nil()),
         }
     }
 
@@ -88,6 +132,8 @@ impl UpsertAnnotation {
             location_end: None,
             content: None,
             style: None,
+            quote: None,
+            hlc: HybridTimestamp::now(Uuid::nil()),
         }
     }
 
@@ -100,4 +146,26 @@ impl UpsertAnnotation {
         self.content = Some(content.into());
         self
     }
+
+    pub fn with_quote(mut self, quote: TextQuoteSelector) -> Self {
+        self.quote = Some(quote);
+        self
+    }
+
+    pub fn with_hlc(mut self, hlc: HybridTimestamp) -> Self {
+        self.hlc = hlc;
+        self
+    }
+}
+
+/// New locators for an annotation after its book's file was replaced
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UpdateAnnotationAnchor {
+    pub location_start: String,
+    pub location_end: Option<String>,
+    pub quote: Option<TextQuoteSelector>,
+    pub anchor_status: AnnotationAnchorStatus,
+    pub hlc: HybridTimestamp,
+    /// Revision the new locators were computed from
+    pub revision: i32,
 }
@@ -0,0 +1,59 @@
+//! App token model.
+
+use chrono::{DateTime, Utc};
+use common::ContentHash;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Prefix that tells app tokens apart from Clerk session tokens
+pub const APP_TOKEN_PREFIX: &str = "ert_";
+
+/// App token record from the database (without its hash)
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AppToken {
+    pub id: Uuid,
+    pub user_id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+/// Data for creating a new app token
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateAppToken {
+    pub id: Uuid,
+    pub user_id: String,
+    pub name: String,
+    pub token_hash: String,
+}
+
+impl CreateAppToken {
+    /// Generate a new random token, returning the record to store and the token itself
+    pub fn generate(user_id: impl Into<String>, name: impl Into<String>) -> (Self, String) {
+        let token = format!(
+            "{}{}{}",
+            APP_TOKEN_PREFIX,
+            Uuid::new_v4().simple(),
+            Uuid::new_v4().simple()
+        );
+
+        let data = Self {
+            id: Uuid::now_v7(),
+            user_id: user_id.into(),
+            name: name.into(),
+            token_hash: hash_app_token(&token),
+        };
+
+        (data, token)
+    }
+}
+
+/// Hash a token the way it is stored
+pub fn hash_app_token(token: &str) -> String {
+    ContentHash::from_bytes(token.as_bytes()).0
+}
+
+/// Check whether a bearer token is an app token rather than a Clerk session token
+pub fn is_app_token(token: &str) -> bool {
+    token.starts_with(APP_TOKEN_PREFIX)
+}
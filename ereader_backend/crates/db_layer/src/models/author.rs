@@ -0,0 +1,38 @@
+//! Author model.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Author record from the database
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Author {
+    pub id: Uuid,
+    pub user_id: String,
+    pub name: String,
+    /// Name the author files under, such as "Le Guin, Ursula K."; derived from the name
+    /// unless set
+    pub sort_name: String,
+    /// Other names books list the author by
+    pub aliases: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Author with the number of books by them
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct AuthorWithCount {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub author: Author,
+    pub book_count: i64,
+}
+
+/// Data for updating an author
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateAuthor {
+    /// New sort name; an empty one goes back to deriving it from the name
+    pub sort_name: Option<String>,
+    /// Replacement list of aliases
+    pub aliases: Option<Vec<String>>,
+}
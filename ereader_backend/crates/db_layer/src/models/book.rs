@@ -1,10 +1,16 @@
 //! Book, FileAsset, and Cover models.
 
 use chrono::{DateTime, Utc};
-use common::BookFormat;
+use common::types::relator;
+use common::{BookFormat, Contributor};
 use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
+/// Identifiers of a book keyed by scheme, such as `isbn` or `goodreads`
+pub type BookIdentifiers = BTreeMap<String, String>;
+
 /// Book record from the database
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Book {
@@ -12,14 +18,23 @@ pub struct Book {
     pub user_id: String,
     pub title: String,
     pub authors: Vec<String>,
+    /// People credited in roles other than author, such as translators
+    pub contributors: Json<Vec<Contributor>>,
     pub description: Option<String>,
     pub language: Option<String>,
     pub publisher: Option<String>,
     pub published_date: Option<String>,
     pub isbn: Option<String>,
+    /// Series the book belongs to, linked by its series name
+    pub series_id: Option<Uuid>,
     pub series_name: Option<String>,
     pub series_index: Option<f32>,
     pub tags: Vec<String>,
+    /// Authors as sorted, such as `Tolkien, J. R. R.`
+    pub author_sort: Option<String>,
+    /// Rating in half stars from 1 to 10
+    pub rating: Option<i16>,
+    pub identifiers: Json<BookIdentifiers>,
     // File fields (optional - book can exist without file)
     pub format: Option<BookFormat>,
     pub content_hash: Option<String>,
@@ -35,6 +50,30 @@ impl Book {
     pub fn has_file(&self) -> bool {
         self.storage_path.is_some()
     }
+
+    /// Names of everyone credited, authors included, by MARC relator code of their role
+    pub fn contributors_by_role(&self) -> BTreeMap<String, Vec<String>> {
+        let mut roles: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        if !self.authors.is_empty() {
+            roles.insert(relator::AUTHOR.to_string(), self.authors.clone());
+        }
+        for contributor in self.contributors.iter() {
+            let names = roles.entry(contributor.role.clone()).or_default();
+            if !names.contains(&contributor.name) {
+                names.push(contributor.name.clone());
+            }
+        }
+        roles
+    }
+}
+
+/// Book with its position in the owner's sync change feed
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BookChange {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub book: Book,
+    pub change_seq: i64,
 }
 
 /// Data for creating a new book
@@ -44,6 +83,8 @@ pub struct CreateBook {
     pub user_id: String,
     pub title: String,
     pub authors: Vec<String>,
+    /// People credited in roles other than author
+    pub contributors: Vec<Contributor>,
     pub description: Option<String>,
     pub language: Option<String>,
     pub publisher: Option<String>,
@@ -52,12 +93,17 @@ pub struct CreateBook {
     pub series_name: Option<String>,
     pub series_index: Option<f32>,
     pub tags: Vec<String>,
+    pub author_sort: Option<String>,
+    pub rating: Option<i16>,
+    pub identifiers: BookIdentifiers,
     // File fields
     pub format: Option<BookFormat>,
     pub content_hash: Option<String>,
     pub file_size: Option<i64>,
     pub storage_path: Option<String>,
     pub original_filename: Option<String>,
+    /// KOReader fingerprint of the file
+    pub partial_md5: Option<String>,
 }
 
 impl CreateBook {
@@ -67,6 +113,7 @@ impl CreateBook {
             user_id: user_id.into(),
             title: title.into(),
             authors: vec![],
+            contributors: vec![],
             description: None,
             language: None,
             publisher: None,
@@ -75,11 +122,15 @@ impl CreateBook {
             series_name: None,
             series_index: None,
             tags: vec![],
+            author_sort: None,
+            rating: None,
+            identifiers: BookIdentifiers::new(),
             format: None,
             content_hash: None,
             file_size: None,
             storage_path: None,
             original_filename: None,
+            partial_md5: None,
         }
     }
 
@@ -93,6 +144,12 @@ impl CreateBook {
         self
     }
 
+    /// Credit someone in a role other than author, given by its MARC relator code
+    pub fn with_contributor(mut self, name: impl Into<String>, role: impl Into<String>) -> Self {
+        self.contributors.push(Contributor::new(name, role));
+        self
+    }
+
     pub fn with_description(mut self, description: impl Into<String>) -> Self {
         self.description = Some(description.into());
         self
@@ -109,6 +166,21 @@ impl CreateBook {
         self
     }
 
+    pub fn with_author_sort(mut self, author_sort: impl Into<String>) -> Self {
+        self.author_sort = Some(author_sort.into());
+        self
+    }
+
+    pub fn with_rating(mut self, rating: i16) -> Self {
+        self.rating = Some(rating);
+        self
+    }
+
+    pub fn with_identifier(mut self, scheme: impl Into<String>, value: impl Into<String>) -> Self {
+        self.identifiers.insert(scheme.into(), value.into());
+        self
+    }
+
     pub fn with_file(
         mut self,
         format: BookFormat,
@@ -124,6 +196,11 @@ impl CreateBook {
         self.original_filename = Some(original_filename.into());
         self
     }
+
+    pub fn with_partial_md5(mut self, partial_md5: impl Into<String>) -> Self {
+        self.partial_md5 = Some(partial_md5.into());
+        self
+    }
 }
 
 /// Data for updating an existing book
@@ -131,6 +208,8 @@ impl CreateBook {
 pub struct UpdateBook {
     pub title: Option<String>,
     pub authors: Option<Vec<String>>,
+    /// Replacement list of people credited in roles other than author
+    pub contributors: Option<Vec<Contributor>>,
     pub description: Option<String>,
     pub language: Option<String>,
     pub publisher: Option<String>,
@@ -139,6 +218,35 @@ pub struct UpdateBook {
     pub series_name: Option<String>,
     pub series_index: Option<f32>,
     pub tags: Option<Vec<String>>,
+    pub author_sort: Option<String>,
+    pub rating: Option<i16>,
+    pub identifiers: Option<BookIdentifiers>,
+}
+
+/// Number of books in a search result sharing a value
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow)]
+pub struct FacetCount {
+    pub value: String,
+    pub count: i64,
+}
+
+/// Most common values among the books matching a search, by count
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookFacets {
+    pub tags: Vec<FacetCount>,
+    pub authors: Vec<FacetCount>,
+    pub languages: Vec<FacetCount>,
+    pub series: Vec<FacetCount>,
+}
+
+/// Title, author or series completing text typed into a search box
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BookCompletion {
+    /// `title`, `author` or `series`
+    pub kind: String,
+    pub value: String,
+    /// Word similarity of the typed text to the value, from 0 to 1
+    pub score: f32,
 }
 
 /// Cover record from the database
@@ -0,0 +1,133 @@
+//! Book text models for full-text search.
+
+use crate::models::Book;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Marks the start of a matched word in a raw snippet
+pub(crate) const MATCH_START: char = '\u{2}';
+
+/// Marks the end of a matched word in a raw snippet
+pub(crate) const MATCH_END: char = '\u{3}';
+
+/// Text search configurations for language subtags, both two- and three-letter
+///
+/// Configurations missing from the database fall back to `simple` when text is stored.
+const TEXT_SEARCH_CONFIGS: &[(&[&str], &str)] = &[
+    (&["ar", "ara"], "arabic"),
+    (&["hy", "arm", "hye"], "armenian"),
+    (&["eu", "baq", "eus"], "basque"),
+    (&["ca", "cat"], "catalan"),
+    (&["da", "dan"], "danish"),
+    (&["nl", "dut", "nld"], "dutch"),
+    (&["en", "eng"], "english"),
+    (&["fi", "fin"], "finnish"),
+    (&["fr", "fre", "fra"], "french"),
+    (&["de", "ger", "deu"], "german"),
+    (&["el", "gre", "ell"], "greek"),
+    (&["hi", "hin"], "hindi"),
+    (&["hu", "hun"], "hungarian"),
+    (&["id", "ind"], "indonesian"),
+    (&["ga", "gle"], "irish"),
+    (&["it", "ita"], "italian"),
+    (&["lt", "lit"], "lithuanian"),
+    (&["ne", "nep"], "nepali"),
+    (&["no", "nb", "nn", "nor", "nob", "nno"], "norwegian"),
+    (&["pt", "por"], "portuguese"),
+    (&["ro", "rum", "ron"], "romanian"),
+    (&["ru", "rus"], "russian"),
+    (&["sr", "srp"], "serbian"),
+    (&["es", "spa"], "spanish"),
+    (&["sv", "swe"], "swedish"),
+    (&["ta", "tam"], "tamil"),
+    (&["tr", "tur"], "turkish"),
+    (&["yi", "yid"], "yiddish"),
+];
+
+/// Text search configuration without stemming or stop words
+pub const SIMPLE_TEXT_SEARCH_CONFIG: &str = "simple";
+
+/// Text of one spine document of a book
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateBookText {
+    pub spine_index: i32,
+    pub title: Option<String>,
+    pub content: String,
+}
+
+/// Book matching a full-text search
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BookTextHit {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub book: Book,
+    /// Rank of the book's best-matching chapter
+    pub rank: f32,
+    pub matching_chapters: i64,
+}
+
+/// Passage of a chapter matching a full-text search
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BookTextSnippet {
+    pub book_id: Uuid,
+    pub spine_index: i32,
+    pub title: Option<String>,
+    pub rank: f32,
+    /// HTML-escaped text with matched words wrapped in `<mark>`
+    pub snippet: String,
+}
+
+/// Text search configuration for a book's language, such as `english` for `en-GB`
+pub fn text_search_config(language: Option<&str>) -> &'static str {
+    let Some(subtag) = language
+        .and_then(|l| l.split(['-', '_']).next())
+        .map(|s| s.trim().to_ascii_lowercase())
+    else {
+        return SIMPLE_TEXT_SEARCH_CONFIG;
+    };
+
+    TEXT_SEARCH_CONFIGS
+        .iter()
+        .find(|(subtags, _)| subtags.contains(&subtag.as_str()))
+        .map_or(SIMPLE_TEXT_SEARCH_CONFIG, |(_, config)| config)
+}
+
+/// Escape a raw snippet for HTML and turn its match markers into `<mark>` elements
+pub(crate) fn snippet_html(raw: &str) -> String {
+    let mut html = String::with_capacity(raw.len() + 32);
+    for c in raw.chars() {
+        match c {
+            MATCH_START => html.push_str("<mark>"),
+            MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_search_config() {
+        assert_eq!(text_search_config(Some("en")), "english");
+        assert_eq!(text_search_config(Some("en-GB")), "english");
+        assert_eq!(text_search_config(Some("FRA")), "french");
+        assert_eq!(text_search_config(Some("pt_BR")), "portuguese");
+        assert_eq!(text_search_config(Some("ja")), "simple");
+        assert_eq!(text_search_config(None), "simple");
+    }
+
+    #[test]
+    fn test_snippet_html() {
+        assert_eq!(
+            snippet_html("a <b> & \u{2}cat\u{3} sat"),
+            "a &lt;b&gt; &amp; <mark>cat</mark> sat"
+        );
+    }
+}
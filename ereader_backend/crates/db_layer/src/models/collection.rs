@@ -9,21 +9,52 @@ use uuid::Uuid;
 pub struct Collection {
     pub id: Uuid,
     pub user_id: String,
+    /// Collection this one is nested in, or `None` at the top level
+    pub parent_id: Option<Uuid>,
     pub name: String,
     pub description: Option<String>,
     pub collection_type: String,
+    /// Book search query selecting the books of a smart collection
+    pub query: Option<String>,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl Collection {
+    /// Check if membership is computed from a query rather than chosen by hand
+    pub fn is_smart(&self) -> bool {
+        self.query.is_some()
+    }
+}
+
+/// Collection with the number of books in it
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CollectionWithCount {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub collection: Collection,
+    pub book_count: i64,
+}
+
+/// Collection with its position in the owner's sync change feed
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CollectionChange {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub collection: Collection,
+    pub change_seq: i64,
+}
+
 /// Data for creating a new collection
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CreateCollection {
     pub id: Uuid,
     pub user_id: String,
+    pub parent_id: Option<Uuid>,
     pub name: String,
     pub description: Option<String>,
     pub collection_type: String,
+    pub query: Option<String>,
 }
 
 impl CreateCollection {
@@ -31,9 +62,20 @@ impl CreateCollection {
         Self {
             id: Uuid::now_v7(),
             user_id: user_id.into(),
+            parent_id: None,
             name: name.into(),
             description: None,
             collection_type: "shelf".to_string(),
+            query: None,
+        }
+    }
+
+    /// Smart collection containing the books matching a search query
+    pub fn smart(user_id: impl Into<String>, name: impl Into<String>, query: impl Into<String>) -> Self {
+        Self {
+            collection_type: CollectionType::Smart.as_str().to_string(),
+            query: Some(query.into()),
+            ..Self::new(user_id, name)
         }
     }
 
@@ -46,6 +88,11 @@ impl CreateCollection {
         self.collection_type = collection_type.into();
         self
     }
+
+    pub fn with_parent(mut self, parent_id: Uuid) -> Self {
+        self.parent_id = Some(parent_id);
+        self
+    }
 }
 
 /// Data for updating an existing collection
@@ -53,6 +100,8 @@ impl CreateCollection {
 pub struct UpdateCollection {
     pub name: Option<String>,
     pub description: Option<String>,
+    /// New query of a smart collection
+    pub query: Option<String>,
 }
 
 /// Collection book membership record
@@ -64,12 +113,23 @@ pub struct CollectionBook {
     pub sort_order: Option<i32>,
 }
 
+/// Collection membership with its position in the owner's sync change feed
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CollectionBookChange {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub membership: CollectionBook,
+    pub change_seq: i64,
+}
+
 /// Collection types
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CollectionType {
     Shelf,
     Tag,
     Series,
+    /// Books matching a stored search query
+    Smart,
 }
 
 impl CollectionType {
@@ -78,6 +138,7 @@ impl CollectionType {
             Self::Shelf => "shelf",
             Self::Tag => "tag",
             Self::Series => "series",
+            Self::Smart => "smart",
         }
     }
 }
@@ -0,0 +1,26 @@
+//! Sync idempotency key model.
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+/// How long a sync idempotency key is honoured
+pub const SYNC_IDEMPOTENCY_KEY_TTL_HOURS: i64 = 24;
+
+/// A client-chosen key for a sync request and the response it produced
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SyncIdempotencyKey {
+    pub user_id: String,
+    pub idempotency_key: String,
+    /// Hash of the request the key was first used with
+    pub request_hash: String,
+    /// Stored response; set before the sync transaction commits
+    pub response: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl SyncIdempotencyKey {
+    /// Keys created before this time have expired and may be reused
+    pub fn expiry_cutoff(now: DateTime<Utc>) -> DateTime<Utc> {
+        now - Duration::hours(SYNC_IDEMPOTENCY_KEY_TTL_HOURS)
+    }
+}
@@ -0,0 +1,19 @@
+//! KOReader sync account model.
+
+use chrono::{DateTime, Utc};
+use common::ContentHash;
+use serde::{Deserialize, Serialize};
+
+/// Credentials KOReader's progress sync plugin signs in with (without the key hash)
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct KosyncAccount {
+    pub user_id: String,
+    pub username: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Hash the key KOReader sends (the MD5 hex of the password) the way it is stored
+pub fn hash_kosync_key(key: &str) -> String {
+    ContentHash::from_bytes(key.to_lowercase().as_bytes()).0
+}
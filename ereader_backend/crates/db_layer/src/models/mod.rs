@@ -1,17 +1,37 @@
 //! Database models representing rows in database tables.
 
 pub mod annotation;
+pub mod app_token;
+pub mod author;
 pub mod book;
+pub mod book_text;
 pub mod collection;
 pub mod device;
+pub mod idempotency;
+pub mod kosync_account;
+pub mod reading_session;
 pub mod reading_state;
+pub mod series;
+pub mod settings;
+pub mod sync_conflict;
 pub mod task;
+pub mod tombstone;
 pub mod user;
 
 pub use annotation::*;
+pub use app_token::*;
+pub use author::*;
 pub use book::*;
+pub use book_text::*;
 pub use collection::*;
 pub use device::*;
+pub use idempotency::*;
+pub use kosync_account::*;
+pub use reading_session::*;
 pub use reading_state::*;
+pub use series::*;
+pub use settings::*;
+pub use sync_conflict::*;
 pub use task::*;
+pub use tombstone::*;
 pub use user::*;
@@ -0,0 +1,172 @@
+//! Reading session model.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Progress at or above which a book counts as finished
+pub const FINISHED_PROGRESS: f32 = 0.99;
+
+/// A stretch of reading recorded by a device
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ReadingSession {
+    pub id: Uuid,
+    pub user_id: String,
+    pub book_id: Uuid,
+    pub device_id: Option<Uuid>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub start_locator: String,
+    pub end_locator: String,
+    pub progress_delta: f32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Data for recording a reading session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateReadingSession {
+    pub id: Uuid,
+    pub book_id: Uuid,
+    pub device_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub start_locator: String,
+    pub end_locator: String,
+    pub progress_delta: f32,
+}
+
+/// Length of the buckets reading time is grouped into
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsPeriod {
+    #[default]
+    Day,
+    /// Weeks starting on Monday
+    Week,
+}
+
+impl StatsPeriod {
+    /// Field name for Postgres `date_trunc`
+    pub fn trunc_unit(&self) -> &'static str {
+        match self {
+            StatsPeriod::Day => "day",
+            StatsPeriod::Week => "week",
+        }
+    }
+}
+
+impl std::fmt::Display for StatsPeriod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.trunc_unit())
+    }
+}
+
+/// Reading time within one day or week
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct ReadingTimeBucket {
+    pub period_start: NaiveDate,
+    pub seconds: i64,
+    pub sessions: i64,
+}
+
+/// Reading totals for one book
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct BookReadingTotals {
+    pub book_id: Uuid,
+    pub seconds: i64,
+    pub sessions: i64,
+    /// Progress gained across sessions, ignoring sessions that went backwards
+    pub progress: f64,
+    pub last_read_at: DateTime<Utc>,
+}
+
+impl BookReadingTotals {
+    /// Average progress per hour of reading (1.0 is the whole book)
+    pub fn progress_per_hour(&self) -> Option<f64> {
+        (self.seconds > 0 && self.progress > 0.0)
+            .then(|| self.progress * 3600.0 / self.seconds as f64)
+    }
+
+    /// Estimated reading time left to finish from the given progress, at the average speed
+    pub fn estimated_remaining_secs(&self, current_progress: f32) -> Option<i64> {
+        let per_hour = self.progress_per_hour()?;
+        let remaining = (1.0 - f64::from(current_progress)).max(0.0);
+        Some((remaining / per_hour * 3600.0).round() as i64)
+    }
+}
+
+/// Consecutive days with at least one reading session
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReadingStreak {
+    /// Streak ending today, or yesterday if there's no reading yet today
+    pub current_days: u32,
+    pub longest_days: u32,
+    pub last_read_on: Option<NaiveDate>,
+}
+
+impl ReadingStreak {
+    /// Compute streaks from the distinct days read, in ascending order
+    pub fn from_days(days: &[NaiveDate], today: NaiveDate) -> Self {
+        let mut longest = 0;
+        let mut run = 0;
+        let mut previous: Option<NaiveDate> = None;
+
+        for &day in days {
+            run = match previous {
+                Some(prev) if prev.succ_opt() == Some(day) => run + 1,
+                Some(prev) if prev == day => run,
+                _ => 1,
+            };
+            longest = longest.max(run);
+            previous = Some(day);
+        }
+
+        let current = match previous {
+            Some(last) if last == today || last.succ_opt() == Some(today) => run,
+            _ => 0,
+        };
+
+        Self {
+            current_days: current,
+            longest_days: longest,
+            last_read_on: previous,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2025, 3, d).unwrap()
+    }
+
+    #[test]
+    fn test_streak_counts_consecutive_days() {
+        let days = [day(1), day(2), day(3), day(5), day(6)];
+
+        let streak = ReadingStreak::from_days(&days, day(7));
+        assert_eq!(streak.current_days, 2);
+        assert_eq!(streak.longest_days, 3);
+        assert_eq!(streak.last_read_on, Some(day(6)));
+
+        assert_eq!(ReadingStreak::from_days(&days, day(8)).current_days, 0);
+        assert_eq!(ReadingStreak::from_days(&[], day(8)), ReadingStreak::default());
+    }
+
+    #[test]
+    fn test_remaining_time_from_average_speed() {
+        let totals = BookReadingTotals {
+            book_id: Uuid::nil(),
+            seconds: 7200,
+            sessions: 3,
+            progress: 0.5,
+            last_read_at: Utc::now(),
+        };
+
+        assert_eq!(totals.progress_per_hour(), Some(0.25));
+        assert_eq!(totals.estimated_remaining_secs(0.5), Some(7200));
+        assert_eq!(totals.estimated_remaining_secs(1.0), Some(0));
+    }
+}
@@ -1,7 +1,7 @@
 //! Reading state model.
 
 use chrono::{DateTime, Utc};
-use common::ReadingLocation;
+use common::{HybridTimestamp, ReadingLocation};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -13,6 +13,10 @@ pub struct ReadingState {
     pub book_id: Uuid,
     pub device_id: Uuid,
     pub location: sqlx::types::Json<ReadingLocation>,
+    pub hlc_physical: i64,
+    pub hlc_logical: i32,
+    pub hlc_device_id: Uuid,
+    pub change_seq: i64,
     pub updated_at: DateTime<Utc>,
 }
 
@@ -21,6 +25,11 @@ impl ReadingState {
     pub fn location(&self) -> &ReadingLocation {
         &self.location.0
     }
+
+    /// Get the hybrid logical clock of the write that produced this state
+    pub fn hlc(&self) -> HybridTimestamp {
+        HybridTimestamp::new(self.hlc_physical, self.hlc_logical as u32, self.hlc_device_id)
+    }
 }
 
 /// Data for creating or updating a reading state
@@ -30,6 +39,7 @@ pub struct UpsertReadingState {
     pub book_id: Uuid,
     pub device_id: Uuid,
     pub location: ReadingLocation,
+    pub hlc: HybridTimestamp,
 }
 
 impl UpsertReadingState {
@@ -44,8 +54,34 @@ impl UpsertReadingState {
             book_id,
             device_id,
             location,
+            hlc: HybridTimestamp::now(device_id),
         }
     }
+
+    pub fn with_hlc(mut self, hlc: HybridTimestamp) -> Self {
+        self.hlc = hlc;
+        self
+    }
+}
+
+/// Last reading position reported by a single device
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct DeviceReadingState {
+    pub book_id: Uuid,
+    pub device_id: Uuid,
+    pub device_name: String,
+    pub location: sqlx::types::Json<ReadingLocation>,
+    pub hlc_physical: i64,
+    pub hlc_logical: i32,
+    pub hlc_device_id: Uuid,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl DeviceReadingState {
+    /// Get the hybrid logical clock of the write that produced this position
+    pub fn hlc(&self) -> HybridTimestamp {
+        HybridTimestamp::new(self.hlc_physical, self.hlc_logical as u32, self.hlc_device_id)
+    }
 }
 
 /// Reading state with book information for display
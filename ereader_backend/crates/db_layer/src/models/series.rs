@@ -0,0 +1,84 @@
+//! Series model.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use super::FINISHED_PROGRESS;
+
+/// Series record from the database
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Series {
+    pub id: Uuid,
+    pub user_id: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// Other names books list the series by
+    pub aliases: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Series with its number of books and how many of them the owner has read
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SeriesWithProgress {
+    #[sqlx(flatten)]
+    #[serde(flatten)]
+    pub series: Series,
+    pub book_count: i64,
+    /// Books read to at least [`FINISHED_PROGRESS`]
+    pub finished_count: i64,
+    /// Books started but not finished
+    pub in_progress_count: i64,
+}
+
+/// Data for updating a series
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpdateSeries {
+    /// New name, which the series' books take as their series name
+    pub name: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Book to read next among a series' books in reading order, given the owner's progress
+/// through each
+///
+/// Continues the last book in the order that was started if it isn't finished, or else
+/// the first unfinished book after it, wrapping around to books skipped earlier. Returns
+/// `None` once every book is finished.
+pub fn next_to_read(books: &[Uuid], progress: &HashMap<Uuid, f32>) -> Option<Uuid> {
+    let progress_of = |id: &Uuid| progress.get(id).copied().unwrap_or_default();
+    let unfinished = |id: &&Uuid| progress_of(id) < FINISHED_PROGRESS;
+
+    let Some(last_started) = books.iter().rposition(|id| progress_of(id) > 0.0) else {
+        return books.first().copied();
+    };
+    let (before, after) = books.split_at(last_started);
+    after.iter().find(unfinished).or_else(|| before.iter().find(unfinished)).copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_to_read() {
+        let books: Vec<Uuid> = (0..4).map(|_| Uuid::now_v7()).collect();
+        let progress = |entries: &[(usize, f32)]| -> HashMap<Uuid, f32> {
+            entries.iter().map(|&(i, p)| (books[i], p)).collect()
+        };
+
+        assert_eq!(next_to_read(&books, &progress(&[])), Some(books[0]));
+        assert_eq!(next_to_read(&books, &progress(&[(0, 1.0), (1, 0.4)])), Some(books[1]));
+        assert_eq!(next_to_read(&books, &progress(&[(0, 1.0), (1, 1.0)])), Some(books[2]));
+        // A skipped book is picked up after the last one
+        assert_eq!(
+            next_to_read(&books, &progress(&[(0, 1.0), (2, 1.0), (3, 0.995)])),
+            Some(books[1])
+        );
+        let all: Vec<(usize, f32)> = (0..4).map(|i| (i, 1.0)).collect();
+        assert_eq!(next_to_read(&books, &progress(&all)), None);
+        assert_eq!(next_to_read(&[], &progress(&[])), None);
+    }
+}
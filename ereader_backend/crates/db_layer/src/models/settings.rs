@@ -0,0 +1,24 @@
+//! Sync settings model.
+
+use chrono::{DateTime, Utc};
+use common::ReadingPositionPolicy;
+use serde::{Deserialize, Serialize};
+
+/// Per-user sync settings record from the database
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SyncSettings {
+    pub user_id: String,
+    pub reading_position_policy: ReadingPositionPolicy,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl SyncSettings {
+    /// Settings used for users who have never changed them
+    pub fn default_for(user_id: impl Into<String>) -> Self {
+        Self {
+            user_id: user_id.into(),
+            reading_position_policy: ReadingPositionPolicy::default(),
+            updated_at: Utc::now(),
+        }
+    }
+}
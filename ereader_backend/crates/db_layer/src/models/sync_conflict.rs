@@ -0,0 +1,79 @@
+//! Sync conflict model.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// How a conflict was resolved
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "conflict_resolution", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictResolution {
+    ServerWins,
+    ClientWins,
+    Merged,
+    /// Edits could not be merged; the client's version was saved as a separate annotation
+    KeptBoth,
+}
+
+/// Version the user picked when resolving a logged conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "conflict_choice", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictChoice {
+    /// Keep the server's version
+    Server,
+    /// Replace the server's version with the device's
+    Local,
+    /// Keep the server's version and restore the device's as a separate annotation
+    Both,
+}
+
+impl std::fmt::Display for ConflictChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictChoice::Server => write!(f, "server"),
+            ConflictChoice::Local => write!(f, "local"),
+            ConflictChoice::Both => write!(f, "both"),
+        }
+    }
+}
+
+/// A conflict found during sync, kept for the user to review
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct SyncConflictEntry {
+    pub id: Uuid,
+    pub user_id: String,
+    /// Device whose version lost, unless it has since been removed
+    pub device_id: Option<Uuid>,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub resolution: ConflictResolution,
+    pub local_version: serde_json::Value,
+    pub server_version: serde_json::Value,
+    /// Annotation holding the device's version, for `KeptBoth` conflicts and those resolved
+    /// by keeping both
+    pub copy_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub resolved_with: Option<ConflictChoice>,
+}
+
+impl SyncConflictEntry {
+    /// Check if the user has resolved the conflict
+    pub fn is_resolved(&self) -> bool {
+        self.resolved_at.is_some()
+    }
+}
+
+/// Data for logging a sync conflict
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreateSyncConflictEntry {
+    pub id: Uuid,
+    pub entity_type: String,
+    pub entity_id: Uuid,
+    pub resolution: ConflictResolution,
+    pub local_version: serde_json::Value,
+    pub server_version: serde_json::Value,
+    pub copy_id: Option<Uuid>,
+}
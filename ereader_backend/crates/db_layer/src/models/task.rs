@@ -116,4 +116,8 @@ pub mod task_types {
     pub const REINDEX_BOOK: &str = "reindex_book";
     pub const GENERATE_COVERS: &str = "generate_covers";
     pub const CLEANUP_ORPHANS: &str = "cleanup_orphans";
+    pub const PRUNE_SYNC_TOMBSTONES: &str = "prune_sync_tombstones";
+    pub const REANCHOR_ANNOTATIONS: &str = "reanchor_annotations";
+    pub const INDEX_BOOK_TEXT: &str = "index_book_text";
+    pub const REFRESH_SMART_COLLECTIONS: &str = "refresh_smart_collections";
 }
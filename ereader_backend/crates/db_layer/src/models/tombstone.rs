@@ -0,0 +1,29 @@
+//! Sync tombstone model.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Kind of library entity a tombstone refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "sync_entity_type", rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum SyncEntityType {
+    Book,
+    Collection,
+    /// A book's membership in a collection
+    CollectionBook,
+}
+
+/// Record of a deleted library entity, kept so devices can learn about the delete
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Tombstone {
+    pub id: i64,
+    pub user_id: String,
+    pub entity_type: SyncEntityType,
+    pub entity_id: Uuid,
+    /// Collection the book was removed from, for `CollectionBook` tombstones
+    pub parent_id: Option<Uuid>,
+    pub change_seq: i64,
+    pub deleted_at: DateTime<Utc>,
+}
@@ -1,12 +1,15 @@
 //! Database connection pool and migration utilities.
 
 use common::config::DatabaseConfig;
-use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::postgres::{PgConnection, PgPool, PgPoolOptions};
 use std::time::Duration;
 
 /// Type alias for the PostgreSQL connection pool
 pub type DbPool = PgPool;
 
+/// A single connection, such as an open transaction, for queries that must share one
+pub type DbConn = PgConnection;
+
 /// Create a new database connection pool from configuration
 pub async fn create_pool(config: &DatabaseConfig) -> Result<DbPool, sqlx::Error> {
     tracing::info!(
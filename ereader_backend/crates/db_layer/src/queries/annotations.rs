@@ -1,9 +1,8 @@
 //! Annotation database queries.
 
-use crate::models::{Annotation, UpsertAnnotation};
-use crate::pool::DbPool;
-use chrono::{DateTime, Utc};
-use common::{Error, Paginated, Pagination, Result};
+use crate::models::{Annotation, AnnotationRevision, UpdateAnnotationAnchor, UpsertAnnotation};
+use crate::pool::{DbConn, DbPool};
+use common::{Error, HybridTimestamp, Paginated, Pagination, Result};
 use uuid::Uuid;
 
 /// Annotation-related database queries
@@ -15,7 +14,9 @@ impl AnnotationQueries {
         let annotation = sqlx::query_as::<_, Annotation>(
             r#"
             SELECT id, user_id, book_id, annotation_type, location_start, location_end,
-                   content, style, created_at, updated_at, deleted_at
+                   content, style, quote_exact, quote_prefix, quote_suffix, anchor_status,
+                   hlc_physical, hlc_logical, hlc_device_id,
+                   revision, change_seq, created_at, updated_at, deleted_at
             FROM annotations
             WHERE id = $1
             "#,
@@ -27,6 +28,26 @@ impl AnnotationQueries {
         Ok(annotation)
     }
 
+    /// Get several annotations by ID, locking them until the transaction ends
+    pub async fn lock_by_ids(conn: &mut DbConn, ids: &[Uuid]) -> Result<Vec<Annotation>> {
+        let annotations = sqlx::query_as::<_, Annotation>(
+            r#"
+            SELECT id, user_id, book_id, annotation_type, location_start, location_end,
+                   content, style, quote_exact, quote_prefix, quote_suffix, anchor_status,
+                   hlc_physical, hlc_logical, hlc_device_id,
+                   revision, change_seq, created_at, updated_at, deleted_at
+            FROM annotations
+            WHERE id = ANY($1)
+            FOR UPDATE
+            "#,
+        )
+        .bind(ids)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(annotations)
+    }
+
     /// Get an annotation by ID, returning an error if not found
     pub async fn get_by_id_required(pool: &DbPool, id: Uuid) -> Result<Annotation> {
         Self::get_by_id(pool, id)
@@ -52,7 +73,9 @@ impl AnnotationQueries {
         let items = sqlx::query_as::<_, Annotation>(
             r#"
             SELECT id, user_id, book_id, annotation_type, location_start, location_end,
-                   content, style, created_at, updated_at, deleted_at
+                   content, style, quote_exact, quote_prefix, quote_suffix, anchor_status,
+                   hlc_physical, hlc_logical, hlc_device_id,
+                   revision, change_seq, created_at, updated_at, deleted_at
             FROM annotations
             WHERE user_id = $1 AND deleted_at IS NULL
             ORDER BY created_at DESC
@@ -77,7 +100,9 @@ impl AnnotationQueries {
         let annotations = sqlx::query_as::<_, Annotation>(
             r#"
             SELECT id, user_id, book_id, annotation_type, location_start, location_end,
-                   content, style, created_at, updated_at, deleted_at
+                   content, style, quote_exact, quote_prefix, quote_suffix, anchor_status,
+                   hlc_physical, hlc_logical, hlc_device_id,
+                   revision, change_seq, created_at, updated_at, deleted_at
             FROM annotations
             WHERE user_id = $1 AND book_id = $2 AND deleted_at IS NULL
             ORDER BY location_start ASC
@@ -91,45 +116,80 @@ impl AnnotationQueries {
         Ok(annotations)
     }
 
-    /// Get annotations updated since a specific time (for sync, includes deleted)
-    pub async fn get_updated_since(
-        pool: &DbPool,
+    /// Get annotations changed after a sync sequence number, oldest change first
+    /// (for sync, includes deleted)
+    pub async fn get_changed_since(
+        conn: &mut DbConn,
         user_id: &str,
-        since: DateTime<Utc>,
+        since_seq: i64,
+        limit: i64,
     ) -> Result<Vec<Annotation>> {
         let annotations = sqlx::query_as::<_, Annotation>(
             r#"
             SELECT id, user_id, book_id, annotation_type, location_start, location_end,
-                   content, style, created_at, updated_at, deleted_at
+                   content, style, quote_exact, quote_prefix, quote_suffix, anchor_status,
+                   hlc_physical, hlc_logical, hlc_device_id,
+                   revision, change_seq, created_at, updated_at, deleted_at
             FROM annotations
-            WHERE user_id = $1 AND updated_at > $2
-            ORDER BY updated_at ASC
+            WHERE user_id = $1 AND change_seq > $2
+            ORDER BY change_seq ASC
+            LIMIT $3
             "#,
         )
         .bind(user_id)
-        .bind(since)
-        .fetch_all(pool)
+        .bind(since_seq)
+        .bind(limit)
+        .fetch_all(conn)
         .await?;
 
         Ok(annotations)
     }
 
     /// Create or update an annotation
+    ///
+    /// Each write bumps the revision and records a snapshot of the mergeable fields.
     pub async fn upsert(pool: &DbPool, data: &UpsertAnnotation) -> Result<Annotation> {
         let annotation = sqlx::query_as::<_, Annotation>(
             r#"
-            INSERT INTO annotations (id, user_id, book_id, annotation_type, location_start,
-                                    location_end, content, style)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            ON CONFLICT (id) DO UPDATE SET
-                annotation_type = EXCLUDED.annotation_type,
-                location_start = EXCLUDED.location_start,
-                location_end = EXCLUDED.location_end,
-                content = EXCLUDED.content,
-                style = EXCLUDED.style,
-                deleted_at = NULL
-            RETURNING id, user_id, book_id, annotation_type, location_start, location_end,
-                      content, style, created_at, updated_at, deleted_at
+            WITH saved AS (
+                INSERT INTO annotations (id, user_id, book_id, annotation_type, location_start,
+                                        location_end, content, style,
+                                        hlc_physical, hlc_logical, hlc_device_id,
+                                        quote_exact, quote_prefix, quote_suffix)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+                ON CONFLICT (id) DO UPDATE SET
+                    annotation_type = EXCLUDED.annotation_type,
+                    location_start = EXCLUDED.location_start,
+                    location_end = EXCLUDED.location_end,
+                    content = EXCLUDED.content,
+                    style = EXCLUDED.style,
+                    hlc_physical = EXCLUDED.hlc_physical,
+                    hlc_logical = EXCLUDED.hlc_logical,
+                    hlc_device_id = EXCLUDED.hlc_device_id,
+                    quote_exact = CASE WHEN EXCLUDED.quote_exact IS NULL
+                                        AND EXCLUDED.location_start = annotations.location_start
+                                       THEN annotations.quote_exact ELSE EXCLUDED.quote_exact END,
+                    quote_prefix = CASE WHEN EXCLUDED.quote_exact IS NULL
+                                         AND EXCLUDED.location_start = annotations.location_start
+                                        THEN annotations.quote_prefix ELSE EXCLUDED.quote_prefix END,
+                    quote_suffix = CASE WHEN EXCLUDED.quote_exact IS NULL
+                                         AND EXCLUDED.location_start = annotations.location_start
+                                        THEN annotations.quote_suffix ELSE EXCLUDED.quote_suffix END,
+                    anchor_status = CASE WHEN EXCLUDED.location_start = annotations.location_start
+                                         THEN annotations.anchor_status
+                                         ELSE 'anchored' END,
+                    revision = annotations.revision + 1,
+                    deleted_at = NULL
+                RETURNING id, user_id, book_id, annotation_type, location_start, location_end,
+                          content, style, quote_exact, quote_prefix, quote_suffix, anchor_status,
+                          hlc_physical, hlc_logical, hlc_device_id,
+                          revision, change_seq, created_at, updated_at, deleted_at
+            ),
+            snapshot AS (
+                INSERT INTO annotation_revisions (annotation_id, revision, location_end, content, style)
+                SELECT id, revision, location_end, content, style FROM saved
+            )
+            SELECT * FROM saved
             "#,
         )
         .bind(data.id)
@@ -140,18 +200,241 @@ impl AnnotationQueries {
         .bind(&data.location_end)
         .bind(&data.content)
         .bind(&data.style)
+        .bind(data.hlc.physical)
+        .bind(data.hlc.logical as i32)
+        .bind(data.hlc.device_id)
+        .bind(data.quote.as_ref().map(|q| q.exact.as_str()))
+        .bind(data.quote.as_ref().map(|q| q.prefix.as_str()))
+        .bind(data.quote.as_ref().map(|q| q.suffix.as_str()))
         .fetch_one(pool)
         .await?;
 
         Ok(annotation)
     }
 
-    /// Soft delete an annotation
-    pub async fn soft_delete(pool: &DbPool, id: Uuid) -> Result<bool> {
+    /// Create or update several annotations in one statement
+    ///
+    /// Each annotation may appear at most once. Revisions are bumped and snapshotted as in
+    /// [`Self::upsert`].
+    pub async fn upsert_many(
+        conn: &mut DbConn,
+        user_id: &str,
+        data: &[UpsertAnnotation],
+    ) -> Result<Vec<Annotation>> {
+        let annotations = sqlx::query_as::<_, Annotation>(
+            r#"
+            WITH saved AS (
+                INSERT INTO annotations (id, user_id, book_id, annotation_type, location_start,
+                                        location_end, content, style,
+                                        hlc_physical, hlc_logical, hlc_device_id,
+                                        quote_exact, quote_prefix, quote_suffix)
+                SELECT u.id, $1, u.book_id, u.annotation_type, u.location_start,
+                       u.location_end, u.content, u.style,
+                       u.hlc_physical, u.hlc_logical, u.hlc_device_id,
+                       u.quote_exact, u.quote_prefix, u.quote_suffix
+                FROM UNNEST($2::uuid[], $3::uuid[], $4::annotation_type[], $5::text[],
+                            $6::text[], $7::text[], $8::text[],
+                            $9::bigint[], $10::int[], $11::uuid[],
+                            $12::text[], $13::text[], $14::text[])
+                    AS u(id, book_id, annotation_type, location_start,
+                         location_end, content, style,
+                         hlc_physical, hlc_logical, hlc_device_id,
+                         quote_exact, quote_prefix, quote_suffix)
+                ON CONFLICT (id) DO UPDATE SET
+                    annotation_type = EXCLUDED.annotation_type,
+                    location_start = EXCLUDED.location_start,
+                    location_end = EXCLUDED.location_end,
+                    content = EXCLUDED.content,
+                    style = EXCLUDED.style,
+                    hlc_physical = EXCLUDED.hlc_physical,
+                    hlc_logical = EXCLUDED.hlc_logical,
+                    hlc_device_id = EXCLUDED.hlc_device_id,
+                    quote_exact = CASE WHEN EXCLUDED.quote_exact IS NULL
+                                        AND EXCLUDED.location_start = annotations.location_start
+                                       THEN annotations.quote_exact ELSE EXCLUDED.quote_exact END,
+                    quote_prefix = CASE WHEN EXCLUDED.quote_exact IS NULL
+                                         AND EXCLUDED.location_start = annotations.location_start
+                                        THEN annotations.quote_prefix ELSE EXCLUDED.quote_prefix END,
+                    quote_suffix = CASE WHEN EXCLUDED.quote_exact IS NULL
+                                         AND EXCLUDED.location_start = annotations.location_start
+                                        THEN annotations.quote_suffix ELSE EXCLUDED.quote_suffix END,
+                    anchor_status = CASE WHEN EXCLUDED.location_start = annotations.location_start
+                                         THEN annotations.anchor_status
+                                         ELSE 'anchored' END,
+                    revision = annotations.revision + 1,
+                    deleted_at = NULL
+                RETURNING id, user_id, book_id, annotation_type, location_start, location_end,
+                          content, style, quote_exact, quote_prefix, quote_suffix, anchor_status,
+                          hlc_physical, hlc_logical, hlc_device_id,
+                          revision, change_seq, created_at, updated_at, deleted_at
+            ),
+            snapshot AS (
+                INSERT INTO annotation_revisions (annotation_id, revision, location_end, content, style)
+                SELECT id, revision, location_end, content, style FROM saved
+            )
+            SELECT * FROM saved
+            "#,
+        )
+        .bind(user_id)
+        .bind(data.iter().map(|d| d.id).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.book_id).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.annotation_type).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.location_start.as_str()).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.location_end.as_deref()).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.content.as_deref()).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.style.as_deref()).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.hlc.physical).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.hlc.logical as i32).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.hlc.device_id).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.quote.as_ref().map(|q| q.exact.as_str())).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.quote.as_ref().map(|q| q.prefix.as_str())).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.quote.as_ref().map(|q| q.suffix.as_str())).collect::<Vec<_>>())
+        .fetch_all(conn)
+        .await?;
+
+        Ok(annotations)
+    }
+
+    /// Move an annotation to new locators after its book's file was replaced
+    ///
+    /// Bumps the revision like any other write, so devices pick up the new locators.
+    /// Returns `None` if the annotation was edited or deleted since `data.revision`.
+    pub async fn update_anchor(
+        pool: &DbPool,
+        id: Uuid,
+        data: &UpdateAnnotationAnchor,
+    ) -> Result<Option<Annotation>> {
+        let annotation = sqlx::query_as::<_, Annotation>(
+            r#"
+            WITH saved AS (
+                UPDATE annotations SET
+                    location_start = $2,
+                    location_end = $3,
+                    quote_exact = $4,
+                    quote_prefix = $5,
+                    quote_suffix = $6,
+                    anchor_status = $7,
+                    hlc_physical = $8,
+                    hlc_logical = $9,
+                    hlc_device_id = $10,
+                    revision = revision + 1
+                WHERE id = $1 AND revision = $11 AND deleted_at IS NULL
+                RETURNING id, user_id, book_id, annotation_type, location_start, location_end,
+                          content, style, quote_exact, quote_prefix, quote_suffix, anchor_status,
+                          hlc_physical, hlc_logical, hlc_device_id,
+                          revision, change_seq, created_at, updated_at, deleted_at
+            ),
+            snapshot AS (
+                INSERT INTO annotation_revisions (annotation_id, revision, location_end, content, style)
+                SELECT id, revision, location_end, content, style FROM saved
+            )
+            SELECT * FROM saved
+            "#,
+        )
+        .bind(id)
+        .bind(&data.location_start)
+        .bind(&data.location_end)
+        .bind(data.quote.as_ref().map(|q| q.exact.as_str()))
+        .bind(data.quote.as_ref().map(|q| q.prefix.as_str()))
+        .bind(data.quote.as_ref().map(|q| q.suffix.as_str()))
+        .bind(data.anchor_status)
+        .bind(data.hlc.physical)
+        .bind(data.hlc.logical as i32)
+        .bind(data.hlc.device_id)
+        .bind(data.revision)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(annotation)
+    }
+
+    /// Get the snapshot of an annotation at a given revision
+    pub async fn get_revision(
+        pool: &DbPool,
+        annotation_id: Uuid,
+        revision: i32,
+    ) -> Result<Option<AnnotationRevision>> {
+        let snapshot = sqlx::query_as::<_, AnnotationRevision>(
+            r#"
+            SELECT annotation_id, revision, location_end, content, style, created_at
+            FROM annotation_revisions
+            WHERE annotation_id = $1 AND revision = $2
+            "#,
+        )
+        .bind(annotation_id)
+        .bind(revision)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(snapshot)
+    }
+
+    /// Get the snapshots for several annotation and revision pairs
+    pub async fn get_revisions(
+        conn: &mut DbConn,
+        keys: &[(Uuid, i32)],
+    ) -> Result<Vec<AnnotationRevision>> {
+        let snapshots = sqlx::query_as::<_, AnnotationRevision>(
+            r#"
+            SELECT r.annotation_id, r.revision, r.location_end, r.content, r.style, r.created_at
+            FROM annotation_revisions r
+            INNER JOIN UNNEST($1::uuid[], $2::int[]) AS k(annotation_id, revision)
+                ON r.annotation_id = k.annotation_id AND r.revision = k.revision
+            "#,
+        )
+        .bind(keys.iter().map(|(id, _)| *id).collect::<Vec<_>>())
+        .bind(keys.iter().map(|(_, revision)| *revision).collect::<Vec<_>>())
+        .fetch_all(conn)
+        .await?;
+
+        Ok(snapshots)
+    }
+
+    /// Soft delete several annotations, recording the clock of each delete
+    ///
+    /// Returns the annotations that were deleted.
+    pub async fn soft_delete_many(
+        conn: &mut DbConn,
+        deletes: &[(Uuid, HybridTimestamp)],
+    ) -> Result<Vec<Annotation>> {
+        let annotations = sqlx::query_as::<_, Annotation>(
+            r#"
+            UPDATE annotations a
+            SET deleted_at = NOW(), hlc_physical = d.hlc_physical,
+                hlc_logical = d.hlc_logical, hlc_device_id = d.hlc_device_id
+            FROM UNNEST($1::uuid[], $2::bigint[], $3::int[], $4::uuid[])
+                AS d(id, hlc_physical, hlc_logical, hlc_device_id)
+            WHERE a.id = d.id AND a.deleted_at IS NULL
+            RETURNING a.id, a.user_id, a.book_id, a.annotation_type, a.location_start,
+                      a.location_end, a.content, a.style, a.quote_exact, a.quote_prefix,
+                      a.quote_suffix, a.anchor_status, a.hlc_physical, a.hlc_logical,
+                      a.hlc_device_id, a.revision, a.change_seq, a.created_at, a.updated_at,
+                      a.deleted_at
+            "#,
+        )
+        .bind(deletes.iter().map(|(id, _)| *id).collect::<Vec<_>>())
+        .bind(deletes.iter().map(|(_, hlc)| hlc.physical).collect::<Vec<_>>())
+        .bind(deletes.iter().map(|(_, hlc)| hlc.logical as i32).collect::<Vec<_>>())
+        .bind(deletes.iter().map(|(_, hlc)| hlc.device_id).collect::<Vec<_>>())
+        .fetch_all(conn)
+        .await?;
+
+        Ok(annotations)
+    }
+
+    /// Soft delete an annotation, recording the clock of the delete
+    pub async fn soft_delete(pool: &DbPool, id: Uuid, hlc: &HybridTimestamp) -> Result<bool> {
         let result = sqlx::query(
-            "UPDATE annotations SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+            r#"
+            UPDATE annotations
+            SET deleted_at = NOW(), hlc_physical = $2, hlc_logical = $3, hlc_device_id = $4
+            WHERE id = $1 AND deleted_at IS NULL
+            "#,
         )
         .bind(id)
+        .bind(hlc.physical)
+        .bind(hlc.logical as i32)
+        .bind(hlc.device_id)
         .execute(pool)
         .await?;
 
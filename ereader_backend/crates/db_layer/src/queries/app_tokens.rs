@@ -0,0 +1,83 @@
+//! App token database queries.
+
+use crate::models::{AppToken, CreateAppToken};
+use crate::pool::DbPool;
+use common::Result;
+use uuid::Uuid;
+
+/// App token database queries
+pub struct AppTokenQueries;
+
+impl AppTokenQueries {
+    /// Store a new app token
+    pub async fn create(pool: &DbPool, data: &CreateAppToken) -> Result<AppToken> {
+        let token = sqlx::query_as::<_, AppToken>(
+            r#"
+            INSERT INTO app_tokens (id, user_id, name, token_hash)
+            VALUES ($1, $2, $3, $4)
+            RETURNING id, user_id, name, created_at, last_used_at
+            "#,
+        )
+        .bind(data.id)
+        .bind(&data.user_id)
+        .bind(&data.name)
+        .bind(&data.token_hash)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    /// List a user's app tokens, newest first
+    pub async fn list_for_user(pool: &DbPool, user_id: &str) -> Result<Vec<AppToken>> {
+        let tokens = sqlx::query_as::<_, AppToken>(
+            r#"
+            SELECT id, user_id, name, created_at, last_used_at
+            FROM app_tokens
+            WHERE user_id = $1
+            ORDER BY created_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    /// Revoke one of a user's app tokens
+    pub async fn delete_for_user(pool: &DbPool, id: Uuid, user_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM app_tokens WHERE id = $1 AND user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Find the token with the given hash and note that it was used
+    ///
+    /// `last_used_at` is only refreshed every few minutes, so clients fetching many covers
+    /// don't cause a write per request.
+    pub async fn authenticate(pool: &DbPool, token_hash: &str) -> Result<Option<AppToken>> {
+        let token = sqlx::query_as::<_, AppToken>(
+            r#"
+            WITH touched AS (
+                UPDATE app_tokens
+                SET last_used_at = NOW()
+                WHERE token_hash = $1
+                  AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '5 minutes')
+            )
+            SELECT id, user_id, name, created_at, last_used_at
+            FROM app_tokens
+            WHERE token_hash = $1
+            "#,
+        )
+        .bind(token_hash)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(token)
+    }
+}
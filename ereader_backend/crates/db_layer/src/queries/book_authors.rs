@@ -0,0 +1,288 @@
+//! Author database queries.
+//!
+//! Books list their authors by name; a database trigger links each book to the owner's
+//! authors matching those names through `book_authors`. Names match ignoring case, spaces
+//! and punctuation, with "Last, First" read as "First Last", and also match an author's
+//! aliases.
+
+use crate::models::{Author, AuthorWithCount, Book, UpdateAuthor};
+use crate::pool::DbPool;
+use common::{Error, Paginated, Pagination, Result};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Author-related database queries
+pub struct AuthorQueries;
+
+impl AuthorQueries {
+    /// List a user's authors credited by books, by sort name, with their number of books
+    ///
+    /// With a role, a MARC relator code such as `trl`, only people credited in that role
+    /// are listed and only books crediting them in it counted.
+    pub async fn list_for_user(
+        pool: &DbPool,
+        user_id: &str,
+        role: Option<&str>,
+        pagination: &Pagination,
+    ) -> Result<Paginated<AuthorWithCount>> {
+        let total = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*) FROM authors a
+            WHERE a.user_id = $1 AND EXISTS (
+                SELECT 1 FROM book_authors ba
+                WHERE ba.author_id = a.id AND ($2::text IS NULL OR ba.role = $2)
+            )
+            "#,
+        )
+        .bind(user_id)
+        .bind(role)
+        .fetch_one(pool)
+        .await?;
+
+        let items = sqlx::query_as::<_, AuthorWithCount>(
+            r#"
+            SELECT a.id, a.user_id, a.name,
+                   COALESCE(a.sort_name, author_default_sort_name(a.name)) AS sort_name,
+                   a.aliases, a.created_at, a.updated_at,
+                   COUNT(DISTINCT ba.book_id) AS book_count
+            FROM authors a
+            JOIN book_authors ba ON ba.author_id = a.id
+            WHERE a.user_id = $1 AND ($2::text IS NULL OR ba.role = $2)
+            GROUP BY a.id
+            ORDER BY LOWER(COALESCE(a.sort_name, author_default_sort_name(a.name))), a.id
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(role)
+        .bind(pagination.limit)
+        .bind(pagination.offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(Paginated::new(items, total, pagination))
+    }
+
+    /// Get an author by ID for a specific user (ensures ownership)
+    pub async fn get_by_id_for_user(pool: &DbPool, id: Uuid, user_id: &str) -> Result<Option<Author>> {
+        let author = sqlx::query_as::<_, Author>(
+            r#"
+            SELECT id, user_id, name, COALESCE(sort_name, author_default_sort_name(name)) AS sort_name,
+                   aliases, created_at, updated_at
+            FROM authors
+            WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(author)
+    }
+
+    /// Get the books crediting an author in any role, by title
+    pub async fn get_books(pool: &DbPool, author_id: Uuid) -> Result<Vec<Book>> {
+        let books = sqlx::query_as::<_, Book>(
+            r#"
+            SELECT DISTINCT b.id, b.user_id, b.title, b.authors, b.contributors, b.description, b.language, b.publisher,
+                   b.published_date, b.isbn, b.series_id, b.series_name, b.series_index, b.tags,
+                   b.author_sort, b.rating, b.identifiers,
+                   b.format, b.content_hash, b.file_size, b.storage_path, b.original_filename,
+                   b.created_at, b.updated_at
+            FROM books b
+            JOIN book_authors ba ON ba.book_id = b.id
+            WHERE ba.author_id = $1
+            ORDER BY b.title, b.id
+            "#,
+        )
+        .bind(author_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(books)
+    }
+
+    /// Set a user's author's sort name or aliases
+    pub async fn update(pool: &DbPool, id: Uuid, user_id: &str, data: &UpdateAuthor) -> Result<Author> {
+        let aliases = data.aliases.as_ref().map(|aliases| {
+            aliases
+                .iter()
+                .map(|a| a.trim().to_string())
+                .filter(|a| !a.is_empty())
+                .collect::<Vec<_>>()
+        });
+
+        let author = sqlx::query_as::<_, Author>(
+            r#"
+            UPDATE authors
+            SET
+                sort_name = CASE WHEN $3::text IS NULL THEN sort_name ELSE NULLIF(BTRIM($3), '') END,
+                aliases = COALESCE($4, aliases)
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, user_id, name, COALESCE(sort_name, author_default_sort_name(name)) AS sort_name,
+                      aliases, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(&data.sort_name)
+        .bind(aliases)
+        .fetch_optional(pool)
+        .await?
+        .ok_or_else(|| Error::not_found_resource("author", id))?;
+
+        Ok(author)
+    }
+
+    /// Give a user's authors the sort names found in a book's metadata, keyed by the names
+    /// the book lists them by, unless they have one set already
+    pub async fn fill_sort_names(pool: &DbPool, user_id: &str, sort_names: &HashMap<String, String>) -> Result<u64> {
+        if sort_names.is_empty() {
+            return Ok(0);
+        }
+        let (names, sorts): (Vec<&str>, Vec<&str>) = sort_names
+            .iter()
+            .map(|(name, sort)| (name.as_str(), sort.trim()))
+            .filter(|(_, sort)| !sort.is_empty())
+            .unzip();
+
+        let result = sqlx::query(
+            r#"
+            UPDATE authors a
+            SET sort_name = s.sort_name
+            FROM UNNEST($2::text[], $3::text[]) AS s(name, sort_name)
+            WHERE a.user_id = $1 AND a.sort_name IS NULL
+              AND a.match_keys @> ARRAY[author_name_key(s.name)]
+            "#,
+        )
+        .bind(user_id)
+        .bind(&names)
+        .bind(&sorts)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Merge a user's authors into one of them
+    ///
+    /// Books crediting the merged authors credit the target author in their place, and the
+    /// merged authors' names and aliases become aliases of the target, so books added later
+    /// under those names are linked to it too.
+    pub async fn merge(pool: &DbPool, user_id: &str, into_id: Uuid, from_ids: &[Uuid]) -> Result<Author> {
+        let mut tx = pool.begin().await?;
+
+        let target = sqlx::query_as::<_, Author>(
+            r#"
+            SELECT id, user_id, name, COALESCE(sort_name, author_default_sort_name(name)) AS sort_name,
+                   aliases, created_at, updated_at
+            FROM authors
+            WHERE id = $1 AND user_id = $2
+            FOR UPDATE
+            "#,
+        )
+        .bind(into_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::not_found_resource("author", into_id))?;
+
+        let from_ids: Vec<Uuid> = from_ids.iter().copied().filter(|id| *id != into_id).collect();
+        let merged = sqlx::query_as::<_, (Uuid, String, Vec<String>)>(
+            "SELECT id, name, aliases FROM authors WHERE user_id = $1 AND id = ANY($2) FOR UPDATE",
+        )
+        .bind(user_id)
+        .bind(&from_ids)
+        .fetch_all(&mut *tx)
+        .await?;
+        if let Some(missing) = from_ids.iter().find(|id| !merged.iter().any(|(found, _, _)| found == *id)) {
+            return Err(Error::not_found_resource("author", missing));
+        }
+
+        let book_ids = sqlx::query_scalar::<_, Uuid>(
+            "SELECT DISTINCT book_id FROM book_authors WHERE author_id = ANY($1)",
+        )
+        .bind(&from_ids)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut aliases = target.aliases.clone();
+        for (_, name, merged_aliases) in merged {
+            for alias in std::iter::once(name).chain(merged_aliases) {
+                if !aliases.contains(&alias) {
+                    aliases.push(alias);
+                }
+            }
+        }
+
+        // Delete the merged authors before taking their names as aliases, so the names
+        // only match the target
+        sqlx::query("DELETE FROM authors WHERE user_id = $1 AND id = ANY($2)")
+            .bind(user_id)
+            .bind(&from_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        let target = sqlx::query_as::<_, Author>(
+            r#"
+            UPDATE authors SET aliases = $2
+            WHERE id = $1
+            RETURNING id, user_id, name, COALESCE(sort_name, author_default_sort_name(name)) AS sort_name,
+                      aliases, created_at, updated_at
+            "#,
+        )
+        .bind(into_id)
+        .bind(&aliases)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        // Credit the target by its name in place of any name it matches, once per book and
+        // role; the trigger relinks the rewritten books
+        sqlx::query(
+            r#"
+            UPDATE books b
+            SET authors = ARRAY(
+                SELECT name FROM (
+                    SELECT DISTINCT ON (name) name, position
+                    FROM (
+                        SELECT CASE
+                                   WHEN author_name_key(a.name) IN (SELECT UNNEST(match_keys) FROM authors WHERE id = $3)
+                                   THEN $2 ELSE a.name
+                               END AS name,
+                               a.position
+                        FROM UNNEST(b.authors) WITH ORDINALITY AS a(name, position)
+                    ) renamed
+                    ORDER BY name, position
+                ) deduplicated
+                ORDER BY position
+            ),
+            contributors = (
+                SELECT COALESCE(JSONB_AGG(value ORDER BY position), '[]')
+                FROM (
+                    SELECT DISTINCT ON (value) value, position
+                    FROM (
+                        SELECT CASE
+                                   WHEN author_name_key(c.value->>'name') IN (SELECT UNNEST(match_keys) FROM authors WHERE id = $3)
+                                   THEN JSONB_SET(c.value, '{name}', TO_JSONB($2::text)) ELSE c.value
+                               END AS value,
+                               c.position
+                        FROM JSONB_ARRAY_ELEMENTS(b.contributors) WITH ORDINALITY AS c(value, position)
+                    ) renamed
+                    ORDER BY value, position
+                ) deduplicated
+            )
+            WHERE b.id = ANY($1)
+            "#,
+        )
+        .bind(&book_ids)
+        .bind(&target.name)
+        .bind(into_id)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(target)
+    }
+}
@@ -0,0 +1,312 @@
+//! Keyset pagination of book lists.
+//!
+//! A cursor records the sort key values and id of the last book on a page. The next page
+//! starts after that position rather than at an offset, so deep pages cost no more than
+//! the first and books added while paginating don't shift later pages.
+
+use crate::models::Book;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::SecondsFormat;
+use common::{Error, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::BookSortOptions;
+
+/// Value a book list is sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortField {
+    Title,
+    CreatedAt,
+    UpdatedAt,
+    /// Position in its series, with books outside a series after all others
+    SeriesIndex,
+    /// Search relevance, computed by the given SQL expression
+    Rank,
+}
+
+impl SortField {
+    fn name(self) -> &'static str {
+        match self {
+            SortField::Title => "title",
+            SortField::CreatedAt => "created_at",
+            SortField::UpdatedAt => "updated_at",
+            SortField::SeriesIndex => "series_index",
+            SortField::Rank => "rank",
+        }
+    }
+
+    /// Type a cursor's text value is cast to for comparison
+    fn sql_type(self) -> &'static str {
+        match self {
+            SortField::Title => "text",
+            SortField::CreatedAt | SortField::UpdatedAt => "timestamptz",
+            SortField::SeriesIndex | SortField::Rank => "real",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SortKey {
+    field: SortField,
+    expr: String,
+    descending: bool,
+}
+
+/// Ordering of a book list, ending with the book id so every position is unique
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct BookOrder {
+    keys: Vec<SortKey>,
+}
+
+impl BookOrder {
+    /// Ordering for list options, newest first by default
+    pub(crate) fn for_sort(sort: &BookSortOptions) -> Self {
+        let field = match sort.sort_by.as_deref() {
+            Some("title") => SortField::Title,
+            Some("created_at") => SortField::CreatedAt,
+            Some("updated_at") => SortField::UpdatedAt,
+            Some("series_index") => SortField::SeriesIndex,
+            _ => SortField::CreatedAt,
+        };
+        let descending = !matches!(sort.sort_order.as_deref(), Some("asc"));
+        let expr = match field {
+            SortField::SeriesIndex => "COALESCE(series_index, 'Infinity'::real)".to_string(),
+            field => field.name().to_string(),
+        };
+        Self {
+            keys: vec![SortKey { field, expr, descending }],
+        }
+    }
+
+    /// Ordering of search results: most relevant first by `rank`, if ranked, then by title
+    pub(crate) fn for_search(rank: Option<&str>) -> Self {
+        let mut keys = Vec::new();
+        if let Some(rank) = rank {
+            keys.push(SortKey {
+                field: SortField::Rank,
+                expr: rank.to_string(),
+                descending: true,
+            });
+        }
+        keys.push(SortKey {
+            field: SortField::Title,
+            expr: "title".to_string(),
+            descending: false,
+        });
+        Self { keys }
+    }
+
+    /// Name recorded in cursors, so a cursor can't be used with another ordering
+    fn name(&self) -> String {
+        self.keys
+            .iter()
+            .map(|k| format!("{}_{}", k.field.name(), if k.descending { "desc" } else { "asc" }))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Direction of the id tiebreaker, which follows the last sort key
+    fn id_descending(&self) -> bool {
+        self.keys.last().is_some_and(|k| k.descending)
+    }
+
+    /// `ORDER BY` list
+    pub(crate) fn order_by(&self) -> String {
+        let direction = |descending: bool| if descending { "DESC" } else { "ASC" };
+        let mut columns: Vec<String> = self
+            .keys
+            .iter()
+            .map(|k| format!("{} {}", k.expr, direction(k.descending)))
+            .collect();
+        columns.push(format!("id {}", direction(self.id_descending())));
+        columns.join(", ")
+    }
+
+    /// Condition selecting books after a cursor, with the cursor's key values bound from
+    /// `first_param` on and its id bound last
+    pub(crate) fn after(&self, first_param: usize) -> String {
+        let param = |i: usize| format!("${}", first_param + i);
+        let typed = |i: usize, key: &SortKey| format!("{}::{}", param(i), key.field.sql_type());
+        let op = |descending: bool| if descending { "<" } else { ">" };
+        let id_param = format!("{}::uuid", param(self.keys.len()));
+
+        if self.keys.iter().all(|k| k.descending == self.id_descending()) {
+            let columns: Vec<&str> = self.keys.iter().map(|k| k.expr.as_str()).collect();
+            let values: Vec<String> = self.keys.iter().enumerate().map(|(i, k)| typed(i, k)).collect();
+            return format!(
+                "({}, id) {} ({}, {})",
+                columns.join(", "),
+                op(self.id_descending()),
+                values.join(", "),
+                id_param
+            );
+        }
+
+        // Mixed directions can't use a row comparison, so spell out the lexicographic order
+        let mut alternatives = Vec::new();
+        let mut equal_so_far: Vec<String> = Vec::new();
+        for (i, key) in self.keys.iter().enumerate() {
+            let mut conditions = equal_so_far.clone();
+            conditions.push(format!("{} {} {}", key.expr, op(key.descending), typed(i, key)));
+            alternatives.push(conditions.join(" AND "));
+            equal_so_far.push(format!("{} = {}", key.expr, typed(i, key)));
+        }
+        equal_so_far.push(format!("id {} {}", op(self.id_descending()), id_param));
+        alternatives.push(equal_so_far.join(" AND "));
+
+        alternatives
+            .iter()
+            .map(|a| format!("({})", a))
+            .collect::<Vec<_>>()
+            .join(" OR ")
+    }
+
+    /// Cursor positioned after a book, given its search rank when ordered by relevance
+    pub(crate) fn cursor_after(&self, book: &Book, rank: Option<f32>) -> BookCursor {
+        let keys = self
+            .keys
+            .iter()
+            .map(|key| match key.field {
+                SortField::Title => book.title.clone(),
+                SortField::CreatedAt => book.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+                SortField::UpdatedAt => book.updated_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+                SortField::SeriesIndex => book
+                    .series_index
+                    .map_or_else(|| "Infinity".to_string(), |i| i.to_string()),
+                SortField::Rank => rank.unwrap_or_default().to_string(),
+            })
+            .collect();
+        BookCursor {
+            order: self.name(),
+            keys,
+            id: book.id,
+        }
+    }
+
+    /// Decode a cursor token taken in this ordering
+    pub(crate) fn decode(&self, token: &str) -> Result<BookCursor> {
+        let invalid = || Error::validation_field("cursor", "invalid cursor");
+        let json = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let cursor: BookCursor = serde_json::from_slice(&json).map_err(|_| invalid())?;
+
+        if cursor.order != self.name() || cursor.keys.len() != self.keys.len() {
+            return Err(Error::validation_field(
+                "cursor",
+                "cursor was taken with a different sort order",
+            ));
+        }
+        Ok(cursor)
+    }
+}
+
+/// Position after a book in a list paginated by cursor
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct BookCursor {
+    /// Ordering the cursor was taken in
+    #[serde(rename = "o")]
+    pub order: String,
+    /// Sort key values of the book, as text
+    #[serde(rename = "k")]
+    pub keys: Vec<String>,
+    #[serde(rename = "i")]
+    pub id: Uuid,
+}
+
+impl BookCursor {
+    /// Opaque URL-safe token
+    pub(crate) fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor serializes"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CreateBook;
+    use chrono::Utc;
+
+    fn book() -> Book {
+        let create = CreateBook::new("user", "Dune").with_series("Dune", 1.5);
+        Book {
+            id: create.id,
+            user_id: create.user_id,
+            title: create.title,
+            authors: create.authors,
+            contributors: sqlx::types::Json(create.contributors),
+            description: None,
+            language: None,
+            publisher: None,
+            published_date: None,
+            isbn: None,
+            series_id: None,
+            series_name: create.series_name,
+            series_index: create.series_index,
+            tags: vec![],
+            author_sort: None,
+            rating: None,
+            identifiers: sqlx::types::Json(Default::default()),
+            format: None,
+            content_hash: None,
+            file_size: None,
+            storage_path: None,
+            original_filename: None,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_list_order() {
+        let order = BookOrder::for_sort(&BookSortOptions::default());
+        assert_eq!(order.order_by(), "created_at DESC, id DESC");
+        assert_eq!(order.after(3), "(created_at, id) < ($3::timestamptz, $4::uuid)");
+
+        let order = BookOrder::for_sort(&BookSortOptions {
+            sort_by: Some("series_index".into()),
+            sort_order: Some("asc".into()),
+        });
+        assert_eq!(
+            order.order_by(),
+            "COALESCE(series_index, 'Infinity'::real) ASC, id ASC"
+        );
+        assert_eq!(
+            order.after(2),
+            "(COALESCE(series_index, 'Infinity'::real), id) > ($2::real, $3::uuid)"
+        );
+    }
+
+    #[test]
+    fn test_search_order() {
+        let order = BookOrder::for_search(Some("r"));
+        assert_eq!(order.order_by(), "r DESC, title ASC, id ASC");
+        assert_eq!(
+            order.after(5),
+            "(r < $5::real) OR (r = $5::real AND title > $6::text) \
+             OR (r = $5::real AND title = $6::text AND id > $7::uuid)"
+        );
+
+        let order = BookOrder::for_search(None);
+        assert_eq!(order.after(2), "(title, id) > ($2::text, $3::uuid)");
+    }
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let book = book();
+        let order = BookOrder::for_sort(&BookSortOptions {
+            sort_by: Some("series_index".into()),
+            sort_order: None,
+        });
+        let cursor = order.cursor_after(&book, None);
+        assert_eq!(cursor.keys, vec!["1.5".to_string()]);
+        assert_eq!(order.decode(&cursor.encode()).unwrap(), cursor);
+
+        let ranked = BookOrder::for_search(Some("r"));
+        let cursor = ranked.cursor_after(&book, Some(0.5714286));
+        assert_eq!(cursor.keys, vec!["0.5714286".to_string(), "Dune".to_string()]);
+
+        assert!(order.decode(&cursor.encode()).is_err());
+        assert!(order.decode("not a cursor").is_err());
+    }
+}
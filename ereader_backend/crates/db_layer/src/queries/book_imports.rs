@@ -0,0 +1,53 @@
+//! Book import record database queries.
+
+use crate::pool::DbPool;
+use common::Result;
+use uuid::Uuid;
+
+/// Records of books imported from other libraries, by their id in the source library
+pub struct BookImportQueries;
+
+impl BookImportQueries {
+    /// Get the source ids already imported from a library
+    pub async fn imported_ids(pool: &DbPool, user_id: &str, source: &str) -> Result<Vec<String>> {
+        let ids = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT source_id
+            FROM book_imports
+            WHERE user_id = $1 AND source = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(source)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(ids)
+    }
+
+    /// Record the book a source book was imported as
+    pub async fn record(
+        pool: &DbPool,
+        user_id: &str,
+        source: &str,
+        source_id: &str,
+        book_id: Uuid,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO book_imports (user_id, source, source_id, book_id)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (user_id, source, source_id) DO UPDATE
+            SET book_id = EXCLUDED.book_id, imported_at = NOW()
+            "#,
+        )
+        .bind(user_id)
+        .bind(source)
+        .bind(source_id)
+        .bind(book_id)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,967 @@
+//! Book search query syntax.
+//!
+//! A query is a list of terms that must all match. Bare words and quoted phrases match
+//! titles, authors, series and descriptions; `field:value` terms filter on one field:
+//!
+//! | Term                       | Matches books                                      |
+//! |----------------------------|----------------------------------------------------|
+//! | `title:dune`               | with the text in their title                       |
+//! | `author:"le guin"`         | with the text in their authors' names              |
+//! | `contributor:pevear`       | with the text in the name of anyone they credit    |
+//! | `translator:pevear`        | with the text in their translators' names; also    |
+//! |                            | `editor:`, `illustrator:` and `narrator:`          |
+//! | `tag:scifi`                | tagged exactly `scifi`, ignoring case              |
+//! | `series:earthsea`          | with the text in their series name                 |
+//! | `publisher:tor`            | with the text in their publisher                   |
+//! | `lang:en`                  | in `en` or a regional variant such as `en-GB`      |
+//! | `format:epub`              | in the format                                      |
+//! | `year:>1970`               | published in a year `=`, `<`, `<=`, `>` or `>=`    |
+//! | `year:1970..1979`          | published in a year within the inclusive range     |
+//! | `has:file`                 | with the property; see [`HasProperty`]             |
+//! | `added:30d`                | added in the last 30 days; also `w`, `m` and `y`   |
+//! | `added:>1y`                | added more than a year ago                         |
+//! | `status:reading`           | started but not finished; see [`ReadingStatus`]    |
+//!
+//! A leading `-` excludes a term, `OR` between terms matches either, and parentheses
+//! group terms, as in `(tag:scifi OR tag:fantasy) -tag:read`. Words with an unknown
+//! field prefix, such as `re:zero`, are matched as text.
+//!
+//! Text, `title:`, `author:` and `series:` terms tolerate typos: besides substrings, they
+//! match titles, author names and series whose trigram word similarity to the text reaches
+//! [`FUZZY_MATCH_THRESHOLD`](crate::queries::books::FUZZY_MATCH_THRESHOLD). Results are
+//! ranked by that similarity, which is highest for exact matches.
+
+use std::collections::HashMap;
+
+use common::types::relator;
+use common::{Error, Result};
+
+use crate::models::FINISHED_PROGRESS;
+
+/// Shortest query word a did-you-mean suggestion corrects
+const MIN_CORRECTED_WORD_LEN: usize = 3;
+
+/// Deepest nesting of parentheses a query may use
+const MAX_QUERY_DEPTH: usize = 32;
+
+/// Most words a query may contain
+const MAX_QUERY_TERMS: usize = 100;
+
+/// Field of a `field:value` term
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Title,
+    Author,
+    /// Someone credited in the role given by its MARC relator code, or in any role
+    Contributor(Option<&'static str>),
+    Tag,
+    Series,
+    Publisher,
+    Language,
+    Format,
+    Year,
+    Has,
+    Added,
+    Status,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "title" => Some(Field::Title),
+            "author" | "authors" => Some(Field::Author),
+            "contributor" | "contributors" => Some(Field::Contributor(None)),
+            "editor" => Some(Field::Contributor(Some(relator::EDITOR))),
+            "translator" => Some(Field::Contributor(Some(relator::TRANSLATOR))),
+            "illustrator" => Some(Field::Contributor(Some(relator::ILLUSTRATOR))),
+            "narrator" => Some(Field::Contributor(Some(relator::NARRATOR))),
+            "tag" | "tags" => Some(Field::Tag),
+            "series" => Some(Field::Series),
+            "publisher" => Some(Field::Publisher),
+            "lang" | "language" => Some(Field::Language),
+            "format" => Some(Field::Format),
+            "year" => Some(Field::Year),
+            "has" => Some(Field::Has),
+            "added" => Some(Field::Added),
+            "status" => Some(Field::Status),
+            _ => None,
+        }
+    }
+}
+
+/// Comparison of a numeric term with its value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Comparison {
+    /// Split a leading operator off a value such as `>=1970`, defaulting to equality
+    fn split(value: &str) -> (Self, &str) {
+        [
+            (">=", Comparison::Ge),
+            ("<=", Comparison::Le),
+            (">", Comparison::Gt),
+            ("<", Comparison::Lt),
+            ("=", Comparison::Eq),
+        ]
+        .into_iter()
+        .find_map(|(op, cmp)| value.strip_prefix(op).map(|rest| (cmp, rest)))
+        .unwrap_or((Comparison::Eq, value))
+    }
+
+    fn as_sql(self) -> &'static str {
+        match self {
+            Comparison::Eq => "=",
+            Comparison::Lt => "<",
+            Comparison::Le => "<=",
+            Comparison::Gt => ">",
+            Comparison::Ge => ">=",
+        }
+    }
+}
+
+/// Property required by a `has:` term
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HasProperty {
+    File,
+    Cover,
+    Series,
+    Tags,
+    Description,
+    Isbn,
+    Rating,
+}
+
+impl HasProperty {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "file" => Some(HasProperty::File),
+            "cover" => Some(HasProperty::Cover),
+            "series" => Some(HasProperty::Series),
+            "tags" | "tag" => Some(HasProperty::Tags),
+            "description" => Some(HasProperty::Description),
+            "isbn" => Some(HasProperty::Isbn),
+            "rating" => Some(HasProperty::Rating),
+            _ => None,
+        }
+    }
+
+    fn condition(self) -> &'static str {
+        match self {
+            HasProperty::File => "storage_path IS NOT NULL",
+            HasProperty::Cover => "EXISTS (SELECT 1 FROM covers c WHERE c.book_id = books.id)",
+            HasProperty::Series => "series_name IS NOT NULL",
+            HasProperty::Tags => "cardinality(tags) > 0",
+            HasProperty::Description => "COALESCE(description, '') <> ''",
+            HasProperty::Isbn => "isbn IS NOT NULL",
+            HasProperty::Rating => "rating IS NOT NULL",
+        }
+    }
+}
+
+/// How far the owner has read a book, as required by a `status:` term
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadingStatus {
+    /// Not opened, or opened without moving past the start
+    Unread,
+    /// Started but not finished
+    Reading,
+    /// Read to at least [`FINISHED_PROGRESS`]
+    Finished,
+}
+
+impl ReadingStatus {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "unread" | "new" => Some(ReadingStatus::Unread),
+            "reading" | "started" | "in-progress" => Some(ReadingStatus::Reading),
+            "finished" | "read" => Some(ReadingStatus::Finished),
+            _ => None,
+        }
+    }
+
+    fn condition(self) -> String {
+        let progress = |condition: &str| {
+            format!(
+                "EXISTS (SELECT 1 FROM reading_states rs WHERE rs.user_id = books.user_id \
+                 AND rs.book_id = books.id AND {condition})"
+            )
+        };
+        let started = "(rs.location->>'progress')::real > 0";
+        let finished = format!("(rs.location->>'progress')::real >= {FINISHED_PROGRESS}");
+        match self {
+            ReadingStatus::Unread => format!("NOT {}", progress(started)),
+            ReadingStatus::Reading => {
+                format!("{} AND NOT {}", progress(started), progress(&finished))
+            }
+            ReadingStatus::Finished => progress(&finished),
+        }
+    }
+}
+
+/// Single condition of a search query
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchTerm {
+    /// Text in the title, the authors, the series or the description
+    Text(String),
+    Title(String),
+    Author(String),
+    /// Text in the name of someone credited in the role given by its MARC relator code,
+    /// or in any role, authors included
+    Contributor(Option<String>, String),
+    Tag(String),
+    Series(String),
+    Publisher(String),
+    Language(String),
+    Format(String),
+    Year(Comparison, i32),
+    Has(HasProperty),
+    /// Days since the book was added to the library, compared with the given number
+    Added(Comparison, i32),
+    Status(ReadingStatus),
+}
+
+/// Boolean combination of search terms
+#[derive(Debug, Clone, PartialEq)]
+pub enum SearchExpr {
+    Term(SearchTerm),
+    Not(Box<SearchExpr>),
+    And(Vec<SearchExpr>),
+    Or(Vec<SearchExpr>),
+}
+
+/// Parsed book search query; an empty query matches every book
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchQuery {
+    expr: Option<SearchExpr>,
+}
+
+impl SearchQuery {
+    /// Parse a query, failing on unbalanced or too deeply nested parentheses, too many
+    /// words and malformed `year:`, `has:`, `added:` or `status:` terms
+    pub fn parse(input: &str) -> Result<Self> {
+        let tokens = tokenize(input);
+        let words = tokens.iter().filter(|t| matches!(t, Token::Word { .. })).count();
+        if words > MAX_QUERY_TERMS {
+            return Err(Error::validation_field(
+                "q",
+                &format!("has more than {MAX_QUERY_TERMS} terms"),
+            ));
+        }
+
+        let mut parser = Parser {
+            tokens: tokens.into_iter().peekable(),
+        };
+        let terms = parser.sequence(0)?;
+        Ok(Self {
+            expr: combine(terms, SearchExpr::And),
+        })
+    }
+
+    /// Condition the query places on books, or `None` if it matches every book
+    pub fn expr(&self) -> Option<&SearchExpr> {
+        self.expr.as_ref()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.expr.is_none()
+    }
+
+    /// Lowercase words of the typo-tolerant terms a book should match, for did-you-mean
+    /// suggestions
+    pub fn fuzzy_words(&self) -> Vec<String> {
+        let mut words: Vec<String> = self
+            .fuzzy_texts()
+            .iter()
+            .flat_map(|text| words(text))
+            .map(|(_, word)| word.to_lowercase())
+            .filter(|word| word.chars().count() >= MIN_CORRECTED_WORD_LEN)
+            .collect();
+        words.sort();
+        words.dedup();
+        words
+    }
+
+    /// Typo-tolerant texts not under a negation, which rank the results
+    fn fuzzy_texts(&self) -> Vec<&str> {
+        fn collect<'a>(expr: &'a SearchExpr, texts: &mut Vec<&'a str>) {
+            match expr {
+                SearchExpr::Term(
+                    SearchTerm::Text(text)
+                    | SearchTerm::Title(text)
+                    | SearchTerm::Author(text)
+                    | SearchTerm::Series(text),
+                ) => texts.push(text),
+                SearchExpr::Term(_) | SearchExpr::Not(_) => {}
+                SearchExpr::And(exprs) | SearchExpr::Or(exprs) => {
+                    exprs.iter().for_each(|e| collect(e, texts))
+                }
+            }
+        }
+
+        let mut texts = Vec::new();
+        if let Some(expr) = &self.expr {
+            collect(expr, &mut texts);
+        }
+        texts
+    }
+
+    /// SQL condition on `books` with parameters numbered from `first_param`
+    pub(crate) fn to_sql(&self, first_param: usize) -> SearchSql {
+        let mut builder = SqlBuilder {
+            next_param: first_param,
+            params: Vec::new(),
+        };
+        let condition = match &self.expr {
+            Some(expr) => builder.expr(expr),
+            None => "TRUE".to_string(),
+        };
+
+        let texts = self.fuzzy_texts();
+        let rank = (!texts.is_empty()).then(|| {
+            let p = builder.bind(SearchParam::Text(texts.join(" ").to_lowercase()));
+            format!(
+                "GREATEST(word_similarity({p}, LOWER(title)), \
+                 word_similarity({p}, LOWER(book_authors_text(authors))), \
+                 word_similarity({p}, LOWER(COALESCE(series_name, ''))))"
+            )
+        });
+
+        SearchSql {
+            condition,
+            rank,
+            params: builder.params,
+        }
+    }
+}
+
+/// Rewrite a query with misspelled words replaced by their corrections, keyed by the
+/// lowercase word, or `None` if no word of the query has a correction
+///
+/// Field names, such as `author` in `author:tolkein`, are left alone.
+pub fn correct_query(input: &str, corrections: &HashMap<String, String>) -> Option<String> {
+    let mut corrected = String::with_capacity(input.len());
+    let mut last = 0;
+    let mut changed = false;
+
+    for (start, word) in words(input) {
+        let end = start + word.len();
+        if input[end..].starts_with(':') {
+            continue;
+        }
+        if let Some(correction) = corrections.get(&word.to_lowercase()) {
+            corrected.push_str(&input[last..start]);
+            corrected.push_str(correction);
+            last = end;
+            changed = true;
+        }
+    }
+    corrected.push_str(&input[last..]);
+
+    changed.then_some(corrected)
+}
+
+/// Runs of alphanumeric characters in text, with their byte offsets
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+/// Parameter bound to a search condition
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SearchParam {
+    Text(String),
+    Int(i32),
+}
+
+/// SQL condition of a search query and the parameters to bind, in order
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SearchSql {
+    pub condition: String,
+    /// Relevance of a matching book from 0 to 1, if the query has typo-tolerant terms
+    pub rank: Option<String>,
+    pub params: Vec<SearchParam>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Open { negated: bool },
+    Close,
+    Or,
+    Word {
+        negated: bool,
+        field: Option<String>,
+        value: String,
+    },
+}
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        if c == ')' {
+            chars.next();
+            tokens.push(Token::Close);
+            continue;
+        }
+
+        let mut negated = false;
+        if c == '-' {
+            chars.next();
+            match chars.peek() {
+                Some(&next) if !next.is_whitespace() && next != ')' => negated = true,
+                // A lone dash excludes nothing
+                _ => continue,
+            }
+        }
+        if chars.next_if_eq(&'(').is_some() {
+            tokens.push(Token::Open { negated });
+            continue;
+        }
+
+        let mut bare = String::new();
+        while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | '"')) {
+            bare.push(c);
+        }
+
+        let token = if (bare.is_empty() || bare.ends_with(':')) && chars.next_if_eq(&'"').is_some() {
+            // An unterminated phrase runs to the end of the query
+            let value: String = chars.by_ref().take_while(|&c| c != '"').collect();
+            let field = bare.strip_suffix(':').filter(|f| !f.is_empty()).map(str::to_string);
+            Token::Word { negated, field, value }
+        } else if bare == "OR" && !negated {
+            Token::Or
+        } else {
+            match bare.split_once(':') {
+                Some((field, value)) if !field.is_empty() && !value.is_empty() => Token::Word {
+                    negated,
+                    field: Some(field.to_string()),
+                    value: value.to_string(),
+                },
+                _ => Token::Word { negated, field: None, value: bare },
+            }
+        };
+        tokens.push(token);
+    }
+
+    tokens
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser {
+    /// Terms up to the closing parenthesis at `depth`, or the end of the query at depth zero
+    fn sequence(&mut self, depth: usize) -> Result<Vec<SearchExpr>> {
+        let mut terms = Vec::new();
+        loop {
+            match self.tokens.peek() {
+                None if depth > 0 => {
+                    return Err(Error::validation_field("q", "missing closing parenthesis"));
+                }
+                None => return Ok(terms),
+                Some(Token::Close) if depth == 0 => {
+                    return Err(Error::validation_field("q", "unexpected closing parenthesis"));
+                }
+                Some(Token::Close) => {
+                    self.tokens.next();
+                    return Ok(terms);
+                }
+                Some(_) => terms.extend(self.alternatives(depth)?),
+            }
+        }
+    }
+
+    /// Terms joined by `OR`
+    fn alternatives(&mut self, depth: usize) -> Result<Option<SearchExpr>> {
+        let mut terms = Vec::new();
+        terms.extend(self.unary(depth)?);
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            terms.extend(self.unary(depth)?);
+        }
+        Ok(combine(terms, SearchExpr::Or))
+    }
+
+    fn unary(&mut self, depth: usize) -> Result<Option<SearchExpr>> {
+        let (negated, expr) = match self.tokens.peek() {
+            None | Some(Token::Close) => return Ok(None),
+            Some(Token::Or) => {
+                // A stray OR at the start or end of a group joins nothing
+                self.tokens.next();
+                return Ok(None);
+            }
+            Some(Token::Open { .. }) => {
+                if depth >= MAX_QUERY_DEPTH {
+                    return Err(Error::validation_field(
+                        "q",
+                        &format!("nests parentheses more than {MAX_QUERY_DEPTH} deep"),
+                    ));
+                }
+                let Some(Token::Open { negated }) = self.tokens.next() else {
+                    unreachable!()
+                };
+                (negated, combine(self.sequence(depth + 1)?, SearchExpr::And))
+            }
+            Some(Token::Word { .. }) => {
+                let Some(Token::Word { negated, field, value }) = self.tokens.next() else {
+                    unreachable!()
+                };
+                (negated, word(field.as_deref(), value)?)
+            }
+        };
+
+        Ok(if negated {
+            expr.map(|e| SearchExpr::Not(Box::new(e)))
+        } else {
+            expr
+        })
+    }
+}
+
+/// Expression for a word, or `None` if its value is empty
+fn word(field: Option<&str>, value: String) -> Result<Option<SearchExpr>> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    let value = value.trim().to_string();
+
+    let Some(name) = field else {
+        return Ok(Some(SearchExpr::Term(SearchTerm::Text(value))));
+    };
+    let Some(field) = Field::from_name(name) else {
+        return Ok(Some(SearchExpr::Term(SearchTerm::Text(format!("{}:{}", name, value)))));
+    };
+
+    let term = match field {
+        Field::Title => SearchTerm::Title(value),
+        Field::Author => SearchTerm::Author(value),
+        Field::Contributor(role) => SearchTerm::Contributor(role.map(str::to_string), value),
+        Field::Tag => SearchTerm::Tag(value),
+        Field::Series => SearchTerm::Series(value),
+        Field::Publisher => SearchTerm::Publisher(value),
+        Field::Language => SearchTerm::Language(value),
+        Field::Format => SearchTerm::Format(value),
+        Field::Year => return year(&value).map(Some),
+        Field::Has => SearchTerm::Has(HasProperty::from_name(&value).ok_or_else(|| {
+            Error::validation_field("q", &format!("unknown property has:{}", value))
+        })?),
+        Field::Added => return added(&value).map(|term| Some(SearchExpr::Term(term))),
+        Field::Status => SearchTerm::Status(ReadingStatus::from_name(&value).ok_or_else(|| {
+            Error::validation_field(
+                "q",
+                &format!("unknown status '{}', expected unread, reading or finished", value),
+            )
+        })?),
+    };
+    Ok(Some(SearchExpr::Term(term)))
+}
+
+/// Expression for a `year:` value such as `1970`, `>=1970` or `1970..1979`
+fn year(value: &str) -> Result<SearchExpr> {
+    let parse = |year: &str| {
+        year.trim().parse::<i32>().map_err(|_| {
+            Error::validation_field("q", &format!("invalid year '{}', expected a number such as year:>1970", year))
+        })
+    };
+
+    if let Some((from, to)) = value.split_once("..") {
+        let mut bounds = Vec::new();
+        if !from.is_empty() {
+            bounds.push(SearchExpr::Term(SearchTerm::Year(Comparison::Ge, parse(from)?)));
+        }
+        if !to.is_empty() {
+            bounds.push(SearchExpr::Term(SearchTerm::Year(Comparison::Le, parse(to)?)));
+        }
+        return combine(bounds, SearchExpr::And)
+            .ok_or_else(|| Error::validation_field("q", "year range needs at least one bound"));
+    }
+
+    let (cmp, year) = Comparison::split(value);
+    Ok(SearchExpr::Term(SearchTerm::Year(cmp, parse(year)?)))
+}
+
+/// Term for an `added:` value such as `30d`, `<2w` or `>1y`, an age in days, weeks,
+/// months or years; a bare age means at most that long ago
+fn added(value: &str) -> Result<SearchTerm> {
+    let invalid = || {
+        Error::validation_field(
+            "q",
+            &format!("invalid age '{}', expected a number of days such as added:30d", value),
+        )
+    };
+
+    let (cmp, age) = match Comparison::split(value) {
+        (Comparison::Eq, age) => (Comparison::Le, age),
+        split => split,
+    };
+    let unit_start = age.find(|c: char| !c.is_ascii_digit()).unwrap_or(age.len());
+    let amount: i32 = age[..unit_start].parse().map_err(|_| invalid())?;
+    let days_per_unit = match age[unit_start..].to_ascii_lowercase().as_str() {
+        "" | "d" => 1,
+        "w" => 7,
+        "m" => 30,
+        "y" => 365,
+        _ => return Err(invalid()),
+    };
+    let days = amount.checked_mul(days_per_unit).ok_or_else(invalid)?;
+    Ok(SearchTerm::Added(cmp, days))
+}
+
+/// Single expression, or `join` of several
+fn combine(mut exprs: Vec<SearchExpr>, join: fn(Vec<SearchExpr>) -> SearchExpr) -> Option<SearchExpr> {
+    match exprs.len() {
+        0 => None,
+        1 => exprs.pop(),
+        _ => Some(join(exprs)),
+    }
+}
+
+/// Escape LIKE wildcards so text matches literally
+pub(crate) fn escape_like(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Lowercase title, as indexed for trigram matching
+const TITLE: &str = "LOWER(title)";
+
+/// Lowercase author names, as indexed for trigram matching
+const AUTHORS: &str = "LOWER(book_authors_text(authors))";
+
+/// Lowercase series name, as indexed for trigram matching
+const SERIES: &str = "LOWER(COALESCE(series_name, ''))";
+
+struct SqlBuilder {
+    next_param: usize,
+    params: Vec<SearchParam>,
+}
+
+impl SqlBuilder {
+    fn bind(&mut self, param: SearchParam) -> String {
+        self.params.push(param);
+        self.next_param += 1;
+        format!("${}", self.next_param - 1)
+    }
+
+    /// Bind a case-insensitive LIKE pattern matching text anywhere in a value
+    fn bind_contains(&mut self, text: &str) -> String {
+        self.bind(SearchParam::Text(format!("%{}%", escape_like(&text.to_lowercase()))))
+    }
+
+    /// Condition matching lowercase columns containing text or similar to it
+    fn fuzzy(&mut self, text: &str, columns: &[&str]) -> String {
+        let pattern = self.bind_contains(text);
+        self.fuzzy_with(&pattern, text, columns)
+    }
+
+    /// [`Self::fuzzy`] with the LIKE pattern already bound
+    fn fuzzy_with(&mut self, pattern: &str, text: &str, columns: &[&str]) -> String {
+        let text = self.bind(SearchParam::Text(text.to_lowercase()));
+        let contains = columns.iter().map(|c| format!("{c} LIKE {pattern}"));
+        let similar = columns.iter().map(|c| format!("{text} <% {c}"));
+        contains.chain(similar).collect::<Vec<_>>().join(" OR ")
+    }
+
+    fn expr(&mut self, expr: &SearchExpr) -> String {
+        match expr {
+            SearchExpr::Term(term) => self.term(term),
+            SearchExpr::Not(expr) => format!("NOT ({})", self.expr(expr)),
+            SearchExpr::And(exprs) => self.join(exprs, " AND "),
+            SearchExpr::Or(exprs) => self.join(exprs, " OR "),
+        }
+    }
+
+    fn join(&mut self, exprs: &[SearchExpr], separator: &str) -> String {
+        let parts: Vec<String> = exprs.iter().map(|e| format!("({})", self.expr(e))).collect();
+        parts.join(separator)
+    }
+
+    fn term(&mut self, term: &SearchTerm) -> String {
+        match term {
+            SearchTerm::Text(text) => {
+                let pattern = self.bind_contains(text);
+                format!(
+                    "{} OR LOWER(COALESCE(description, '')) LIKE {pattern}",
+                    self.fuzzy_with(&pattern, text, &[TITLE, AUTHORS, SERIES])
+                )
+            }
+            SearchTerm::Title(text) => self.fuzzy(text, &[TITLE]),
+            SearchTerm::Author(text) => self.fuzzy(text, &[AUTHORS]),
+            SearchTerm::Contributor(role, text) => {
+                let pattern = self.bind_contains(text);
+                let credited = format!(
+                    "EXISTS (SELECT 1 FROM jsonb_array_elements(contributors) c \
+                     WHERE LOWER(c->>'name') LIKE {pattern}{})",
+                    match role {
+                        Some(role) => format!(" AND c->>'role' = {}", self.bind(SearchParam::Text(role.clone()))),
+                        None => String::new(),
+                    }
+                );
+                match role {
+                    Some(_) => credited,
+                    None => format!("{AUTHORS} LIKE {pattern} OR {credited}"),
+                }
+            }
+            SearchTerm::Tag(tag) => format!(
+                "EXISTS (SELECT 1 FROM unnest(tags) t WHERE LOWER(t) = {})",
+                self.bind(SearchParam::Text(tag.to_lowercase()))
+            ),
+            SearchTerm::Series(text) => self.fuzzy(text, &[SERIES]),
+            SearchTerm::Publisher(text) => {
+                format!("LOWER(COALESCE(publisher, '')) LIKE {}", self.bind_contains(text))
+            }
+            SearchTerm::Language(language) => {
+                let language = language.to_lowercase().replace('_', "-");
+                let region = format!("{}-%", escape_like(&language));
+                format!(
+                    "LOWER(COALESCE(language, '')) = {} OR LOWER(COALESCE(language, '')) LIKE {}",
+                    self.bind(SearchParam::Text(language)),
+                    self.bind(SearchParam::Text(region))
+                )
+            }
+            SearchTerm::Format(format) => format!(
+                "COALESCE(format::text, '') = {}",
+                self.bind(SearchParam::Text(format.to_lowercase()))
+            ),
+            SearchTerm::Year(cmp, year) => format!(
+                "COALESCE(substring(published_date FROM '^[0-9]{{4}}')::int {} {}, false)",
+                cmp.as_sql(),
+                self.bind(SearchParam::Int(*year))
+            ),
+            SearchTerm::Has(property) => property.condition().to_string(),
+            // Comparing the age with a number of days compares the creation time the other way
+            SearchTerm::Added(cmp, days) => format!(
+                "now() - created_at {} make_interval(days => {})",
+                cmp.as_sql(),
+                self.bind(SearchParam::Int(*days))
+            ),
+            SearchTerm::Status(status) => status.condition(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(term: SearchTerm) -> SearchExpr {
+        SearchExpr::Term(term)
+    }
+
+    fn not(expr: SearchExpr) -> SearchExpr {
+        SearchExpr::Not(Box::new(expr))
+    }
+
+    fn parse(input: &str) -> SearchExpr {
+        SearchQuery::parse(input).unwrap().expr.unwrap()
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        assert_eq!(
+            parse(r#"author:"le guin" tag:scifi -tag:read series:earthsea lang:en format:epub year:>1970 has:file"#),
+            SearchExpr::And(vec![
+                term(SearchTerm::Author("le guin".into())),
+                term(SearchTerm::Tag("scifi".into())),
+                not(term(SearchTerm::Tag("read".into()))),
+                term(SearchTerm::Series("earthsea".into())),
+                term(SearchTerm::Language("en".into())),
+                term(SearchTerm::Format("epub".into())),
+                term(SearchTerm::Year(Comparison::Gt, 1970)),
+                term(SearchTerm::Has(HasProperty::File)),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_text() {
+        assert_eq!(parse("dune"), term(SearchTerm::Text("dune".into())));
+        assert_eq!(
+            parse(r#""left hand" re:zero"#),
+            SearchExpr::And(vec![
+                term(SearchTerm::Text("left hand".into())),
+                term(SearchTerm::Text("re:zero".into())),
+            ])
+        );
+        assert_eq!(parse(r#"title:"unterminated phrase"#), term(SearchTerm::Title("unterminated phrase".into())));
+        assert!(SearchQuery::parse("  - \"\" ").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_or_and_groups() {
+        assert_eq!(
+            parse("tag:scifi OR tag:fantasy year:1970..1979"),
+            SearchExpr::And(vec![
+                SearchExpr::Or(vec![
+                    term(SearchTerm::Tag("scifi".into())),
+                    term(SearchTerm::Tag("fantasy".into())),
+                ]),
+                SearchExpr::And(vec![
+                    term(SearchTerm::Year(Comparison::Ge, 1970)),
+                    term(SearchTerm::Year(Comparison::Le, 1979)),
+                ]),
+            ])
+        );
+        assert_eq!(
+            parse("-(tag:a OR tag:b) OR"),
+            not(SearchExpr::Or(vec![
+                term(SearchTerm::Tag("a".into())),
+                term(SearchTerm::Tag("b".into())),
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(SearchQuery::parse("(tag:a").is_err());
+        assert!(SearchQuery::parse("tag:a)").is_err());
+        assert!(SearchQuery::parse("year:soon").is_err());
+        assert!(SearchQuery::parse("year:..").is_err());
+        assert!(SearchQuery::parse("has:everything").is_err());
+        assert!(SearchQuery::parse("added:soon").is_err());
+        assert!(SearchQuery::parse("added:3x").is_err());
+        assert!(SearchQuery::parse("status:abandoned").is_err());
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let nested = |depth: usize| format!("{}tag:a{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(parse(&nested(MAX_QUERY_DEPTH)), term(SearchTerm::Tag("a".into())));
+        assert!(SearchQuery::parse(&nested(MAX_QUERY_DEPTH + 1)).is_err());
+        assert!(SearchQuery::parse(&nested(5000)).is_err());
+        assert!(SearchQuery::parse(&"-(".repeat(5000)).is_err());
+
+        let words = |count: usize| vec!["dune"; count].join(" OR ");
+        assert!(SearchQuery::parse(&words(MAX_QUERY_TERMS)).is_ok());
+        assert!(SearchQuery::parse(&words(MAX_QUERY_TERMS + 1)).is_err());
+    }
+
+    #[test]
+    fn test_parse_added_and_status() {
+        assert_eq!(
+            parse("tag:unread-pile lang:de added:30d"),
+            SearchExpr::And(vec![
+                term(SearchTerm::Tag("unread-pile".into())),
+                term(SearchTerm::Language("de".into())),
+                term(SearchTerm::Added(Comparison::Le, 30)),
+            ])
+        );
+        assert_eq!(parse("added:>1y"), term(SearchTerm::Added(Comparison::Gt, 365)));
+        assert_eq!(parse("added:<2W"), term(SearchTerm::Added(Comparison::Lt, 14)));
+        assert_eq!(parse("status:reading"), term(SearchTerm::Status(ReadingStatus::Reading)));
+        assert_eq!(
+            parse("-status:finished"),
+            not(term(SearchTerm::Status(ReadingStatus::Finished)))
+        );
+    }
+
+    #[test]
+    fn test_added_and_status_sql() {
+        let sql = SearchQuery::parse("added:30d status:finished").unwrap().to_sql(2);
+        assert_eq!(
+            sql.condition,
+            "(now() - created_at <= make_interval(days => $2)) AND \
+             (EXISTS (SELECT 1 FROM reading_states rs WHERE rs.user_id = books.user_id \
+             AND rs.book_id = books.id AND (rs.location->>'progress')::real >= 0.99))"
+        );
+        assert_eq!(sql.rank, None);
+        assert_eq!(sql.params, vec![SearchParam::Int(30)]);
+    }
+
+    #[test]
+    fn test_to_sql() {
+        let sql = SearchQuery::parse("-tag:Read 50%_Off year:<=2000 has:series")
+            .unwrap()
+            .to_sql(2);
+        assert_eq!(
+            sql.condition,
+            "(NOT (EXISTS (SELECT 1 FROM unnest(tags) t WHERE LOWER(t) = $2))) AND \
+             (LOWER(title) LIKE $3 OR LOWER(book_authors_text(authors)) LIKE $3 \
+             OR LOWER(COALESCE(series_name, '')) LIKE $3 OR $4 <% LOWER(title) \
+             OR $4 <% LOWER(book_authors_text(authors)) OR $4 <% LOWER(COALESCE(series_name, '')) \
+             OR LOWER(COALESCE(description, '')) LIKE $3) AND \
+             (COALESCE(substring(published_date FROM '^[0-9]{4}')::int <= $5, false)) AND \
+             (series_name IS NOT NULL)"
+        );
+        assert_eq!(
+            sql.rank.as_deref(),
+            Some(
+                "GREATEST(word_similarity($6, LOWER(title)), \
+                 word_similarity($6, LOWER(book_authors_text(authors))), \
+                 word_similarity($6, LOWER(COALESCE(series_name, ''))))"
+            )
+        );
+        assert_eq!(
+            sql.params,
+            vec![
+                SearchParam::Text("read".into()),
+                SearchParam::Text("%50\\%\\_off%".into()),
+                SearchParam::Text("50%_off".into()),
+                SearchParam::Int(2000),
+                SearchParam::Text("50%_off".into()),
+            ]
+        );
+
+        let all = SearchQuery::default().to_sql(2);
+        assert_eq!(all.condition, "TRUE");
+        assert_eq!(all.rank, None);
+        assert!(all.params.is_empty());
+    }
+
+    #[test]
+    fn test_contributor_sql() {
+        let sql = SearchQuery::parse("translator:Pevear contributor:pevear").unwrap().to_sql(2);
+        assert_eq!(
+            sql.condition,
+            "(EXISTS (SELECT 1 FROM jsonb_array_elements(contributors) c \
+             WHERE LOWER(c->>'name') LIKE $2 AND c->>'role' = $3)) AND \
+             (LOWER(book_authors_text(authors)) LIKE $4 OR EXISTS (SELECT 1 FROM \
+             jsonb_array_elements(contributors) c WHERE LOWER(c->>'name') LIKE $4))"
+        );
+        assert_eq!(
+            sql.params,
+            vec![
+                SearchParam::Text("%pevear%".into()),
+                SearchParam::Text("trl".into()),
+                SearchParam::Text("%pevear%".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fuzzy_words() {
+        let query = SearchQuery::parse(r#"Tolkein author:"J.R.R. Tolkein" -title:silmarillion tag:fantasy"#).unwrap();
+        assert_eq!(query.fuzzy_words(), vec!["tolkein".to_string()]);
+    }
+
+    #[test]
+    fn test_correct_query() {
+        let corrections = HashMap::from([
+            ("tolkein".to_string(), "tolkien".to_string()),
+            ("author".to_string(), "autor".to_string()),
+        ]);
+        assert_eq!(
+            correct_query(r#"hobbit author:"Tolkein" or Tolkein"#, &corrections).as_deref(),
+            Some(r#"hobbit author:"tolkien" or tolkien"#)
+        );
+        assert_eq!(correct_query("hobbit", &corrections), None);
+    }
+}
@@ -0,0 +1,271 @@
+//! Series database queries.
+
+use crate::models::{Book, Series, SeriesWithProgress, UpdateSeries, FINISHED_PROGRESS};
+use crate::pool::DbPool;
+use common::{Error, Paginated, Pagination, Result};
+use uuid::Uuid;
+
+/// Series-related database queries
+pub struct SeriesQueries;
+
+impl SeriesQueries {
+    /// List a user's series that have books, by name, with the user's progress through each
+    pub async fn list_for_user(
+        pool: &DbPool,
+        user_id: &str,
+        pagination: &Pagination,
+    ) -> Result<Paginated<SeriesWithProgress>> {
+        let total = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*) FROM series s
+            WHERE s.user_id = $1 AND EXISTS (SELECT 1 FROM books b WHERE b.series_id = s.id)
+            "#,
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        let items = sqlx::query_as::<_, SeriesWithProgress>(
+            r#"
+            SELECT s.id, s.user_id, s.name, s.description, s.aliases, s.created_at, s.updated_at,
+                   COUNT(*) AS book_count,
+                   COUNT(*) FILTER (WHERE (rs.location->>'progress')::real >= $2)
+                       AS finished_count,
+                   COUNT(*) FILTER (WHERE (rs.location->>'progress')::real > 0
+                                      AND (rs.location->>'progress')::real < $2)
+                       AS in_progress_count
+            FROM series s
+            JOIN books b ON b.series_id = s.id
+            LEFT JOIN reading_states rs ON rs.user_id = s.user_id AND rs.book_id = b.id
+            WHERE s.user_id = $1
+            GROUP BY s.id
+            ORDER BY LOWER(s.name), s.id
+            LIMIT $3 OFFSET $4
+            "#,
+        )
+        .bind(user_id)
+        .bind(FINISHED_PROGRESS)
+        .bind(pagination.limit)
+        .bind(pagination.offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(Paginated::new(items, total, pagination))
+    }
+
+    /// Get a series by ID for a specific user (ensures ownership)
+    pub async fn get_by_id_for_user(pool: &DbPool, id: Uuid, user_id: &str) -> Result<Option<Series>> {
+        let series = sqlx::query_as::<_, Series>(
+            r#"
+            SELECT id, user_id, name, description, aliases, created_at, updated_at
+            FROM series
+            WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(series)
+    }
+
+    /// Get a user's series with the user's progress through it
+    pub async fn get_with_progress(
+        pool: &DbPool,
+        id: Uuid,
+        user_id: &str,
+    ) -> Result<Option<SeriesWithProgress>> {
+        let series = sqlx::query_as::<_, SeriesWithProgress>(
+            r#"
+            SELECT s.id, s.user_id, s.name, s.description, s.aliases, s.created_at, s.updated_at,
+                   COUNT(b.id) AS book_count,
+                   COUNT(b.id) FILTER (WHERE (rs.location->>'progress')::real >= $3)
+                       AS finished_count,
+                   COUNT(b.id) FILTER (WHERE (rs.location->>'progress')::real > 0
+                                         AND (rs.location->>'progress')::real < $3)
+                       AS in_progress_count
+            FROM series s
+            LEFT JOIN books b ON b.series_id = s.id
+            LEFT JOIN reading_states rs ON rs.user_id = s.user_id AND rs.book_id = b.id
+            WHERE s.id = $1 AND s.user_id = $2
+            GROUP BY s.id
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(FINISHED_PROGRESS)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(series)
+    }
+
+    /// Get the books of a series in reading order: by series index, with unnumbered books
+    /// last
+    pub async fn get_books(pool: &DbPool, series_id: Uuid) -> Result<Vec<Book>> {
+        let books = sqlx::query_as::<_, Book>(
+            r#"
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
+                   format, content_hash, file_size, storage_path, original_filename,
+                   created_at, updated_at
+            FROM books
+            WHERE series_id = $1
+            ORDER BY series_index NULLS LAST, title, id
+            "#,
+        )
+        .bind(series_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(books)
+    }
+
+    /// Rename or describe a user's series; its books take the new name
+    ///
+    /// Fails with a conflict if the user has another series of the new name.
+    pub async fn update(pool: &DbPool, id: Uuid, user_id: &str, data: &UpdateSeries) -> Result<Series> {
+        let mut tx = pool.begin().await?;
+
+        let name = data.name.as_deref().map(str::trim);
+        if name == Some("") {
+            return Err(Error::validation_field("name", "series name can't be empty"));
+        }
+        if let Some(name) = name {
+            let taken = sqlx::query_scalar::<_, bool>(
+                r#"
+                SELECT EXISTS(
+                    SELECT 1 FROM series WHERE user_id = $1 AND LOWER(name) = LOWER($2) AND id <> $3
+                )
+                "#,
+            )
+            .bind(user_id)
+            .bind(name)
+            .bind(id)
+            .fetch_one(&mut *tx)
+            .await?;
+            if taken {
+                return Err(Error::Conflict(format!(
+                    "a series named '{}' already exists; merge the series instead",
+                    name
+                )));
+            }
+        }
+
+        let series = sqlx::query_as::<_, Series>(
+            r#"
+            UPDATE series
+            SET
+                name = COALESCE($3, name),
+                description = COALESCE($4, description)
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, user_id, name, description, aliases, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(name)
+        .bind(&data.description)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::not_found_resource("series", id))?;
+
+        if name.is_some() {
+            sqlx::query(
+                "UPDATE books SET series_name = $2 WHERE series_id = $1 AND series_name IS DISTINCT FROM $2",
+            )
+            .bind(id)
+            .bind(&series.name)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+
+        Ok(series)
+    }
+
+    /// Merge a user's series into one of them: their books move to the target series,
+    /// taking its name, and the merged series are deleted
+    ///
+    /// The merged series' names and aliases become aliases of the target, so books added
+    /// later under those names join it too.
+    pub async fn merge(pool: &DbPool, user_id: &str, into_id: Uuid, from_ids: &[Uuid]) -> Result<Series> {
+        let mut tx = pool.begin().await?;
+
+        let target = sqlx::query_as::<_, Series>(
+            r#"
+            SELECT id, user_id, name, description, aliases, created_at, updated_at
+            FROM series
+            WHERE id = $1 AND user_id = $2
+            FOR UPDATE
+            "#,
+        )
+        .bind(into_id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::not_found_resource("series", into_id))?;
+
+        let from_ids: Vec<Uuid> = from_ids.iter().copied().filter(|id| *id != into_id).collect();
+        let merged = sqlx::query_as::<_, (Uuid, String, Vec<String>)>(
+            "SELECT id, name, aliases FROM series WHERE user_id = $1 AND id = ANY($2) FOR UPDATE",
+        )
+        .bind(user_id)
+        .bind(&from_ids)
+        .fetch_all(&mut *tx)
+        .await?;
+        if let Some(missing) = from_ids.iter().find(|id| !merged.iter().any(|(found, _, _)| found == *id)) {
+            return Err(Error::not_found_resource("series", missing));
+        }
+
+        // Series names match ignoring case and surrounding spaces, so aliases differing
+        // only in those are kept once
+        let key = |name: &str| name.trim().to_lowercase();
+        let mut keys: Vec<String> = std::iter::once(&target.name)
+            .chain(&target.aliases)
+            .map(|name| key(name))
+            .collect();
+        let mut aliases = target.aliases.clone();
+        for (_, name, merged_aliases) in merged {
+            for alias in std::iter::once(name).chain(merged_aliases) {
+                if !keys.contains(&key(&alias)) {
+                    keys.push(key(&alias));
+                    aliases.push(alias);
+                }
+            }
+        }
+
+        // Renaming the books links them to the target series
+        sqlx::query("UPDATE books SET series_name = $3 WHERE user_id = $1 AND series_id = ANY($2)")
+            .bind(user_id)
+            .bind(&from_ids)
+            .bind(&target.name)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("DELETE FROM series WHERE user_id = $1 AND id = ANY($2)")
+            .bind(user_id)
+            .bind(&from_ids)
+            .execute(&mut *tx)
+            .await?;
+
+        let target = sqlx::query_as::<_, Series>(
+            r#"
+            UPDATE series SET aliases = $2
+            WHERE id = $1
+            RETURNING id, user_id, name, description, aliases, created_at, updated_at
+            "#,
+        )
+        .bind(into_id)
+        .bind(&aliases)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(target)
+    }
+}
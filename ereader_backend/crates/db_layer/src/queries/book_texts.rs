@@ -0,0 +1,170 @@
+//! Book text database queries for full-text search.
+
+use crate::models::book_text::{snippet_html, MATCH_END, MATCH_START};
+use crate::models::{BookTextHit, BookTextSnippet, CreateBookText};
+use crate::pool::DbPool;
+use common::{Paginated, Pagination, Result};
+use uuid::Uuid;
+
+/// The query parsed by every text search configuration, so each chapter can be matched
+/// against the query parsed the way its text was
+const QUERIES_CTE: &str = r#"
+    queries AS (
+        SELECT c.oid::regconfig AS config, websearch_to_tsquery(c.oid::regconfig, $2) AS query
+        FROM pg_ts_config c
+    )
+"#;
+
+/// Words of context around the matches in a snippet
+const SNIPPET_MIN_WORDS: i32 = 15;
+const SNIPPET_MAX_WORDS: i32 = 35;
+
+/// Book text database queries
+pub struct BookTextQueries;
+
+impl BookTextQueries {
+    /// Replace the stored text of a book's chapters
+    ///
+    /// `config` names the text search configuration for the book's language; `simple` is
+    /// used when the database doesn't have it.
+    pub async fn replace_for_book(
+        pool: &DbPool,
+        book_id: Uuid,
+        config: &str,
+        chapters: &[CreateBookText],
+    ) -> Result<()> {
+        let spine_indexes: Vec<i32> = chapters.iter().map(|c| c.spine_index).collect();
+        let titles: Vec<Option<String>> = chapters.iter().map(|c| c.title.clone()).collect();
+        let contents: Vec<&str> = chapters.iter().map(|c| c.content.as_str()).collect();
+
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("DELETE FROM book_text WHERE book_id = $1")
+            .bind(book_id)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO book_text (book_id, spine_index, title, content, config)
+            SELECT $1, t.spine_index, t.title, t.content,
+                   COALESCE((SELECT c.oid::regconfig FROM pg_ts_config c WHERE c.cfgname = $5),
+                            'simple'::regconfig)
+            FROM UNNEST($2::int[], $3::text[], $4::text[]) AS t(spine_index, title, content)
+            "#,
+        )
+        .bind(book_id)
+        .bind(&spine_indexes)
+        .bind(&titles)
+        .bind(&contents)
+        .bind(config)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Search the text of a user's books, best match first
+    ///
+    /// The query uses web search syntax: quoted phrases, `or` and `-` to exclude words.
+    pub async fn search(
+        pool: &DbPool,
+        user_id: &str,
+        query: &str,
+        pagination: &Pagination,
+    ) -> Result<Paginated<BookTextHit>> {
+        let matches = format!(
+            r#"
+            WITH {QUERIES_CTE},
+            matches AS (
+                SELECT bt.book_id,
+                       MAX(ts_rank_cd(bt.search_vector, q.query)) AS rank,
+                       COUNT(*) AS matching_chapters
+                FROM book_text bt
+                JOIN queries q ON q.config = bt.config
+                JOIN books b ON b.id = bt.book_id
+                WHERE b.user_id = $1 AND bt.search_vector @@ q.query
+                GROUP BY bt.book_id
+            )
+            "#
+        );
+
+        let total = sqlx::query_scalar::<_, i64>(&format!("{matches} SELECT COUNT(*) FROM matches"))
+            .bind(user_id)
+            .bind(query)
+            .fetch_one(pool)
+            .await?;
+
+        let items = sqlx::query_as::<_, BookTextHit>(&format!(
+            r#"
+            {matches}
+            SELECT b.id, b.user_id, b.title, b.authors, b.contributors, b.description, b.language, b.publisher,
+                   b.published_date, b.isbn, b.series_id, b.series_name, b.series_index, b.tags,
+                   b.author_sort, b.rating, b.identifiers,
+                   b.format, b.content_hash, b.file_size, b.storage_path, b.original_filename,
+                   b.created_at, b.updated_at, m.rank, m.matching_chapters
+            FROM matches m
+            JOIN books b ON b.id = m.book_id
+            ORDER BY m.rank DESC, b.title, b.id
+            LIMIT $3 OFFSET $4
+            "#
+        ))
+        .bind(user_id)
+        .bind(query)
+        .bind(pagination.limit)
+        .bind(pagination.offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(Paginated::new(items, total, pagination))
+    }
+
+    /// Get snippets of the best-matching chapters of each book, best first
+    pub async fn snippets(
+        pool: &DbPool,
+        book_ids: &[Uuid],
+        query: &str,
+        per_book: i64,
+    ) -> Result<Vec<BookTextSnippet>> {
+        let options = format!(
+            r#"StartSel="{}", StopSel="{}", MinWords={}, MaxWords={}"#,
+            MATCH_START, MATCH_END, SNIPPET_MIN_WORDS, SNIPPET_MAX_WORDS
+        );
+
+        let mut snippets = sqlx::query_as::<_, BookTextSnippet>(&format!(
+            r#"
+            WITH {QUERIES_CTE},
+            ranked AS (
+                SELECT bt.book_id, bt.spine_index, bt.title, bt.content, bt.config, q.query,
+                       ts_rank_cd(bt.search_vector, q.query) AS rank,
+                       ROW_NUMBER() OVER (
+                           PARTITION BY bt.book_id
+                           ORDER BY ts_rank_cd(bt.search_vector, q.query) DESC, bt.spine_index
+                       ) AS position
+                FROM book_text bt
+                JOIN queries q ON q.config = bt.config
+                WHERE bt.book_id = ANY($1) AND bt.search_vector @@ q.query
+            )
+            SELECT book_id, spine_index, title, rank,
+                   ts_headline(config, content, query, $4) AS snippet
+            FROM ranked
+            WHERE position <= $3
+            ORDER BY book_id, rank DESC, spine_index
+            "#
+        ))
+        .bind(book_ids)
+        .bind(query)
+        .bind(per_book)
+        .bind(options)
+        .fetch_all(pool)
+        .await?;
+
+        for snippet in &mut snippets {
+            snippet.snippet = snippet_html(&snippet.snippet);
+        }
+
+        Ok(snippets)
+    }
+}
@@ -1,8 +1,15 @@
 //! Book database queries.
 
-use crate::models::{Book, CreateBook, UpdateBook};
-use crate::pool::DbPool;
-use common::{BookFormat, Error, Paginated, Pagination, Result};
+use crate::models::{
+    Book, BookChange, BookCompletion, BookFacets, CreateBook, FacetCount, UpdateBook,
+};
+use crate::queries::book_cursor::BookOrder;
+use crate::queries::book_search::{correct_query, escape_like, SearchParam, SearchQuery};
+use crate::pool::{DbConn, DbPool};
+use common::{BookFormat, CursorPaginated, Error, Paginated, Pagination, Result};
+use sqlx::types::Json;
+use sqlx::{Postgres, Transaction};
+use std::collections::HashMap;
 use uuid::Uuid;
 
 /// Sorting options for book list
@@ -33,54 +40,91 @@ impl BookSortOptions {
 pub struct BookFilterOptions {
     pub tag: Option<String>,
     pub series: Option<String>,
+    /// Name of one of the authors
     pub author: Option<String>,
+    /// Name of anyone credited, in any role
+    pub contributor: Option<String>,
 }
 
-/// Book-related database queries
-pub struct BookQueries;
-
-impl BookQueries {
-    /// List books for a user with pagination and filtering
-    pub async fn list_for_user(
-        pool: &DbPool,
-        user_id: &str,
-        pagination: &Pagination,
-        sort: &BookSortOptions,
-        filter: &BookFilterOptions,
-    ) -> Result<Paginated<Book>> {
-        // Build the WHERE clause dynamically using owned strings
+impl BookFilterOptions {
+    /// Condition on a user's books, with the user id bound as `$1` and the filter values
+    /// from `first_param` on, and the next free parameter number
+    fn where_clause(&self, first_param: usize) -> (String, usize) {
         let mut where_clauses: Vec<String> = vec!["user_id = $1".to_string()];
-        let mut param_idx = 2;
+        let mut param_idx = first_param;
 
-        if filter.tag.is_some() {
+        if self.tag.is_some() {
             where_clauses.push(format!("${}::text = ANY(tags)", param_idx));
             param_idx += 1;
         }
-        if filter.series.is_some() {
+        if self.series.is_some() {
             where_clauses.push(format!("series_name = ${}", param_idx));
             param_idx += 1;
         }
-        if filter.author.is_some() {
-            where_clauses.push(format!("${}::text = ANY(authors)", param_idx));
+        // Any spelling or alias of a name matches
+        let credited = |param: usize, role: &str| {
+            format!(
+                "id IN (SELECT ba.book_id FROM book_authors ba JOIN authors a ON a.id = ba.author_id \
+                 WHERE a.user_id = $1 AND a.match_keys @> ARRAY[author_name_key(${}::text)]{})",
+                param, role
+            )
+        };
+        if self.author.is_some() {
+            where_clauses.push(credited(param_idx, " AND ba.role = 'aut'"));
+            param_idx += 1;
+        }
+        if self.contributor.is_some() {
+            where_clauses.push(credited(param_idx, ""));
             param_idx += 1;
         }
 
-        let where_clause = where_clauses.join(" AND ");
+        (where_clauses.join(" AND "), param_idx)
+    }
 
-        // Determine sort column and order
-        let sort_column = match sort.sort_by.as_deref() {
-            Some("title") => "title",
-            Some("created_at") => "created_at",
-            Some("updated_at") => "updated_at",
-            Some("series_index") => "series_index",
-            _ => "created_at",
-        };
+    /// Values to bind for [`Self::where_clause`], in order
+    fn values(&self) -> impl Iterator<Item = &String> {
+        [&self.tag, &self.series, &self.author, &self.contributor].into_iter().flatten()
+    }
+}
 
-        let sort_order = match sort.sort_order.as_deref() {
-            Some("asc") => "ASC",
-            Some("desc") => "DESC",
-            _ => "DESC",
-        };
+/// Word similarity from 0 to 1 at which a title, author or series matches a search term
+///
+/// Lower than the pg_trgm default of 0.6 so that single transposed letters match, as in
+/// "tolkein" for "Tolkien".
+pub const FUZZY_MATCH_THRESHOLD: f32 = 0.45;
+
+/// Match by trigram word similarity from [`FUZZY_MATCH_THRESHOLD`] for the rest of the
+/// current transaction
+pub(crate) async fn set_fuzzy_threshold(conn: &mut DbConn) -> Result<()> {
+    sqlx::query("SELECT set_config('pg_trgm.word_similarity_threshold', $1, true)")
+        .bind(FUZZY_MATCH_THRESHOLD.to_string())
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
+/// Book with its relevance to a search
+#[derive(sqlx::FromRow)]
+struct RankedBook {
+    #[sqlx(flatten)]
+    book: Book,
+    rank: f32,
+}
+
+/// Book-related database queries
+pub struct BookQueries;
+
+impl BookQueries {
+    /// List books for a user with pagination and filtering
+    pub async fn list_for_user(
+        pool: &DbPool,
+        user_id: &str,
+        pagination: &Pagination,
+        sort: &BookSortOptions,
+        filter: &BookFilterOptions,
+    ) -> Result<Paginated<Book>> {
+        let (where_clause, param_idx) = filter.where_clause(2);
+        let order = BookOrder::for_sort(sort);
 
         // Get total count
         let count_query = format!(
@@ -89,15 +133,8 @@ impl BookQueries {
         );
 
         let mut count_builder = sqlx::query_scalar::<_, i64>(&count_query).bind(user_id);
-
-        if let Some(tag) = &filter.tag {
-            count_builder = count_builder.bind(tag);
-        }
-        if let Some(series) = &filter.series {
-            count_builder = count_builder.bind(series);
-        }
-        if let Some(author) = &filter.author {
-            count_builder = count_builder.bind(author);
+        for value in filter.values() {
+            count_builder = count_builder.bind(value);
         }
 
         let total = count_builder.fetch_one(pool).await?;
@@ -105,32 +142,25 @@ impl BookQueries {
         // Get paginated results
         let query = format!(
             r#"
-            SELECT id, user_id, title, authors, description, language, publisher,
-                   published_date, isbn, series_name, series_index, tags,
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
                    format, content_hash, file_size, storage_path, original_filename,
                    created_at, updated_at
             FROM books
             WHERE {}
-            ORDER BY {} {}
+            ORDER BY {}
             LIMIT ${} OFFSET ${}
             "#,
             where_clause,
-            sort_column,
-            sort_order,
+            order.order_by(),
             param_idx,
             param_idx + 1
         );
 
         let mut query_builder = sqlx::query_as::<_, Book>(&query).bind(user_id);
-
-        if let Some(tag) = &filter.tag {
-            query_builder = query_builder.bind(tag);
-        }
-        if let Some(series) = &filter.series {
-            query_builder = query_builder.bind(series);
-        }
-        if let Some(author) = &filter.author {
-            query_builder = query_builder.bind(author);
+        for value in filter.values() {
+            query_builder = query_builder.bind(value);
         }
 
         let items = query_builder
@@ -142,12 +172,94 @@ impl BookQueries {
         Ok(Paginated::new(items, total, pagination))
     }
 
+    /// List books for a user a page at a time, continuing after a cursor from the previous
+    /// page
+    ///
+    /// The books are only counted when `include_total` is set, as counting a large library
+    /// costs more than fetching a page of it.
+    pub async fn list_for_user_by_cursor(
+        pool: &DbPool,
+        user_id: &str,
+        limit: i64,
+        cursor: Option<&str>,
+        include_total: bool,
+        sort: &BookSortOptions,
+        filter: &BookFilterOptions,
+    ) -> Result<CursorPaginated<Book>> {
+        let order = BookOrder::for_sort(sort);
+        let cursor = cursor.map(|token| order.decode(token)).transpose()?;
+        let (where_clause, param_idx) = filter.where_clause(2);
+
+        let total = if include_total {
+            let count_query = format!("SELECT COUNT(*) FROM books WHERE {}", where_clause);
+            let mut count_builder = sqlx::query_scalar::<_, i64>(&count_query).bind(user_id);
+            for value in filter.values() {
+                count_builder = count_builder.bind(value);
+            }
+            Some(count_builder.fetch_one(pool).await?)
+        } else {
+            None
+        };
+
+        let (after, limit_param) = match &cursor {
+            Some(cursor) => (
+                format!(" AND ({})", order.after(param_idx)),
+                param_idx + cursor.keys.len() + 1,
+            ),
+            None => (String::new(), param_idx),
+        };
+        let query = format!(
+            r#"
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
+                   format, content_hash, file_size, storage_path, original_filename,
+                   created_at, updated_at
+            FROM books
+            WHERE {}{}
+            ORDER BY {}
+            LIMIT ${}
+            "#,
+            where_clause,
+            after,
+            order.order_by(),
+            limit_param
+        );
+
+        let mut query_builder = sqlx::query_as::<_, Book>(&query).bind(user_id);
+        for value in filter.values() {
+            query_builder = query_builder.bind(value);
+        }
+        if let Some(cursor) = &cursor {
+            for key in &cursor.keys {
+                query_builder = query_builder.bind(key);
+            }
+            query_builder = query_builder.bind(cursor.id);
+        }
+
+        // Fetch one extra book to learn whether there is another page
+        let mut items = query_builder.bind(limit + 1).fetch_all(pool).await?;
+        let next_cursor = if items.len() as i64 > limit {
+            items.truncate(limit as usize);
+            items.last().map(|book| order.cursor_after(book, None).encode())
+        } else {
+            None
+        };
+
+        Ok(CursorPaginated {
+            items,
+            next_cursor,
+            total,
+        })
+    }
+
     /// Get a book by ID
     pub async fn get_by_id(pool: &DbPool, id: Uuid) -> Result<Option<Book>> {
         let book = sqlx::query_as::<_, Book>(
             r#"
-            SELECT id, user_id, title, authors, description, language, publisher,
-                   published_date, isbn, series_name, series_index, tags,
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
                    format, content_hash, file_size, storage_path, original_filename,
                    created_at, updated_at
             FROM books
@@ -176,8 +288,9 @@ impl BookQueries {
     ) -> Result<Option<Book>> {
         let book = sqlx::query_as::<_, Book>(
             r#"
-            SELECT id, user_id, title, authors, description, language, publisher,
-                   published_date, isbn, series_name, series_index, tags,
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
                    format, content_hash, file_size, storage_path, original_filename,
                    created_at, updated_at
             FROM books
@@ -198,10 +311,14 @@ impl BookQueries {
             r#"
             INSERT INTO books (id, user_id, title, authors, description, language, publisher,
                               published_date, isbn, series_name, series_index, tags,
-                              format, content_hash, file_size, storage_path, original_filename)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17)
-            RETURNING id, user_id, title, authors, description, language, publisher,
-                      published_date, isbn, series_name, series_index, tags,
+                              author_sort, rating, identifiers,
+                              format, content_hash, file_size, storage_path, original_filename,
+                              partial_md5, contributors)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17,
+                    $18, $19, $20, $21, $22)
+            RETURNING id, user_id, title, authors, contributors, description, language, publisher,
+                      published_date, isbn, series_id, series_name, series_index, tags,
+                      author_sort, rating, identifiers,
                       format, content_hash, file_size, storage_path, original_filename,
                       created_at, updated_at
             "#,
@@ -218,11 +335,16 @@ impl BookQueries {
         .bind(&data.series_name)
         .bind(data.series_index)
         .bind(&data.tags)
+        .bind(&data.author_sort)
+        .bind(data.rating)
+        .bind(Json(&data.identifiers))
         .bind(data.format)
         .bind(&data.content_hash)
         .bind(data.file_size)
         .bind(&data.storage_path)
         .bind(&data.original_filename)
+        .bind(&data.partial_md5)
+        .bind(Json(&data.contributors))
         .fetch_one(pool)
         .await?;
 
@@ -244,10 +366,15 @@ impl BookQueries {
                 isbn = COALESCE($8, isbn),
                 series_name = COALESCE($9, series_name),
                 series_index = COALESCE($10, series_index),
-                tags = COALESCE($11, tags)
+                tags = COALESCE($11, tags),
+                author_sort = COALESCE($12, author_sort),
+                rating = COALESCE($13, rating),
+                identifiers = COALESCE($14, identifiers),
+                contributors = COALESCE($15, contributors)
             WHERE id = $1
-            RETURNING id, user_id, title, authors, description, language, publisher,
-                      published_date, isbn, series_name, series_index, tags,
+            RETURNING id, user_id, title, authors, contributors, description, language, publisher,
+                      published_date, isbn, series_id, series_name, series_index, tags,
+                      author_sort, rating, identifiers,
                       format, content_hash, file_size, storage_path, original_filename,
                       created_at, updated_at
             "#,
@@ -263,6 +390,10 @@ impl BookQueries {
         .bind(&data.series_name)
         .bind(data.series_index)
         .bind(&data.tags)
+        .bind(&data.author_sort)
+        .bind(data.rating)
+        .bind(data.identifiers.as_ref().map(Json))
+        .bind(data.contributors.as_ref().map(Json))
         .fetch_optional(pool)
         .await?
         .ok_or_else(|| Error::not_found_resource("book", id))?;
@@ -280,55 +411,362 @@ impl BookQueries {
         Ok(result.rows_affected() > 0)
     }
 
-    /// Search books by title, author, or description
+    /// Search a user's books with a parsed query
+    ///
+    /// Queries with typo-tolerant terms rank books by similarity, so exact matches come
+    /// before near misses; other queries are ordered by title.
     pub async fn search(
         pool: &DbPool,
         user_id: &str,
-        query: &str,
+        query: &SearchQuery,
         pagination: &Pagination,
     ) -> Result<Paginated<Book>> {
-        let search_pattern = format!("%{}%", query.to_lowercase());
+        let sql = query.to_sql(2);
+        let mut tx = Self::begin_fuzzy(pool).await?;
 
         // Get total count
-        let total = sqlx::query_scalar::<_, i64>(
+        let count_query = format!(
+            "SELECT COUNT(*) FROM books WHERE user_id = $1 AND ({})",
+            sql.condition
+        );
+        let mut count_builder = sqlx::query_scalar::<_, i64>(&count_query).bind(user_id);
+        for param in &sql.params {
+            count_builder = match param {
+                SearchParam::Text(text) => count_builder.bind(text),
+                SearchParam::Int(value) => count_builder.bind(value),
+            };
+        }
+        let total = count_builder.fetch_one(&mut *tx).await?;
+
+        // Get paginated results
+        let order = BookOrder::for_search(sql.rank.as_deref());
+        let items_query = format!(
             r#"
-            SELECT COUNT(*)
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
+                   format, content_hash, file_size, storage_path, original_filename,
+                   created_at, updated_at
             FROM books
-            WHERE user_id = $1
-              AND (LOWER(title) LIKE $2
-                   OR EXISTS (SELECT 1 FROM unnest(authors) a WHERE LOWER(a) LIKE $2)
-                   OR LOWER(COALESCE(description, '')) LIKE $2)
+            WHERE user_id = $1 AND ({})
+            ORDER BY {}
+            LIMIT ${} OFFSET ${}
             "#,
-        )
-        .bind(user_id)
-        .bind(&search_pattern)
-        .fetch_one(pool)
-        .await?;
+            sql.condition,
+            order.order_by(),
+            sql.params.len() + 2,
+            sql.params.len() + 3
+        );
+        let mut items_builder = sqlx::query_as::<_, Book>(&items_query).bind(user_id);
+        for param in &sql.params {
+            items_builder = match param {
+                SearchParam::Text(text) => items_builder.bind(text),
+                SearchParam::Int(value) => items_builder.bind(value),
+            };
+        }
+        let items = items_builder
+            .bind(pagination.limit)
+            .bind(pagination.offset)
+            .fetch_all(&mut *tx)
+            .await?;
 
-        // Get paginated results
-        let items = sqlx::query_as::<_, Book>(
+        tx.commit().await?;
+
+        Ok(Paginated::new(items, total, pagination))
+    }
+
+    /// Count a user's books matching a search query
+    pub async fn count_matching(pool: &DbPool, user_id: &str, query: &SearchQuery) -> Result<i64> {
+        let sql = query.to_sql(2);
+        let mut tx = Self::begin_fuzzy(pool).await?;
+
+        let count_query = format!(
+            "SELECT COUNT(*) FROM books WHERE user_id = $1 AND ({})",
+            sql.condition
+        );
+        let mut builder = sqlx::query_scalar::<_, i64>(&count_query).bind(user_id);
+        for param in &sql.params {
+            builder = match param {
+                SearchParam::Text(text) => builder.bind(text),
+                SearchParam::Int(value) => builder.bind(value),
+            };
+        }
+        let count = builder.fetch_one(&mut *tx).await?;
+
+        tx.commit().await?;
+
+        Ok(count)
+    }
+
+    /// Search a user's books a page at a time, continuing after a cursor from the previous
+    /// page, in the order of [`Self::search`]
+    ///
+    /// The matches are only counted when `include_total` is set.
+    pub async fn search_by_cursor(
+        pool: &DbPool,
+        user_id: &str,
+        query: &SearchQuery,
+        limit: i64,
+        cursor: Option<&str>,
+        include_total: bool,
+    ) -> Result<CursorPaginated<Book>> {
+        let sql = query.to_sql(2);
+        let order = BookOrder::for_search(sql.rank.as_deref());
+        let cursor = cursor.map(|token| order.decode(token)).transpose()?;
+        let mut tx = Self::begin_fuzzy(pool).await?;
+
+        let total = if include_total {
+            let count_query = format!(
+                "SELECT COUNT(*) FROM books WHERE user_id = $1 AND ({})",
+                sql.condition
+            );
+            let mut count_builder = sqlx::query_scalar::<_, i64>(&count_query).bind(user_id);
+            for param in &sql.params {
+                count_builder = match param {
+                    SearchParam::Text(text) => count_builder.bind(text),
+                    SearchParam::Int(value) => count_builder.bind(value),
+                };
+            }
+            Some(count_builder.fetch_one(&mut *tx).await?)
+        } else {
+            None
+        };
+
+        let first_cursor_param = sql.params.len() + 2;
+        let (after, limit_param) = match &cursor {
+            Some(cursor) => (
+                format!(" AND ({})", order.after(first_cursor_param)),
+                first_cursor_param + cursor.keys.len() + 1,
+            ),
+            None => (String::new(), first_cursor_param),
+        };
+        let items_query = format!(
             r#"
-            SELECT id, user_id, title, authors, description, language, publisher,
-                   published_date, isbn, series_name, series_index, tags,
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
                    format, content_hash, file_size, storage_path, original_filename,
-                   created_at, updated_at
+                   created_at, updated_at,
+                   {} AS rank
             FROM books
-            WHERE user_id = $1
-              AND (LOWER(title) LIKE $2
-                   OR EXISTS (SELECT 1 FROM unnest(authors) a WHERE LOWER(a) LIKE $2)
-                   OR LOWER(COALESCE(description, '')) LIKE $2)
-            ORDER BY title ASC
-            LIMIT $3 OFFSET $4
+            WHERE user_id = $1 AND ({}){}
+            ORDER BY {}
+            LIMIT ${}
+            "#,
+            sql.rank.as_deref().unwrap_or("0::real"),
+            sql.condition,
+            after,
+            order.order_by(),
+            limit_param
+        );
+        let mut items_builder = sqlx::query_as::<_, RankedBook>(&items_query).bind(user_id);
+        for param in &sql.params {
+            items_builder = match param {
+                SearchParam::Text(text) => items_builder.bind(text),
+                SearchParam::Int(value) => items_builder.bind(value),
+            };
+        }
+        if let Some(cursor) = &cursor {
+            for key in &cursor.keys {
+                items_builder = items_builder.bind(key);
+            }
+            items_builder = items_builder.bind(cursor.id);
+        }
+
+        // Fetch one extra book to learn whether there is another page
+        let mut rows = items_builder.bind(limit + 1).fetch_all(&mut *tx).await?;
+        tx.commit().await?;
+
+        let next_cursor = if rows.len() as i64 > limit {
+            rows.truncate(limit as usize);
+            rows.last()
+                .map(|row| order.cursor_after(&row.book, Some(row.rank)).encode())
+        } else {
+            None
+        };
+
+        Ok(CursorPaginated {
+            items: rows.into_iter().map(|row| row.book).collect(),
+            next_cursor,
+            total,
+        })
+    }
+
+    /// Most common tags, authors, languages and series among all books matching a query,
+    /// up to `limit` values each
+    pub async fn search_facets(
+        pool: &DbPool,
+        user_id: &str,
+        query: &SearchQuery,
+        limit: i64,
+    ) -> Result<BookFacets> {
+        let sql = query.to_sql(2);
+        let facets_query = format!(
+            r#"
+            WITH matched AS (
+                SELECT tags, authors, language, series_name
+                FROM books
+                WHERE user_id = $1 AND ({})
+            ),
+            counts AS (
+                SELECT 'tag' AS facet, t AS value, COUNT(*) AS count
+                FROM matched, unnest(tags) t
+                GROUP BY t
+                UNION ALL
+                SELECT 'author', a, COUNT(*)
+                FROM matched, unnest(authors) a
+                GROUP BY a
+                UNION ALL
+                SELECT 'language', language, COUNT(*)
+                FROM matched
+                WHERE language IS NOT NULL
+                GROUP BY language
+                UNION ALL
+                SELECT 'series', series_name, COUNT(*)
+                FROM matched
+                WHERE series_name IS NOT NULL
+                GROUP BY series_name
+            )
+            SELECT facet, value, count
+            FROM (
+                SELECT facet, value, count,
+                       ROW_NUMBER() OVER (PARTITION BY facet ORDER BY count DESC, value) AS n
+                FROM counts
+            ) ranked
+            WHERE n <= ${}
+            ORDER BY facet, n
+            "#,
+            sql.condition,
+            sql.params.len() + 2
+        );
+
+        let mut builder = sqlx::query_as::<_, (String, String, i64)>(&facets_query).bind(user_id);
+        for param in &sql.params {
+            builder = match param {
+                SearchParam::Text(text) => builder.bind(text),
+                SearchParam::Int(value) => builder.bind(value),
+            };
+        }
+        let mut tx = Self::begin_fuzzy(pool).await?;
+        let rows = builder.bind(limit).fetch_all(&mut *tx).await?;
+        tx.commit().await?;
+
+        let mut facets = BookFacets::default();
+        for (facet, value, count) in rows {
+            let counts = match facet.as_str() {
+                "tag" => &mut facets.tags,
+                "author" => &mut facets.authors,
+                "language" => &mut facets.languages,
+                _ => &mut facets.series,
+            };
+            counts.push(FacetCount { value, count });
+        }
+        Ok(facets)
+    }
+
+    /// Query with misspelled words replaced by the most similar words in the titles,
+    /// authors and series of the user's books, or `None` if there is nothing to correct
+    pub async fn did_you_mean(
+        pool: &DbPool,
+        user_id: &str,
+        input: &str,
+        query: &SearchQuery,
+    ) -> Result<Option<String>> {
+        let words = query.fuzzy_words();
+        if words.is_empty() {
+            return Ok(None);
+        }
+
+        let corrections: HashMap<String, String> = sqlx::query_as::<_, (String, String)>(
+            r#"
+            WITH vocabulary AS (
+                SELECT DISTINCT word
+                FROM books,
+                     regexp_split_to_table(
+                         LOWER(title || ' ' || book_authors_text(authors) || ' '
+                               || COALESCE(series_name, '')),
+                         '[^[:alnum:]]+') AS word
+                WHERE user_id = $1 AND length(word) >= 3
+            )
+            SELECT q.word, best.word
+            FROM unnest($2::text[]) AS q(word)
+            CROSS JOIN LATERAL (
+                SELECT v.word
+                FROM vocabulary v
+                WHERE v.word % q.word
+                ORDER BY similarity(v.word, q.word) DESC, v.word
+                LIMIT 1
+            ) best
+            WHERE best.word <> q.word
             "#,
         )
         .bind(user_id)
-        .bind(&search_pattern)
-        .bind(pagination.limit)
-        .bind(pagination.offset)
+        .bind(&words)
         .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+
+        Ok(correct_query(input, &corrections))
+    }
+
+    /// Titles, authors and series of the user's books starting with or similar to typed
+    /// text, prefix matches first
+    pub async fn autocomplete(
+        pool: &DbPool,
+        user_id: &str,
+        text: &str,
+        limit: i64,
+    ) -> Result<Vec<BookCompletion>> {
+        let text = text.trim().to_lowercase();
+        let contains = format!("%{}%", escape_like(&text));
+        let prefix = format!("{}%", escape_like(&text));
+
+        let mut tx = Self::begin_fuzzy(pool).await?;
+        let completions = sqlx::query_as::<_, BookCompletion>(
+            r#"
+            WITH candidates AS (
+                SELECT 'title' AS kind, title AS value
+                FROM books
+                WHERE user_id = $1 AND (LOWER(title) LIKE $3 OR $2 <% LOWER(title))
+                UNION
+                SELECT 'author', a
+                FROM books, unnest(authors) a
+                WHERE user_id = $1
+                  AND (LOWER(book_authors_text(authors)) LIKE $3
+                       OR $2 <% LOWER(book_authors_text(authors)))
+                UNION
+                SELECT 'series', series_name
+                FROM books
+                WHERE user_id = $1 AND series_name IS NOT NULL
+                  AND (LOWER(COALESCE(series_name, '')) LIKE $3
+                       OR $2 <% LOWER(COALESCE(series_name, '')))
+            )
+            SELECT kind, value, word_similarity($2, LOWER(value)) AS score
+            FROM candidates
+            WHERE LOWER(value) LIKE $3 OR $2 <% LOWER(value)
+            ORDER BY LOWER(value) LIKE $4 DESC, score DESC, value
+            LIMIT $5
+            "#,
+        )
+        .bind(user_id)
+        .bind(&text)
+        .bind(&contains)
+        .bind(&prefix)
+        .bind(limit)
+        .fetch_all(&mut *tx)
         .await?;
+        tx.commit().await?;
 
-        Ok(Paginated::new(items, total, pagination))
+        Ok(completions)
+    }
+
+    /// Start a transaction in which trigram word similarity matches from
+    /// [`FUZZY_MATCH_THRESHOLD`]
+    async fn begin_fuzzy(pool: &DbPool) -> Result<Transaction<'static, Postgres>> {
+        let mut tx = pool.begin().await?;
+        set_fuzzy_threshold(&mut tx).await?;
+        Ok(tx)
     }
 
     /// Find a book by content hash for a specific user (for deduplication)
@@ -339,8 +777,9 @@ impl BookQueries {
     ) -> Result<Option<Book>> {
         let book = sqlx::query_as::<_, Book>(
             r#"
-            SELECT id, user_id, title, authors, description, language, publisher,
-                   published_date, isbn, series_name, series_index, tags,
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
                    format, content_hash, file_size, storage_path, original_filename,
                    created_at, updated_at
             FROM books
@@ -373,10 +812,12 @@ impl BookQueries {
                 content_hash = $3,
                 file_size = $4,
                 storage_path = $5,
-                original_filename = $6
+                original_filename = $6,
+                spine_paths = NULL
             WHERE id = $1
-            RETURNING id, user_id, title, authors, description, language, publisher,
-                      published_date, isbn, series_name, series_index, tags,
+            RETURNING id, user_id, title, authors, contributors, description, language, publisher,
+                      published_date, isbn, series_id, series_name, series_index, tags,
+                      author_sort, rating, identifiers,
                       format, content_hash, file_size, storage_path, original_filename,
                       created_at, updated_at
             "#,
@@ -393,4 +834,123 @@ impl BookQueries {
 
         Ok(book)
     }
+
+    /// Record the archive paths of the documents in a book's spine
+    pub async fn set_spine_paths(pool: &DbPool, id: Uuid, paths: &[String]) -> Result<()> {
+        sqlx::query("UPDATE books SET spine_paths = $2 WHERE id = $1")
+            .bind(id)
+            .bind(paths)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Get the recorded spine paths of a user's books, by book
+    ///
+    /// `None` for a book whose file hasn't been indexed since they were recorded; a book
+    /// without a file has none.
+    pub async fn get_spine_paths(
+        pool: &DbPool,
+        user_id: &str,
+        ids: &[Uuid],
+    ) -> Result<HashMap<Uuid, Option<Vec<String>>>> {
+        let rows = sqlx::query_as::<_, (Uuid, Option<Vec<String>>)>(
+            r#"
+            SELECT id, CASE WHEN storage_path IS NULL THEN '{}' ELSE spine_paths END
+            FROM books
+            WHERE user_id = $1 AND id = ANY($2)
+            "#,
+        )
+        .bind(user_id)
+        .bind(ids)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(rows.into_iter().collect())
+    }
+
+    /// Store the KOReader fingerprint of a book's file
+    pub async fn set_partial_md5(pool: &DbPool, id: Uuid, partial_md5: &str) -> Result<()> {
+        sqlx::query("UPDATE books SET partial_md5 = $2 WHERE id = $1")
+            .bind(id)
+            .bind(partial_md5)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Find the book KOReader means by a document id
+    ///
+    /// KOReader identifies documents by the partial MD5 of the file or, if configured to,
+    /// by the MD5 of the file name. Fingerprint matches are preferred.
+    pub async fn find_by_kosync_document(
+        pool: &DbPool,
+        user_id: &str,
+        document: &str,
+    ) -> Result<Option<Book>> {
+        let book = sqlx::query_as::<_, Book>(
+            r#"
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
+                   format, content_hash, file_size, storage_path, original_filename,
+                   created_at, updated_at
+            FROM books
+            WHERE user_id = $1
+              AND (partial_md5 = $2 OR md5(original_filename) = $2)
+            ORDER BY partial_md5 = $2 DESC NULLS LAST, updated_at DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(user_id)
+        .bind(document.to_lowercase())
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(book)
+    }
+
+    /// Get books created or changed after a sync sequence number, oldest change first
+    pub async fn get_changed_since(
+        conn: &mut DbConn,
+        user_id: &str,
+        since_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<BookChange>> {
+        let books = sqlx::query_as::<_, BookChange>(
+            r#"
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
+                   format, content_hash, file_size, storage_path, original_filename,
+                   created_at, updated_at, change_seq
+            FROM books
+            WHERE user_id = $1 AND change_seq > $2
+            ORDER BY change_seq ASC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(since_seq)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(books)
+    }
+
+    /// Get which of the given books exist and belong to the user
+    pub async fn owned_ids(conn: &mut DbConn, user_id: &str, ids: &[Uuid]) -> Result<Vec<Uuid>> {
+        let owned = sqlx::query_scalar::<_, Uuid>(
+            "SELECT id FROM books WHERE user_id = $1 AND id = ANY($2)",
+        )
+        .bind(user_id)
+        .bind(ids)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(owned)
+    }
 }
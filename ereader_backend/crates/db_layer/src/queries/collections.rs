@@ -1,10 +1,24 @@
 //! Collection database queries.
 
-use crate::models::{Book, Collection, CollectionBook, CreateCollection, UpdateCollection};
-use crate::pool::DbPool;
+use crate::models::{
+    Book, Collection, CollectionBook, CollectionBookChange, CollectionChange, CollectionWithCount,
+    CreateCollection, UpdateCollection,
+};
+use crate::pool::{DbConn, DbPool};
+use crate::queries::book_search::{SearchParam, SearchQuery};
+use crate::queries::books::{set_fuzzy_threshold, BookQueries};
 use common::{Error, Paginated, Pagination, Result};
+use sqlx::Connection;
+use std::collections::HashSet;
 use uuid::Uuid;
 
+/// Order of a hand-picked collection's books: by position, then by title for books
+/// without one
+const MEMBER_ORDER: &str = "cb.sort_order NULLS LAST, b.title, b.id";
+
+/// Most books a bulk membership or ordering operation accepts
+pub const MAX_BULK_BOOKS: usize = 1000;
+
 /// Collection-related database queries
 pub struct CollectionQueries;
 
@@ -13,7 +27,7 @@ impl CollectionQueries {
     pub async fn list_for_user(pool: &DbPool, user_id: &str) -> Result<Vec<Collection>> {
         let collections = sqlx::query_as::<_, Collection>(
             r#"
-            SELECT id, user_id, name, description, collection_type, created_at, updated_at
+            SELECT id, user_id, parent_id, name, description, collection_type, query, created_at, updated_at
             FROM collections
             WHERE user_id = $1
             ORDER BY name ASC
@@ -26,11 +40,173 @@ impl CollectionQueries {
         Ok(collections)
     }
 
+    /// List all collections for a user with the number of books in each
+    ///
+    /// Smart collections are counted from their stored membership, which is refreshed as
+    /// books and collections change. One whose query no longer parses counts as empty.
+    pub async fn list_with_counts(pool: &DbPool, user_id: &str) -> Result<Vec<CollectionWithCount>> {
+        let mut collections = sqlx::query_as::<_, CollectionWithCount>(
+            r#"
+            SELECT c.id, c.user_id, c.parent_id, c.name, c.description, c.collection_type, c.query,
+                   c.created_at, c.updated_at,
+                   (SELECT COUNT(*) FROM collection_books cb WHERE cb.collection_id = c.id)
+                       AS book_count
+            FROM collections c
+            WHERE c.user_id = $1
+            ORDER BY c.name ASC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+        clear_unparsable_counts(&mut collections);
+
+        Ok(collections)
+    }
+
+    /// List a page of a user's collections by name, with the number of books in each,
+    /// counted as by [`Self::list_with_counts`]
+    pub async fn list_page_with_counts(
+        pool: &DbPool,
+        user_id: &str,
+        pagination: &Pagination,
+    ) -> Result<Paginated<CollectionWithCount>> {
+        let total = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM collections WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
+
+        let mut items = sqlx::query_as::<_, CollectionWithCount>(
+            r#"
+            SELECT c.id, c.user_id, c.parent_id, c.name, c.description, c.collection_type, c.query,
+                   c.created_at, c.updated_at,
+                   (SELECT COUNT(*) FROM collection_books cb WHERE cb.collection_id = c.id)
+                       AS book_count
+            FROM collections c
+            WHERE c.user_id = $1
+            ORDER BY c.name ASC, c.id
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(pagination.limit)
+        .bind(pagination.offset)
+        .fetch_all(pool)
+        .await?;
+        clear_unparsable_counts(&mut items);
+
+        Ok(Paginated::new(items, total, pagination))
+    }
+
+    /// List the collections directly inside a parent, or the top-level collections if the
+    /// parent is `None`
+    pub async fn list_children(
+        pool: &DbPool,
+        user_id: &str,
+        parent_id: Option<Uuid>,
+    ) -> Result<Vec<Collection>> {
+        let collections = sqlx::query_as::<_, Collection>(
+            r#"
+            SELECT id, user_id, parent_id, name, description, collection_type, query, created_at, updated_at
+            FROM collections
+            WHERE user_id = $1 AND parent_id IS NOT DISTINCT FROM $2
+            ORDER BY name ASC
+            "#,
+        )
+        .bind(user_id)
+        .bind(parent_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(collections)
+    }
+
+    /// Get a collection and every collection nested in it at any depth, parents before
+    /// their children
+    pub async fn get_subtree(pool: &DbPool, id: Uuid) -> Result<Vec<Collection>> {
+        let collections = sqlx::query_as::<_, Collection>(
+            r#"
+            WITH RECURSIVE subtree AS (
+                SELECT id, 0 AS depth FROM collections WHERE id = $1
+                UNION
+                SELECT c.id, s.depth + 1 FROM collections c JOIN subtree s ON c.parent_id = s.id
+            )
+            SELECT c.id, c.user_id, c.parent_id, c.name, c.description, c.collection_type, c.query,
+                   c.created_at, c.updated_at
+            FROM subtree s
+            JOIN collections c ON c.id = s.id
+            ORDER BY s.depth, c.name
+            "#,
+        )
+        .bind(id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(collections)
+    }
+
+    /// Move a user's collection into another of their collections, or to the top level if
+    /// the parent is `None`
+    ///
+    /// Fails if the parent doesn't exist or is the collection itself or nested in it.
+    pub async fn set_parent(
+        pool: &DbPool,
+        user_id: &str,
+        id: Uuid,
+        parent_id: Option<Uuid>,
+    ) -> Result<Collection> {
+        let mut tx = pool.begin().await?;
+
+        // Serialise moves within a user's tree so two concurrent moves can't form a cycle
+        sqlx::query("SELECT pg_advisory_xact_lock(hashtext('collections:' || $1))")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+
+        if let Some(parent_id) = parent_id {
+            let ancestors = sqlx::query_scalar::<_, Uuid>(
+                r#"
+                WITH RECURSIVE ancestors(id, parent_id) AS (
+                    SELECT id, parent_id FROM collections WHERE id = $1 AND user_id = $2
+                    UNION
+                    SELECT c.id, c.parent_id FROM collections c JOIN ancestors a ON c.id = a.parent_id
+                )
+                SELECT id FROM ancestors
+                "#,
+            )
+            .bind(parent_id)
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await?;
+
+            check_parent(id, parent_id, &ancestors)?;
+        }
+
+        let collection = sqlx::query_as::<_, Collection>(
+            r#"
+            UPDATE collections
+            SET parent_id = $3
+            WHERE id = $1 AND user_id = $2
+            RETURNING id, user_id, parent_id, name, description, collection_type, query, created_at, updated_at
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(parent_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| Error::not_found_resource("collection", id))?;
+
+        tx.commit().await?;
+
+        Ok(collection)
+    }
+
     /// Get a collection by ID
     pub async fn get_by_id(pool: &DbPool, id: Uuid) -> Result<Option<Collection>> {
         let collection = sqlx::query_as::<_, Collection>(
             r#"
-            SELECT id, user_id, name, description, collection_type, created_at, updated_at
+            SELECT id, user_id, parent_id, name, description, collection_type, query, created_at, updated_at
             FROM collections
             WHERE id = $1
             "#,
@@ -57,7 +233,7 @@ impl CollectionQueries {
     ) -> Result<Option<Collection>> {
         let collection = sqlx::query_as::<_, Collection>(
             r#"
-            SELECT id, user_id, name, description, collection_type, created_at, updated_at
+            SELECT id, user_id, parent_id, name, description, collection_type, query, created_at, updated_at
             FROM collections
             WHERE id = $1 AND user_id = $2
             "#,
@@ -74,16 +250,18 @@ impl CollectionQueries {
     pub async fn create(pool: &DbPool, data: &CreateCollection) -> Result<Collection> {
         let collection = sqlx::query_as::<_, Collection>(
             r#"
-            INSERT INTO collections (id, user_id, name, description, collection_type)
-            VALUES ($1, $2, $3, $4, $5)
-            RETURNING id, user_id, name, description, collection_type, created_at, updated_at
+            INSERT INTO collections (id, user_id, parent_id, name, description, collection_type, query)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            RETURNING id, user_id, parent_id, name, description, collection_type, query, created_at, updated_at
             "#,
         )
         .bind(data.id)
         .bind(&data.user_id)
+        .bind(data.parent_id)
         .bind(&data.name)
         .bind(&data.description)
         .bind(&data.collection_type)
+        .bind(&data.query)
         .fetch_one(pool)
         .await?;
 
@@ -97,14 +275,16 @@ impl CollectionQueries {
             UPDATE collections
             SET
                 name = COALESCE($2, name),
-                description = COALESCE($3, description)
+                description = COALESCE($3, description),
+                query = CASE WHEN query IS NULL THEN NULL ELSE COALESCE($4, query) END
             WHERE id = $1
-            RETURNING id, user_id, name, description, collection_type, created_at, updated_at
+            RETURNING id, user_id, parent_id, name, description, collection_type, query, created_at, updated_at
             "#,
         )
         .bind(id)
         .bind(&data.name)
         .bind(&data.description)
+        .bind(&data.query)
         .fetch_optional(pool)
         .await?
         .ok_or_else(|| Error::not_found_resource("collection", id))?;
@@ -122,7 +302,7 @@ impl CollectionQueries {
         Ok(result.rows_affected() > 0)
     }
 
-    /// Add a book to a collection
+    /// Add a book to a collection, at the end of its order unless a position is given
     pub async fn add_book(
         pool: &DbPool,
         collection_id: Uuid,
@@ -132,9 +312,13 @@ impl CollectionQueries {
         let membership = sqlx::query_as::<_, CollectionBook>(
             r#"
             INSERT INTO collection_books (collection_id, book_id, sort_order)
-            VALUES ($1, $2, $3)
+            VALUES (
+                $1, $2,
+                COALESCE($3, (SELECT COALESCE(MAX(sort_order) + 1, 0)
+                              FROM collection_books WHERE collection_id = $1))
+            )
             ON CONFLICT (collection_id, book_id) DO UPDATE SET
-                sort_order = COALESCE(EXCLUDED.sort_order, collection_books.sort_order)
+                sort_order = COALESCE($3, collection_books.sort_order)
             RETURNING collection_id, book_id, added_at, sort_order
             "#,
         )
@@ -160,12 +344,18 @@ impl CollectionQueries {
         Ok(result.rows_affected() > 0)
     }
 
-    /// Get all books in a collection
+    /// Get all books in a collection, evaluating a smart collection's query now
     pub async fn get_books(
         pool: &DbPool,
-        collection_id: Uuid,
+        collection: &Collection,
         pagination: &Pagination,
     ) -> Result<Paginated<Book>> {
+        if let Some(query) = &collection.query {
+            let query = SearchQuery::parse(query)?;
+            return BookQueries::search(pool, &collection.user_id, &query, pagination).await;
+        }
+        let collection_id = collection.id;
+
         // Get total count
         let total = sqlx::query_scalar::<_, i64>(
             "SELECT COUNT(*) FROM collection_books WHERE collection_id = $1",
@@ -175,24 +365,315 @@ impl CollectionQueries {
         .await?;
 
         // Get paginated results
-        let items = sqlx::query_as::<_, Book>(
+        let items_query = format!(
             r#"
-            SELECT b.id, b.user_id, b.title, b.authors, b.description, b.language, b.publisher,
-                   b.published_date, b.isbn, b.series_name, b.series_index, b.tags, b.created_at, b.updated_at
+            SELECT b.id, b.user_id, b.title, b.authors, b.contributors, b.description, b.language, b.publisher,
+                   b.published_date, b.isbn, b.series_id, b.series_name, b.series_index, b.tags,
+                   b.author_sort, b.rating, b.identifiers,
+                   b.format, b.content_hash, b.file_size, b.storage_path, b.original_filename,
+                   b.created_at, b.updated_at
             FROM books b
             JOIN collection_books cb ON b.id = cb.book_id
             WHERE cb.collection_id = $1
-            ORDER BY COALESCE(cb.sort_order, 0), b.title
+            ORDER BY {}
             LIMIT $2 OFFSET $3
             "#,
+            MEMBER_ORDER
+        );
+        let items = sqlx::query_as::<_, Book>(&items_query)
+            .bind(collection_id)
+            .bind(pagination.limit)
+            .bind(pagination.offset)
+            .fetch_all(pool)
+            .await?;
+
+        Ok(Paginated::new(items, total, pagination))
+    }
+
+    /// Get the books in a collection or in any collection nested in it, each book once,
+    /// ordered by title
+    pub async fn get_subtree_books(
+        pool: &DbPool,
+        collection: &Collection,
+        pagination: &Pagination,
+    ) -> Result<Paginated<Book>> {
+        let subtree = Self::get_subtree(pool, collection.id).await?;
+
+        // Hand-picked collections contribute their members, smart ones their matching books
+        let manual: Vec<Uuid> = subtree.iter().filter(|c| !c.is_smart()).map(|c| c.id).collect();
+        let mut conditions =
+            vec!["id IN (SELECT book_id FROM collection_books WHERE collection_id = ANY($2))".to_string()];
+        let mut params = Vec::new();
+        for query in subtree.iter().filter_map(|c| c.query.as_deref()) {
+            let sql = SearchQuery::parse(query)?.to_sql(params.len() + 3);
+            conditions.push(sql.condition);
+            params.extend(sql.params);
+        }
+        let condition = conditions
+            .iter()
+            .map(|c| format!("({})", c))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+
+        let mut tx = pool.begin().await?;
+        set_fuzzy_threshold(&mut tx).await?;
+
+        let count_query = format!(
+            "SELECT COUNT(*) FROM books WHERE user_id = $1 AND ({})",
+            condition
+        );
+        let mut count_builder = sqlx::query_scalar::<_, i64>(&count_query)
+            .bind(&collection.user_id)
+            .bind(&manual);
+        for param in &params {
+            count_builder = match param {
+                SearchParam::Text(text) => count_builder.bind(text),
+                SearchParam::Int(value) => count_builder.bind(value),
+            };
+        }
+        let total = count_builder.fetch_one(&mut *tx).await?;
+
+        let items_query = format!(
+            r#"
+            SELECT id, user_id, title, authors, contributors, description, language, publisher,
+                   published_date, isbn, series_id, series_name, series_index, tags,
+                   author_sort, rating, identifiers,
+                   format, content_hash, file_size, storage_path, original_filename,
+                   created_at, updated_at
+            FROM books
+            WHERE user_id = $1 AND ({})
+            ORDER BY title, id
+            LIMIT ${} OFFSET ${}
+            "#,
+            condition,
+            params.len() + 3,
+            params.len() + 4
+        );
+        let mut items_builder = sqlx::query_as::<_, Book>(&items_query)
+            .bind(&collection.user_id)
+            .bind(&manual);
+        for param in &params {
+            items_builder = match param {
+                SearchParam::Text(text) => items_builder.bind(text),
+                SearchParam::Int(value) => items_builder.bind(value),
+            };
+        }
+        let items = items_builder
+            .bind(pagination.limit)
+            .bind(pagination.offset)
+            .fetch_all(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(Paginated::new(items, total, pagination))
+    }
+
+    /// Add books to the end of a collection in the given order, skipping books already in
+    /// it, and return how many were added
+    ///
+    /// Fails without adding any if a book doesn't exist or belongs to another user.
+    pub async fn add_books(
+        pool: &DbPool,
+        user_id: &str,
+        collection_id: Uuid,
+        book_ids: &[Uuid],
+    ) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+        let added = Self::append_books(&mut tx, user_id, collection_id, book_ids).await?;
+        tx.commit().await?;
+
+        Ok(added)
+    }
+
+    /// Remove books from a collection and return how many were in it
+    pub async fn remove_books(pool: &DbPool, collection_id: Uuid, book_ids: &[Uuid]) -> Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM collection_books WHERE collection_id = $1 AND book_id = ANY($2)",
         )
         .bind(collection_id)
-        .bind(pagination.limit)
-        .bind(pagination.offset)
-        .fetch_all(pool)
+        .bind(book_ids)
+        .execute(pool)
         .await?;
 
-        Ok(Paginated::new(items, total, pagination))
+        Ok(result.rows_affected())
+    }
+
+    /// Move books from one collection to the end of another, skipping books not in the
+    /// source, and return how many were moved
+    pub async fn move_books(
+        pool: &DbPool,
+        user_id: &str,
+        from_collection_id: Uuid,
+        to_collection_id: Uuid,
+        book_ids: &[Uuid],
+    ) -> Result<u64> {
+        let mut tx = pool.begin().await?;
+
+        let members = sqlx::query_scalar::<_, Uuid>(
+            r#"
+            SELECT cb.book_id
+            FROM collection_books cb
+            JOIN unnest($2::uuid[]) WITH ORDINALITY AS t(book_id, position)
+                ON t.book_id = cb.book_id
+            WHERE cb.collection_id = $1
+            ORDER BY t.position
+            FOR UPDATE OF cb
+            "#,
+        )
+        .bind(from_collection_id)
+        .bind(book_ids)
+        .fetch_all(&mut *tx)
+        .await?;
+        let mut seen = HashSet::new();
+        let moving: Vec<Uuid> = members.into_iter().filter(|id| seen.insert(*id)).collect();
+
+        Self::append_books(&mut tx, user_id, to_collection_id, &moving).await?;
+        sqlx::query("DELETE FROM collection_books WHERE collection_id = $1 AND book_id = ANY($2)")
+            .bind(from_collection_id)
+            .bind(&moving)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(moving.len() as u64)
+    }
+
+    /// Put the given books first in a collection's order, in the given order, followed by
+    /// its other books in their current order
+    ///
+    /// Fails without changing the order if a book isn't in the collection.
+    pub async fn set_order(pool: &DbPool, collection_id: Uuid, book_ids: &[Uuid]) -> Result<()> {
+        check_bulk_size(book_ids)?;
+
+        let mut tx = pool.begin().await?;
+
+        let members: HashSet<Uuid> = Self::ordered_book_ids(&mut tx, collection_id)
+            .await?
+            .into_iter()
+            .collect();
+        check_members(&members, book_ids)?;
+        Self::write_order(&mut tx, collection_id, book_ids).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Move a book to a zero-based position in a collection's order, or to the end if the
+    /// position is past it; returns false if the book isn't in the collection
+    pub async fn move_book(
+        pool: &DbPool,
+        collection_id: Uuid,
+        book_id: Uuid,
+        position: usize,
+    ) -> Result<bool> {
+        let mut tx = pool.begin().await?;
+
+        let mut order = Self::ordered_book_ids(&mut tx, collection_id).await?;
+        if !move_within(&mut order, book_id, position) {
+            return Ok(false);
+        }
+        Self::write_order(&mut tx, collection_id, &order).await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Books of a collection in order, locked until the end of the transaction
+    async fn ordered_book_ids(conn: &mut DbConn, collection_id: Uuid) -> Result<Vec<Uuid>> {
+        let query = format!(
+            r#"
+            SELECT cb.book_id
+            FROM collection_books cb
+            JOIN books b ON b.id = cb.book_id
+            WHERE cb.collection_id = $1
+            ORDER BY {}
+            FOR UPDATE OF cb
+            "#,
+            MEMBER_ORDER
+        );
+        let ids = sqlx::query_scalar::<_, Uuid>(&query)
+            .bind(collection_id)
+            .fetch_all(conn)
+            .await?;
+
+        Ok(ids)
+    }
+
+    /// Number a collection's books from zero, the given books first, touching only the
+    /// memberships whose position changes
+    async fn write_order(conn: &mut DbConn, collection_id: Uuid, book_ids: &[Uuid]) -> Result<()> {
+        let query = format!(
+            r#"
+            UPDATE collection_books target
+            SET sort_order = ordered.position
+            FROM (
+                SELECT cb.book_id,
+                       (ROW_NUMBER() OVER (ORDER BY t.position NULLS LAST, {}) - 1)::int
+                           AS position
+                FROM collection_books cb
+                JOIN books b ON b.id = cb.book_id
+                LEFT JOIN (
+                    SELECT book_id, MIN(position) AS position
+                    FROM unnest($2::uuid[]) WITH ORDINALITY AS u(book_id, position)
+                    GROUP BY book_id
+                ) t ON t.book_id = cb.book_id
+                WHERE cb.collection_id = $1
+            ) ordered
+            WHERE target.collection_id = $1
+              AND target.book_id = ordered.book_id
+              AND target.sort_order IS DISTINCT FROM ordered.position
+            "#,
+            MEMBER_ORDER
+        );
+        sqlx::query(&query)
+            .bind(collection_id)
+            .bind(book_ids)
+            .execute(conn)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Add books the user owns to the end of a collection, skipping members
+    async fn append_books(
+        conn: &mut DbConn,
+        user_id: &str,
+        collection_id: Uuid,
+        book_ids: &[Uuid],
+    ) -> Result<u64> {
+        let owned: HashSet<Uuid> = BookQueries::owned_ids(&mut *conn, user_id, book_ids)
+            .await?
+            .into_iter()
+            .collect();
+        if let Some(missing) = book_ids.iter().find(|id| !owned.contains(id)) {
+            return Err(Error::not_found_resource("book", missing));
+        }
+
+        let result = sqlx::query(
+            r#"
+            INSERT INTO collection_books (collection_id, book_id, sort_order)
+            SELECT $1, t.book_id,
+                   (SELECT COALESCE(MAX(sort_order) + 1, 0)
+                    FROM collection_books WHERE collection_id = $1) + t.position::int - 1
+            FROM (
+                SELECT book_id, MIN(position) AS position
+                FROM unnest($2::uuid[]) WITH ORDINALITY AS u(book_id, position)
+                GROUP BY book_id
+            ) t
+            ORDER BY t.position
+            ON CONFLICT (collection_id, book_id) DO NOTHING
+            "#,
+        )
+        .bind(collection_id)
+        .bind(book_ids)
+        .execute(conn)
+        .await?;
+
+        Ok(result.rows_affected())
     }
 
     /// Check if a book is in a collection
@@ -207,4 +688,284 @@ impl CollectionQueries {
 
         Ok(exists)
     }
+
+    /// Users who have at least one smart collection
+    pub async fn smart_collection_owners(pool: &DbPool) -> Result<Vec<String>> {
+        let users = sqlx::query_scalar::<_, String>(
+            "SELECT DISTINCT user_id FROM collections WHERE query IS NOT NULL",
+        )
+        .fetch_all(pool)
+        .await?;
+
+        Ok(users)
+    }
+
+    /// Check if a user has any smart collections
+    pub async fn has_smart_collections(pool: &DbPool, user_id: &str) -> Result<bool> {
+        let exists = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS(SELECT 1 FROM collections WHERE user_id = $1 AND query IS NOT NULL)",
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(exists)
+    }
+
+    /// Bring the stored membership of a user's smart collections in line with their queries
+    ///
+    /// Devices only see collection_books rows, so this runs whenever a smart collection or
+    /// the user's books change: books that stopped matching are removed, leaving
+    /// tombstones, and newly matching books are added with a fresh change sequence.
+    /// Collections whose query no longer parses are skipped.
+    pub async fn refresh_smart_memberships(conn: &mut DbConn, user_id: &str) -> Result<()> {
+        let collections = sqlx::query_as::<_, (Uuid, String)>(
+            "SELECT id, query FROM collections WHERE user_id = $1 AND query IS NOT NULL",
+        )
+        .bind(user_id)
+        .fetch_all(&mut *conn)
+        .await?;
+        if collections.is_empty() {
+            return Ok(());
+        }
+
+        let mut tx = conn.begin().await?;
+        set_fuzzy_threshold(&mut tx).await?;
+
+        for (collection_id, query) in collections {
+            // A query that no longer parses leaves its collection as it was rather than
+            // holding up the user's other collections
+            let sql = match SearchQuery::parse(&query) {
+                Ok(query) => query.to_sql(3),
+                Err(e) => {
+                    tracing::warn!(%collection_id, error = %e, "Smart collection query doesn't parse");
+                    continue;
+                }
+            };
+            let statements = [
+                format!(
+                    r#"
+                    DELETE FROM collection_books cb
+                    WHERE cb.collection_id = $1
+                      AND NOT EXISTS (
+                          SELECT 1 FROM books
+                          WHERE books.id = cb.book_id AND books.user_id = $2 AND ({})
+                      )
+                    "#,
+                    sql.condition
+                ),
+                format!(
+                    r#"
+                    INSERT INTO collection_books (collection_id, book_id)
+                    SELECT $1, id FROM books
+                    WHERE user_id = $2 AND ({})
+                    ON CONFLICT (collection_id, book_id) DO NOTHING
+                    "#,
+                    sql.condition
+                ),
+            ];
+
+            for statement in &statements {
+                let mut builder = sqlx::query(statement).bind(collection_id).bind(user_id);
+                for param in &sql.params {
+                    builder = match param {
+                        SearchParam::Text(text) => builder.bind(text),
+                        SearchParam::Int(value) => builder.bind(value),
+                    };
+                }
+                builder.execute(&mut *tx).await?;
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Get collections created or changed after a sync sequence number, oldest change first
+    pub async fn get_changed_since(
+        conn: &mut DbConn,
+        user_id: &str,
+        since_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<CollectionChange>> {
+        let collections = sqlx::query_as::<_, CollectionChange>(
+            r#"
+            SELECT id, user_id, parent_id, name, description, collection_type, query, created_at, updated_at,
+                   change_seq
+            FROM collections
+            WHERE user_id = $1 AND change_seq > $2
+            ORDER BY change_seq ASC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(since_seq)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(collections)
+    }
+
+    /// Get collection memberships added or changed after a sync sequence number
+    pub async fn get_memberships_changed_since(
+        conn: &mut DbConn,
+        user_id: &str,
+        since_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<CollectionBookChange>> {
+        let memberships = sqlx::query_as::<_, CollectionBookChange>(
+            r#"
+            SELECT cb.collection_id, cb.book_id, cb.added_at, cb.sort_order, cb.change_seq
+            FROM collection_books cb
+            JOIN collections c ON c.id = cb.collection_id
+            WHERE c.user_id = $1 AND cb.change_seq > $2
+            ORDER BY cb.change_seq ASC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(since_seq)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(memberships)
+    }
+}
+
+/// Count smart collections whose query no longer parses as empty, whatever membership they
+/// were last given
+fn clear_unparsable_counts(collections: &mut [CollectionWithCount]) {
+    for entry in collections {
+        let Some(query) = &entry.collection.query else {
+            continue;
+        };
+        if let Err(e) = SearchQuery::parse(query) {
+            tracing::warn!(
+                collection_id = %entry.collection.id,
+                error = %e,
+                "Smart collection query doesn't parse"
+            );
+            entry.book_count = 0;
+        }
+    }
+}
+
+/// Check that a collection can move under a parent, given the parent and its ancestors
+///
+/// An empty list means the parent doesn't exist.
+fn check_parent(id: Uuid, parent_id: Uuid, ancestors: &[Uuid]) -> Result<()> {
+    if ancestors.is_empty() {
+        return Err(Error::not_found_resource("collection", parent_id));
+    }
+    if ancestors.contains(&id) {
+        return Err(Error::validation_field(
+            "parent_id",
+            "a collection can't be moved inside itself",
+        ));
+    }
+    Ok(())
+}
+
+/// Check that a bulk operation names no more than [`MAX_BULK_BOOKS`] books
+fn check_bulk_size(book_ids: &[Uuid]) -> Result<()> {
+    if book_ids.len() > MAX_BULK_BOOKS {
+        return Err(Error::validation_field(
+            "book_ids",
+            &format!("lists more than {} books", MAX_BULK_BOOKS),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that every book of a new order is in the collection
+fn check_members(members: &HashSet<Uuid>, book_ids: &[Uuid]) -> Result<()> {
+    if let Some(missing) = book_ids.iter().find(|id| !members.contains(id)) {
+        return Err(Error::validation_field(
+            "book_ids",
+            &format!("book {} is not in the collection", missing),
+        ));
+    }
+    Ok(())
+}
+
+/// Move a book to a zero-based position in an order, or to the end if the position is
+/// past it; returns false if the book isn't in the order
+fn move_within(order: &mut Vec<Uuid>, book_id: Uuid, position: usize) -> bool {
+    let Some(current) = order.iter().position(|id| *id == book_id) else {
+        return false;
+    };
+    order.remove(current);
+    order.insert(position.min(order.len()), book_id);
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(count: usize) -> Vec<Uuid> {
+        (0..count).map(|_| Uuid::now_v7()).collect()
+    }
+
+    #[test]
+    fn test_check_parent() {
+        let [root, middle, leaf] = [Uuid::now_v7(), Uuid::now_v7(), Uuid::now_v7()];
+        let other = Uuid::now_v7();
+
+        // Moving the root under its grandchild would close a cycle
+        assert!(matches!(
+            check_parent(root, leaf, &[leaf, middle, root]),
+            Err(Error::Validation(_))
+        ));
+        assert!(matches!(check_parent(root, root, &[root]), Err(Error::Validation(_))));
+        assert!(check_parent(leaf, other, &[other]).is_ok());
+        assert!(check_parent(leaf, root, &[root]).is_ok());
+        assert!(matches!(check_parent(leaf, other, &[]), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn test_check_bulk_size() {
+        assert!(check_bulk_size(&ids(MAX_BULK_BOOKS)).is_ok());
+        assert!(matches!(
+            check_bulk_size(&ids(MAX_BULK_BOOKS + 1)),
+            Err(Error::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_members() {
+        let books = ids(3);
+        let members: HashSet<Uuid> = books.iter().copied().collect();
+
+        assert!(check_members(&members, &[books[2], books[0]]).is_ok());
+        assert!(check_members(&members, &[]).is_ok());
+        assert!(matches!(
+            check_members(&members, &[books[1], Uuid::now_v7()]),
+            Err(Error::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn test_move_within() {
+        let books = ids(4);
+
+        let mut order = books.clone();
+        assert!(move_within(&mut order, books[3], 1));
+        assert_eq!(order, vec![books[0], books[3], books[1], books[2]]);
+
+        let mut order = books.clone();
+        assert!(move_within(&mut order, books[0], 2));
+        assert_eq!(order, vec![books[1], books[2], books[0], books[3]]);
+
+        let mut order = books.clone();
+        assert!(move_within(&mut order, books[1], 100));
+        assert_eq!(order, vec![books[0], books[2], books[3], books[1]]);
+
+        let mut order = books.clone();
+        assert!(!move_within(&mut order, Uuid::now_v7(), 0));
+        assert_eq!(order, books);
+    }
 }
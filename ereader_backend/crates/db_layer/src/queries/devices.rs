@@ -33,6 +33,27 @@ impl DeviceQueries {
             .ok_or_else(|| Error::not_found_resource("device", id))
     }
 
+    /// Get a device by ID for a specific user (ensures ownership)
+    pub async fn get_by_id_for_user(
+        pool: &DbPool,
+        id: Uuid,
+        user_id: &str,
+    ) -> Result<Option<Device>> {
+        let device = sqlx::query_as::<_, Device>(
+            r#"
+            SELECT id, user_id, name, device_type, public_key, last_sync_at, created_at
+            FROM devices
+            WHERE id = $1 AND user_id = $2
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(device)
+    }
+
     /// Get all devices for a user
     pub async fn get_for_user(pool: &DbPool, user_id: &str) -> Result<Vec<Device>> {
         let devices = sqlx::query_as::<_, Device>(
@@ -70,6 +91,46 @@ impl DeviceQueries {
         Ok(device)
     }
 
+    /// Get or register the device an external client knows by its own id
+    ///
+    /// The device's name is refreshed from `data` when it already exists.
+    pub async fn upsert_external(
+        pool: &DbPool,
+        data: &CreateDevice,
+        external_id: &str,
+    ) -> Result<Device> {
+        let device = sqlx::query_as::<_, Device>(
+            r#"
+            INSERT INTO devices (id, user_id, name, device_type, public_key, external_id)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (user_id, external_id) WHERE external_id IS NOT NULL
+            DO UPDATE SET name = EXCLUDED.name
+            RETURNING id, user_id, name, device_type, public_key, last_sync_at, created_at
+            "#,
+        )
+        .bind(data.id)
+        .bind(&data.user_id)
+        .bind(&data.name)
+        .bind(&data.device_type)
+        .bind(&data.public_key)
+        .bind(external_id)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(device)
+    }
+
+    /// Get the id an external client knows a device by, if it was registered by one
+    pub async fn get_external_id(pool: &DbPool, id: Uuid) -> Result<Option<String>> {
+        let external_id =
+            sqlx::query_scalar::<_, Option<String>>("SELECT external_id FROM devices WHERE id = $1")
+                .bind(id)
+                .fetch_optional(pool)
+                .await?;
+
+        Ok(external_id.flatten())
+    }
+
     /// Update the last sync time for a device
     pub async fn update_last_sync(pool: &DbPool, id: Uuid, time: DateTime<Utc>) -> Result<()> {
         sqlx::query(
@@ -0,0 +1,94 @@
+//! Sync idempotency key database queries.
+
+use crate::models::SyncIdempotencyKey;
+use crate::pool::{DbConn, DbPool};
+use chrono::{DateTime, Utc};
+use common::Result;
+
+/// Sync idempotency key database queries
+pub struct SyncIdempotencyQueries;
+
+impl SyncIdempotencyQueries {
+    /// Claim a key for a request
+    ///
+    /// Returns `None` when the key is new (or had expired) and now belongs to this request,
+    /// otherwise the existing record. A concurrent request holding the same key blocks this
+    /// call until its transaction finishes.
+    pub async fn claim(
+        conn: &mut DbConn,
+        user_id: &str,
+        key: &str,
+        request_hash: &str,
+        expired_before: DateTime<Utc>,
+    ) -> Result<Option<SyncIdempotencyKey>> {
+        let claimed = sqlx::query_scalar::<_, String>(
+            r#"
+            INSERT INTO sync_idempotency_keys (user_id, idempotency_key, request_hash)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id, idempotency_key) DO UPDATE SET
+                request_hash = EXCLUDED.request_hash,
+                response = NULL,
+                created_at = NOW()
+            WHERE sync_idempotency_keys.created_at < $4
+            RETURNING idempotency_key
+            "#,
+        )
+        .bind(user_id)
+        .bind(key)
+        .bind(request_hash)
+        .bind(expired_before)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        if claimed.is_some() {
+            return Ok(None);
+        }
+
+        let existing = sqlx::query_as::<_, SyncIdempotencyKey>(
+            r#"
+            SELECT user_id, idempotency_key, request_hash, response, created_at
+            FROM sync_idempotency_keys
+            WHERE user_id = $1 AND idempotency_key = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(key)
+        .fetch_one(conn)
+        .await?;
+
+        Ok(Some(existing))
+    }
+
+    /// Store the response for a claimed key
+    pub async fn store_response(
+        conn: &mut DbConn,
+        user_id: &str,
+        key: &str,
+        response: &serde_json::Value,
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE sync_idempotency_keys
+            SET response = $3
+            WHERE user_id = $1 AND idempotency_key = $2
+            "#,
+        )
+        .bind(user_id)
+        .bind(key)
+        .bind(response)
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Delete keys created before a cutoff, returning how many were removed
+    pub async fn prune_older_than(pool: &DbPool, cutoff: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM sync_idempotency_keys WHERE created_at < $1")
+            .bind(cutoff)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
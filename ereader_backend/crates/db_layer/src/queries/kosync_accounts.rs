@@ -0,0 +1,88 @@
+//! KOReader sync account database queries.
+
+use crate::models::KosyncAccount;
+use crate::pool::DbPool;
+use common::{Error, Result};
+
+/// KOReader sync account database queries
+pub struct KosyncAccountQueries;
+
+impl KosyncAccountQueries {
+    /// Get a user's account, if they have set one up
+    pub async fn get_for_user(pool: &DbPool, user_id: &str) -> Result<Option<KosyncAccount>> {
+        let account = sqlx::query_as::<_, KosyncAccount>(
+            r#"
+            SELECT user_id, username, created_at, updated_at
+            FROM kosync_accounts
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(account)
+    }
+
+    /// Set a user's username and key, replacing any previous ones
+    pub async fn upsert(
+        pool: &DbPool,
+        user_id: &str,
+        username: &str,
+        key_hash: &str,
+    ) -> Result<KosyncAccount> {
+        let account = sqlx::query_as::<_, KosyncAccount>(
+            r#"
+            INSERT INTO kosync_accounts (user_id, username, key_hash)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (user_id) DO UPDATE
+            SET username = EXCLUDED.username, key_hash = EXCLUDED.key_hash, updated_at = NOW()
+            RETURNING user_id, username, created_at, updated_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(username)
+        .bind(key_hash)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::Database(db) if db.is_unique_violation() => {
+                Error::Conflict(format!("username {} is already taken", username))
+            }
+            e => e.into(),
+        })?;
+
+        Ok(account)
+    }
+
+    /// Remove a user's account
+    pub async fn delete_for_user(pool: &DbPool, user_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM kosync_accounts WHERE user_id = $1")
+            .bind(user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Find the account matching a username and key hash
+    pub async fn authenticate(
+        pool: &DbPool,
+        username: &str,
+        key_hash: &str,
+    ) -> Result<Option<KosyncAccount>> {
+        let account = sqlx::query_as::<_, KosyncAccount>(
+            r#"
+            SELECT user_id, username, created_at, updated_at
+            FROM kosync_accounts
+            WHERE username = $1 AND key_hash = $2
+            "#,
+        )
+        .bind(username)
+        .bind(key_hash)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(account)
+    }
+}
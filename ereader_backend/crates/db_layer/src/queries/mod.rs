@@ -1,19 +1,44 @@
 //! Database queries organized by entity type.
 
 pub mod annotations;
+pub mod app_tokens;
+pub mod book_authors;
+pub mod book_cursor;
+pub mod book_imports;
+pub mod book_search;
+pub mod book_series;
+pub mod book_texts;
 pub mod books;
 pub mod collections;
 pub mod covers;
 pub mod devices;
+pub mod idempotency_keys;
+pub mod kosync_accounts;
+pub mod reading_sessions;
 pub mod reading_states;
+pub mod sync_conflicts;
+pub mod sync_settings;
 pub mod tasks;
+pub mod tombstones;
 pub mod users;
 
 pub use annotations::AnnotationQueries;
-pub use books::{BookQueries, BookSortOptions, BookFilterOptions};
+pub use app_tokens::AppTokenQueries;
+pub use book_authors::AuthorQueries;
+pub use book_imports::BookImportQueries;
+pub use book_search::SearchQuery;
+pub use book_series::SeriesQueries;
+pub use book_texts::BookTextQueries;
+pub use books::{BookQueries, BookSortOptions, BookFilterOptions, FUZZY_MATCH_THRESHOLD};
 pub use collections::CollectionQueries;
 pub use covers::CoverQueries;
 pub use devices::DeviceQueries;
+pub use idempotency_keys::SyncIdempotencyQueries;
+pub use kosync_accounts::KosyncAccountQueries;
+pub use reading_sessions::ReadingSessionQueries;
 pub use reading_states::ReadingStateQueries;
+pub use sync_conflicts::SyncConflictQueries;
+pub use sync_settings::SyncSettingsQueries;
 pub use tasks::TaskQueries;
+pub use tombstones::TombstoneQueries;
 pub use users::UserQueries;
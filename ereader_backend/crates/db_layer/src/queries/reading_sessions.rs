@@ -0,0 +1,151 @@
+//! Reading session database queries.
+//!
+//! Statistics group sessions by the local day they started on, given the client's offset
+//! from UTC in minutes.
+
+use crate::models::{BookReadingTotals, CreateReadingSession, ReadingTimeBucket, StatsPeriod};
+use crate::pool::{DbConn, DbPool};
+use chrono::{DateTime, NaiveDate, Utc};
+use common::Result;
+use uuid::Uuid;
+
+/// Reading session database queries
+pub struct ReadingSessionQueries;
+
+impl ReadingSessionQueries {
+    /// Record sessions, skipping any already recorded, and return how many were new
+    pub async fn insert_many(
+        conn: &mut DbConn,
+        user_id: &str,
+        data: &[CreateReadingSession],
+    ) -> Result<u64> {
+        let result = sqlx::query(
+            r#"
+            INSERT INTO reading_sessions (id, user_id, book_id, device_id, started_at, ended_at,
+                                          start_locator, end_locator, progress_delta)
+            SELECT u.id, $1, u.book_id, u.device_id, u.started_at, u.ended_at,
+                   u.start_locator, u.end_locator, u.progress_delta
+            FROM UNNEST($2::uuid[], $3::uuid[], $4::uuid[], $5::timestamptz[], $6::timestamptz[],
+                        $7::text[], $8::text[], $9::real[])
+                AS u(id, book_id, device_id, started_at, ended_at,
+                     start_locator, end_locator, progress_delta)
+            ON CONFLICT (id) DO NOTHING
+            "#,
+        )
+        .bind(user_id)
+        .bind(data.iter().map(|d| d.id).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.book_id).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.device_id).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.started_at).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.ended_at).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.start_locator.as_str()).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.end_locator.as_str()).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.progress_delta).collect::<Vec<_>>())
+        .execute(conn)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Total reading time per day or week for sessions started in `[from, to)`
+    pub async fn reading_time(
+        pool: &DbPool,
+        user_id: &str,
+        period: StatsPeriod,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        utc_offset_minutes: i32,
+    ) -> Result<Vec<ReadingTimeBucket>> {
+        let buckets = sqlx::query_as::<_, ReadingTimeBucket>(
+            r#"
+            SELECT date_trunc($2, started_at AT TIME ZONE 'UTC' + make_interval(mins => $3))::date
+                       AS period_start,
+                   SUM(EXTRACT(EPOCH FROM ended_at - started_at))::bigint AS seconds,
+                   COUNT(*) AS sessions
+            FROM reading_sessions
+            WHERE user_id = $1 AND started_at >= $4 AND started_at < $5
+            GROUP BY 1
+            ORDER BY 1
+            "#,
+        )
+        .bind(user_id)
+        .bind(period.trunc_unit())
+        .bind(utc_offset_minutes)
+        .bind(from)
+        .bind(to)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(buckets)
+    }
+
+    /// Distinct local days with at least one session, oldest first
+    pub async fn reading_days(
+        pool: &DbPool,
+        user_id: &str,
+        utc_offset_minutes: i32,
+    ) -> Result<Vec<NaiveDate>> {
+        let days = sqlx::query_scalar::<_, NaiveDate>(
+            r#"
+            SELECT DISTINCT (started_at AT TIME ZONE 'UTC' + make_interval(mins => $2))::date AS day
+            FROM reading_sessions
+            WHERE user_id = $1
+            ORDER BY day
+            "#,
+        )
+        .bind(user_id)
+        .bind(utc_offset_minutes)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(days)
+    }
+
+    /// Reading totals for each book the user has sessions for, most recently read first
+    pub async fn book_totals(pool: &DbPool, user_id: &str) -> Result<Vec<BookReadingTotals>> {
+        let totals = sqlx::query_as::<_, BookReadingTotals>(
+            r#"
+            SELECT book_id,
+                   SUM(EXTRACT(EPOCH FROM ended_at - started_at))::bigint AS seconds,
+                   COUNT(*) AS sessions,
+                   SUM(GREATEST(progress_delta, 0))::float8 AS progress,
+                   MAX(ended_at) AS last_read_at
+            FROM reading_sessions
+            WHERE user_id = $1
+            GROUP BY book_id
+            ORDER BY last_read_at DESC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(totals)
+    }
+
+    /// Reading totals for one book, if the user has sessions for it
+    pub async fn totals_for_book(
+        pool: &DbPool,
+        user_id: &str,
+        book_id: Uuid,
+    ) -> Result<Option<BookReadingTotals>> {
+        let totals = sqlx::query_as::<_, BookReadingTotals>(
+            r#"
+            SELECT book_id,
+                   SUM(EXTRACT(EPOCH FROM ended_at - started_at))::bigint AS seconds,
+                   COUNT(*) AS sessions,
+                   SUM(GREATEST(progress_delta, 0))::float8 AS progress,
+                   MAX(ended_at) AS last_read_at
+            FROM reading_sessions
+            WHERE user_id = $1 AND book_id = $2
+            GROUP BY book_id
+            "#,
+        )
+        .bind(user_id)
+        .bind(book_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(totals)
+    }
+}
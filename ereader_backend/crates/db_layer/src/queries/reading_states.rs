@@ -1,9 +1,9 @@
 //! Reading state database queries.
 
-use crate::models::{ReadingState, UpsertReadingState};
-use crate::pool::DbPool;
-use chrono::{DateTime, Utc};
-use common::Result;
+use crate::models::{DeviceReadingState, ReadingState, UpsertReadingState};
+use crate::pool::{DbConn, DbPool};
+use common::{ReadingLocation, Result};
+use sqlx::types::Json;
 use uuid::Uuid;
 
 /// Reading state-related database queries
@@ -18,7 +18,8 @@ impl ReadingStateQueries {
     ) -> Result<Option<ReadingState>> {
         let state = sqlx::query_as::<_, ReadingState>(
             r#"
-            SELECT id, user_id, book_id, device_id, location, updated_at
+            SELECT id, user_id, book_id, device_id, location,
+                   hlc_physical, hlc_logical, hlc_device_id, change_seq, updated_at
             FROM reading_states
             WHERE user_id = $1 AND book_id = $2
             "#,
@@ -35,7 +36,8 @@ impl ReadingStateQueries {
     pub async fn get_for_user(pool: &DbPool, user_id: &str) -> Result<Vec<ReadingState>> {
         let states = sqlx::query_as::<_, ReadingState>(
             r#"
-            SELECT id, user_id, book_id, device_id, location, updated_at
+            SELECT id, user_id, book_id, device_id, location,
+                   hlc_physical, hlc_logical, hlc_device_id, change_seq, updated_at
             FROM reading_states
             WHERE user_id = $1
             ORDER BY updated_at DESC
@@ -48,49 +50,201 @@ impl ReadingStateQueries {
         Ok(states)
     }
 
-    /// Get reading states updated since a specific time (for sync)
-    pub async fn get_updated_since(
+    /// Get a user's reading states for several books
+    pub async fn get_for_books(
         pool: &DbPool,
         user_id: &str,
-        since: DateTime<Utc>,
+        book_ids: &[Uuid],
     ) -> Result<Vec<ReadingState>> {
         let states = sqlx::query_as::<_, ReadingState>(
             r#"
-            SELECT id, user_id, book_id, device_id, location, updated_at
+            SELECT id, user_id, book_id, device_id, location,
+                   hlc_physical, hlc_logical, hlc_device_id, change_seq, updated_at
             FROM reading_states
-            WHERE user_id = $1 AND updated_at > $2
-            ORDER BY updated_at ASC
+            WHERE user_id = $1 AND book_id = ANY($2)
             "#,
         )
         .bind(user_id)
-        .bind(since)
+        .bind(book_ids)
         .fetch_all(pool)
         .await?;
 
         Ok(states)
     }
 
-    /// Create or update a reading state
-    pub async fn upsert(pool: &DbPool, data: &UpsertReadingState) -> Result<ReadingState> {
-        let state = sqlx::query_as::<_, ReadingState>(
+    /// Get reading states changed after a sync sequence number, oldest change first
+    pub async fn get_changed_since(
+        conn: &mut DbConn,
+        user_id: &str,
+        since_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<ReadingState>> {
+        let states = sqlx::query_as::<_, ReadingState>(
+            r#"
+            SELECT id, user_id, book_id, device_id, location,
+                   hlc_physical, hlc_logical, hlc_device_id, change_seq, updated_at
+            FROM reading_states
+            WHERE user_id = $1 AND change_seq > $2
+            ORDER BY change_seq ASC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(since_seq)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(states)
+    }
+
+    /// Get the reading states for several books, locking them until the transaction ends
+    pub async fn lock_for_books(
+        conn: &mut DbConn,
+        user_id: &str,
+        book_ids: &[Uuid],
+    ) -> Result<Vec<ReadingState>> {
+        let states = sqlx::query_as::<_, ReadingState>(
+            r#"
+            SELECT id, user_id, book_id, device_id, location,
+                   hlc_physical, hlc_logical, hlc_device_id, change_seq, updated_at
+            FROM reading_states
+            WHERE user_id = $1 AND book_id = ANY($2)
+            FOR UPDATE
+            "#,
+        )
+        .bind(user_id)
+        .bind(book_ids)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(states)
+    }
+
+    /// Create or update the reading states of several books in one statement
+    ///
+    /// Each book may appear at most once.
+    pub async fn upsert_many(
+        conn: &mut DbConn,
+        user_id: &str,
+        data: &[UpsertReadingState],
+    ) -> Result<Vec<ReadingState>> {
+        let columns = ReadingStateColumns::from(data);
+        let states = sqlx::query_as::<_, ReadingState>(
             r#"
-            INSERT INTO reading_states (user_id, book_id, device_id, location)
-            VALUES ($1, $2, $3, $4)
+            INSERT INTO reading_states (user_id, book_id, device_id, location,
+                                        hlc_physical, hlc_logical, hlc_device_id)
+            SELECT $1, * FROM UNNEST($2::uuid[], $3::uuid[], $4::jsonb[],
+                                     $5::bigint[], $6::int[], $7::uuid[])
             ON CONFLICT (user_id, book_id) DO UPDATE SET
                 device_id = EXCLUDED.device_id,
                 location = EXCLUDED.location,
+                hlc_physical = EXCLUDED.hlc_physical,
+                hlc_logical = EXCLUDED.hlc_logical,
+                hlc_device_id = EXCLUDED.hlc_device_id,
                 updated_at = NOW()
-            RETURNING id, user_id, book_id, device_id, location, updated_at
+            RETURNING id, user_id, book_id, device_id, location,
+                      hlc_physical, hlc_logical, hlc_device_id, change_seq, updated_at
             "#,
         )
-        .bind(&data.user_id)
-        .bind(data.book_id)
-        .bind(data.device_id)
-        .bind(sqlx::types::Json(&data.location))
-        .fetch_one(pool)
+        .bind(user_id)
+        .bind(columns.book_ids)
+        .bind(columns.device_ids)
+        .bind(columns.locations)
+        .bind(columns.hlc_physical)
+        .bind(columns.hlc_logical)
+        .bind(columns.hlc_device_ids)
+        .fetch_all(conn)
         .await?;
 
-        Ok(state)
+        Ok(states)
+    }
+
+    /// Get the last position reported by each of the user's devices for a book
+    pub async fn get_device_positions(
+        pool: &DbPool,
+        user_id: &str,
+        book_id: Uuid,
+    ) -> Result<Vec<DeviceReadingState>> {
+        let positions = sqlx::query_as::<_, DeviceReadingState>(
+            r#"
+            SELECT drs.book_id, drs.device_id, d.name AS device_name, drs.location,
+                   drs.hlc_physical, drs.hlc_logical, drs.hlc_device_id, drs.updated_at
+            FROM device_reading_states drs
+            INNER JOIN devices d ON d.id = drs.device_id
+            WHERE drs.user_id = $1 AND drs.book_id = $2
+            ORDER BY drs.hlc_physical DESC, drs.hlc_logical DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(book_id)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(positions)
+    }
+
+    /// Get the last position reported by each of the user's devices for several books
+    pub async fn get_device_positions_for_books(
+        conn: &mut DbConn,
+        user_id: &str,
+        book_ids: &[Uuid],
+    ) -> Result<Vec<DeviceReadingState>> {
+        let positions = sqlx::query_as::<_, DeviceReadingState>(
+            r#"
+            SELECT drs.book_id, drs.device_id, d.name AS device_name, drs.location,
+                   drs.hlc_physical, drs.hlc_logical, drs.hlc_device_id, drs.updated_at
+            FROM device_reading_states drs
+            INNER JOIN devices d ON d.id = drs.device_id
+            WHERE drs.user_id = $1 AND drs.book_id = ANY($2)
+            ORDER BY drs.hlc_physical DESC, drs.hlc_logical DESC
+            "#,
+        )
+        .bind(user_id)
+        .bind(book_ids)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(positions)
+    }
+
+    /// Record the positions reported by devices for several books in one statement
+    ///
+    /// Each book and device pair may appear at most once. As with a single position,
+    /// older writes never replace a newer one.
+    pub async fn upsert_device_positions(
+        conn: &mut DbConn,
+        user_id: &str,
+        data: &[UpsertReadingState],
+    ) -> Result<()> {
+        let columns = ReadingStateColumns::from(data);
+        sqlx::query(
+            r#"
+            INSERT INTO device_reading_states (user_id, book_id, device_id, location,
+                                               hlc_physical, hlc_logical, hlc_device_id)
+            SELECT $1, * FROM UNNEST($2::uuid[], $3::uuid[], $4::jsonb[],
+                                     $5::bigint[], $6::int[], $7::uuid[])
+            ON CONFLICT (user_id, book_id, device_id) DO UPDATE SET
+                location = EXCLUDED.location,
+                hlc_physical = EXCLUDED.hlc_physical,
+                hlc_logical = EXCLUDED.hlc_logical,
+                hlc_device_id = EXCLUDED.hlc_device_id,
+                updated_at = NOW()
+            WHERE (device_reading_states.hlc_physical, device_reading_states.hlc_logical)
+                < (EXCLUDED.hlc_physical, EXCLUDED.hlc_logical)
+            "#,
+        )
+        .bind(user_id)
+        .bind(columns.book_ids)
+        .bind(columns.device_ids)
+        .bind(columns.locations)
+        .bind(columns.hlc_physical)
+        .bind(columns.hlc_logical)
+        .bind(columns.hlc_device_ids)
+        .execute(conn)
+        .await?;
+
+        Ok(())
     }
 
     /// Delete reading state for a book
@@ -104,3 +258,26 @@ impl ReadingStateQueries {
         Ok(result.rows_affected() > 0)
     }
 }
+
+/// Reading state upserts split into one array per column, for `UNNEST`
+struct ReadingStateColumns<'a> {
+    book_ids: Vec<Uuid>,
+    device_ids: Vec<Uuid>,
+    locations: Vec<Json<&'a ReadingLocation>>,
+    hlc_physical: Vec<i64>,
+    hlc_logical: Vec<i32>,
+    hlc_device_ids: Vec<Uuid>,
+}
+
+impl<'a> From<&'a [UpsertReadingState]> for ReadingStateColumns<'a> {
+    fn from(data: &'a [UpsertReadingState]) -> Self {
+        Self {
+            book_ids: data.iter().map(|d| d.book_id).collect(),
+            device_ids: data.iter().map(|d| d.device_id).collect(),
+            locations: data.iter().map(|d| Json(&d.location)).collect(),
+            hlc_physical: data.iter().map(|d| d.hlc.physical).collect(),
+            hlc_logical: data.iter().map(|d| d.hlc.logical as i32).collect(),
+            hlc_device_ids: data.iter().map(|d| d.hlc.device_id).collect(),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! Sync conflict database queries.
+
+use crate::models::{ConflictChoice, CreateSyncConflictEntry, SyncConflictEntry};
+use crate::pool::{DbConn, DbPool};
+use chrono::{DateTime, Utc};
+use common::{Paginated, Pagination, Result};
+use uuid::Uuid;
+
+/// Sync conflict database queries
+pub struct SyncConflictQueries;
+
+impl SyncConflictQueries {
+    /// Log conflicts found while merging a sync batch from a device
+    pub async fn create_many(
+        conn: &mut DbConn,
+        user_id: &str,
+        device_id: Uuid,
+        data: &[CreateSyncConflictEntry],
+    ) -> Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO sync_conflicts (id, user_id, device_id, entity_type, entity_id,
+                                        resolution, local_version, server_version, copy_id)
+            SELECT u.id, $1, $2, u.entity_type, u.entity_id,
+                   u.resolution, u.local_version, u.server_version, u.copy_id
+            FROM UNNEST($3::uuid[], $4::text[], $5::uuid[], $6::conflict_resolution[],
+                        $7::jsonb[], $8::jsonb[], $9::uuid[])
+                AS u(id, entity_type, entity_id,
+                     resolution, local_version, server_version, copy_id)
+            "#,
+        )
+        .bind(user_id)
+        .bind(device_id)
+        .bind(data.iter().map(|d| d.id).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.entity_type.as_str()).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.entity_id).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.resolution).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| &d.local_version).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| &d.server_version).collect::<Vec<_>>())
+        .bind(data.iter().map(|d| d.copy_id).collect::<Vec<_>>())
+        .execute(conn)
+        .await?;
+
+        Ok(())
+    }
+
+    /// List a user's unresolved conflicts, newest first
+    pub async fn list_unresolved(
+        pool: &DbPool,
+        user_id: &str,
+        pagination: &Pagination,
+    ) -> Result<Paginated<SyncConflictEntry>> {
+        let total = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM sync_conflicts WHERE user_id = $1 AND resolved_at IS NULL",
+        )
+        .bind(user_id)
+        .fetch_one(pool)
+        .await?;
+
+        let items = sqlx::query_as::<_, SyncConflictEntry>(
+            r#"
+            SELECT id, user_id, device_id, entity_type, entity_id, resolution,
+                   local_version, server_version, copy_id,
+                   created_at, resolved_at, resolved_with
+            FROM sync_conflicts
+            WHERE user_id = $1 AND resolved_at IS NULL
+            ORDER BY created_at DESC
+            LIMIT $2 OFFSET $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(pagination.limit)
+        .bind(pagination.offset)
+        .fetch_all(pool)
+        .await?;
+
+        Ok(Paginated::new(items, total, pagination))
+    }
+
+    /// Get a user's conflict and lock it until the transaction ends
+    pub async fn lock_for_user(
+        conn: &mut DbConn,
+        id: Uuid,
+        user_id: &str,
+    ) -> Result<Option<SyncConflictEntry>> {
+        let conflict = sqlx::query_as::<_, SyncConflictEntry>(
+            r#"
+            SELECT id, user_id, device_id, entity_type, entity_id, resolution,
+                   local_version, server_version, copy_id,
+                   created_at, resolved_at, resolved_with
+            FROM sync_conflicts
+            WHERE id = $1 AND user_id = $2
+            FOR UPDATE
+            "#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(conflict)
+    }
+
+    /// Record the version the user picked for a conflict, and the id of the copy of the
+    /// device's version if one was kept
+    pub async fn mark_resolved(
+        conn: &mut DbConn,
+        id: Uuid,
+        choice: ConflictChoice,
+        copy_id: Option<Uuid>,
+    ) -> Result<SyncConflictEntry> {
+        let conflict = sqlx::query_as::<_, SyncConflictEntry>(
+            r#"
+            UPDATE sync_conflicts
+            SET resolved_at = NOW(), resolved_with = $2, copy_id = $3
+            WHERE id = $1
+            RETURNING id, user_id, device_id, entity_type, entity_id, resolution,
+                      local_version, server_version, copy_id,
+                      created_at, resolved_at, resolved_with
+            "#,
+        )
+        .bind(id)
+        .bind(choice)
+        .bind(copy_id)
+        .fetch_one(conn)
+        .await?;
+
+        Ok(conflict)
+    }
+
+    /// Delete conflicts resolved before a cutoff, returning how many were removed
+    pub async fn prune_resolved_before(pool: &DbPool, cutoff: DateTime<Utc>) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM sync_conflicts WHERE resolved_at < $1")
+            .bind(cutoff)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,50 @@
+//! Sync settings database queries.
+
+use crate::models::SyncSettings;
+use crate::pool::DbPool;
+use common::{ReadingPositionPolicy, Result};
+
+/// Sync settings-related database queries
+pub struct SyncSettingsQueries;
+
+impl SyncSettingsQueries {
+    /// Get sync settings for a user, falling back to defaults if none are stored
+    pub async fn get_for_user(pool: &DbPool, user_id: &str) -> Result<SyncSettings> {
+        let settings = sqlx::query_as::<_, SyncSettings>(
+            r#"
+            SELECT user_id, reading_position_policy, updated_at
+            FROM sync_settings
+            WHERE user_id = $1
+            "#,
+        )
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+
+        Ok(settings.unwrap_or_else(|| SyncSettings::default_for(user_id)))
+    }
+
+    /// Set the reading position policy for a user
+    pub async fn set_reading_position_policy(
+        pool: &DbPool,
+        user_id: &str,
+        policy: ReadingPositionPolicy,
+    ) -> Result<SyncSettings> {
+        let settings = sqlx::query_as::<_, SyncSettings>(
+            r#"
+            INSERT INTO sync_settings (user_id, reading_position_policy)
+            VALUES ($1, $2)
+            ON CONFLICT (user_id) DO UPDATE SET
+                reading_position_policy = EXCLUDED.reading_position_policy,
+                updated_at = NOW()
+            RETURNING user_id, reading_position_policy, updated_at
+            "#,
+        )
+        .bind(user_id)
+        .bind(policy)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(settings)
+    }
+}
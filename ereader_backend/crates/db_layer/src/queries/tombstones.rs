@@ -0,0 +1,79 @@
+//! Sync tombstone database queries.
+
+use crate::models::Tombstone;
+use crate::pool::{DbConn, DbPool};
+use chrono::{DateTime, Utc};
+use common::Result;
+
+/// Tombstone-related database queries
+pub struct TombstoneQueries;
+
+impl TombstoneQueries {
+    /// Get tombstones recorded after a sync sequence number, oldest first
+    pub async fn get_changed_since(
+        conn: &mut DbConn,
+        user_id: &str,
+        since_seq: i64,
+        limit: i64,
+    ) -> Result<Vec<Tombstone>> {
+        let tombstones = sqlx::query_as::<_, Tombstone>(
+            r#"
+            SELECT id, user_id, entity_type, entity_id, parent_id, change_seq, deleted_at
+            FROM sync_tombstones
+            WHERE user_id = $1 AND change_seq > $2
+            ORDER BY change_seq ASC
+            LIMIT $3
+            "#,
+        )
+        .bind(user_id)
+        .bind(since_seq)
+        .bind(limit)
+        .fetch_all(conn)
+        .await?;
+
+        Ok(tombstones)
+    }
+
+    /// Get the newest change sequence number whose tombstones may have been pruned
+    ///
+    /// A client resuming from an older sequence number may have missed deletes.
+    pub async fn pruned_seq(conn: &mut DbConn, user_id: &str) -> Result<i64> {
+        let seq = sqlx::query_scalar::<_, i64>(
+            "SELECT pruned_seq FROM sync_change_counters WHERE user_id = $1",
+        )
+        .bind(user_id)
+        .fetch_optional(conn)
+        .await?;
+
+        Ok(seq.unwrap_or(0))
+    }
+
+    /// Delete tombstones older than a cutoff, returning how many were removed
+    pub async fn prune_older_than(pool: &DbPool, cutoff: DateTime<Utc>) -> Result<i64> {
+        let pruned = sqlx::query_scalar::<_, i64>(
+            r#"
+            WITH pruned AS (
+                DELETE FROM sync_tombstones
+                WHERE deleted_at < $1
+                RETURNING user_id, change_seq
+            ),
+            marked AS (
+                UPDATE sync_change_counters c
+                SET pruned_seq = GREATEST(c.pruned_seq, p.max_seq)
+                FROM (
+                    SELECT user_id, MAX(change_seq) AS max_seq
+                    FROM pruned
+                    GROUP BY user_id
+                ) p
+                WHERE c.user_id = p.user_id
+            )
+            SELECT COUNT(*) FROM pruned
+            "#,
+        )
+        .bind(cutoff)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(pruned)
+    }
+}
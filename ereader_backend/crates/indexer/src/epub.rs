@@ -1,8 +1,11 @@
 //! EPUB format handler.
 
+use crate::text::{BookText, ChapterText};
 use crate::traits::{BookMetadata, FormatHandler, LocationInfo, LocationItem};
-use common::{BookFormat, Error, Result};
-use epub::doc::EpubDoc;
+use common::types::relator;
+use common::{BookFormat, Contributor, Error, Result};
+use epub::doc::{EpubDoc, MetadataItem, NavPoint};
+use std::collections::HashMap;
 use std::io::Cursor;
 
 /// Handler for EPUB files
@@ -37,14 +40,35 @@ impl FormatHandler for EpubHandler {
             metadata.title = Some(title.value.clone());
         }
 
-        // Authors (creator)
-        if let Some(creator) = doc.mdata("creator") {
-            // Split multiple authors if separated by common delimiters
-            metadata.authors = creator.value
-                .split(&[',', ';', '&'][..])
+        // Creators and contributors, with their roles and the names they file under
+        for item in doc.metadata.iter().filter(|item| matches!(item.property.as_str(), "creator" | "contributor")) {
+            // A name such as "Smith, John" is one person, but an element may list several
+            // people separated by semicolons or ampersands
+            let names: Vec<String> = item.value
+                .split(&[';', '&'][..])
                 .map(|s| s.trim().to_string())
                 .filter(|s| !s.is_empty())
                 .collect();
+            // EPUB 2 gives it as an opf:file-as attribute, EPUB 3 as a refining meta
+            if let ([name], Some(file_as)) = (names.as_slice(), item.refinement("file-as"))
+                && !file_as.value.trim().is_empty()
+            {
+                metadata.sort_names.insert(name.clone(), file_as.value.trim().to_string());
+            }
+            for role in creator_roles(item) {
+                for name in &names {
+                    if role == relator::AUTHOR {
+                        if !metadata.authors.contains(name) {
+                            metadata.authors.push(name.clone());
+                        }
+                    } else {
+                        let contributor = Contributor::new(name.clone(), role.clone());
+                        if !metadata.contributors.contains(&contributor) {
+                            metadata.contributors.push(contributor);
+                        }
+                    }
+                }
+            }
         }
 
         // Description
@@ -87,6 +111,7 @@ impl FormatHandler for EpubHandler {
         tracing::debug!(
             title = ?metadata.title,
             authors = ?metadata.authors,
+            contributors = metadata.contributors.len(),
             "Extracted EPUB metadata"
         );
 
@@ -120,8 +145,10 @@ impl FormatHandler for EpubHandler {
         let mut items = Vec::new();
 
         for (order, spine_item) in spine.iter().enumerate() {
+            let path = resource_path(&doc, &spine_item.idref);
             items.push(LocationItem {
                 id: spine_item.idref.clone(),
+                path,
                 label: None,  // Could extract from NCX/NAV
                 order: order as u32,
             });
@@ -139,15 +166,179 @@ impl FormatHandler for EpubHandler {
             items,
         })
     }
+
+    fn extract_text(&self, data: &[u8]) -> Result<BookText> {
+        let cursor = Cursor::new(data);
+        let mut doc = EpubDoc::from_reader(cursor)
+            .map_err(|e| Error::Validation(format!("Failed to parse EPUB: {}", e)))?;
+
+        let spine = doc.spine.clone();
+        let titles = toc_titles(&doc.toc);
+        let mut chapters = Vec::with_capacity(spine.len());
+
+        for (index, spine_item) in spine.iter().enumerate() {
+            let path = resource_path(&doc, &spine_item.idref);
+            let Some((xhtml, _mime)) = doc.get_resource_str(&spine_item.idref) else {
+                tracing::warn!(idref = %spine_item.idref, "Spine item missing from EPUB");
+                continue;
+            };
+            match ChapterText::from_xhtml(index, spine_item.idref.clone(), &xhtml) {
+                Ok(mut chapter) => {
+                    chapter.title = path.and_then(|path| titles.get(&path).cloned());
+                    chapters.push(chapter);
+                }
+                Err(e) => {
+                    // One malformed document shouldn't hide the rest of the book
+                    tracing::warn!(idref = %spine_item.idref, error = %e, "Skipping unreadable spine item");
+                }
+            }
+        }
+
+        tracing::debug!(chapters = chapters.len(), "Extracted EPUB text");
+
+        Ok(BookText { chapters })
+    }
+}
+
+/// MARC relator codes of a creator's or contributor's roles
+///
+/// EPUB 2 gives a role as an opf:role attribute and EPUB 3 as `role` metas refining the
+/// element. Creators without one are authors, and contributors unspecified contributors.
+fn creator_roles(item: &MetadataItem) -> Vec<String> {
+    let roles: Vec<String> = item
+        .refined
+        .iter()
+        .filter(|r| r.property == "role" && r.scheme.as_deref().is_none_or(|s| s == "marc:relators"))
+        .filter_map(|r| relator::normalize(&r.value))
+        .collect();
+    if !roles.is_empty() {
+        return roles;
+    }
+    let role = if item.property == "creator" { relator::AUTHOR } else { relator::CONTRIBUTOR };
+    vec![role.to_string()]
+}
+
+/// Archive path of a manifest item
+fn resource_path(doc: &EpubDoc<Cursor<&[u8]>>, idref: &str) -> Option<String> {
+    doc.resources
+        .get(idref)
+        .map(|r| r.path.to_string_lossy().replace('\\', "/"))
+}
+
+/// Titles of the table of contents entries by the archive path they point into
+///
+/// A document with several entries takes the first.
+fn toc_titles(toc: &[NavPoint]) -> HashMap<String, String> {
+    fn collect(points: &[NavPoint], titles: &mut HashMap<String, String>) {
+        for point in points {
+            let content = point.content.to_string_lossy().replace('\\', "/");
+            let path = content.split('#').next().unwrap_or_default();
+            let label = point.label.trim();
+            if !label.is_empty() {
+                titles
+                    .entry(path.to_string())
+                    .or_insert_with(|| label.to_string());
+            }
+            collect(&point.children, titles);
+        }
+    }
+
+    let mut titles = HashMap::new();
+    collect(toc, &mut titles);
+    titles
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io::Write;
+    use zip::write::SimpleFileOptions;
+    use zip::ZipWriter;
+
+    /// EPUB of one empty chapter with the given package version and metadata elements
+    fn epub(version: &str, metadata: &str) -> Vec<u8> {
+        let opf = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="{version}" unique-identifier="id">
+<metadata xmlns:dc="http://purl.org/dc/elements/1.1/" xmlns:opf="http://www.idpf.org/2007/opf">
+<dc:identifier id="id">test</dc:identifier><dc:title>Test</dc:title>{metadata}
+</metadata>
+<manifest><item id="ch01" href="ch01.xhtml" media-type="application/xhtml+xml"/></manifest>
+<spine><itemref idref="ch01"/></spine>
+</package>"#
+        );
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        zip.start_file("mimetype", options).unwrap();
+        zip.write_all(b"application/epub+zip").unwrap();
+        zip.start_file("META-INF/container.xml", options).unwrap();
+        zip.write_all(
+            br#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#,
+        )
+        .unwrap();
+        zip.start_file("OEBPS/content.opf", options).unwrap();
+        zip.write_all(opf.as_bytes()).unwrap();
+        zip.start_file("OEBPS/ch01.xhtml", options).unwrap();
+        zip.write_all(b"<html xmlns=\"http://www.w3.org/1999/xhtml\"><body/></html>").unwrap();
+        zip.finish().unwrap().into_inner()
+    }
 
     #[test]
     fn test_epub_handler_format() {
         let handler = EpubHandler::new();
         assert_eq!(handler.format(), BookFormat::Epub);
     }
+
+    #[test]
+    fn test_extract_creators() {
+        let handler = EpubHandler::new();
+
+        let epub2 = epub(
+            "2.0",
+            r#"<dc:creator opf:file-as="Pratchett, Terry" opf:role="aut">Terry Pratchett</dc:creator>
+<dc:creator>Gaiman, Neil</dc:creator>
+<dc:creator opf:role="ill">Paul Kidby</dc:creator>
+<dc:contributor opf:role="TRL">Jane Doe; John Roe</dc:contributor>
+<dc:contributor>Calibre</dc:contributor>"#,
+        );
+        let metadata = handler.extract_metadata(&epub2).unwrap();
+        assert_eq!(metadata.authors, vec!["Terry Pratchett", "Gaiman, Neil"]);
+        assert_eq!(
+            metadata.contributors,
+            vec![
+                Contributor::new("Paul Kidby", relator::ILLUSTRATOR),
+                Contributor::new("Jane Doe", relator::TRANSLATOR),
+                Contributor::new("John Roe", relator::TRANSLATOR),
+                Contributor::new("Calibre", relator::CONTRIBUTOR),
+            ]
+        );
+        assert_eq!(metadata.sort_names.len(), 1);
+        assert_eq!(metadata.sort_names["Terry Pratchett"], "Pratchett, Terry");
+        assert_eq!(metadata.author_sort().as_deref(), Some("Pratchett, Terry & Gaiman, Neil"));
+
+        let epub3 = epub(
+            "3.0",
+            r##"<dc:creator id="a1">Ursula K. Le Guin</dc:creator>
+<meta refines="#a1" property="file-as">Le Guin, Ursula K.</meta>
+<meta refines="#a1" property="role" scheme="marc:relators">aut</meta>
+<dc:creator id="c2">Richard Pevear</dc:creator>
+<meta refines="#c2" property="role" scheme="marc:relators">trl</meta>
+<meta refines="#c2" property="role" scheme="marc:relators">edt</meta>
+<meta refines="#c2" property="file-as">Pevear, Richard</meta>"##,
+        );
+        let metadata = handler.extract_metadata(&epub3).unwrap();
+        assert_eq!(metadata.authors, vec!["Ursula K. Le Guin"]);
+        assert_eq!(
+            metadata.contributors,
+            vec![
+                Contributor::new("Richard Pevear", relator::TRANSLATOR),
+                Contributor::new("Richard Pevear", relator::EDITOR),
+            ]
+        );
+        assert_eq!(metadata.sort_names["Richard Pevear"], "Pevear, Richard");
+        assert_eq!(metadata.author_sort().as_deref(), Some("Le Guin, Ursula K."));
+    }
 }
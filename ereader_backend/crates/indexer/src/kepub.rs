@@ -0,0 +1,282 @@
+//! KEPUB conversion for Kobo devices.
+//!
+//! Kobo devices open KEPUBs with their own rendering engine, which pages faster and keeps
+//! reading statistics. A KEPUB is an EPUB whose content documents wrap each sentence in a
+//! `<span class="koboSpan" id="kobo.{paragraph}.{sentence}">`; the device's bookmarks name
+//! these spans. Content documents that aren't well-formed XML are copied unchanged, so a
+//! conversion never loses text.
+
+use crate::text::{BLOCK_ELEMENTS, HTML_ENTITIES};
+use common::{Error, Result};
+use std::io::{Cursor, Read, Write};
+use xml::reader::{ParserConfig, XmlEvent};
+use xml::writer::{EmitterConfig, XmlEvent as WriterEvent};
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+/// Class marking the spans Kobo devices locate bookmarks by
+pub const KOBO_SPAN_CLASS: &str = "koboSpan";
+
+/// Elements whose text is left alone
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style", "svg", "math"];
+
+/// Characters that end a sentence
+const SENTENCE_ENDS: &[char] = &['.', '!', '?', '…'];
+
+/// Characters that may follow a sentence end before the space, such as closing quotes
+const SENTENCE_TRAILERS: &[char] = &['"', '\'', '”', '’', ')', ']', '»'];
+
+/// Convert an EPUB to a KEPUB
+pub fn convert_to_kepub(data: &[u8]) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(Cursor::new(data))
+        .map_err(|e| Error::Validation(format!("Failed to read EPUB archive: {}", e)))?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let write_error = |e: zip::result::ZipError| Error::Internal(format!("Failed to write KEPUB: {}", e));
+
+    // The mimetype entry must come first and be stored uncompressed
+    writer
+        .start_file(
+            "mimetype",
+            SimpleFileOptions::default().compression_method(CompressionMethod::Stored),
+        )
+        .map_err(write_error)?;
+    writer.write_all(b"application/epub+zip")?;
+
+    let mut converted = 0;
+    for index in 0..archive.len() {
+        let mut entry = archive
+            .by_index(index)
+            .map_err(|e| Error::Validation(format!("Failed to read EPUB archive: {}", e)))?;
+        let name = entry.name().to_string();
+        if name == "mimetype" {
+            continue;
+        }
+        if !is_content_document(&name) {
+            writer.raw_copy_file(entry).map_err(write_error)?;
+            continue;
+        }
+
+        let mut content = Vec::new();
+        entry.read_to_end(&mut content)?;
+        let output = match std::str::from_utf8(&content).map_err(|e| e.to_string()).and_then(
+            |xhtml| add_kobo_spans(xhtml).map_err(|e| e.to_string()),
+        ) {
+            Ok(xhtml) => {
+                converted += 1;
+                xhtml.into_bytes()
+            }
+            Err(e) => {
+                tracing::warn!(document = %name, error = %e, "Copying content document unconverted");
+                content
+            }
+        };
+
+        writer
+            .start_file(
+                name,
+                SimpleFileOptions::default().compression_method(CompressionMethod::Deflated),
+            )
+            .map_err(write_error)?;
+        writer.write_all(&output)?;
+    }
+
+    tracing::debug!(documents = converted, "Converted EPUB to KEPUB");
+
+    Ok(writer.finish().map_err(write_error)?.into_inner())
+}
+
+/// Wrap the sentences of an XHTML content document in Kobo spans
+pub fn add_kobo_spans(xhtml: &str) -> Result<String> {
+    let mut config = ParserConfig::new()
+        .trim_whitespace(false)
+        .whitespace_to_characters(true)
+        .cdata_to_characters(true)
+        .coalesce_characters(true);
+    for (name, value) in HTML_ENTITIES {
+        config = config.add_entity(*name, *value);
+    }
+
+    let mut output = Vec::with_capacity(xhtml.len() * 2);
+    let mut writer = EmitterConfig::new()
+        .perform_indent(false)
+        .create_writer(&mut output);
+    let write_error =
+        |e: xml::writer::Error| Error::Internal(format!("Failed to write content document: {}", e));
+
+    let mut in_body = false;
+    // Depth inside skipped elements and existing Kobo spans
+    let mut skipped = 0usize;
+    let mut paragraph = 0u32;
+    let mut sentence = 0u32;
+
+    for event in config.create_reader(xhtml.as_bytes()) {
+        let event = event.map_err(|e| {
+            Error::Validation(format!("Failed to parse content document: {}", e))
+        })?;
+        match &event {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => {
+                let local = name.local_name.as_str();
+                let is_kobo_span = local == "span"
+                    && attributes
+                        .iter()
+                        .any(|a| a.name.local_name == "class" && a.value == KOBO_SPAN_CLASS);
+                if skipped > 0 || SKIPPED_ELEMENTS.contains(&local) || is_kobo_span {
+                    skipped += 1;
+                } else if local == "body" {
+                    in_body = true;
+                } else if in_body && BLOCK_ELEMENTS.contains(&local) {
+                    // Each block element starts a new paragraph of spans
+                    paragraph += 1;
+                    sentence = 0;
+                }
+            }
+            XmlEvent::EndElement { name } => {
+                if skipped > 0 {
+                    skipped -= 1;
+                } else if name.local_name == "body" {
+                    in_body = false;
+                }
+            }
+            XmlEvent::Characters(text) if in_body && skipped == 0 => {
+                paragraph = paragraph.max(1);
+                for piece in split_sentences(text) {
+                    if piece.trim().is_empty() {
+                        writer.write(WriterEvent::characters(piece)).map_err(write_error)?;
+                        continue;
+                    }
+                    sentence += 1;
+                    let id = format!("kobo.{}.{}", paragraph, sentence);
+                    writer
+                        .write(
+                            WriterEvent::start_element("span")
+                                .attr("class", KOBO_SPAN_CLASS)
+                                .attr("id", &id),
+                        )
+                        .map_err(write_error)?;
+                    writer.write(WriterEvent::characters(piece)).map_err(write_error)?;
+                    writer.write(WriterEvent::end_element()).map_err(write_error)?;
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        if let Some(event) = event.as_writer_event() {
+            writer.write(event).map_err(write_error)?;
+        }
+    }
+
+    String::from_utf8(output).map_err(|e| Error::Internal(e.to_string()))
+}
+
+/// Split text after each sentence end that is followed by whitespace
+///
+/// The whitespace stays with the sentence before it.
+fn split_sentences(text: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut ended = false;
+    let mut gap = false;
+
+    for (i, c) in text.char_indices() {
+        if gap && !c.is_whitespace() {
+            pieces.push(&text[start..i]);
+            start = i;
+            ended = false;
+            gap = false;
+        }
+        if c.is_whitespace() {
+            gap = ended;
+        } else if SENTENCE_ENDS.contains(&c) {
+            ended = true;
+        } else if !SENTENCE_TRAILERS.contains(&c) {
+            ended = false;
+        }
+    }
+    if start < text.len() {
+        pieces.push(&text[start..]);
+    }
+
+    pieces
+}
+
+/// Whether an archive entry is an XHTML content document
+fn is_content_document(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    lower.ends_with(".xhtml") || lower.ends_with(".html") || lower.ends_with(".htm")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAPTER: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml"><head><title>One. Two.</title></head>
+<body><h1>Chapter&nbsp;1</h1><p>It was late. "Who's there?" she asked.</p><p>No <em>one</em> answered.</p></body></html>"#;
+
+    #[test]
+    fn test_split_sentences() {
+        assert_eq!(
+            split_sentences(r#"It was late. "Who's there?" she asked."#),
+            vec!["It was late. ", r#""Who's there?" "#, "she asked."]
+        );
+        assert_eq!(split_sentences("v1.2 works"), vec!["v1.2 works"]);
+        assert_eq!(split_sentences("  "), vec!["  "]);
+    }
+
+    #[test]
+    fn test_add_kobo_spans() {
+        let converted = add_kobo_spans(CHAPTER).unwrap();
+
+        assert!(converted.contains("<!DOCTYPE html>"));
+        assert!(converted.contains("<title>One. Two.</title>"));
+        assert!(converted.contains(
+            "<h1><span class=\"koboSpan\" id=\"kobo.1.1\">Chapter\u{a0}1</span></h1>"
+        ));
+        assert!(converted.contains(r#"<span class="koboSpan" id="kobo.2.1">It was late. </span>"#));
+        assert!(converted.contains(r#"<span class="koboSpan" id="kobo.2.3">she asked.</span>"#));
+        // Inline elements continue the paragraph
+        assert!(converted.contains(
+            r#"<em><span class="koboSpan" id="kobo.3.2">one</span></em>"#
+        ));
+
+        // Converting again leaves existing spans alone
+        assert_eq!(add_kobo_spans(&converted).unwrap(), converted);
+        assert!(add_kobo_spans("<html><body><p>Broken</body></html>").is_err());
+    }
+
+    #[test]
+    fn test_convert_to_kepub() {
+        let mut epub = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = SimpleFileOptions::default();
+        epub.start_file("META-INF/container.xml", options).unwrap();
+        epub.write_all(b"<container/>").unwrap();
+        epub.start_file("mimetype", options).unwrap();
+        epub.write_all(b"application/epub+zip").unwrap();
+        epub.start_file("OEBPS/ch01.xhtml", options).unwrap();
+        epub.write_all(CHAPTER.as_bytes()).unwrap();
+        epub.start_file("OEBPS/broken.xhtml", options).unwrap();
+        epub.write_all(b"<p>Unclosed").unwrap();
+        let epub = epub.finish().unwrap().into_inner();
+
+        let kepub = convert_to_kepub(&epub).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(kepub)).unwrap();
+        let read = |archive: &mut ZipArchive<Cursor<Vec<u8>>>, name: &str| {
+            let mut content = String::new();
+            archive.by_name(name).unwrap().read_to_string(&mut content).unwrap();
+            content
+        };
+
+        let first = archive.by_index(0).unwrap();
+        assert_eq!(first.name(), "mimetype");
+        assert_eq!(first.compression(), CompressionMethod::Stored);
+        drop(first);
+        assert_eq!(archive.len(), 4);
+        assert!(read(&mut archive, "OEBPS/ch01.xhtml").contains(r#"id="kobo.2.1""#));
+        assert_eq!(read(&mut archive, "OEBPS/broken.xhtml"), "<p>Unclosed");
+        assert_eq!(read(&mut archive, "META-INF/container.xml"), "<container/>");
+    }
+}
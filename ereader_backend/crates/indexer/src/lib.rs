@@ -5,6 +5,8 @@
 //! - Metadata extraction (title, authors, description, etc.)
 //! - Cover image extraction
 //! - Location calculation for navigation
+//! - Chapter text extraction for re-anchoring annotations
+//! - KEPUB conversion for Kobo devices
 //!
 //! To add support for new formats in the future:
 //! 1. Create a new handler module (e.g., `pdf.rs`)
@@ -12,9 +14,13 @@
 //! 3. Add the handler to `handler_for_format()`
 
 pub mod epub;
+pub mod kepub;
+pub mod text;
 pub mod traits;
 
 pub use epub::EpubHandler;
+pub use kepub::convert_to_kepub;
+pub use text::{BookText, ChapterText, TextRange};
 pub use traits::{BookMetadata, FormatHandler, LocationInfo, LocationItem};
 
 use common::BookFormat;
@@ -47,6 +53,24 @@ pub fn calculate_locations(format: BookFormat, data: &[u8]) -> common::Result<Lo
     handler.calculate_locations(data)
 }
 
+/// Archive paths of the documents in a book's spine, in reading order, based on format
+///
+/// Documents without a path in the archive get an empty one, so indices stay spine positions.
+pub fn spine_paths(format: BookFormat, data: &[u8]) -> common::Result<Vec<String>> {
+    Ok(calculate_locations(format, data)?
+        .items
+        .into_iter()
+        .map(|item| item.path.unwrap_or_default())
+        .collect())
+}
+
+/// Extract the text of each spine document based on format
+pub fn extract_text(format: BookFormat, data: &[u8]) -> common::Result<BookText> {
+    let handler = handler_for_format(format)
+        .ok_or_else(|| common::Error::Validation(format!("Unsupported format: {}", format)))?;
+    handler.extract_text(data)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
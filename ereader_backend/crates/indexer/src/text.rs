@@ -0,0 +1,788 @@
+//! Chapter text extraction and text-quote anchoring.
+//!
+//! Annotations are located with EPUB CFIs, which stop pointing at the right text when a
+//! book's file is replaced. The annotated text is therefore also kept as a
+//! [`TextQuoteSelector`]. This module maps between CFIs and positions in the text of each
+//! spine document, so selectors can be captured from one file and found again in another.
+//!
+//! Only the subset of CFI syntax that reading systems produce for text locations is
+//! understood: a spine step, element steps (id assertions are written but not required when
+//! reading), a final text step with a character offset, and the `epubcfi(P,S,E)` range form.
+//! Character offsets count UTF-16 code units, as in the DOM.
+
+use common::TextQuoteSelector;
+use std::cmp::Ordering;
+use xml::reader::{ParserConfig, XmlEvent};
+
+/// Characters of context captured on each side of a quote
+pub const QUOTE_CONTEXT_CHARS: usize = 32;
+
+/// Elements whose text is not part of the readable content
+const SKIPPED_ELEMENTS: &[&str] = &["head", "script", "style"];
+
+/// Elements that separate their text from the text around them
+pub(crate) const BLOCK_ELEMENTS: &[&str] = &[
+    "p", "div", "h1", "h2", "h3", "h4", "h5", "h6", "li", "dt", "dd", "blockquote", "pre",
+    "td", "th", "caption", "figcaption", "section", "article", "aside", "header", "footer",
+];
+
+/// HTML entities commonly found in XHTML content documents, which XML doesn't predefine
+pub(crate) const HTML_ENTITIES: &[(&str, &str)] = &[
+    ("nbsp", "\u{a0}"),
+    ("shy", "\u{ad}"),
+    ("ndash", "\u{2013}"),
+    ("mdash", "\u{2014}"),
+    ("lsquo", "\u{2018}"),
+    ("rsquo", "\u{2019}"),
+    ("ldquo", "\u{201c}"),
+    ("rdquo", "\u{201d}"),
+    ("hellip", "\u{2026}"),
+    ("copy", "\u{a9}"),
+];
+
+/// Text of a book, one entry per spine document
+#[derive(Debug, Clone, Default)]
+pub struct BookText {
+    pub chapters: Vec<ChapterText>,
+}
+
+/// Plain text of a spine document and where each piece of it sits in the markup
+#[derive(Debug, Clone)]
+pub struct ChapterText {
+    /// Position in the spine
+    pub spine_index: usize,
+    /// Manifest id of the document
+    pub idref: String,
+    /// Title of the document in the table of contents
+    pub title: Option<String>,
+    /// Text nodes concatenated in document order, as a DOM range would serialize them
+    pub text: String,
+    nodes: Vec<TextNode>,
+    /// Byte positions in the text where block elements start or end
+    breaks: Vec<usize>,
+    normalized: NormalizedText,
+}
+
+/// A span of text located in a book, as a pair of point CFIs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextRange {
+    pub start: String,
+    pub end: String,
+}
+
+/// One step of a CFI path: a child index and an optional id assertion
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    index: u32,
+    id: Option<String>,
+}
+
+/// A text node within a chapter
+#[derive(Debug, Clone)]
+struct TextNode {
+    /// Element steps from the root element to the node's parent
+    parent: Vec<Step>,
+    /// Odd CFI index of the text chunk the node belongs to
+    step: u32,
+    /// UTF-16 offset of the node within its chunk
+    chunk_offset: usize,
+    /// Byte range of the node in the chapter text
+    start: usize,
+    end: usize,
+}
+
+/// A location parsed from a CFI
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct CfiPoint {
+    spine_index: usize,
+    /// Child indices below the root element; the last is odd for a text location
+    steps: Vec<u32>,
+    /// UTF-16 character offset into the text chunk
+    offset: Option<usize>,
+}
+
+/// Whitespace-collapsed text with a map back to the original byte positions
+#[derive(Debug, Clone, Default)]
+struct NormalizedText {
+    text: String,
+    /// Original byte position of each normalized byte that starts a character
+    origin: Vec<usize>,
+}
+
+impl BookText {
+    /// Capture a text-quote selector for the text between two CFIs
+    ///
+    /// `start` may also be a range CFI, in which case `end` is not needed. A point with no
+    /// end (such as a bookmark) captures the text that follows it.
+    pub fn quote_at(&self, start: &str, end: Option<&str>) -> Option<TextQuoteSelector> {
+        let (start, end) = match (CfiPoint::parse_range(start), end) {
+            (Some((s, e)), _) => (s, Some(e)),
+            (None, end) => (CfiPoint::parse(start)?, end.and_then(CfiPoint::parse)),
+        };
+
+        let chapter = self.chapter(start.spine_index)?;
+        let from = chapter.position_of(&start)?;
+        let to = match end {
+            Some(end) if end.spine_index == start.spine_index => chapter.position_of(&end)?,
+            _ => advance_chars(&chapter.text, from, QUOTE_CONTEXT_CHARS),
+        };
+        if to <= from {
+            return None;
+        }
+
+        Some(chapter.quote(from, to))
+    }
+
+    /// Find the text a selector describes
+    ///
+    /// When the text occurs more than once, the occurrence whose surroundings best match the
+    /// selector's prefix and suffix wins, then the one closest to `near` (a CFI from the
+    /// annotation's previous location).
+    pub fn locate(&self, quote: &TextQuoteSelector, near: Option<&str>) -> Option<TextRange> {
+        let exact = normalize(&quote.exact);
+        let exact = exact.text.trim();
+        if exact.is_empty() {
+            return None;
+        }
+        let prefix = normalize(&quote.prefix).text;
+        let suffix = normalize(&quote.suffix).text;
+        let near_spine = near.and_then(|cfi| {
+            CfiPoint::parse_range(cfi)
+                .map(|(s, _)| s)
+                .or_else(|| CfiPoint::parse(cfi))
+                .map(|p| p.spine_index)
+        });
+
+        let mut best: Option<(usize, usize, &ChapterText, usize)> = None;
+        for chapter in &self.chapters {
+            let haystack = &chapter.normalized.text;
+            for (at, _) in haystack.match_indices(exact) {
+                let context = common_suffix_chars(&prefix, &haystack[..at])
+                    + common_prefix_chars(&suffix, &haystack[at + exact.len()..]);
+                let distance = near_spine.map_or(0, |n| n.abs_diff(chapter.spine_index));
+                let better = match best {
+                    None => true,
+                    Some((best_context, best_distance, _, _)) => {
+                        match context.cmp(&best_context) {
+                            Ordering::Greater => true,
+                            Ordering::Less => false,
+                            Ordering::Equal => distance < best_distance,
+                        }
+                    }
+                };
+                if better {
+                    best = Some((context, distance, chapter, at));
+                }
+            }
+        }
+
+        let (_, _, chapter, at) = best?;
+        let (from, to) = chapter.normalized.original_span(at, exact);
+        Some(TextRange {
+            start: chapter.cfi_at(from, false)?,
+            end: chapter.cfi_at(to, true)?,
+        })
+    }
+
+    fn chapter(&self, spine_index: usize) -> Option<&ChapterText> {
+        self.chapters.iter().find(|c| c.spine_index == spine_index)
+    }
+}
+
+impl TextRange {
+    /// Express the range as a single `epubcfi(P,S,E)` CFI
+    ///
+    /// Falls back to the start point if the ends can't share a parent path.
+    pub fn to_range_cfi(&self) -> String {
+        let (Some(start), Some(end)) = (strip_cfi(&self.start), strip_cfi(&self.end)) else {
+            return self.start.clone();
+        };
+        let (Some((start_doc, start_path)), Some((end_doc, end_path))) =
+            (start.split_once('!'), end.split_once('!'))
+        else {
+            return self.start.clone();
+        };
+        if start_doc != end_doc {
+            return self.start.clone();
+        }
+
+        // Share every whole step except the final (text) step of each end
+        let start_steps = split_steps(start_path);
+        let end_steps = split_steps(end_path);
+        let shared = start_steps[..start_steps.len().saturating_sub(1)]
+            .iter()
+            .zip(&end_steps[..end_steps.len().saturating_sub(1)])
+            .take_while(|(a, b)| a == b)
+            .count();
+
+        format!(
+            "epubcfi({}!{},{},{})",
+            start_doc,
+            start_steps[..shared].concat(),
+            start_steps[shared..].concat(),
+            end_steps[shared..].concat()
+        )
+    }
+}
+
+impl ChapterText {
+    /// Extract the text of an XHTML content document
+    pub fn from_xhtml(
+        spine_index: usize,
+        idref: impl Into<String>,
+        xhtml: &str,
+    ) -> common::Result<Self> {
+        let mut config = ParserConfig::new()
+            .trim_whitespace(false)
+            .whitespace_to_characters(true)
+            .cdata_to_characters(true)
+            .ignore_comments(true)
+            .coalesce_characters(true);
+        for (name, value) in HTML_ENTITIES {
+            config = config.add_entity(*name, *value);
+        }
+
+        /// An open element while walking the document
+        struct Frame {
+            path: Vec<Step>,
+            element_children: u32,
+            skipped: bool,
+        }
+
+        let mut text = String::new();
+        let mut nodes: Vec<TextNode> = Vec::new();
+        let mut breaks: Vec<usize> = Vec::new();
+        let mut stack: Vec<Frame> = Vec::new();
+
+        for event in config.create_reader(xhtml.as_bytes()) {
+            let event = event.map_err(|e| {
+                common::Error::Validation(format!("Failed to parse content document: {}", e))
+            })?;
+            match event {
+                XmlEvent::StartElement {
+                    name, attributes, ..
+                } => {
+                    let skip = SKIPPED_ELEMENTS.contains(&name.local_name.as_str());
+                    if is_break(&name.local_name) {
+                        breaks.push(text.len());
+                    }
+                    let frame = match stack.last_mut() {
+                        // The root element is where CFI paths start
+                        None => Frame {
+                            path: Vec::new(),
+                            element_children: 0,
+                            skipped: skip,
+                        },
+                        Some(parent) => {
+                            parent.element_children += 1;
+                            let mut path = parent.path.clone();
+                            path.push(Step {
+                                index: parent.element_children * 2,
+                                id: attributes
+                                    .iter()
+                                    .find(|a| a.name.local_name == "id")
+                                    .map(|a| a.value.clone()),
+                            });
+                            Frame {
+                                path,
+                                element_children: 0,
+                                skipped: parent.skipped || skip,
+                            }
+                        }
+                    };
+                    stack.push(frame);
+                }
+                XmlEvent::EndElement { name } => {
+                    stack.pop();
+                    if is_break(&name.local_name) {
+                        breaks.push(text.len());
+                    }
+                }
+                XmlEvent::Characters(chunk) => {
+                    let Some(parent) = stack.last() else {
+                        continue;
+                    };
+                    if parent.skipped || chunk.is_empty() {
+                        continue;
+                    }
+                    let step = parent.element_children * 2 + 1;
+                    let chunk_offset = match nodes.last() {
+                        Some(prev) if prev.step == step && prev.parent == parent.path => {
+                            prev.chunk_offset + text[prev.start..prev.end].encode_utf16().count()
+                        }
+                        _ => 0,
+                    };
+                    let start = text.len();
+                    text.push_str(&chunk);
+                    nodes.push(TextNode {
+                        parent: parent.path.clone(),
+                        step,
+                        chunk_offset,
+                        start,
+                        end: text.len(),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let normalized = normalize(&text);
+        Ok(Self {
+            spine_index,
+            idref: idref.into(),
+            title: None,
+            text,
+            nodes,
+            breaks,
+            normalized,
+        })
+    }
+
+    /// The chapter's text for indexing, with blocks kept apart and whitespace collapsed
+    ///
+    /// Unlike [`Self::text`], words from adjacent paragraphs never run together.
+    pub fn plain_text(&self) -> String {
+        let mut plain = String::with_capacity(self.text.len());
+        let mut breaks = self.breaks.iter().peekable();
+        let mut space = false;
+
+        for (i, c) in self.text.char_indices() {
+            while breaks.next_if(|&&at| at <= i).is_some() {
+                space = true;
+            }
+            if c == '\u{ad}' {
+                continue;
+            }
+            if c.is_whitespace() {
+                space = true;
+                continue;
+            }
+            if space && !plain.is_empty() {
+                plain.push(' ');
+            }
+            space = false;
+            plain.push(c);
+        }
+
+        plain
+    }
+
+    /// Build a selector for a byte range of the chapter text
+    fn quote(&self, from: usize, to: usize) -> TextQuoteSelector {
+        let prefix_start = retreat_chars(&self.text, from, QUOTE_CONTEXT_CHARS);
+        let suffix_end = advance_chars(&self.text, to, QUOTE_CONTEXT_CHARS);
+
+        TextQuoteSelector::new(&self.text[from..to])
+            .with_context(&self.text[prefix_start..from], &self.text[to..suffix_end])
+    }
+
+    /// Resolve a CFI to a byte position in the chapter text
+    fn position_of(&self, point: &CfiPoint) -> Option<usize> {
+        if let (Some(offset), Some((&step, parent))) = (point.offset, point.steps.split_last())
+            && step % 2 == 1
+        {
+            let in_chunk = self.nodes.iter().filter(|n| {
+                n.step == step && n.parent.iter().map(|s| s.index).eq(parent.iter().copied())
+            });
+            for node in in_chunk {
+                let node_text = &self.text[node.start..node.end];
+                let len = node_text.encode_utf16().count();
+                if offset >= node.chunk_offset && offset <= node.chunk_offset + len {
+                    return Some(node.start + utf16_to_byte(node_text, offset - node.chunk_offset));
+                }
+            }
+        }
+
+        // Otherwise take the first text at or after the location
+        let position = self
+            .nodes
+            .iter()
+            .find(|n| {
+                let key = n.parent.iter().map(|s| s.index).chain([n.step]);
+                key.cmp(point.steps.iter().copied()) != Ordering::Less
+            })
+            .map_or(self.text.len(), |n| n.start);
+
+        Some(position)
+    }
+
+    /// Build a point CFI for a byte position in the chapter text
+    ///
+    /// An `end` position belongs to the text node it closes rather than the one it opens.
+    fn cfi_at(&self, position: usize, end: bool) -> Option<String> {
+        let node = self.nodes.iter().find(|n| {
+            if end {
+                n.start < position && position <= n.end
+            } else {
+                n.start <= position && position < n.end
+            }
+        })?;
+        let offset =
+            node.chunk_offset + self.text[node.start..position].encode_utf16().count();
+
+        let mut path = String::new();
+        for step in &node.parent {
+            path.push_str(&format_step(step.index, step.id.as_deref()));
+        }
+
+        Some(format!(
+            "epubcfi(/6/{}!{}/{}:{})",
+            format_step((self.spine_index as u32 + 1) * 2, Some(&self.idref)).trim_start_matches('/'),
+            path,
+            node.step,
+            offset
+        ))
+    }
+}
+
+impl CfiPoint {
+    /// Parse a point CFI such as `epubcfi(/6/4[ch01]!/4/2[p1]/3:10)`
+    fn parse(cfi: &str) -> Option<Self> {
+        let inner = strip_cfi(cfi)?;
+        if inner.contains(',') {
+            return None;
+        }
+        Self::parse_inner(inner)
+    }
+
+    /// Parse a range CFI such as `epubcfi(/6/4!/4/2,/1:0,/1:10)` into its two ends
+    fn parse_range(cfi: &str) -> Option<(Self, Self)> {
+        let inner = strip_cfi(cfi)?;
+        let mut parts = split_unescaped(inner, ',');
+        let (parent, start, end) = (parts.next()?, parts.next()?, parts.next()?);
+
+        Some((
+            Self::parse_inner(&format!("{}{}", parent, start))?,
+            Self::parse_inner(&format!("{}{}", parent, end))?,
+        ))
+    }
+
+    fn parse_inner(inner: &str) -> Option<Self> {
+        let (package, content) = inner.split_once('!')?;
+        let spine_step = parse_steps(package)?.0.get(1).copied()?;
+        let spine_index = (spine_step / 2).checked_sub(1)? as usize;
+        let (steps, offset) = parse_steps(content)?;
+
+        Some(Self {
+            spine_index,
+            steps,
+            offset,
+        })
+    }
+}
+
+impl NormalizedText {
+    /// Map a match in the normalized text back to a byte range of the original text
+    fn original_span(&self, at: usize, matched: &str) -> (usize, usize) {
+        let from = self.origin[at];
+        let (last_at, last_char) = matched
+            .char_indices()
+            .last()
+            .map_or((0, ' '), |(i, c)| (i, c));
+
+        (from, self.origin[at + last_at] + last_char.len_utf8())
+    }
+}
+
+/// Whether an element separates the text before it from the text after it
+fn is_break(name: &str) -> bool {
+    name == "br" || BLOCK_ELEMENTS.contains(&name)
+}
+
+/// Collapse whitespace runs to a single space, remembering where each character came from
+fn normalize(text: &str) -> NormalizedText {
+    let mut normalized = NormalizedText {
+        text: String::with_capacity(text.len()),
+        origin: Vec::with_capacity(text.len()),
+    };
+    let mut in_space = false;
+
+    for (i, c) in text.char_indices() {
+        let c = if c.is_whitespace() {
+            if in_space {
+                continue;
+            }
+            in_space = true;
+            ' '
+        } else {
+            in_space = false;
+            c
+        };
+        normalized.origin.push(i);
+        // Keep one entry per byte so match offsets index `origin` directly
+        normalized.origin.extend(std::iter::repeat_n(i, c.len_utf8() - 1));
+        normalized.text.push(c);
+    }
+
+    normalized
+}
+
+/// Whether a CFI is a range (`epubcfi(P,S,E)`) rather than a point
+pub fn is_range_cfi(cfi: &str) -> bool {
+    strip_cfi(cfi).is_some_and(|inner| split_unescaped(inner, ',').count() == 3)
+}
+
+/// Strip the `epubcfi(...)` wrapper
+fn strip_cfi(cfi: &str) -> Option<&str> {
+    cfi.trim().strip_prefix("epubcfi(")?.strip_suffix(')')
+}
+
+/// Parse `/4[id]/10/3:12` into child indices and an optional character offset
+fn parse_steps(path: &str) -> Option<(Vec<u32>, Option<usize>)> {
+    let mut steps = Vec::new();
+    let mut offset = None;
+
+    for step in split_steps(path) {
+        let step = step.strip_prefix('/')?;
+        let digits_end = step
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(step.len());
+        steps.push(step[..digits_end].parse().ok()?);
+
+        let rest = skip_assertion(&step[digits_end..]);
+        if let Some(rest) = rest.strip_prefix(':') {
+            let digits_end = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            offset = Some(rest[..digits_end].parse().ok()?);
+        }
+    }
+
+    Some((steps, offset))
+}
+
+/// Split a CFI path into its `/n[...]` steps, keeping assertions intact
+fn split_steps(path: &str) -> Vec<&str> {
+    let mut steps = Vec::new();
+    let mut start = 0;
+    let mut in_assertion = false;
+    let mut escaped = false;
+
+    for (i, c) in path.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '^' => escaped = true,
+            '[' => in_assertion = true,
+            ']' => in_assertion = false,
+            '/' if !in_assertion && i > start => {
+                steps.push(&path[start..i]);
+                start = i;
+            }
+            _ => {}
+        }
+    }
+    if start < path.len() {
+        steps.push(&path[start..]);
+    }
+
+    steps
+}
+
+/// Split on a separator that is outside assertions and not escaped
+fn split_unescaped(text: &str, separator: char) -> impl Iterator<Item = &str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut in_assertion = false;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '^' => escaped = true,
+            '[' => in_assertion = true,
+            ']' => in_assertion = false,
+            c if c == separator && !in_assertion => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+
+    parts.into_iter()
+}
+
+/// Skip a leading `[...]` assertion
+fn skip_assertion(text: &str) -> &str {
+    if !text.starts_with('[') {
+        return text;
+    }
+    let mut escaped = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '^' => escaped = true,
+            ']' => return &text[i + 1..],
+            _ => {}
+        }
+    }
+    ""
+}
+
+/// Format a path step, escaping the id assertion
+fn format_step(index: u32, id: Option<&str>) -> String {
+    match id {
+        Some(id) => {
+            let mut escaped = String::with_capacity(id.len());
+            for c in id.chars() {
+                if matches!(c, '^' | '[' | ']' | '(' | ')' | ',' | ';' | '=') {
+                    escaped.push('^');
+                }
+                escaped.push(c);
+            }
+            format!("/{}[{}]", index, escaped)
+        }
+        None => format!("/{}", index),
+    }
+}
+
+/// Convert a UTF-16 offset into a byte offset, clamped to the text
+fn utf16_to_byte(text: &str, offset: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in text.char_indices() {
+        if units >= offset {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// Byte position `count` characters after `from`
+fn advance_chars(text: &str, from: usize, count: usize) -> usize {
+    text[from..]
+        .char_indices()
+        .nth(count)
+        .map_or(text.len(), |(i, _)| from + i)
+}
+
+/// Byte position `count` characters before `to`
+fn retreat_chars(text: &str, to: usize, count: usize) -> usize {
+    text[..to]
+        .char_indices()
+        .rev()
+        .nth(count.saturating_sub(1))
+        .map_or(0, |(i, _)| i)
+}
+
+/// Number of characters `a` ends with that `b` also ends with
+fn common_suffix_chars(a: &str, b: &str) -> usize {
+    a.chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+/// Number of characters `a` starts with that `b` also starts with
+fn common_prefix_chars(a: &str, b: &str) -> usize {
+    a.chars()
+        .zip(b.chars())
+        .take_while(|(x, y)| x == y)
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAPTER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<html xmlns="http://www.w3.org/1999/xhtml">
+<head><title>Chapter One</title></head>
+<body id="body01">
+<p>The cat sat on the mat.</p>
+<p id="p2">The cat sat on the <em>old</em> hat&nbsp;again.</p>
+</body>
+</html>"#;
+
+    fn book() -> BookText {
+        BookText {
+            chapters: vec![ChapterText::from_xhtml(1, "ch01", CHAPTER).unwrap()],
+        }
+    }
+
+    #[test]
+    fn test_from_xhtml_skips_head() {
+        let chapter = ChapterText::from_xhtml(0, "ch01", CHAPTER).unwrap();
+        assert!(!chapter.text.contains("Chapter One"));
+        assert!(chapter.text.contains("The cat sat on the mat."));
+        assert!(chapter.text.contains("old hat\u{a0}again."));
+    }
+
+    #[test]
+    fn test_plain_text_separates_blocks() {
+        let chapter = ChapterText::from_xhtml(
+            0,
+            "ch02",
+            "<html><body><h1>Two</h1><p>Ex\u{ad}ample<br/>line</p><p>The <em>end</em>.</p></body></html>",
+        )
+        .unwrap();
+        assert_eq!(chapter.text, "TwoEx\u{ad}amplelineThe end.");
+        assert_eq!(chapter.plain_text(), "Two Example line The end.");
+
+        let chapter = ChapterText::from_xhtml(0, "ch01", CHAPTER).unwrap();
+        assert_eq!(
+            chapter.plain_text(),
+            "The cat sat on the mat. The cat sat on the old hat again."
+        );
+    }
+
+    #[test]
+    fn test_cfi_round_trip() {
+        let book = book();
+        let quote = book
+            .quote_at(
+                "epubcfi(/6/4[ch01]!/4[body01]/4[p2]/1:4)",
+                Some("epubcfi(/6/4[ch01]!/4[body01]/4[p2]/1:7)"),
+            )
+            .unwrap();
+        assert_eq!(quote.exact, "cat");
+        assert!(quote.prefix.ends_with("mat.\nThe "));
+        assert!(quote.suffix.starts_with(" sat on the old"));
+
+        let range = book.locate(&quote, None).unwrap();
+        assert_eq!(range.start, "epubcfi(/6/4[ch01]!/4[body01]/4[p2]/1:4)");
+        assert_eq!(range.end, "epubcfi(/6/4[ch01]!/4[body01]/4[p2]/1:7)");
+        assert_eq!(
+            range.to_range_cfi(),
+            "epubcfi(/6/4[ch01]!/4[body01]/4[p2],/1:4,/1:7)"
+        );
+        assert!(is_range_cfi(&range.to_range_cfi()));
+        assert!(!is_range_cfi(&range.start));
+    }
+
+    #[test]
+    fn test_quote_at_range_cfi() {
+        let quote = book()
+            .quote_at("epubcfi(/6/4!/4/4,/1:19,/3:4)", None)
+            .unwrap();
+        assert_eq!(quote.exact, "old hat");
+    }
+
+    #[test]
+    fn test_locate_uses_context_to_pick_occurrence() {
+        let book = book();
+        let first = TextQuoteSelector::new("The cat sat").with_context("", " on the mat");
+        let second = TextQuoteSelector::new("The cat sat").with_context("", " on the old");
+
+        let first = book.locate(&first, None).unwrap();
+        let second = book.locate(&second, None).unwrap();
+        assert_eq!(first.start, "epubcfi(/6/4[ch01]!/4[body01]/2/1:0)");
+        assert_eq!(second.start, "epubcfi(/6/4[ch01]!/4[body01]/4[p2]/1:0)");
+    }
+
+    #[test]
+    fn test_locate_across_elements_and_whitespace() {
+        let quote = TextQuoteSelector::new("the  old\nhat");
+        let range = book().locate(&quote, None).unwrap();
+        assert_eq!(range.start, "epubcfi(/6/4[ch01]!/4[body01]/4[p2]/1:15)");
+        assert_eq!(range.end, "epubcfi(/6/4[ch01]!/4[body01]/4[p2]/3:4)");
+    }
+
+    #[test]
+    fn test_locate_missing_text() {
+        let quote = TextQuoteSelector::new("a dog");
+        assert!(book().locate(&quote, None).is_none());
+    }
+}
@@ -1,12 +1,18 @@
 //! Indexer trait definitions.
 
-use common::{BookFormat, Result};
+use crate::text::BookText;
+use common::{BookFormat, Contributor, Result};
+use std::collections::HashMap;
 
 /// Extracted metadata from an ebook
 #[derive(Debug, Clone, Default)]
 pub struct BookMetadata {
     pub title: Option<String>,
     pub authors: Vec<String>,
+    /// People credited in roles other than author, such as translators
+    pub contributors: Vec<Contributor>,
+    /// Names the authors and contributors file under, such as "Tolkien, J. R. R.", by name
+    pub sort_names: HashMap<String, String>,
     pub description: Option<String>,
     pub language: Option<String>,
     pub publisher: Option<String>,
@@ -23,6 +29,20 @@ impl BookMetadata {
         self.title.clone().unwrap_or_else(|| default.to_string())
     }
 
+    /// Sort name of the author list, as in "Pratchett, Terry & Gaiman, Neil", if the
+    /// book gives the sort name of any author
+    pub fn author_sort(&self) -> Option<String> {
+        if !self.authors.iter().any(|a| self.sort_names.contains_key(a)) {
+            return None;
+        }
+        let names: Vec<&str> = self
+            .authors
+            .iter()
+            .map(|a| self.sort_names.get(a).unwrap_or(a).as_str())
+            .collect();
+        Some(names.join(" & "))
+    }
+
     /// Check if we have any meaningful metadata
     pub fn has_data(&self) -> bool {
         self.title.is_some()
@@ -44,6 +64,8 @@ pub struct LocationInfo {
 pub struct LocationItem {
     /// Item identifier (spine id for EPUB, page number for PDF)
     pub id: String,
+    /// Path of the document inside the ebook archive, for formats packaged as archives
+    pub path: Option<String>,
     /// Human-readable label
     pub label: Option<String>,
     /// Order in the book
@@ -63,4 +85,7 @@ pub trait FormatHandler: Send + Sync {
 
     /// Calculate location information for navigation
     fn calculate_locations(&self, data: &[u8]) -> Result<LocationInfo>;
+
+    /// Extract the text of each spine document, for locating annotated passages
+    fn extract_text(&self, data: &[u8]) -> Result<BookText>;
 }
@@ -0,0 +1,214 @@
+//! Change events pushed to a user's connected devices.
+
+use crate::types::{AnnotationSync, ReadingStateSync};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::Stream;
+use uuid::Uuid;
+
+/// Default number of events buffered per user for slow subscribers
+pub const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
+/// A change made on behalf of a user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncEvent {
+    #[serde(skip)]
+    pub user_id: String,
+    /// Device that made the change, if known; it is not notified of its own changes
+    pub origin_device_id: Option<Uuid>,
+    #[serde(flatten)]
+    pub change: SyncChange,
+}
+
+impl SyncEvent {
+    pub fn new(user_id: impl Into<String>, change: SyncChange) -> Self {
+        Self {
+            user_id: user_id.into(),
+            origin_device_id: None,
+            change,
+        }
+    }
+
+    pub fn from_device(mut self, device_id: Uuid) -> Self {
+        self.origin_device_id = Some(device_id);
+        self
+    }
+}
+
+/// What changed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "snake_case")]
+pub enum SyncChange {
+    ReadingState(ReadingStateSync),
+    Annotation(AnnotationSync),
+    Book { book_id: Uuid, action: ChangeAction },
+    Collection { collection_id: Uuid, action: ChangeAction },
+}
+
+impl SyncChange {
+    /// Event name used on the wire
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::ReadingState(_) => "reading_state",
+            Self::Annotation(_) => "annotation",
+            Self::Book { .. } => "book",
+            Self::Collection { .. } => "collection",
+        }
+    }
+}
+
+/// Kind of change made to a library entity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeAction {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// In-process fan-out of sync events to stream subscribers
+///
+/// Each user has a channel of their own, created on first subscription and dropped with
+/// its last subscriber, so one user's burst of changes can't make another's streams lag.
+#[derive(Debug, Clone)]
+pub struct SyncEventBus {
+    capacity: usize,
+    senders: Arc<DashMap<String, broadcast::Sender<SyncEvent>>>,
+}
+
+impl SyncEventBus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            senders: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Publish an event; it is dropped if nobody is listening
+    pub fn publish(&self, event: SyncEvent) {
+        if let Some(sender) = self.senders.get(&event.user_id) {
+            let _ = sender.send(event);
+        }
+    }
+
+    /// Subscribe to a user's events
+    pub fn subscribe(&self, user_id: &str) -> SyncEventStream {
+        let receiver = self
+            .senders
+            .entry(user_id.to_string())
+            .or_insert_with(|| broadcast::channel(self.capacity).0)
+            .subscribe();
+
+        SyncEventStream {
+            inner: BroadcastStream::new(receiver),
+            user_id: user_id.to_string(),
+            senders: Arc::clone(&self.senders),
+        }
+    }
+
+    /// Number of users with a channel open
+    pub fn channel_count(&self) -> usize {
+        self.senders.len()
+    }
+}
+
+impl Default for SyncEventBus {
+    fn default() -> Self {
+        Self::new(DEFAULT_EVENT_CAPACITY)
+    }
+}
+
+/// A subscriber's stream of one user's events
+///
+/// Yields a [`BroadcastStreamRecvError::Lagged`] with the number of events missed when the
+/// subscriber falls behind.
+pub struct SyncEventStream {
+    inner: BroadcastStream<SyncEvent>,
+    user_id: String,
+    senders: Arc<DashMap<String, broadcast::Sender<SyncEvent>>>,
+}
+
+impl Stream for SyncEventStream {
+    type Item = Result<SyncEvent, BroadcastStreamRecvError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl Drop for SyncEventStream {
+    fn drop(&mut self) {
+        // This stream's receiver is still open, so it is the last one if it is the only one
+        self.senders
+            .remove_if(&self.user_id, |_, sender| sender.receiver_count() <= 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio_stream::StreamExt;
+
+    fn book_event(user_id: &str) -> SyncEvent {
+        SyncEvent::new(
+            user_id,
+            SyncChange::Book {
+                book_id: Uuid::new_v4(),
+                action: ChangeAction::Updated,
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_events_reach_only_their_user() {
+        let bus = SyncEventBus::new(8);
+        let mut alice = bus.subscribe("alice");
+        let mut bob = bus.subscribe("bob");
+
+        bus.publish(book_event("alice"));
+        bus.publish(book_event("carol"));
+
+        let received = alice.next().await.unwrap().unwrap();
+        assert_eq!(received.user_id, "alice");
+        let nothing = tokio::time::timeout(std::time::Duration::from_millis(20), bob.next()).await;
+        assert!(nothing.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_lag_is_per_user() {
+        let bus = SyncEventBus::new(2);
+        let mut alice = bus.subscribe("alice");
+        let mut bob = bus.subscribe("bob");
+
+        for _ in 0..5 {
+            bus.publish(book_event("alice"));
+        }
+        bus.publish(book_event("bob"));
+
+        assert!(matches!(alice.next().await, Some(Err(BroadcastStreamRecvError::Lagged(3)))));
+        assert_eq!(bob.next().await.unwrap().unwrap().user_id, "bob");
+    }
+
+    #[test]
+    fn test_channel_dropped_with_last_subscriber() {
+        let bus = SyncEventBus::new(8);
+        let first = bus.subscribe("alice");
+        let second = bus.subscribe("alice");
+        assert_eq!(bus.channel_count(), 1);
+
+        drop(first);
+        assert_eq!(bus.channel_count(), 1);
+        drop(second);
+        assert_eq!(bus.channel_count(), 0);
+
+        // Publishing with nobody listening opens no channel
+        bus.publish(book_event("alice"));
+        assert_eq!(bus.channel_count(), 0);
+    }
+}
@@ -0,0 +1,171 @@
+//! Translation between Kobo bookmarks and reading locations.
+//!
+//! Kobo devices report a bookmark as a `KoboSpan` id inside a content document, which is
+//! named by its path in the EPUB archive, along with the progress through the whole book.
+//! Content documents are looked up in the book's spine, so bookmarks translate to CFIs at
+//! chapter granularity and the progress carries the precise position. When a Kobo device
+//! reported the current position, it is handed back its own bookmark unchanged.
+
+use common::ReadingLocation;
+use db_layer::DeviceReadingState;
+use serde::{Deserialize, Serialize};
+
+use crate::kosync::{cfi_chapter, chapter_cfi};
+use crate::types::ReadingStateSync;
+
+/// Device type Kobo devices are registered with
+pub const KOBO_DEVICE_TYPE: &str = "kobo";
+
+/// Location type of bookmarks in KEPUBs
+pub const KOBO_SPAN_TYPE: &str = "KoboSpan";
+
+/// Bookmark location as Kobo devices send it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct KoboLocation {
+    /// Span id such as `kobo.12.3`
+    pub value: String,
+    #[serde(rename = "Type")]
+    pub kind: String,
+    /// Path of the content document in the EPUB archive
+    pub source: String,
+}
+
+impl KoboLocation {
+    /// Location of the start of a content document
+    pub fn chapter_start(source: impl Into<String>) -> Self {
+        Self {
+            value: "kobo.1.1".to_string(),
+            kind: KOBO_SPAN_TYPE.to_string(),
+            source: source.into(),
+        }
+    }
+
+    /// Locator the bookmark is stored under as the device's own position
+    pub fn to_locator(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Parse a locator stored by [`Self::to_locator`]
+    pub fn from_locator(locator: &str) -> Option<Self> {
+        serde_json::from_str(locator).ok()
+    }
+}
+
+/// Reading location for a bookmark reported by a Kobo device
+///
+/// `spine` lists the archive paths of the book's content documents in reading order. When
+/// the bookmark's document can't be found there, the chapter is estimated from `progress`.
+pub fn location_from_kobo(
+    location: Option<&KoboLocation>,
+    progress: f32,
+    spine: &[String],
+) -> ReadingLocation {
+    let progress = progress.clamp(0.0, 1.0);
+    let index = location
+        .and_then(|l| spine_position(spine, &l.source))
+        .unwrap_or_else(|| {
+            let estimate = (progress * spine.len() as f32) as usize;
+            estimate.min(spine.len().saturating_sub(1))
+        });
+
+    ReadingLocation::new(chapter_cfi(index + 1), progress)
+}
+
+/// Bookmark to report to a Kobo device for the current reading state
+///
+/// `reported` is the last position of the device that wrote the state, if that device is
+/// a Kobo; its bookmark is used when it is the one the state came from.
+pub fn kobo_location(
+    state: &ReadingStateSync,
+    reported: Option<&DeviceReadingState>,
+    spine: &[String],
+) -> Option<KoboLocation> {
+    if let Some(reported) = reported.filter(|r| r.hlc() == state.hlc)
+        && let Some(location) = KoboLocation::from_locator(&reported.location.0.locator)
+    {
+        return Some(location);
+    }
+
+    let chapter = cfi_chapter(&state.location.locator)?;
+    spine
+        .get(chapter - 1)
+        .map(|source| KoboLocation::chapter_start(source.clone()))
+}
+
+/// Position in the spine of a content document named by its archive path
+fn spine_position(spine: &[String], source: &str) -> Option<usize> {
+    let source = source.trim_start_matches('/');
+    spine.iter().position(|path| {
+        path == source
+            || path.ends_with(&format!("/{}", source))
+            || source.ends_with(&format!("/{}", path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::HybridTimestamp;
+    use uuid::Uuid;
+
+    fn spine() -> Vec<String> {
+        ["OEBPS/Text/cover.xhtml", "OEBPS/Text/ch01.xhtml", "OEBPS/Text/ch02.xhtml"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    #[test]
+    fn test_bookmarks_translate_at_chapter_granularity() {
+        let bookmark = KoboLocation {
+            value: "kobo.14.2".to_string(),
+            kind: KOBO_SPAN_TYPE.to_string(),
+            source: "OEBPS/Text/ch02.xhtml".to_string(),
+        };
+        let location = location_from_kobo(Some(&bookmark), 0.8, &spine());
+        assert_eq!(location.locator, "epubcfi(/6/6!/4)");
+        assert_eq!(location.progress, 0.8);
+
+        // Unknown documents fall back to the progress
+        let elsewhere = KoboLocation::chapter_start("missing.xhtml");
+        assert_eq!(
+            location_from_kobo(Some(&elsewhere), 0.5, &spine()).locator,
+            "epubcfi(/6/4!/4)"
+        );
+        assert_eq!(location_from_kobo(None, 1.0, &spine()).locator, "epubcfi(/6/6!/4)");
+        assert_eq!(location_from_kobo(None, 0.3, &[]).locator, "epubcfi(/6/2!/4)");
+    }
+
+    #[test]
+    fn test_kobo_location_prefers_own_bookmark() {
+        let device_id = Uuid::new_v4();
+        let hlc = HybridTimestamp::new(1_700_000_000_000, 0, device_id);
+        let state = ReadingStateSync {
+            book_id: Uuid::new_v4(),
+            location: ReadingLocation::new("epubcfi(/6/4!/4/2/1:0)", 0.4),
+            hlc,
+            base_hlc: None,
+            updated_at: chrono::Utc::now(),
+        };
+
+        let translated = kobo_location(&state, None, &spine()).unwrap();
+        assert_eq!(translated, KoboLocation::chapter_start("OEBPS/Text/ch01.xhtml"));
+
+        let bookmark = KoboLocation {
+            value: "kobo.7.1".to_string(),
+            kind: KOBO_SPAN_TYPE.to_string(),
+            source: "Text/ch01.xhtml".to_string(),
+        };
+        let reported = DeviceReadingState {
+            book_id: state.book_id,
+            device_id,
+            device_name: "Kobo Clara".to_string(),
+            location: sqlx::types::Json(ReadingLocation::new(bookmark.to_locator(), 0.4)),
+            hlc_physical: hlc.physical,
+            hlc_logical: hlc.logical as i32,
+            hlc_device_id: device_id,
+            updated_at: state.updated_at,
+        };
+        assert_eq!(kobo_location(&state, Some(&reported), &spine()), Some(bookmark));
+    }
+}
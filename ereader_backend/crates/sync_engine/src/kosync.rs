@@ -0,0 +1,130 @@
+//! Translation between KOReader progress sync (kosync) positions and reading locations.
+//!
+//! KOReader reports EPUB positions as CREngine XPointers such as
+//! `/body/DocFragment[12]/body/div/p[3]/text().42`, where the `DocFragment` index is the
+//! 1-based position of the chapter in the spine. The rest of the path can't be mapped onto
+//! an EPUB CFI without the chapter's markup, so positions are translated at chapter
+//! granularity and the percentage carries the precise progress. When a KOReader device
+//! reported the current position, it is handed back its own XPointer unchanged.
+
+use common::{Error, ReadingLocation, Result};
+use db_layer::{DeviceReadingState, ReadingState};
+
+/// Device type KOReader devices are registered with
+pub const KOREADER_DEVICE_TYPE: &str = "koreader";
+
+/// Progress fraction for a percentage reported by KOReader
+///
+/// KOReader reports a fraction of the book rather than a percentage despite the name. Values
+/// outside 0.0 to 1.0 are clamped so a bogus one can't win under the furthest-progress
+/// policy; values that aren't numbers are rejected.
+pub fn kosync_percentage(percentage: f32) -> Result<f32> {
+    if !percentage.is_finite() {
+        return Err(Error::validation_field("percentage", "must be a finite number"));
+    }
+    Ok(percentage.clamp(0.0, 1.0))
+}
+
+/// Reading location for a position reported by KOReader
+///
+/// A position that doesn't translate to a CFI, such as a PDF page number, is kept in the
+/// location's `kosync` field rather than passed off as a locator.
+pub fn location_from_kosync(progress: &str, percentage: f32) -> ReadingLocation {
+    match xpointer_to_cfi(progress) {
+        Some(cfi) => ReadingLocation::new(cfi, percentage),
+        None => ReadingLocation {
+            kosync: Some(progress.to_string()),
+            ..ReadingLocation::new("", percentage)
+        },
+    }
+}
+
+/// Position to report to KOReader for the current reading state
+///
+/// `reported` is the last position of the device that wrote the state, if that device is
+/// a KOReader; its XPointer is used when it is the one the state came from.
+pub fn kosync_progress(state: &ReadingState, reported: Option<&DeviceReadingState>) -> String {
+    if let Some(reported) = reported.filter(|r| r.hlc() == state.hlc()) {
+        return reported.location.0.locator.clone();
+    }
+
+    let location = state.location();
+    if let Some(reported) = &location.kosync {
+        return reported.clone();
+    }
+    cfi_to_xpointer(&location.locator).unwrap_or_else(|| location.locator.clone())
+}
+
+/// Translate an XPointer to a CFI for the start of its chapter
+pub fn xpointer_to_cfi(xpointer: &str) -> Option<String> {
+    let rest = xpointer.strip_prefix("/body/DocFragment[")?;
+    let chapter: usize = rest[..rest.find(']')?].parse().ok()?;
+    (chapter > 0).then(|| chapter_cfi(chapter))
+}
+
+/// Translate a CFI to an XPointer for the start of its chapter
+pub fn cfi_to_xpointer(cfi: &str) -> Option<String> {
+    cfi_chapter(cfi).map(|chapter| format!("/body/DocFragment[{}]/body", chapter))
+}
+
+/// CFI for the start of a chapter, given its 1-based position in the spine
+pub fn chapter_cfi(chapter: usize) -> String {
+    format!("epubcfi(/6/{}!/4)", chapter * 2)
+}
+
+/// 1-based position in the spine of the chapter a CFI points into
+pub fn cfi_chapter(cfi: &str) -> Option<usize> {
+    let inner = cfi.strip_prefix("epubcfi(")?.strip_suffix(')')?;
+    let package_path = inner.split('!').next()?;
+
+    // The second step of the package path selects the spine item
+    let spine_step = package_path.split('/').nth(2)?;
+    let digits = spine_step
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(spine_step, |end| &spine_step[..end]);
+    let step: usize = digits.parse().ok()?;
+
+    (step >= 2 && step.is_multiple_of(2)).then_some(step / 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kosync_percentage() {
+        assert_eq!(kosync_percentage(0.42).unwrap(), 0.42);
+        assert_eq!(kosync_percentage(5.0).unwrap(), 1.0);
+        assert_eq!(kosync_percentage(-0.5).unwrap(), 0.0);
+        assert!(matches!(kosync_percentage(f32::NAN), Err(Error::Validation(_))));
+        assert!(matches!(kosync_percentage(f32::INFINITY), Err(Error::Validation(_))));
+    }
+
+    #[test]
+    fn test_positions_translate_at_chapter_granularity() {
+        assert_eq!(
+            xpointer_to_cfi("/body/DocFragment[12]/body/div/p[3]/text().42").as_deref(),
+            Some("epubcfi(/6/24!/4)")
+        );
+        assert_eq!(
+            cfi_to_xpointer("epubcfi(/6/24[chap12]!/4/2/1:0)").as_deref(),
+            Some("/body/DocFragment[12]/body")
+        );
+        assert_eq!(
+            cfi_to_xpointer("epubcfi(/6/4!/4/2,/1:0,/1:5)").as_deref(),
+            Some("/body/DocFragment[2]/body")
+        );
+
+        assert_eq!(xpointer_to_cfi("42"), None);
+        assert_eq!(cfi_to_xpointer("/body/DocFragment[1]"), None);
+
+        let location = location_from_kosync("17", 1.2);
+        assert_eq!(location.locator, "");
+        assert_eq!(location.kosync.as_deref(), Some("17"));
+        assert_eq!(location.progress, 1.0);
+
+        let location = location_from_kosync("/body/DocFragment[3]/body/p[2]/text().7", 0.1);
+        assert_eq!(location.locator, "epubcfi(/6/6!/4)");
+        assert!(location.kosync.is_none());
+    }
+}
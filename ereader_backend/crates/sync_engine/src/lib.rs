@@ -2,14 +2,28 @@
 //!
 //! This crate provides:
 //! - Sync request/response types
-//! - Last-write-wins (LWW) conflict resolution
-//! - Batch sync processing
+//! - Last-write-wins (LWW) conflict resolution ordered by hybrid logical clocks
+//! - Per-device reading positions with a per-user resolution policy
+//! - Upload of reading sessions for reading-time statistics
+//! - Three-way merges of concurrent annotation edits
+//! - A log of annotation conflicts the user can review and resolve
+//! - Change events for pushing updates to connected devices
+//! - Translation of KOReader progress sync positions and Kobo bookmarks
+//! - Batch sync processing over a paged per-user change feed, including library deltas
+//!   and tombstones
 
+pub mod events;
+pub mod kobo;
+pub mod kosync;
 pub mod merge;
+pub mod three_way;
 pub mod types;
 
+pub use events::{ChangeAction, SyncChange, SyncEvent, SyncEventBus, SyncEventStream};
 pub use merge::SyncMerger;
 pub use types::{
-    AnnotationSync, AnnotationTypeSync, ConflictResolution, ReadingStateSync, SyncConflict,
-    SyncRequest, SyncResponse,
+    AnnotationSync, AnnotationTypeSync, BookSync, CollectionMembershipSync, CollectionSync,
+    ConflictChoice, ConflictResolution, DeviceReadingPosition, ReadingSessionSync,
+    ReadingStateSync, SyncConflict, SyncRequest,
+    SyncResponse, SyncToken, TombstoneSync,
 };
@@ -1,238 +1,1524 @@
 //! Sync merge logic implementing last-write-wins (LWW) conflict resolution.
+//!
+//! Writes are ordered by hybrid logical clocks rather than wall-clock timestamps, so a
+//! device with a drifting clock cannot silently win or lose a merge.
+//!
+//! Reading positions are additionally recorded per device. The canonical position is then
+//! chosen by the user's [`ReadingPositionPolicy`].
+//!
+//! A sync batch is merged in memory against rows loaded up front and written back in bulk
+//! within a single transaction.
+//!
+//! Annotation conflicts where the device's version differs from the server's are logged, so
+//! the user can later pick a version or restore the losing note.
 
 use crate::types::{
-    AnnotationSync, ConflictResolution, ReadingStateSync, SyncConflict,
-    SyncRequest, SyncResponse,
+    AnnotationSync, BookSync, CollectionMembershipSync, CollectionSync, ConflictChoice,
+    ConflictResolution,
+    DeviceReadingPosition, ReadingSessionSync, ReadingStateSync, SyncConflict, SyncRequest, SyncResponse, SyncToken,
+    TombstoneSync,
+};
+use crate::events::{SyncChange, SyncEvent, SyncEventBus};
+use crate::kobo::{location_from_kobo, KoboLocation};
+use crate::kosync::{kosync_percentage, location_from_kosync};
+use crate::three_way::{merge_field, merge_text, TextMerge};
+use chrono::{DateTime, Duration, Utc};
+use common::{
+    AnnotationType, ContentHash, Error, HybridTimestamp, ReadingLocation, ReadingPositionPolicy,
+    Result,
 };
-use chrono::{DateTime, Utc};
-use common::Result;
 use db_layer::{
-    AnnotationQueries, DeviceQueries, DbPool, ReadingStateQueries,
-    UpsertAnnotation, UpsertReadingState,
+    Annotation, AnnotationQueries, AnnotationRevision, BookQueries, CollectionQueries,
+    CreateReadingSession, CreateSyncConflictEntry, DbConn, DbPool, DeviceQueries, ReadingState, ReadingStateQueries,
+    ReadingSessionQueries, SyncConflictEntry, SyncConflictQueries, SyncIdempotencyKey,
+    SyncIdempotencyQueries, SyncSettingsQueries, TombstoneQueries, UpsertAnnotation,
+    UpsertReadingState,
 };
+use sqlx::{Postgres, Transaction};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
 use uuid::Uuid;
 
+/// Default limit on how far ahead of server time a client clock may be
+pub const DEFAULT_MAX_CLOCK_DRIFT_SECS: i64 = 300;
+
+/// Number of changes returned per sync page when the client doesn't ask for a size
+pub const DEFAULT_PAGE_SIZE: i64 = 500;
+
+/// Largest page a client may request
+pub const MAX_PAGE_SIZE: i64 = 1000;
+
+/// Longest idempotency key a client may send
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+/// Longest reading session a client may upload
+pub const MAX_SESSION_HOURS: i64 = 24;
+
+/// Writes decided while merging a sync batch, applied in bulk once every item is merged
+#[derive(Default)]
+struct PendingWrites {
+    device_positions: Vec<UpsertReadingState>,
+    reading_states: Vec<UpsertReadingState>,
+    annotations: Vec<UpsertAnnotation>,
+    annotation_deletes: Vec<(Uuid, HybridTimestamp)>,
+    conflicts: Vec<LoggedConflict>,
+    reading_sessions: Vec<CreateReadingSession>,
+}
+
+/// An annotation conflict to keep for the user to review
+struct LoggedConflict {
+    id: Uuid,
+    annotation_id: Uuid,
+    resolution: ConflictResolution,
+    local: AnnotationSync,
+    server: AnnotationSync,
+    copy_id: Option<Uuid>,
+}
+
+impl LoggedConflict {
+    fn to_entry(&self) -> Result<CreateSyncConflictEntry> {
+        Ok(CreateSyncConflictEntry {
+            id: self.id,
+            entity_type: "annotation".to_string(),
+            entity_id: self.annotation_id,
+            resolution: self.resolution,
+            local_version: serde_json::to_value(&self.local)?,
+            server_version: serde_json::to_value(&self.server)?,
+            copy_id: self.copy_id,
+        })
+    }
+}
+
+/// Outcome of merging one reading state
+enum ReadingStateMerge {
+    Applied,
+    Conflict(SyncConflict),
+    /// Devices disagree and the user picks; alternatives are filled in after the batch
+    AskUser(SyncConflict),
+}
+
 /// Sync merger that processes sync requests
 pub struct SyncMerger<'a> {
     pool: &'a DbPool,
+    max_clock_drift: Duration,
+    events: Option<&'a SyncEventBus>,
 }
 
 impl<'a> SyncMerger<'a> {
     pub fn new(pool: &'a DbPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            max_clock_drift: Duration::seconds(DEFAULT_MAX_CLOCK_DRIFT_SECS),
+            events: None,
+        }
+    }
+
+    /// Override the allowed clock drift for incoming timestamps
+    pub fn with_max_clock_drift(mut self, max_clock_drift: Duration) -> Self {
+        self.max_clock_drift = max_clock_drift;
+        self
+    }
+
+    /// Publish applied changes to the user's other devices
+    pub fn with_events(mut self, events: &'a SyncEventBus) -> Self {
+        self.events = Some(events);
+        self
     }
 
     /// Process a complete sync request
+    ///
+    /// The whole batch is applied in one transaction: existing rows are loaded and written
+    /// back in bulk, and a failure leaves nothing applied. With an idempotency key, the
+    /// response is stored alongside the changes so a retry returns it unchanged.
     pub async fn process_sync(
         &self,
         user_id: &str,
         request: SyncRequest,
     ) -> Result<SyncResponse> {
+        self.verify_device(user_id, request.device_id).await?;
+
         let server_time = Utc::now();
-        let last_sync_at = request.last_sync_at.unwrap_or_else(|| {
-            DateTime::from_timestamp(0, 0).unwrap_or_else(Utc::now)
-        });
+        self.validate_clocks(&request, server_time)?;
+        self.validate_sessions(&request.reading_sessions, server_time)?;
+
+        let policy = self.position_policy(user_id).await?;
+
+        let mut tx = self.pool.begin().await?;
+
+        if let Some(key) = &request.idempotency_key
+            && let Some(response) =
+                Self::claim_idempotency_key(&mut tx, user_id, key, &request, server_time).await?
+        {
+            tracing::debug!(user_id, idempotency_key = %key, "Replaying stored sync response");
+            return Ok(response);
+        }
 
+        let device_id = request.device_id;
+        let mut writes = PendingWrites::default();
         let mut conflicts = Vec::new();
 
+        // Drop items for books the user doesn't own
+        let book_ids: Vec<Uuid> = request
+            .reading_states
+            .iter()
+            .map(|s| s.book_id)
+            .chain(request.annotations.iter().map(|a| a.book_id))
+            .collect();
+        let owned: HashSet<Uuid> = BookQueries::owned_ids(&mut tx, user_id, &book_ids)
+            .await?
+            .into_iter()
+            .collect();
+        let owns = |book_id: Uuid, kind: &str| {
+            let known = owned.contains(&book_id);
+            if !known {
+                tracing::warn!(book_id = %book_id, "Skipping {} for unknown book", kind);
+            }
+            known
+        };
+        let reading_states: Vec<ReadingStateSync> = latest_by(
+            request.reading_states,
+            |s| Some(s.book_id),
+            |s| s.hlc,
+        )
+        .into_iter()
+        .filter(|s| owns(s.book_id, "reading state"))
+        .collect();
+        let annotations: Vec<AnnotationSync> = latest_by(request.annotations, |a| a.id, |a| a.hlc)
+            .into_iter()
+            .filter(|a| owns(a.book_id, "annotation"))
+            .collect();
+        writes.reading_sessions = request
+            .reading_sessions
+            .into_iter()
+            .filter(|s| owns(s.book_id, "reading session"))
+            .map(|s| Self::session_insert(device_id, s))
+            .collect();
+
         // Process reading states from client
-        for state in &request.reading_states {
-            if let Some(conflict) = self
-                .merge_reading_state(user_id, request.device_id, state)
+        let state_book_ids: Vec<Uuid> = reading_states.iter().map(|s| s.book_id).collect();
+        let server_states: HashMap<Uuid, ReadingState> =
+            ReadingStateQueries::lock_for_books(&mut tx, user_id, &state_book_ids)
                 .await?
-            {
-                conflicts.push(conflict);
+                .into_iter()
+                .map(|s| (s.book_id, s))
+                .collect();
+        let mut position_choices = Vec::new();
+        for state in &reading_states {
+            let server = server_states.get(&state.book_id);
+            match Self::merge_reading_state(user_id, device_id, policy, server, state, &mut writes) {
+                ReadingStateMerge::Applied => {}
+                ReadingStateMerge::Conflict(conflict) => conflicts.push(conflict),
+                ReadingStateMerge::AskUser(conflict) => {
+                    position_choices.push(conflicts.len());
+                    conflicts.push(conflict);
+                }
             }
         }
 
         // Process annotations from client
-        for annotation in &request.annotations {
-            if let Some(conflict) = self
-                .merge_annotation(user_id, annotation)
+        let annotation_ids: Vec<Uuid> = annotations.iter().filter_map(|a| a.id).collect();
+        let server_annotations: HashMap<Uuid, Annotation> =
+            AnnotationQueries::lock_by_ids(&mut tx, &annotation_ids)
                 .await?
+                .into_iter()
+                .map(|a| (a.id, a))
+                .collect();
+        if let Some(foreign) = server_annotations.values().find(|a| a.user_id != user_id) {
+            return Err(Error::Forbidden(format!(
+                "annotation {} belongs to another user",
+                foreign.id
+            )));
+        }
+        let base_keys: Vec<(Uuid, i32)> = annotations
+            .iter()
+            .filter_map(|a| {
+                let server = server_annotations.get(&a.id?)?;
+                Self::base_revision_needed(server, a)
+            })
+            .collect();
+        let bases: HashMap<(Uuid, i32), AnnotationRevision> =
+            AnnotationQueries::get_revisions(&mut tx, &base_keys)
+                .await?
+                .into_iter()
+                .map(|b| ((b.annotation_id, b.revision), b))
+                .collect();
+        for annotation in &annotations {
+            let server = annotation.id.and_then(|id| server_annotations.get(&id));
+            if let Some(conflict) =
+                Self::merge_annotation(user_id, server, &bases, annotation, &mut writes)
             {
                 conflicts.push(conflict);
             }
         }
 
-        // Get updates from server since last sync
-        let server_reading_states = self
-            .get_reading_states_since(user_id, last_sync_at)
-            .await?;
-        let server_annotations = self
-            .get_annotations_since(user_id, last_sync_at)
+        let changes = Self::apply_writes(&mut tx, user_id, &writes).await?;
+        if !writes.conflicts.is_empty() {
+            let entries = writes
+                .conflicts
+                .iter()
+                .map(LoggedConflict::to_entry)
+                .collect::<Result<Vec<_>>>()?;
+            SyncConflictQueries::create_many(&mut tx, user_id, device_id, &entries).await?;
+        }
+
+        // Competing positions are listed once this device's position is recorded
+        if !position_choices.is_empty() {
+            let choice_books: Vec<Uuid> = position_choices
+                .iter()
+                .filter_map(|&i| conflicts[i].entity_id.parse().ok())
+                .collect();
+            let mut alternatives: HashMap<Uuid, Vec<DeviceReadingPosition>> = HashMap::new();
+            for position in
+                ReadingStateQueries::get_device_positions_for_books(&mut tx, user_id, &choice_books)
+                    .await?
+            {
+                alternatives
+                    .entry(position.book_id)
+                    .or_default()
+                    .push(position.into());
+            }
+            for &i in &position_choices {
+                if let Ok(book_id) = conflicts[i].entity_id.parse::<Uuid>() {
+                    conflicts[i].alternatives = alternatives.remove(&book_id).unwrap_or_default();
+                }
+            }
+        }
+
+        // Get changes from server after the client's cursor
+        let limit = request
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE);
+        let mut response = Self::get_changes_since(
+            &mut tx,
+            user_id,
+            request.sync_token.unwrap_or_default(),
+            limit,
+        )
+        .await?;
+        response.server_time = server_time;
+        response.conflicts = conflicts;
+
+        if let Some(key) = &request.idempotency_key {
+            SyncIdempotencyQueries::store_response(
+                &mut tx,
+                user_id,
+                key,
+                &serde_json::to_value(&response)?,
+            )
             .await?;
+        }
+
+        tx.commit().await?;
+
+        for change in changes {
+            self.publish(user_id, Some(device_id), change);
+        }
 
         // Update device last sync time
-        DeviceQueries::update_last_sync(self.pool, request.device_id, server_time).await?;
+        DeviceQueries::update_last_sync(self.pool, device_id, server_time).await?;
 
-        Ok(SyncResponse {
-            server_time,
-            reading_states: server_reading_states,
-            annotations: server_annotations,
-            conflicts,
-        })
+        Ok(response)
+    }
+
+    /// Claim an idempotency key for a request, returning the stored response if the key
+    /// was already used
+    async fn claim_idempotency_key(
+        conn: &mut DbConn,
+        user_id: &str,
+        key: &str,
+        request: &SyncRequest,
+        server_time: DateTime<Utc>,
+    ) -> Result<Option<SyncResponse>> {
+        if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+            return Err(Error::validation_field(
+                "idempotency_key",
+                &format!("must be 1 to {} characters", MAX_IDEMPOTENCY_KEY_LEN),
+            ));
+        }
+
+        let request_hash = ContentHash::from_bytes(&serde_json::to_vec(request)?);
+        let expired_before = SyncIdempotencyKey::expiry_cutoff(server_time);
+        let Some(existing) = SyncIdempotencyQueries::claim(
+            conn,
+            user_id,
+            key,
+            request_hash.as_str(),
+            expired_before,
+        )
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        if existing.request_hash != request_hash.as_str() {
+            return Err(Error::Conflict(format!(
+                "idempotency key {} was already used for a different request",
+                key
+            )));
+        }
+        let response = existing.response.ok_or_else(|| {
+            Error::Conflict(format!("request with idempotency key {} is still in progress", key))
+        })?;
+
+        Ok(Some(serde_json::from_value(response)?))
+    }
+
+    /// Set the reading position for a book directly, bypassing conflict checks
+    ///
+    /// Used by the single-book endpoint, where the request itself is the latest write
+    /// (including a user's pick between competing positions under `AskUser`).
+    /// The write is stamped with a clock that follows whatever is currently stored.
+    pub async fn update_reading_state(
+        &self,
+        user_id: &str,
+        device_id: Uuid,
+        book_id: Uuid,
+        location: ReadingLocation,
+    ) -> Result<ReadingStateSync> {
+        self.write_reading_state(user_id, device_id, book_id, location.clone(), location, None)
+            .await
+    }
+
+    /// Record a position reported through KOReader's progress sync
+    ///
+    /// The device's own position keeps KOReader's XPointer so it can be handed back
+    /// unchanged, while the translated location is merged into the shared reading state by
+    /// the user's position policy. The percentage is clamped to 0.0 to 1.0, and one that
+    /// isn't a finite number is rejected.
+    pub async fn update_from_kosync(
+        &self,
+        user_id: &str,
+        device_id: Uuid,
+        book_id: Uuid,
+        progress: &str,
+        percentage: f32,
+    ) -> Result<ReadingStateSync> {
+        let percentage = kosync_percentage(percentage)?;
+        let reported = ReadingLocation::new(progress, percentage);
+        let shared = location_from_kosync(progress, percentage);
+        let policy = self.position_policy(user_id).await?;
+
+        self.write_reading_state(user_id, device_id, book_id, reported, shared, Some(policy))
+            .await
+    }
+
+    /// Record a bookmark reported through Kobo store sync
+    ///
+    /// The device's own position keeps the Kobo bookmark so it can be handed back
+    /// unchanged, while the location translated against the book's `spine` (archive paths
+    /// of its content documents) is merged into the shared reading state by the user's
+    /// position policy.
+    pub async fn update_from_kobo(
+        &self,
+        user_id: &str,
+        device_id: Uuid,
+        book_id: Uuid,
+        location: Option<&KoboLocation>,
+        progress: f32,
+        spine: &[String],
+    ) -> Result<ReadingStateSync> {
+        let shared = location_from_kobo(location, progress, spine);
+        let reported = match location {
+            Some(location) => ReadingLocation::new(location.to_locator(), progress),
+            None => shared.clone(),
+        };
+        let policy = self.position_policy(user_id).await?;
+
+        self.write_reading_state(user_id, device_id, book_id, reported, shared, Some(policy))
+            .await
+    }
+
+    /// Get a page of the user's changes after a sync token, without a batch to merge
+    ///
+    /// For clients that only pull changes, such as Kobo devices.
+    pub async fn changes_since(
+        &self,
+        user_id: &str,
+        token: SyncToken,
+        limit: i64,
+    ) -> Result<SyncResponse> {
+        let mut conn = self.pool.acquire().await?;
+        Self::get_changes_since(&mut conn, user_id, token, limit.clamp(1, MAX_PAGE_SIZE)).await
+    }
+
+    /// Resolve a logged conflict with the version the user picked
+    ///
+    /// `Server` keeps the annotation as it is now, `Local` replaces it with the device's
+    /// version, and `Both` keeps it and restores the device's version as a separate
+    /// annotation, whose id the resolved conflict records. The copy saved for a `KeptBoth`
+    /// conflict is removed unless both are kept.
+    pub async fn resolve_conflict(
+        &self,
+        user_id: &str,
+        conflict_id: Uuid,
+        choice: ConflictChoice,
+    ) -> Result<SyncConflictEntry> {
+        let mut tx = self.pool.begin().await?;
+
+        let conflict = SyncConflictQueries::lock_for_user(&mut tx, conflict_id, user_id)
+            .await?
+            .ok_or_else(|| Error::not_found_resource("sync conflict", conflict_id))?;
+        if conflict.is_resolved() {
+            return Err(Error::Conflict(format!(
+                "sync conflict {} is already resolved",
+                conflict_id
+            )));
+        }
+        let local: AnnotationSync = serde_json::from_value(conflict.local_version.clone())?;
+
+        let ids: Vec<Uuid> = std::iter::once(conflict.entity_id)
+            .chain(conflict.copy_id)
+            .collect();
+        let current: HashMap<Uuid, Annotation> = AnnotationQueries::lock_by_ids(&mut tx, &ids)
+            .await?
+            .into_iter()
+            .map(|a| (a.id, a))
+            .collect();
+        let live = |id: Uuid| current.get(&id).filter(|a| !a.is_deleted());
+        let now = Utc::now();
+        let next_clock = |id: Uuid| {
+            current
+                .get(&id)
+                .map_or(local.hlc, Annotation::hlc)
+                .successor(now, Uuid::nil())
+        };
+
+        let mut writes = PendingWrites::default();
+        let mut copy_id = conflict.copy_id;
+        match choice {
+            ConflictChoice::Server => {}
+            ConflictChoice::Local if local.deleted => {
+                if live(conflict.entity_id).is_some() {
+                    writes
+                        .annotation_deletes
+                        .push((conflict.entity_id, next_clock(conflict.entity_id)));
+                }
+            }
+            ConflictChoice::Local => {
+                let upsert = Self::annotation_upsert(user_id, conflict.entity_id, &local)
+                    .with_hlc(next_clock(conflict.entity_id));
+                writes.annotations.push(upsert);
+            }
+            ConflictChoice::Both if !local.deleted => {
+                // Restore the copy if there is none or it was deleted since, recording a new
+                // copy's id on the conflict
+                let copy_id = *copy_id.get_or_insert_with(Uuid::now_v7);
+                if live(copy_id).is_none() {
+                    let upsert = Self::annotation_upsert(user_id, copy_id, &local)
+                        .with_hlc(next_clock(copy_id));
+                    writes.annotations.push(upsert);
+                }
+            }
+            ConflictChoice::Both => {}
+        }
+        if choice != ConflictChoice::Both
+            && let Some(copy_id) = conflict.copy_id
+            && live(copy_id).is_some()
+        {
+            writes.annotation_deletes.push((copy_id, next_clock(copy_id)));
+        }
+
+        if !writes.annotations.is_empty()
+            && BookQueries::owned_ids(&mut tx, user_id, &[local.book_id])
+                .await?
+                .is_empty()
+        {
+            return Err(Error::not_found_resource("book", local.book_id));
+        }
+
+        let changes = Self::apply_writes(&mut tx, user_id, &writes).await?;
+        let conflict =
+            SyncConflictQueries::mark_resolved(&mut tx, conflict_id, choice, copy_id).await?;
+
+        tx.commit().await?;
+
+        for change in changes {
+            self.publish(user_id, None, change);
+        }
+
+        Ok(conflict)
+    }
+
+    /// Ensure the device exists and is registered to the user
+    async fn verify_device(&self, user_id: &str, device_id: Uuid) -> Result<()> {
+        DeviceQueries::get_by_id_for_user(self.pool, device_id, user_id)
+            .await?
+            .ok_or_else(|| Error::not_found_resource("device", device_id))?;
+
+        Ok(())
+    }
+
+    /// Check that a book exists and belongs to the user
+    async fn owns_book(&self, user_id: &str, book_id: Uuid) -> Result<bool> {
+        Ok(BookQueries::get_by_id_for_user(self.pool, book_id, user_id)
+            .await?
+            .is_some())
+    }
+
+    /// Reject the request if any clock is too far ahead of server time or has a logical
+    /// counter too large to store
+    fn validate_clocks(&self, request: &SyncRequest, server_time: DateTime<Utc>) -> Result<()> {
+        let clocks = request
+            .reading_states
+            .iter()
+            .map(|s| &s.hlc)
+            .chain(request.annotations.iter().map(|a| &a.hlc));
+
+        for hlc in clocks {
+            validate_clock(hlc, server_time, self.max_clock_drift)?;
+        }
+
+        Ok(())
     }
 
-    /// Merge a reading state using LWW
-    async fn merge_reading_state(
+    /// Reject the request if any reading session is malformed or ends in the future
+    fn validate_sessions(
         &self,
+        sessions: &[ReadingSessionSync],
+        server_time: DateTime<Utc>,
+    ) -> Result<()> {
+        let max_duration = Duration::hours(MAX_SESSION_HOURS);
+        for session in sessions {
+            let problem = if session.ended_at < session.started_at {
+                Some("ends before it starts".to_string())
+            } else if session.ended_at - session.started_at > max_duration {
+                Some(format!("is longer than {} hours", MAX_SESSION_HOURS))
+            } else if session.ended_at > server_time + self.max_clock_drift {
+                Some("ends in the future".to_string())
+            } else if !(-1.0..=1.0).contains(&session.progress_delta) {
+                Some("has a progress delta outside -1.0 to 1.0".to_string())
+            } else {
+                None
+            };
+
+            if let Some(problem) = problem {
+                return Err(Error::validation_field(
+                    "reading_sessions",
+                    &format!("session {} {}", session.id, problem),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Merge a reading state according to the user's position policy
+    ///
+    /// The device's own position is always recorded; only the canonical position is
+    /// subject to the policy.
+    fn merge_reading_state(
         user_id: &str,
         device_id: Uuid,
+        policy: ReadingPositionPolicy,
+        server: Option<&ReadingState>,
         client_state: &ReadingStateSync,
-    ) -> Result<Option<SyncConflict>> {
-        // Get current server state
-        let server_state = ReadingStateQueries::get_for_book(
-            self.pool,
+        writes: &mut PendingWrites,
+    ) -> ReadingStateMerge {
+        let upsert = UpsertReadingState::new(
             user_id,
             client_state.book_id,
+            device_id,
+            client_state.location.clone(),
         )
-        .await?;
+        .with_hlc(client_state.hlc);
+        writes.device_positions.push(upsert.clone());
 
-        let conflict = if let Some(ref server) = server_state {
-            // Check for conflict (both modified)
-            if server.updated_at > client_state.updated_at {
-                // Server wins
-                Some(SyncConflict {
-                    entity_type: "reading_state".to_string(),
-                    entity_id: client_state.book_id.to_string(),
-                    local_updated_at: client_state.updated_at,
-                    server_updated_at: server.updated_at,
-                    resolution: ConflictResolution::ServerWins,
-                })
-            } else if server.updated_at < client_state.updated_at {
-                // Client wins - update server
-                let upsert = UpsertReadingState::new(
-                    user_id,
-                    client_state.book_id,
-                    device_id,
-                    client_state.location.clone(),
-                );
-                ReadingStateQueries::upsert(self.pool, &upsert).await?;
-                None
-            } else {
-                // Same time - no conflict
-                None
-            }
-        } else {
+        let Some(server) = server else {
             // No server state - just insert
-            let upsert = UpsertReadingState::new(
-                user_id,
-                client_state.book_id,
-                device_id,
-                client_state.location.clone(),
-            );
-            ReadingStateQueries::upsert(self.pool, &upsert).await?;
-            None
+            writes.reading_states.push(upsert);
+            return ReadingStateMerge::Applied;
         };
 
-        Ok(conflict)
+        let server_hlc = server.hlc();
+        if server_hlc == client_state.hlc {
+            // Same write - already applied
+            return ReadingStateMerge::Applied;
+        }
+
+        let client_wins = match policy {
+            ReadingPositionPolicy::LatestWrite => client_state.hlc > server_hlc,
+            ReadingPositionPolicy::FurthestProgress => {
+                let client_progress = client_state.location.progress;
+                let server_progress = server.location().progress;
+                client_progress > server_progress
+                    || (client_progress == server_progress && client_state.hlc > server_hlc)
+            }
+            ReadingPositionPolicy::AskUser => {
+                // Positions compete only if both moved since the device last synced;
+                // otherwise the newer one simply wins
+                let moved_since_base =
+                    |hlc: HybridTimestamp| client_state.base_hlc.is_none_or(|base| hlc > base);
+                let competing = server.device_id != device_id
+                    && moved_since_base(server_hlc)
+                    && moved_since_base(client_state.hlc)
+                    && server.location().locator != client_state.location.locator;
+                if competing {
+                    return ReadingStateMerge::AskUser(Self::reading_state_conflict(
+                        server,
+                        client_state,
+                        ConflictResolution::Merged,
+                    ));
+                }
+                client_state.hlc > server_hlc
+            }
+        };
+
+        if !client_wins {
+            return ReadingStateMerge::Conflict(Self::reading_state_conflict(
+                server,
+                client_state,
+                ConflictResolution::ServerWins,
+            ));
+        }
+
+        // Client wins - update server. An older write chosen by progress still needs a
+        // clock that orders after the position it replaces.
+        let hlc = clock_after(server_hlc, client_state.hlc);
+        writes.reading_states.push(upsert.with_hlc(hlc));
+
+        ReadingStateMerge::Applied
     }
 
-    /// Merge an annotation using LWW
-    async fn merge_annotation(
-        &self,
+    /// Build a conflict record for a reading state
+    ///
+    /// For an `AskUser` choice the canonical position is left unchanged, and each device's
+    /// position is attached as an alternative once this device's position is recorded.
+    fn reading_state_conflict(
+        server: &ReadingState,
+        client_state: &ReadingStateSync,
+        resolution: ConflictResolution,
+    ) -> SyncConflict {
+        SyncConflict {
+            entity_type: "reading_state".to_string(),
+            entity_id: client_state.book_id.to_string(),
+            local_updated_at: client_state.updated_at,
+            server_updated_at: server.updated_at,
+            local_hlc: client_state.hlc,
+            server_hlc: server.hlc(),
+            resolution,
+            alternatives: vec![],
+            copy_id: None,
+            conflict_id: None,
+        }
+    }
+
+    /// Merge an annotation
+    ///
+    /// Edits made on top of the current server revision are applied directly. Concurrent
+    /// edits are merged three-way against the revision the client started from; deletes and
+    /// edits without a known base fall back to LWW.
+    fn merge_annotation(
         user_id: &str,
+        server: Option<&Annotation>,
+        bases: &HashMap<(Uuid, i32), AnnotationRevision>,
         client_annotation: &AnnotationSync,
-    ) -> Result<Option<SyncConflict>> {
-        let annotation_id = client_annotation.id.unwrap_or_else(Uuid::now_v7);
-
-        // Get current server state
-        let server_annotation = AnnotationQueries::get_by_id(self.pool, annotation_id).await?;
-
-        let conflict = if let Some(ref server) = server_annotation {
-            // Check for conflict
-            if server.updated_at > client_annotation.updated_at {
-                // Server wins
-                Some(SyncConflict {
-                    entity_type: "annotation".to_string(),
-                    entity_id: annotation_id.to_string(),
-                    local_updated_at: client_annotation.updated_at,
-                    server_updated_at: server.updated_at,
-                    resolution: ConflictResolution::ServerWins,
-                })
-            } else if server.updated_at < client_annotation.updated_at {
-                // Client wins - update or delete
-                if client_annotation.deleted {
-                    AnnotationQueries::soft_delete(self.pool, annotation_id).await?;
-                } else {
-                    let upsert = UpsertAnnotation {
-                        id: annotation_id,
-                        user_id: user_id.to_string(),
-                        book_id: client_annotation.book_id,
-                        annotation_type: client_annotation.annotation_type.into(),
-                        location_start: client_annotation.location_start.clone(),
-                        location_end: client_annotation.location_end.clone(),
-                        content: client_annotation.content.clone(),
-                        style: client_annotation.style.clone(),
-                    };
-                    AnnotationQueries::upsert(self.pool, &upsert).await?;
-                }
-                None
-            } else {
-                // Same time - no conflict
-                None
+        writes: &mut PendingWrites,
+    ) -> Option<SyncConflict> {
+        let Some(server) = server else {
+            if !client_annotation.deleted {
+                // No server state and not deleted - insert
+                let annotation_id = client_annotation.id.unwrap_or_else(Uuid::now_v7);
+                writes
+                    .annotations
+                    .push(Self::annotation_upsert(user_id, annotation_id, client_annotation));
             }
-        } else if !client_annotation.deleted {
-            // No server state and not deleted - insert
-            let upsert = UpsertAnnotation {
-                id: annotation_id,
-                user_id: user_id.to_string(),
-                book_id: client_annotation.book_id,
-                annotation_type: client_annotation.annotation_type.into(),
-                location_start: client_annotation.location_start.clone(),
-                location_end: client_annotation.location_end.clone(),
-                content: client_annotation.content.clone(),
-                style: client_annotation.style.clone(),
-            };
-            AnnotationQueries::upsert(self.pool, &upsert).await?;
-            None
+            return None;
+        };
+
+        let server_hlc = server.hlc();
+        if server_hlc == client_annotation.hlc {
+            // Same write - already applied
+            return None;
+        }
+
+        if client_annotation.revision == Some(server.revision)
+            && !client_annotation.deleted
+            && !server.is_deleted()
+        {
+            // Nothing changed on the server since the client synced - fast-forward
+            let upsert = Self::annotation_upsert(user_id, server.id, client_annotation)
+                .with_hlc(clock_after(server_hlc, client_annotation.hlc));
+            writes.annotations.push(upsert);
+            return None;
+        }
+
+        let base = Self::base_revision_needed(server, client_annotation)
+            .and_then(|key| bases.get(&key));
+        match base {
+            Some(base) => Self::three_way_merge(user_id, server, base, client_annotation, writes),
+            None => Self::last_write_wins(user_id, server, client_annotation, writes),
+        }
+    }
+
+    /// The base revision a concurrent annotation edit should be merged against, if any
+    fn base_revision_needed(
+        server: &Annotation,
+        client_annotation: &AnnotationSync,
+    ) -> Option<(Uuid, i32)> {
+        let revision = client_annotation.revision?;
+        let mergeable = !client_annotation.deleted
+            && !server.is_deleted()
+            && revision != server.revision
+            && server.hlc() != client_annotation.hlc;
+
+        mergeable.then_some((server.id, revision))
+    }
+
+    /// Resolve an annotation update by comparing clocks alone
+    fn last_write_wins(
+        user_id: &str,
+        server: &Annotation,
+        client_annotation: &AnnotationSync,
+        writes: &mut PendingWrites,
+    ) -> Option<SyncConflict> {
+        if server.hlc() > client_annotation.hlc {
+            // Server wins
+            let mut conflict =
+                Self::annotation_conflict(server, client_annotation, ConflictResolution::ServerWins);
+            if Self::versions_differ(server, client_annotation) {
+                Self::log_conflict(server, client_annotation, &mut conflict, writes);
+            }
+            return Some(conflict);
+        }
+
+        // Client wins - update or delete
+        if client_annotation.deleted {
+            writes
+                .annotation_deletes
+                .push((server.id, client_annotation.hlc));
+        } else {
+            writes
+                .annotations
+                .push(Self::annotation_upsert(user_id, server.id, client_annotation));
+        }
+
+        None
+    }
+
+    /// Combine concurrent edits to an annotation made on top of a shared base revision
+    ///
+    /// Note content is merged line by line and `style`/`location_end` field by field. If the
+    /// content cannot be merged cleanly, the server keeps its version and the client's version
+    /// is saved as a new annotation.
+    fn three_way_merge(
+        user_id: &str,
+        server: &Annotation,
+        base: &AnnotationRevision,
+        client_annotation: &AnnotationSync,
+        writes: &mut PendingWrites,
+    ) -> Option<SyncConflict> {
+        let client_is_newer = client_annotation.hlc > server.hlc();
+
+        let content = match merge_text(
+            base.content.as_deref(),
+            server.content.as_deref(),
+            client_annotation.content.as_deref(),
+        ) {
+            TextMerge::Clean(content) => content,
+            TextMerge::Conflict => {
+                let copy = Self::annotation_upsert(user_id, Uuid::now_v7(), client_annotation);
+                let mut conflict = Self::annotation_conflict(
+                    server,
+                    client_annotation,
+                    ConflictResolution::KeptBoth,
+                );
+                conflict.copy_id = Some(copy.id);
+                writes.annotations.push(copy);
+                Self::log_conflict(server, client_annotation, &mut conflict, writes);
+                return Some(conflict);
+            }
+        };
+        let style = merge_field(
+            &base.style,
+            &server.style,
+            &client_annotation.style,
+            client_is_newer,
+        );
+        let location_end = merge_field(
+            &base.location_end,
+            &server.location_end,
+            &client_annotation.location_end,
+            client_is_newer,
+        );
+
+        // Fields without a base snapshot follow the newer write; the quote goes with the
+        // location it describes
+        let (annotation_type, location_start, quote) = if client_is_newer {
+            (
+                client_annotation.annotation_type.into(),
+                client_annotation.location_start.clone(),
+                client_annotation.quote.clone(),
+            )
         } else {
-            None
+            (server.annotation_type, server.location_start.clone(), server.quote())
         };
 
-        Ok(conflict)
+        let unchanged = content == server.content
+            && style == server.style
+            && location_end == server.location_end
+            && annotation_type == server.annotation_type
+            && location_start == server.location_start;
+        if unchanged {
+            // The client had nothing the server doesn't already have
+            return Some(Self::annotation_conflict(
+                server,
+                client_annotation,
+                ConflictResolution::ServerWins,
+            ));
+        }
+
+        let server_changed = server.content != base.content
+            || server.style != base.style
+            || server.location_end != base.location_end;
+
+        let upsert = UpsertAnnotation {
+            id: server.id,
+            user_id: user_id.to_string(),
+            book_id: server.book_id,
+            annotation_type,
+            location_start,
+            location_end,
+            content,
+            style,
+            quote,
+            hlc: clock_after(server.hlc(), client_annotation.hlc),
+        };
+        writes.annotations.push(upsert);
+
+        server_changed.then(|| {
+            let mut conflict =
+                Self::annotation_conflict(server, client_annotation, ConflictResolution::Merged);
+            Self::log_conflict(server, client_annotation, &mut conflict, writes);
+            conflict
+        })
+    }
+
+    /// Build a conflict record for an annotation
+    fn annotation_conflict(
+        server: &Annotation,
+        client_annotation: &AnnotationSync,
+        resolution: ConflictResolution,
+    ) -> SyncConflict {
+        SyncConflict {
+            entity_type: "annotation".to_string(),
+            entity_id: server.id.to_string(),
+            local_updated_at: client_annotation.updated_at,
+            server_updated_at: server.updated_at,
+            local_hlc: client_annotation.hlc,
+            server_hlc: server.hlc(),
+            resolution,
+            alternatives: vec![],
+            copy_id: None,
+            conflict_id: None,
+        }
+    }
+
+    /// Whether the client's version of an annotation says anything the server's doesn't
+    fn versions_differ(server: &Annotation, client_annotation: &AnnotationSync) -> bool {
+        if client_annotation.deleted || server.is_deleted() {
+            return client_annotation.deleted != server.is_deleted();
+        }
+
+        let annotation_type: AnnotationType = client_annotation.annotation_type.into();
+        annotation_type != server.annotation_type
+            || client_annotation.location_start != server.location_start
+            || client_annotation.location_end != server.location_end
+            || client_annotation.content != server.content
+            || client_annotation.style != server.style
     }
 
-    /// Get reading states updated since a given time
-    async fn get_reading_states_since(
+    /// Keep a copy of an annotation conflict for the user to review
+    fn log_conflict(
+        server: &Annotation,
+        client_annotation: &AnnotationSync,
+        conflict: &mut SyncConflict,
+        writes: &mut PendingWrites,
+    ) {
+        let id = Uuid::now_v7();
+        conflict.conflict_id = Some(id);
+        writes.conflicts.push(LoggedConflict {
+            id,
+            annotation_id: server.id,
+            resolution: conflict.resolution,
+            local: client_annotation.clone(),
+            server: server.clone().into(),
+            copy_id: conflict.copy_id,
+        });
+    }
+
+    /// Build the upsert for a client annotation
+    fn annotation_upsert(
+        user_id: &str,
+        annotation_id: Uuid,
+        client_annotation: &AnnotationSync,
+    ) -> UpsertAnnotation {
+        UpsertAnnotation {
+            id: annotation_id,
+            user_id: user_id.to_string(),
+            book_id: client_annotation.book_id,
+            annotation_type: client_annotation.annotation_type.into(),
+            location_start: client_annotation.location_start.clone(),
+            location_end: client_annotation.location_end.clone(),
+            content: client_annotation.content.clone(),
+            style: client_annotation.style.clone(),
+            quote: client_annotation.quote.clone(),
+            hlc: client_annotation.hlc,
+        }
+    }
+
+    /// Build the insert for an uploaded reading session
+    fn session_insert(device_id: Uuid, session: ReadingSessionSync) -> CreateReadingSession {
+        CreateReadingSession {
+            id: session.id,
+            book_id: session.book_id,
+            device_id,
+            started_at: session.started_at,
+            ended_at: session.ended_at,
+            start_locator: session.start_locator,
+            end_locator: session.end_locator,
+            progress_delta: session.progress_delta,
+        }
+    }
+
+    /// Record a device's position for a book outside of a sync batch
+    ///
+    /// `reported` is recorded as the device's own position. `shared` is merged into the
+    /// reading state by `policy` like a position from a sync batch, or replaces it when
+    /// there is none. The write is stamped with a clock that follows whatever is stored.
+    async fn write_reading_state(
         &self,
         user_id: &str,
-        since: DateTime<Utc>,
-    ) -> Result<Vec<ReadingStateSync>> {
-        let states = ReadingStateQueries::get_updated_since(self.pool, user_id, since).await?;
+        device_id: Uuid,
+        book_id: Uuid,
+        reported: ReadingLocation,
+        shared: ReadingLocation,
+        policy: Option<ReadingPositionPolicy>,
+    ) -> Result<ReadingStateSync> {
+        self.verify_device(user_id, device_id).await?;
+        if !self.owns_book(user_id, book_id).await? {
+            return Err(Error::not_found_resource("book", book_id));
+        }
 
-        Ok(states
-            .into_iter()
-            .map(|s| ReadingStateSync {
-                book_id: s.book_id,
-                location: s.location.0.clone(),
-                updated_at: s.updated_at,
-            })
-            .collect())
+        let mut tx = self.pool.begin().await?;
+        let current = ReadingStateQueries::lock_for_books(&mut tx, user_id, &[book_id])
+            .await?
+            .pop();
+
+        let now = Utc::now();
+        let hlc = current
+            .as_ref()
+            .map(|existing| existing.hlc().successor(now, device_id))
+            .unwrap_or_else(|| HybridTimestamp::from_datetime(now, device_id));
+
+        let mut writes = PendingWrites::default();
+        match policy {
+            Some(policy) => {
+                let incoming = ReadingStateSync {
+                    book_id,
+                    location: shared,
+                    hlc,
+                    base_hlc: None,
+                    updated_at: now,
+                };
+                // A position left out by the policy stays recorded for the device
+                Self::merge_reading_state(user_id, device_id, policy, current.as_ref(), &incoming, &mut writes);
+            }
+            None => writes
+                .reading_states
+                .push(UpsertReadingState::new(user_id, book_id, device_id, shared).with_hlc(hlc)),
+        }
+        writes.device_positions =
+            vec![UpsertReadingState::new(user_id, book_id, device_id, reported).with_hlc(hlc)];
+
+        self.save_reading_state(tx, user_id, device_id, &writes, current).await
     }
 
-    /// Get annotations updated since a given time
-    async fn get_annotations_since(
+    /// Store the canonical reading state and notify other devices
+    ///
+    /// Applies the writes decided in `tx` and commits it, returning the stored state, or
+    /// `current` if it was left unchanged.
+    async fn save_reading_state(
         &self,
+        mut tx: Transaction<'_, Postgres>,
         user_id: &str,
-        since: DateTime<Utc>,
-    ) -> Result<Vec<AnnotationSync>> {
-        let annotations = AnnotationQueries::get_updated_since(self.pool, user_id, since).await?;
+        device_id: Uuid,
+        writes: &PendingWrites,
+        current: Option<ReadingState>,
+    ) -> Result<ReadingStateSync> {
+        let changes = Self::apply_writes(&mut tx, user_id, writes).await?;
+        tx.commit().await?;
 
-        Ok(annotations
-            .into_iter()
-            .map(|a| AnnotationSync {
-                id: Some(a.id),
-                book_id: a.book_id,
-                annotation_type: a.annotation_type.into(),
-                location_start: a.location_start,
-                location_end: a.location_end,
-                content: a.content,
-                style: a.style,
-                updated_at: a.updated_at,
-                deleted: a.deleted_at.is_some(),
-            })
-            .collect())
+        let mut state = current.map(ReadingStateSync::from);
+        for change in changes {
+            if let SyncChange::ReadingState(stored) = &change {
+                state = Some(stored.clone());
+            }
+            self.publish(user_id, Some(device_id), change);
+        }
+
+        state.ok_or_else(|| Error::Internal("reading state was not stored".to_string()))
+    }
+
+    /// The user's policy for choosing between devices' reading positions
+    async fn position_policy(&self, user_id: &str) -> Result<ReadingPositionPolicy> {
+        Ok(SyncSettingsQueries::get_for_user(self.pool, user_id)
+            .await?
+            .reading_position_policy)
+    }
+
+    /// Apply the writes decided for a sync batch, returning the changes to publish once the
+    /// transaction commits
+    async fn apply_writes(
+        conn: &mut DbConn,
+        user_id: &str,
+        writes: &PendingWrites,
+    ) -> Result<Vec<SyncChange>> {
+        let mut changes = Vec::new();
+
+        if !writes.device_positions.is_empty() {
+            ReadingStateQueries::upsert_device_positions(conn, user_id, &writes.device_positions)
+                .await?;
+        }
+        if !writes.reading_states.is_empty() {
+            let states =
+                ReadingStateQueries::upsert_many(conn, user_id, &writes.reading_states).await?;
+            changes.extend(states.into_iter().map(|s| SyncChange::ReadingState(s.into())));
+        }
+        if !writes.annotations.is_empty() {
+            let annotations =
+                AnnotationQueries::upsert_many(conn, user_id, &writes.annotations).await?;
+            changes.extend(annotations.into_iter().map(|a| SyncChange::Annotation(a.into())));
+        }
+        if !writes.annotation_deletes.is_empty() {
+            let deleted =
+                AnnotationQueries::soft_delete_many(conn, &writes.annotation_deletes).await?;
+            changes.extend(deleted.into_iter().map(|a| SyncChange::Annotation(a.into())));
+        }
+        if !writes.reading_sessions.is_empty() {
+            // Sessions only feed statistics, so other devices aren't told about them
+            ReadingSessionQueries::insert_many(conn, user_id, &writes.reading_sessions).await?;
+        }
+
+        Ok(changes)
+    }
+
+    /// Send a change event if the merger was given an event bus
+    fn publish(&self, user_id: &str, device_id: Option<Uuid>, change: SyncChange) {
+        if let Some(events) = self.events {
+            let event = SyncEvent::new(user_id, change);
+            events.publish(match device_id {
+                Some(device_id) => event.from_device(device_id),
+                None => event,
+            });
+        }
+    }
+
+    /// Get the next page of changes after a sync token
+    ///
+    /// All synced entities share the user's change sequence, so the page is the `limit`
+    /// lowest sequence numbers across every source, and the new token is the highest one
+    /// included. A token older than the newest pruned tombstone restarts the feed from the
+    /// beginning with `reset` set.
+    async fn get_changes_since(
+        conn: &mut DbConn,
+        user_id: &str,
+        token: SyncToken,
+        limit: i64,
+    ) -> Result<SyncResponse> {
+        let pruned_seq = TombstoneQueries::pruned_seq(&mut *conn, user_id).await?;
+        let reset = token.predates_pruning(pruned_seq);
+        let since = if reset { 0 } else { token.seq() };
+
+        // Fetch one extra row per source so `has_more` is exact
+        let fetch = limit + 1;
+        let states = ReadingStateQueries::get_changed_since(&mut *conn, user_id, since, fetch).await?;
+        let annotations =
+            AnnotationQueries::get_changed_since(&mut *conn, user_id, since, fetch).await?;
+        let books = BookQueries::get_changed_since(&mut *conn, user_id, since, fetch).await?;
+        let collections =
+            CollectionQueries::get_changed_since(&mut *conn, user_id, since, fetch).await?;
+        let memberships =
+            CollectionQueries::get_memberships_changed_since(&mut *conn, user_id, since, fetch)
+                .await?;
+        let tombstones = TombstoneQueries::get_changed_since(&mut *conn, user_id, since, fetch).await?;
+
+        let mut seqs: Vec<i64> = states
+            .iter()
+            .map(|s| s.change_seq)
+            .chain(annotations.iter().map(|a| a.change_seq))
+            .chain(books.iter().map(|b| b.change_seq))
+            .chain(collections.iter().map(|c| c.change_seq))
+            .chain(memberships.iter().map(|m| m.change_seq))
+            .chain(tombstones.iter().map(|t| t.change_seq))
+            .collect();
+        seqs.sort_unstable();
+
+        let has_more = seqs.len() as i64 > limit;
+        let last_seq = if has_more {
+            seqs[limit as usize - 1]
+        } else {
+            seqs.last().copied().unwrap_or(since)
+        };
+
+        Ok(SyncResponse {
+            sync_token: SyncToken::new(last_seq).with_horizon(pruned_seq),
+            has_more,
+            reset,
+            reading_states: states
+                .into_iter()
+                .filter(|s| s.change_seq <= last_seq)
+                .map(ReadingStateSync::from)
+                .collect(),
+            annotations: annotations
+                .into_iter()
+                .filter(|a| a.change_seq <= last_seq)
+                .map(AnnotationSync::from)
+                .collect(),
+            books: books
+                .into_iter()
+                .filter(|b| b.change_seq <= last_seq)
+                .map(|b| BookSync::from(b.book))
+                .collect(),
+            collections: collections
+                .into_iter()
+                .filter(|c| c.change_seq <= last_seq)
+                .map(|c| CollectionSync::from(c.collection))
+                .collect(),
+            collection_books: memberships
+                .into_iter()
+                .filter(|m| m.change_seq <= last_seq)
+                .map(|m| CollectionMembershipSync::from(m.membership))
+                .collect(),
+            tombstones: tombstones
+                .into_iter()
+                .filter(|t| t.change_seq <= last_seq)
+                .map(TombstoneSync::from)
+                .collect(),
+            ..SyncResponse::empty()
+        })
+    }
+}
+
+/// Pick a clock for a write that must order after the current one
+///
+/// The incoming clock is kept when it is already newer; otherwise the write is stamped just
+/// after the current clock on behalf of the incoming device.
+fn clock_after(current: HybridTimestamp, incoming: HybridTimestamp) -> HybridTimestamp {
+    if incoming > current {
+        incoming
+    } else {
+        current.successor(Utc::now(), incoming.device_id)
+    }
+}
+
+/// Keep only the newest write (by clock) for each key, in order of first appearance
+///
+/// A device may report several writes to the same row in one batch; only the last of them
+/// matters, and each row can be written at most once by a bulk upsert. Items without a key
+/// are all kept.
+fn latest_by<T, K>(
+    items: Vec<T>,
+    key: impl Fn(&T) -> Option<K>,
+    hlc: impl Fn(&T) -> HybridTimestamp,
+) -> Vec<T>
+where
+    K: Eq + Hash,
+{
+    let mut positions: HashMap<K, usize> = HashMap::new();
+    let mut latest: Vec<T> = Vec::with_capacity(items.len());
+
+    for item in items {
+        let Some(k) = key(&item) else {
+            latest.push(item);
+            continue;
+        };
+        match positions.get(&k) {
+            Some(&i) => {
+                if hlc(&item) > hlc(&latest[i]) {
+                    latest[i] = item;
+                }
+            }
+            None => {
+                positions.insert(k, latest.len());
+                latest.push(item);
+            }
+        }
+    }
+
+    latest
+}
+
+/// Reject a clock too far ahead of server time or with a logical counter too large to store
+fn validate_clock(hlc: &HybridTimestamp, server_time: DateTime<Utc>, max_drift: Duration) -> Result<()> {
+    if hlc.is_ahead_of(server_time, max_drift) {
+        return Err(Error::validation_field(
+            "hlc",
+            &format!(
+                "clock {} is more than {}s ahead of server time {}",
+                hlc,
+                max_drift.num_seconds(),
+                server_time.timestamp_millis()
+            ),
+        ));
+    }
+    if hlc.logical > HybridTimestamp::MAX_LOGICAL {
+        return Err(Error::validation_field(
+            "hlc",
+            &format!(
+                "clock {} has a logical counter above {}",
+                hlc,
+                HybridTimestamp::MAX_LOGICAL
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(physical: i64) -> HybridTimestamp {
+        HybridTimestamp::new(physical, 0, Uuid::nil())
+    }
+
+    fn server_position(device_id: Uuid, locator: &str, progress: f32, physical: i64) -> ReadingState {
+        ReadingState {
+            id: Uuid::nil(),
+            user_id: "user".to_string(),
+            book_id: Uuid::nil(),
+            device_id,
+            location: sqlx::types::Json(ReadingLocation::new(locator, progress)),
+            hlc_physical: physical,
+            hlc_logical: 0,
+            hlc_device_id: device_id,
+            change_seq: 1,
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn client_position(locator: &str, progress: f32, physical: i64, base: Option<i64>) -> ReadingStateSync {
+        ReadingStateSync {
+            book_id: Uuid::nil(),
+            location: ReadingLocation::new(locator, progress),
+            hlc: clock(physical),
+            base_hlc: base.map(clock),
+            updated_at: Utc::now(),
+        }
+    }
+
+    fn merge_position(
+        policy: ReadingPositionPolicy,
+        server: &ReadingState,
+        client: &ReadingStateSync,
+    ) -> (ReadingStateMerge, PendingWrites) {
+        let mut writes = PendingWrites::default();
+        let merge = SyncMerger::merge_reading_state("user", Uuid::nil(), policy, Some(server), client, &mut writes);
+        (merge, writes)
+    }
+
+    #[test]
+    fn test_latest_write_policy() {
+        let other_device = Uuid::new_v4();
+        let server = server_position(other_device, "epubcfi(/6/8!)", 0.5, 20);
+
+        let (merge, writes) = merge_position(
+            ReadingPositionPolicy::LatestWrite,
+            &server,
+            &client_position("epubcfi(/6/4!)", 0.2, 30, None),
+        );
+        assert!(matches!(merge, ReadingStateMerge::Applied));
+        assert_eq!(writes.reading_states.len(), 1);
+        assert_eq!(writes.device_positions.len(), 1);
+
+        let (merge, writes) = merge_position(
+            ReadingPositionPolicy::LatestWrite,
+            &server,
+            &client_position("epubcfi(/6/10!)", 0.7, 10, None),
+        );
+        assert!(matches!(merge, ReadingStateMerge::Conflict(c) if c.resolution == ConflictResolution::ServerWins));
+        assert!(writes.reading_states.is_empty());
+        assert_eq!(writes.device_positions.len(), 1);
+    }
+
+    #[test]
+    fn test_furthest_progress_policy() {
+        let other_device = Uuid::new_v4();
+        let server = server_position(other_device, "epubcfi(/6/8!)", 0.5, 20);
+
+        // An older write further into the book wins, stamped after the position it replaces
+        let (merge, writes) = merge_position(
+            ReadingPositionPolicy::FurthestProgress,
+            &server,
+            &client_position("epubcfi(/6/10!)", 0.7, 10, None),
+        );
+        assert!(matches!(merge, ReadingStateMerge::Applied));
+        assert!(writes.reading_states[0].hlc > server.hlc());
+
+        let (merge, writes) = merge_position(
+            ReadingPositionPolicy::FurthestProgress,
+            &server,
+            &client_position("epubcfi(/6/4!)", 0.2, 30, None),
+        );
+        assert!(matches!(merge, ReadingStateMerge::Conflict(_)));
+        assert!(writes.reading_states.is_empty());
+    }
+
+    #[test]
+    fn test_ask_user_policy_when_both_moved() {
+        let other_device = Uuid::new_v4();
+        let server = server_position(other_device, "epubcfi(/6/8!)", 0.5, 20);
+
+        let (merge, writes) = merge_position(
+            ReadingPositionPolicy::AskUser,
+            &server,
+            &client_position("epubcfi(/6/4!)", 0.2, 30, Some(15)),
+        );
+        assert!(matches!(merge, ReadingStateMerge::AskUser(c) if c.resolution == ConflictResolution::Merged));
+        assert!(writes.reading_states.is_empty());
+        assert_eq!(writes.device_positions.len(), 1);
+
+        // Without a base every differing position from another device competes
+        let (merge, _) = merge_position(
+            ReadingPositionPolicy::AskUser,
+            &server,
+            &client_position("epubcfi(/6/4!)", 0.2, 30, None),
+        );
+        assert!(matches!(merge, ReadingStateMerge::AskUser(_)));
+    }
+
+    #[test]
+    fn test_ask_user_policy_when_one_side_moved() {
+        let other_device = Uuid::new_v4();
+        let server = server_position(other_device, "epubcfi(/6/8!)", 0.5, 20);
+
+        // The other device moved on while this one resends the position it synced
+        let (merge, writes) = merge_position(
+            ReadingPositionPolicy::AskUser,
+            &server,
+            &client_position("epubcfi(/6/4!)", 0.2, 10, Some(10)),
+        );
+        assert!(matches!(merge, ReadingStateMerge::Conflict(c) if c.resolution == ConflictResolution::ServerWins));
+        assert!(writes.reading_states.is_empty());
+
+        // This device moved on from the shared position
+        let mut client = client_position("epubcfi(/6/10!)", 0.7, 30, None);
+        client.base_hlc = Some(server.hlc());
+        let (merge, writes) = merge_position(ReadingPositionPolicy::AskUser, &server, &client);
+        assert!(matches!(merge, ReadingStateMerge::Applied));
+        assert_eq!(writes.reading_states.len(), 1);
+
+        // Positions from the same device never compete
+        let own = server_position(Uuid::nil(), "epubcfi(/6/8!)", 0.5, 20);
+        let (merge, _) = merge_position(
+            ReadingPositionPolicy::AskUser,
+            &own,
+            &client_position("epubcfi(/6/4!)", 0.2, 30, None),
+        );
+        assert!(matches!(merge, ReadingStateMerge::Applied));
+    }
+
+    #[test]
+    fn test_first_position_is_applied() {
+        let mut writes = PendingWrites::default();
+        let client = client_position("epubcfi(/6/4!)", 0.2, 30, None);
+        let merge = SyncMerger::merge_reading_state(
+            "user",
+            Uuid::nil(),
+            ReadingPositionPolicy::AskUser,
+            None,
+            &client,
+            &mut writes,
+        );
+        assert!(matches!(merge, ReadingStateMerge::Applied));
+        assert_eq!(writes.reading_states.len(), 1);
+    }
+
+    #[test]
+    fn test_latest_by_keeps_newest_write_per_key() {
+        let writes = vec![("a", 1), ("b", 5), ("a", 3), ("a", 2), (" ", 4)];
+
+        let latest = latest_by(
+            writes,
+            |(key, _)| (!key.trim().is_empty()).then_some(*key),
+            |(_, physical)| clock(*physical),
+        );
+
+        assert_eq!(latest, vec![("a", 3), ("b", 5), (" ", 4)]);
+    }
+
+    fn server_note(content: &str, physical: i64) -> Annotation {
+        Annotation {
+            id: Uuid::nil(),
+            user_id: "user".to_string(),
+            book_id: Uuid::nil(),
+            annotation_type: AnnotationType::Note,
+            location_start: "epubcfi(/6/4!/4/2/1:0)".to_string(),
+            location_end: None,
+            content: Some(content.to_string()),
+            style: None,
+            quote_exact: None,
+            quote_prefix: None,
+            quote_suffix: None,
+            anchor_status: Default::default(),
+            hlc_physical: physical,
+            hlc_logical: 0,
+            hlc_device_id: Uuid::nil(),
+            revision: 2,
+            change_seq: 1,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            deleted_at: None,
+        }
+    }
+
+    #[test]
+    fn test_losing_edit_is_logged() {
+        let server = server_note("server text", 20);
+        let mut client: AnnotationSync = server_note("offline text", 10).into();
+        client.revision = None;
+
+        let mut writes = PendingWrites::default();
+        let conflict =
+            SyncMerger::merge_annotation("user", Some(&server), &HashMap::new(), &client, &mut writes)
+                .unwrap();
+
+        assert_eq!(conflict.resolution, ConflictResolution::ServerWins);
+        assert_eq!(writes.conflicts.len(), 1);
+        assert_eq!(conflict.conflict_id, Some(writes.conflicts[0].id));
+        assert_eq!(writes.conflicts[0].local.content.as_deref(), Some("offline text"));
+        assert_eq!(writes.conflicts[0].server.content.as_deref(), Some("server text"));
+    }
+
+    #[test]
+    fn test_stale_copy_of_same_version_is_not_logged() {
+        let server = server_note("same text", 20);
+        let mut client: AnnotationSync = server_note("same text", 10).into();
+        client.revision = None;
+
+        let mut writes = PendingWrites::default();
+        let conflict =
+            SyncMerger::merge_annotation("user", Some(&server), &HashMap::new(), &client, &mut writes)
+                .unwrap();
+
+        assert_eq!(conflict.resolution, ConflictResolution::ServerWins);
+        assert!(conflict.conflict_id.is_none());
+        assert!(writes.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_validate_clock() {
+        let now = DateTime::from_timestamp_millis(100_000).unwrap();
+        let drift = Duration::seconds(60);
+
+        assert!(validate_clock(&clock(100_000), now, drift).is_ok());
+        assert!(validate_clock(&clock(160_001), now, drift).is_err());
+
+        let largest = HybridTimestamp::new(100_000, HybridTimestamp::MAX_LOGICAL, Uuid::nil());
+        assert!(validate_clock(&largest, now, drift).is_ok());
+        let wrapping = HybridTimestamp::new(100_000, HybridTimestamp::MAX_LOGICAL + 1, Uuid::nil());
+        assert!(matches!(validate_clock(&wrapping, now, drift), Err(Error::Validation(_))));
     }
 }
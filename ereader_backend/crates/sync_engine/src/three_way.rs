@@ -0,0 +1,95 @@
+//! Three-way merge helpers for annotation fields.
+//!
+//! Each helper takes the base revision both sides started from, the current server value,
+//! and the incoming client value.
+
+/// Result of merging a text field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextMerge {
+    /// Edits were combined (or only one side changed)
+    Clean(Option<String>),
+    /// Both sides changed the same lines
+    Conflict,
+}
+
+/// Merge note content line by line
+pub fn merge_text(
+    base: Option<&str>,
+    server: Option<&str>,
+    client: Option<&str>,
+) -> TextMerge {
+    if server == client || client == base {
+        return TextMerge::Clean(server.map(str::to_string));
+    }
+    if server == base {
+        return TextMerge::Clean(client.map(str::to_string));
+    }
+
+    match diffy::merge(
+        base.unwrap_or_default(),
+        server.unwrap_or_default(),
+        client.unwrap_or_default(),
+    ) {
+        Ok(merged) if merged.is_empty() => TextMerge::Clean(None),
+        Ok(merged) => TextMerge::Clean(Some(merged)),
+        Err(_) => TextMerge::Conflict,
+    }
+}
+
+/// Merge a field that cannot be combined, preferring whichever side changed it
+///
+/// If both sides changed it to different values, the newer write wins.
+pub fn merge_field<T: PartialEq + Clone>(base: &T, server: &T, client: &T, client_is_newer: bool) -> T {
+    if client == base || (server != base && !client_is_newer) {
+        server.clone()
+    } else {
+        client.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_text_one_side_changed() {
+        assert_eq!(
+            merge_text(Some("a"), Some("a"), Some("b")),
+            TextMerge::Clean(Some("b".to_string()))
+        );
+        assert_eq!(
+            merge_text(Some("a"), Some("b"), Some("a")),
+            TextMerge::Clean(Some("b".to_string()))
+        );
+        assert_eq!(merge_text(Some("a"), Some("a"), None), TextMerge::Clean(None));
+    }
+
+    #[test]
+    fn test_merge_text_combines_disjoint_edits() {
+        let base = "first\nsecond\nthird\n";
+        let server = "first edited\nsecond\nthird\n";
+        let client = "first\nsecond\nthird edited\n";
+
+        assert_eq!(
+            merge_text(Some(base), Some(server), Some(client)),
+            TextMerge::Clean(Some("first edited\nsecond\nthird edited\n".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_merge_text_conflict() {
+        assert_eq!(
+            merge_text(Some("note"), Some("server note"), Some("client note")),
+            TextMerge::Conflict
+        );
+    }
+
+    #[test]
+    fn test_merge_field() {
+        let base = Some("yellow");
+        assert_eq!(merge_field(&base, &Some("blue"), &base, true), Some("blue"));
+        assert_eq!(merge_field(&base, &base, &Some("green"), false), Some("green"));
+        assert_eq!(merge_field(&base, &Some("blue"), &Some("green"), true), Some("green"));
+        assert_eq!(merge_field(&base, &Some("blue"), &Some("green"), false), Some("blue"));
+    }
+}
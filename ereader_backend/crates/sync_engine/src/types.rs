@@ -1,7 +1,11 @@
 //! Sync request and response types.
 
 use chrono::{DateTime, Utc};
-use common::ReadingLocation;
+use common::{
+    AnnotationAnchorStatus, BookFormat, Error, HybridTimestamp, ReadingLocation, TextQuoteSelector,
+};
+pub use db_layer::{ConflictChoice, ConflictResolution};
+use db_layer::SyncEntityType;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -9,12 +13,107 @@ use uuid::Uuid;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncRequest {
     pub device_id: Uuid,
+    /// Token from the previous response; omit for a full sync
     #[serde(default)]
-    pub last_sync_at: Option<DateTime<Utc>>,
+    pub sync_token: Option<SyncToken>,
+    /// Maximum number of changes to return in this page
+    #[serde(default)]
+    pub limit: Option<i64>,
+    /// Client-chosen key for this request; a retry with the same key returns the original
+    /// response instead of applying the changes again
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
     #[serde(default)]
     pub reading_states: Vec<ReadingStateSync>,
     #[serde(default)]
     pub annotations: Vec<AnnotationSync>,
+    /// Finished reading sessions; upload-only, and safe to resend
+    #[serde(default)]
+    pub reading_sessions: Vec<ReadingSessionSync>,
+}
+
+/// Opaque cursor into a user's change feed
+///
+/// Wraps the per-user change sequence number of the last change the client has seen, and
+/// the pruning horizon when the token was issued. Tombstones up to the horizon were already
+/// gone then, so a client paging through a full sync that started after it missed none of
+/// them, even while its sequence number is still below the horizon.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct SyncToken {
+    seq: i64,
+    horizon: i64,
+}
+
+impl SyncToken {
+    const PREFIX: &'static str = "v1.";
+
+    pub fn new(seq: i64) -> Self {
+        Self { seq, horizon: 0 }
+    }
+
+    /// Record the pruning horizon the token is issued under
+    pub fn with_horizon(mut self, horizon: i64) -> Self {
+        self.horizon = horizon;
+        self
+    }
+
+    /// Change sequence number the token resumes after
+    pub fn seq(&self) -> i64 {
+        self.seq
+    }
+
+    /// Pruning horizon the token was issued under
+    pub fn horizon(&self) -> i64 {
+        self.horizon
+    }
+
+    /// Whether a client resuming from this token may have missed tombstones pruned up to
+    /// `pruned_seq`, and must rebuild its library
+    pub fn predates_pruning(&self, pruned_seq: i64) -> bool {
+        self.seq > 0 && self.seq.max(self.horizon) < pruned_seq
+    }
+}
+
+impl std::fmt::Display for SyncToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{:x}", Self::PREFIX, self.seq)?;
+        if self.horizon > 0 {
+            write!(f, ".{:x}", self.horizon)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::str::FromStr for SyncToken {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |hex: &str| i64::from_str_radix(hex, 16).ok().filter(|n| *n >= 0);
+        s.strip_prefix(Self::PREFIX)
+            .and_then(|rest| match rest.split_once('.') {
+                Some((seq, horizon)) => Some(Self {
+                    seq: parse(seq)?,
+                    horizon: parse(horizon)?,
+                }),
+                None => Some(Self::new(parse(rest)?)),
+            })
+            .ok_or_else(|| Error::validation_field("sync_token", "invalid sync token"))
+    }
+}
+
+impl TryFrom<String> for SyncToken {
+    type Error = Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<SyncToken> for String {
+    fn from(token: SyncToken) -> Self {
+        token.to_string()
+    }
 }
 
 /// Reading state to sync
@@ -22,9 +121,43 @@ pub struct SyncRequest {
 pub struct ReadingStateSync {
     pub book_id: Uuid,
     pub location: ReadingLocation,
+    /// Clock of the write, used for conflict resolution
+    pub hlc: HybridTimestamp,
+    /// Clock of the shared position the device last synced; under `AskUser`, only
+    /// positions that both moved since then compete. Without it any differing position
+    /// from another device does.
+    #[serde(default)]
+    pub base_hlc: Option<HybridTimestamp>,
     pub updated_at: DateTime<Utc>,
 }
 
+impl From<db_layer::ReadingState> for ReadingStateSync {
+    fn from(state: db_layer::ReadingState) -> Self {
+        Self {
+            book_id: state.book_id,
+            hlc: state.hlc(),
+            base_hlc: Some(state.hlc()),
+            location: state.location.0,
+            updated_at: state.updated_at,
+        }
+    }
+}
+
+/// A stretch of reading recorded on a device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadingSessionSync {
+    /// Client-chosen id; a session already recorded is ignored
+    pub id: Uuid,
+    pub book_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub start_locator: String,
+    pub end_locator: String,
+    /// Change in progress (0.0-1.0 scale) over the session
+    #[serde(default)]
+    pub progress_delta: f32,
+}
+
 /// Annotation type for sync
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
@@ -68,17 +201,176 @@ pub struct AnnotationSync {
     pub content: Option<String>,
     #[serde(default)]
     pub style: Option<String>,
+    /// The annotated text and its context, captured by the client when the annotation
+    /// was made; used to find the text again if the book's file is replaced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quote: Option<TextQuoteSelector>,
+    /// Set by the server; `orphaned` annotations could not be placed in a replaced file
+    #[serde(default)]
+    pub anchor_status: AnnotationAnchorStatus,
+    /// Clock of the write, used for conflict resolution
+    pub hlc: HybridTimestamp,
+    /// Server revision this copy was last synced at; edits are merged against it
+    #[serde(default)]
+    pub revision: Option<i32>,
     pub updated_at: DateTime<Utc>,
     #[serde(default)]
     pub deleted: bool,
 }
 
+impl From<db_layer::Annotation> for AnnotationSync {
+    fn from(annotation: db_layer::Annotation) -> Self {
+        Self {
+            id: Some(annotation.id),
+            book_id: annotation.book_id,
+            annotation_type: annotation.annotation_type.into(),
+            quote: annotation.quote(),
+            anchor_status: annotation.anchor_status,
+            hlc: annotation.hlc(),
+            revision: Some(annotation.revision),
+            deleted: annotation.is_deleted(),
+            location_start: annotation.location_start,
+            location_end: annotation.location_end,
+            content: annotation.content,
+            style: annotation.style,
+            updated_at: annotation.updated_at,
+        }
+    }
+}
+
+/// Book metadata to sync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BookSync {
+    pub id: Uuid,
+    pub title: String,
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+    pub language: Option<String>,
+    pub publisher: Option<String>,
+    pub published_date: Option<String>,
+    pub isbn: Option<String>,
+    pub series_name: Option<String>,
+    pub series_index: Option<f32>,
+    pub tags: Vec<String>,
+    pub format: Option<BookFormat>,
+    /// Changes when a new file is uploaded; devices should re-download
+    pub content_hash: Option<String>,
+    pub file_size: Option<i64>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<db_layer::Book> for BookSync {
+    fn from(book: db_layer::Book) -> Self {
+        Self {
+            id: book.id,
+            title: book.title,
+            authors: book.authors,
+            description: book.description,
+            language: book.language,
+            publisher: book.publisher,
+            published_date: book.published_date,
+            isbn: book.isbn,
+            series_name: book.series_name,
+            series_index: book.series_index,
+            tags: book.tags,
+            format: book.format,
+            content_hash: book.content_hash,
+            file_size: book.file_size,
+            created_at: book.created_at,
+            updated_at: book.updated_at,
+        }
+    }
+}
+
+/// Collection to sync
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionSync {
+    pub id: Uuid,
+    /// Collection this one is nested in, for browsing collections as folders
+    #[serde(default)]
+    pub parent_id: Option<Uuid>,
+    pub name: String,
+    pub description: Option<String>,
+    pub collection_type: String,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<db_layer::Collection> for CollectionSync {
+    fn from(collection: db_layer::Collection) -> Self {
+        Self {
+            id: collection.id,
+            parent_id: collection.parent_id,
+            name: collection.name,
+            description: collection.description,
+            collection_type: collection.collection_type,
+            created_at: collection.created_at,
+            updated_at: collection.updated_at,
+        }
+    }
+}
+
+/// A book's membership in a collection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionMembershipSync {
+    pub collection_id: Uuid,
+    pub book_id: Uuid,
+    pub sort_order: Option<i32>,
+    pub added_at: DateTime<Utc>,
+}
+
+impl From<db_layer::CollectionBook> for CollectionMembershipSync {
+    fn from(membership: db_layer::CollectionBook) -> Self {
+        Self {
+            collection_id: membership.collection_id,
+            book_id: membership.book_id,
+            sort_order: membership.sort_order,
+            added_at: membership.added_at,
+        }
+    }
+}
+
+/// A deleted library entity
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TombstoneSync {
+    pub entity_type: SyncEntityType,
+    /// Deleted entity; the book for `collection_book` tombstones
+    pub entity_id: Uuid,
+    /// Collection the book left, for `collection_book` tombstones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<Uuid>,
+    pub deleted_at: DateTime<Utc>,
+}
+
+impl From<db_layer::Tombstone> for TombstoneSync {
+    fn from(tombstone: db_layer::Tombstone) -> Self {
+        Self {
+            entity_type: tombstone.entity_type,
+            entity_id: tombstone.entity_id,
+            parent_id: tombstone.parent_id,
+            deleted_at: tombstone.deleted_at,
+        }
+    }
+}
+
 /// Sync response to client
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncResponse {
     pub server_time: DateTime<Utc>,
+    /// Token to send with the next request
+    pub sync_token: SyncToken,
+    /// More changes are pending; request again with `sync_token` to continue
+    pub has_more: bool,
+    /// The client's token predates pruned tombstones; it should discard its local library
+    /// and rebuild it from this and the following pages
+    pub reset: bool,
     pub reading_states: Vec<ReadingStateSync>,
     pub annotations: Vec<AnnotationSync>,
+    pub books: Vec<BookSync>,
+    pub collections: Vec<CollectionSync>,
+    pub collection_books: Vec<CollectionMembershipSync>,
+    pub tombstones: Vec<TombstoneSync>,
     pub conflicts: Vec<SyncConflict>,
 }
 
@@ -86,8 +378,15 @@ impl SyncResponse {
     pub fn empty() -> Self {
         Self {
             server_time: Utc::now(),
+            sync_token: SyncToken::default(),
+            has_more: false,
+            reset: false,
             reading_states: vec![],
             annotations: vec![],
+            books: vec![],
+            collections: vec![],
+            collection_books: vec![],
+            tombstones: vec![],
             conflicts: vec![],
         }
     }
@@ -100,14 +399,92 @@ pub struct SyncConflict {
     pub entity_id: String,
     pub local_updated_at: DateTime<Utc>,
     pub server_updated_at: DateTime<Utc>,
+    pub local_hlc: HybridTimestamp,
+    pub server_hlc: HybridTimestamp,
     pub resolution: ConflictResolution,
+    /// Competing values the client should choose between, for `Merged` conflicts
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<DeviceReadingPosition>,
+    /// Annotation created to hold the client's version, for `KeptBoth` conflicts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy_id: Option<Uuid>,
+    /// Logged copy of the conflict, which the user can review and resolve later
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conflict_id: Option<Uuid>,
 }
 
-/// How a conflict was resolved
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-pub enum ConflictResolution {
-    ServerWins,
-    ClientWins,
-    Merged,
+/// Reading position last reported by one device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceReadingPosition {
+    pub device_id: Uuid,
+    pub device_name: String,
+    pub location: ReadingLocation,
+    pub hlc: HybridTimestamp,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<db_layer::DeviceReadingState> for DeviceReadingPosition {
+    fn from(state: db_layer::DeviceReadingState) -> Self {
+        let hlc = state.hlc();
+        Self {
+            device_id: state.device_id,
+            device_name: state.device_name,
+            hlc,
+            location: state.location.0,
+            updated_at: state.updated_at,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sync_token_roundtrip() {
+        let token = SyncToken::new(1234);
+        let parsed: SyncToken = token.to_string().parse().unwrap();
+        assert_eq!(parsed, token);
+
+        let json = serde_json::to_string(&token).unwrap();
+        assert_eq!(serde_json::from_str::<SyncToken>(&json).unwrap(), token);
+    }
+
+    #[test]
+    fn test_sync_token_keeps_horizon() {
+        let token = SyncToken::new(0x20).with_horizon(0x50);
+        assert_eq!(token.to_string(), "v1.20.50");
+        assert_eq!(token.to_string().parse::<SyncToken>().unwrap(), token);
+        assert_eq!("v1.20".parse::<SyncToken>().unwrap(), SyncToken::new(0x20));
+    }
+
+    #[test]
+    fn test_pruned_token_resets_once_then_pages() {
+        let pruned_seq = 50;
+
+        // A client last synced before tombstones were pruned has to rebuild
+        assert!(SyncToken::new(10).predates_pruning(pruned_seq));
+        assert!(!SyncToken::new(60).predates_pruning(pruned_seq));
+        assert!(!SyncToken::default().predates_pruning(pruned_seq));
+
+        // The pages of the rebuild are issued under the current horizon, so they resume
+        // without another reset even while still below it
+        let mut token = SyncToken::default();
+        for last_seq in [15, 30, 45, 70] {
+            assert!(!token.predates_pruning(pruned_seq));
+            token = SyncToken::new(last_seq).with_horizon(pruned_seq);
+        }
+
+        // Pruning further while a rebuild is still below the new horizon resets it again
+        let paging = SyncToken::new(30).with_horizon(pruned_seq);
+        assert!(paging.predates_pruning(80));
+    }
+
+    #[test]
+    fn test_sync_token_rejects_garbage() {
+        assert!("1234".parse::<SyncToken>().is_err());
+        assert!("v1.zz".parse::<SyncToken>().is_err());
+        assert!("v1.20.".parse::<SyncToken>().is_err());
+        assert!(serde_json::from_str::<SyncToken>("\"nope\"").is_err());
+    }
 }
@@ -1,12 +1,17 @@
 //! Worker daemon binary entry point.
 
 use common::config::AppConfig;
+use db_layer::models::TaskStatus;
 use std::sync::Arc;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use worker_daemon::{
     TaskScheduler,
     scheduler::SchedulerConfig,
-    tasks::{CleanupOrphansHandler, GenerateCoversHandler, ReindexBookHandler},
+    tasks::{
+        CleanupOrphansHandler, GenerateCoversHandler, IndexBookTextHandler,
+        PruneTombstonesHandler, ReanchorAnnotationsHandler, RefreshSmartCollectionsHandler,
+        ReindexBookHandler, smart_collections, tombstones,
+    },
 };
 
 #[tokio::main]
@@ -43,6 +48,32 @@ async fn main() -> anyhow::Result<()> {
         task_timeout: std::time::Duration::from_secs(config.worker.task_timeout_secs.into()),
     };
 
+    // Make sure tombstone pruning is queued; each run schedules the next
+    let pending_prunes = db_layer::queries::TaskQueries::get_by_type(
+        &pool,
+        db_layer::models::task_types::PRUNE_SYNC_TOMBSTONES,
+    )
+    .await?;
+    if !pending_prunes
+        .iter()
+        .any(|task| !matches!(task.status(), TaskStatus::Completed | TaskStatus::Failed))
+    {
+        tombstones::schedule_next(&pool).await?;
+    }
+
+    // Likewise for the periodic smart collection refresh
+    let pending_refreshes = db_layer::queries::TaskQueries::get_by_type(
+        &pool,
+        db_layer::models::task_types::REFRESH_SMART_COLLECTIONS,
+    )
+    .await?;
+    if !pending_refreshes.iter().any(|task| {
+        task.payload.get("user_id").is_none()
+            && !matches!(task.status(), TaskStatus::Completed | TaskStatus::Failed)
+    }) {
+        smart_collections::schedule_next(&pool).await?;
+    }
+
     // Create scheduler
     let mut scheduler = TaskScheduler::new(pool, storage, scheduler_config);
 
@@ -50,6 +81,12 @@ async fn main() -> anyhow::Result<()> {
     scheduler.register_handler(ReindexBookHandler);
     scheduler.register_handler(GenerateCoversHandler);
     scheduler.register_handler(CleanupOrphansHandler);
+    scheduler.register_handler(PruneTombstonesHandler::new(
+        config.worker.tombstone_retention_days,
+    ));
+    scheduler.register_handler(ReanchorAnnotationsHandler);
+    scheduler.register_handler(IndexBookTextHandler);
+    scheduler.register_handler(RefreshSmartCollectionsHandler);
 
     tracing::info!("Task handlers registered");
 
@@ -0,0 +1,77 @@
+//! Book text indexing task handler.
+
+use crate::scheduler::TaskContext;
+use crate::tasks::TaskHandler;
+use async_trait::async_trait;
+use db_layer::models::{text_search_config, task_types, Book, CreateBookText};
+use db_layer::queries::{BookQueries, BookTextQueries};
+use db_layer::DbPool;
+use serde::Deserialize;
+use storage_layer::traits::Storage;
+use uuid::Uuid;
+
+/// Payload for index book text task
+#[derive(Debug, Deserialize)]
+struct IndexTextPayload {
+    book_id: Uuid,
+}
+
+/// Handler for storing the text of a book's file for full-text search
+pub struct IndexBookTextHandler;
+
+#[async_trait]
+impl TaskHandler for IndexBookTextHandler {
+    fn task_type(&self) -> &'static str {
+        task_types::INDEX_BOOK_TEXT
+    }
+
+    async fn execute(&self, ctx: &TaskContext, payload: &serde_json::Value) -> anyhow::Result<()> {
+        let payload: IndexTextPayload = serde_json::from_value(payload.clone())?;
+
+        tracing::info!(book_id = %payload.book_id, "Indexing book text");
+
+        let book = BookQueries::get_by_id(&ctx.pool, payload.book_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Book not found: {}", payload.book_id))?;
+
+        let Some(storage_path) = &book.storage_path else {
+            tracing::warn!(book_id = %book.id, "No file found for book");
+            return Ok(());
+        };
+
+        let data = ctx.storage.retrieve(storage_path).await?;
+        index_book_text(&ctx.pool, &book, &data).await
+    }
+}
+
+/// Replace the stored text of a book with the text of its file, and record its spine
+///
+/// Text is indexed in the book's language, so reindex it when the language changes.
+pub(crate) async fn index_book_text(pool: &DbPool, book: &Book, data: &[u8]) -> anyhow::Result<()> {
+    let format = book
+        .format
+        .ok_or_else(|| anyhow::anyhow!("Book has storage_path but no format: {}", book.id))?;
+
+    let text = indexer::extract_text(format, data)?;
+    let chapters: Vec<CreateBookText> = text
+        .chapters
+        .iter()
+        .map(|chapter| CreateBookText {
+            spine_index: chapter.spine_index as i32,
+            title: chapter.title.clone(),
+            content: chapter.plain_text(),
+        })
+        .filter(|chapter| !chapter.content.is_empty())
+        .collect();
+
+    let config = text_search_config(book.language.as_deref());
+    BookTextQueries::replace_for_book(pool, book.id, config, &chapters).await?;
+
+    // Kobo bookmarks are translated against the spine's archive paths
+    let spine = indexer::spine_paths(format, data)?;
+    BookQueries::set_spine_paths(pool, book.id, &spine).await?;
+
+    tracing::info!(book_id = %book.id, chapters = chapters.len(), config, "Book text indexed");
+
+    Ok(())
+}
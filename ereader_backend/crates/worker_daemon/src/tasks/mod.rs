@@ -1,8 +1,12 @@
 //! Background task handlers.
 
 pub mod reindex;
+pub mod book_text;
 pub mod covers;
 pub mod cleanup;
+pub mod tombstones;
+pub mod reanchor;
+pub mod smart_collections;
 
 use crate::scheduler::TaskContext;
 use async_trait::async_trait;
@@ -10,8 +14,12 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 pub use reindex::ReindexBookHandler;
+pub use book_text::IndexBookTextHandler;
 pub use covers::GenerateCoversHandler;
 pub use cleanup::CleanupOrphansHandler;
+pub use tombstones::PruneTombstonesHandler;
+pub use reanchor::ReanchorAnnotationsHandler;
+pub use smart_collections::RefreshSmartCollectionsHandler;
 
 /// Trait for task handlers
 #[async_trait]
@@ -0,0 +1,162 @@
+//! Annotation re-anchoring task handler.
+
+use crate::scheduler::TaskContext;
+use crate::tasks::TaskHandler;
+use async_trait::async_trait;
+use chrono::Utc;
+use common::{AnnotationAnchorStatus, BookFormat};
+use db_layer::models::{Annotation, UpdateAnnotationAnchor, task_types};
+use db_layer::queries::{AnnotationQueries, BookQueries};
+use indexer::{BookText, text::is_range_cfi};
+use serde::Deserialize;
+use storage_layer::traits::Storage;
+use uuid::Uuid;
+
+/// Payload for re-anchor annotations task
+#[derive(Debug, Deserialize)]
+struct ReanchorPayload {
+    book_id: Uuid,
+    /// Storage path of the file the annotations were made against
+    #[serde(default)]
+    previous_storage_path: Option<String>,
+}
+
+/// Handler for moving a book's annotations onto a replacement file
+///
+/// Each annotation is found again in the new file by its text quote. Annotations made before
+/// quotes were stored get one captured from the previous file first, when it is still in
+/// storage. Annotations whose text can't be found are marked orphaned and keep their old
+/// locators, so nothing is lost.
+pub struct ReanchorAnnotationsHandler;
+
+#[async_trait]
+impl TaskHandler for ReanchorAnnotationsHandler {
+    fn task_type(&self) -> &'static str {
+        task_types::REANCHOR_ANNOTATIONS
+    }
+
+    async fn execute(&self, ctx: &TaskContext, payload: &serde_json::Value) -> anyhow::Result<()> {
+        let payload: ReanchorPayload = serde_json::from_value(payload.clone())?;
+
+        tracing::info!(book_id = %payload.book_id, "Re-anchoring annotations");
+
+        let book = BookQueries::get_by_id(&ctx.pool, payload.book_id)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Book not found: {}", payload.book_id))?;
+
+        let (Some(storage_path), Some(format)) = (&book.storage_path, book.format) else {
+            tracing::warn!(book_id = %book.id, "No file found for book");
+            return Ok(());
+        };
+
+        let annotations = AnnotationQueries::get_for_book(&ctx.pool, &book.user_id, book.id).await?;
+        if annotations.is_empty() {
+            return Ok(());
+        }
+
+        let data = ctx.storage.retrieve(storage_path).await?;
+        let new_text = indexer::extract_text(format, &data)?;
+
+        let old_text = match &payload.previous_storage_path {
+            Some(path) if annotations.iter().any(|a| a.quote_exact.is_none()) => {
+                load_text(ctx, format, path).await
+            }
+            _ => None,
+        };
+
+        let (mut reanchored, mut orphaned, mut skipped) = (0, 0, 0);
+
+        for annotation in &annotations {
+            let quote = annotation.quote().or_else(|| {
+                old_text.as_ref().and_then(|text| {
+                    text.quote_at(&annotation.location_start, annotation.location_end.as_deref())
+                })
+            });
+
+            let Some(quote) = quote else {
+                // Nothing to search for; leave the annotation where it was
+                skipped += 1;
+                continue;
+            };
+
+            let update = match new_text.locate(&quote, Some(&annotation.location_start)) {
+                Some(range) => {
+                    reanchored += 1;
+                    let (location_start, location_end) = if annotation.location_end.is_some() {
+                        (range.start.clone(), Some(range.end))
+                    } else if is_range_cfi(&annotation.location_start) {
+                        (range.to_range_cfi(), None)
+                    } else {
+                        (range.start, None)
+                    };
+                    anchor_update(
+                        annotation,
+                        location_start,
+                        location_end,
+                        quote,
+                        AnnotationAnchorStatus::Reanchored,
+                    )
+                }
+                None => {
+                    orphaned += 1;
+                    anchor_update(
+                        annotation,
+                        annotation.location_start.clone(),
+                        annotation.location_end.clone(),
+                        quote,
+                        AnnotationAnchorStatus::Orphaned,
+                    )
+                }
+            };
+
+            if AnnotationQueries::update_anchor(&ctx.pool, annotation.id, &update)
+                .await?
+                .is_none()
+            {
+                tracing::debug!(annotation_id = %annotation.id, "Annotation changed while re-anchoring");
+            }
+        }
+
+        tracing::info!(
+            book_id = %book.id,
+            reanchored,
+            orphaned,
+            skipped,
+            "Annotations re-anchored"
+        );
+
+        Ok(())
+    }
+}
+
+/// Extract the text of a previous file, if it can still be read
+async fn load_text(ctx: &TaskContext, format: BookFormat, path: &str) -> Option<BookText> {
+    let data = match ctx.storage.retrieve(path).await {
+        Ok(data) => data,
+        Err(e) => {
+            tracing::warn!(path, error = %e, "Previous book file unavailable");
+            return None;
+        }
+    };
+
+    indexer::extract_text(format, &data)
+        .inspect_err(|e| tracing::warn!(path, error = %e, "Failed to read previous book file"))
+        .ok()
+}
+
+fn anchor_update(
+    annotation: &Annotation,
+    location_start: String,
+    location_end: Option<String>,
+    quote: common::TextQuoteSelector,
+    anchor_status: AnnotationAnchorStatus,
+) -> UpdateAnnotationAnchor {
+    UpdateAnnotationAnchor {
+        location_start,
+        location_end,
+        quote: Some(quote),
+        anchor_status,
+        hlc: annotation.hlc().successor(Utc::now(), Uuid::nil()),
+        revision: annotation.revision,
+    }
+}
@@ -1,6 +1,7 @@
 //! Reindex book task handler.
 
 use crate::scheduler::TaskContext;
+use crate::tasks::book_text::index_book_text;
 use crate::tasks::TaskHandler;
 use async_trait::async_trait;
 use serde::Deserialize;
@@ -55,6 +56,7 @@ impl TaskHandler for ReindexBookHandler {
         let metadata = handler.extract_metadata(&data)?;
 
         // Update book with new metadata
+        let author_sort = metadata.author_sort();
         let update = db_layer::models::UpdateBook {
             title: Some(metadata.title.unwrap_or_else(|| book.title.clone())),
             authors: if metadata.authors.is_empty() {
@@ -62,6 +64,11 @@ impl TaskHandler for ReindexBookHandler {
             } else {
                 Some(metadata.authors)
             },
+            contributors: if metadata.contributors.is_empty() {
+                None
+            } else {
+                Some(metadata.contributors)
+            },
             description: metadata.description.or(book.description.clone()),
             language: metadata.language.or(book.language.clone()),
             publisher: metadata.publisher.or(book.publisher.clone()),
@@ -70,9 +77,21 @@ impl TaskHandler for ReindexBookHandler {
             series_name: None,
             series_index: None,
             tags: None,
+            author_sort: author_sort.or(book.author_sort.clone()),
+            rating: None,
+            identifiers: None,
         };
 
-        db_layer::queries::BookQueries::update_metadata(&ctx.pool, book.id, &update).await?;
+        let book = db_layer::queries::BookQueries::update_metadata(&ctx.pool, book.id, &update).await?;
+        db_layer::queries::AuthorQueries::fill_sort_names(&ctx.pool, &book.user_id, &metadata.sort_names)
+            .await?;
+
+        // Fingerprint for KOReader progress sync, for files uploaded before it was stored
+        let partial_md5 = common::PartialMd5::from_bytes(&data);
+        db_layer::queries::BookQueries::set_partial_md5(&ctx.pool, book.id, partial_md5.as_str())
+            .await?;
+
+        index_book_text(&ctx.pool, &book, &data).await?;
 
         tracing::info!(book_id = %book.id, "Book reindexed successfully");
 
@@ -0,0 +1,82 @@
+//! Smart collection membership refresh task handler.
+
+use crate::scheduler::TaskContext;
+use crate::tasks::TaskHandler;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use db_layer::models::{CreateTask, task_types};
+use db_layer::queries::{CollectionQueries, TaskQueries};
+use serde::Deserialize;
+
+/// How often every user's smart collections are refreshed
+const REFRESH_INTERVAL_MINUTES: i64 = 15;
+
+/// Payload for refresh smart collections task
+#[derive(Debug, Deserialize)]
+struct RefreshPayload {
+    /// User whose books changed, or `None` for the periodic refresh of every user
+    #[serde(default)]
+    user_id: Option<String>,
+}
+
+/// Handler for bringing the stored membership of smart collections in line with their queries
+///
+/// The server queues a run for a user whenever their books change. Queries can also start or
+/// stop matching without a book being written, through reading progress or `added:` ages, so
+/// a periodic run refreshes every user with smart collections and schedules the next one.
+pub struct RefreshSmartCollectionsHandler;
+
+#[async_trait]
+impl TaskHandler for RefreshSmartCollectionsHandler {
+    fn task_type(&self) -> &'static str {
+        task_types::REFRESH_SMART_COLLECTIONS
+    }
+
+    async fn execute(&self, ctx: &TaskContext, payload: &serde_json::Value) -> anyhow::Result<()> {
+        let payload: RefreshPayload = serde_json::from_value(payload.clone())?;
+
+        let Some(user_id) = payload.user_id else {
+            // A failed run is only logged: the next run is its retry, and retrying this
+            // task as well would start a second chain of runs
+            if let Err(e) = refresh_all(ctx).await {
+                tracing::error!(error = %e, "Failed to refresh smart collections");
+            }
+            schedule_next(&ctx.pool).await?;
+            return Ok(());
+        };
+
+        let mut conn = ctx.pool.acquire().await?;
+        CollectionQueries::refresh_smart_memberships(&mut conn, &user_id).await?;
+        tracing::info!(user_id = %user_id, "Refreshed smart collections");
+
+        Ok(())
+    }
+}
+
+/// Refresh the smart collections of every user who has any, carrying on past users whose
+/// refresh fails
+async fn refresh_all(ctx: &TaskContext) -> anyhow::Result<()> {
+    let users = CollectionQueries::smart_collection_owners(&ctx.pool).await?;
+    let mut conn = ctx.pool.acquire().await?;
+
+    let mut failed = 0;
+    for user_id in &users {
+        if let Err(e) = CollectionQueries::refresh_smart_memberships(&mut conn, user_id).await {
+            tracing::error!(user_id = %user_id, error = %e, "Failed to refresh smart collections");
+            failed += 1;
+        }
+    }
+
+    tracing::info!(users = users.len(), failed, "Refreshed smart collections");
+
+    Ok(())
+}
+
+/// Queue the next periodic refresh
+pub async fn schedule_next(pool: &db_layer::DbPool) -> anyhow::Result<()> {
+    let task = CreateTask::new(task_types::REFRESH_SMART_COLLECTIONS, serde_json::json!({}))
+        .scheduled_at(Utc::now() + Duration::minutes(REFRESH_INTERVAL_MINUTES));
+    TaskQueries::create(pool, &task).await?;
+
+    Ok(())
+}
@@ -0,0 +1,79 @@
+//! Sync tombstone pruning task handler.
+
+use crate::scheduler::TaskContext;
+use crate::tasks::TaskHandler;
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use db_layer::models::{CreateTask, SyncIdempotencyKey, task_types};
+use db_layer::queries::{
+    SyncConflictQueries, SyncIdempotencyQueries, TaskQueries, TombstoneQueries,
+};
+
+/// How often tombstones are pruned
+const PRUNE_INTERVAL_HOURS: i64 = 24;
+
+/// Handler for deleting sync tombstones past their retention period
+///
+/// Expired sync idempotency keys and sync conflicts resolved before the retention period are
+/// removed at the same time. Each run schedules the next one, so a single queued task keeps
+/// pruning going.
+pub struct PruneTombstonesHandler {
+    retention: Duration,
+}
+
+impl PruneTombstonesHandler {
+    pub fn new(retention_days: u32) -> Self {
+        Self {
+            retention: Duration::days(retention_days.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl TaskHandler for PruneTombstonesHandler {
+    fn task_type(&self) -> &'static str {
+        task_types::PRUNE_SYNC_TOMBSTONES
+    }
+
+    async fn execute(&self, ctx: &TaskContext, _payload: &serde_json::Value) -> anyhow::Result<()> {
+        // A failed run is only logged: the next run is its retry, and retrying this task as
+        // well would start a second chain of runs
+        if let Err(e) = self.prune(ctx).await {
+            tracing::error!(error = %e, "Failed to prune sync tombstones");
+        }
+        schedule_next(&ctx.pool).await?;
+
+        Ok(())
+    }
+}
+
+impl PruneTombstonesHandler {
+    /// Delete everything past its retention period
+    async fn prune(&self, ctx: &TaskContext) -> anyhow::Result<()> {
+        let cutoff = Utc::now() - self.retention;
+        let pruned = TombstoneQueries::prune_older_than(&ctx.pool, cutoff).await?;
+
+        tracing::info!(pruned, cutoff = %cutoff, "Pruned sync tombstones");
+
+        let expired = SyncIdempotencyQueries::prune_older_than(
+            &ctx.pool,
+            SyncIdempotencyKey::expiry_cutoff(Utc::now()),
+        )
+        .await?;
+        tracing::info!(expired, "Pruned sync idempotency keys");
+
+        let resolved = SyncConflictQueries::prune_resolved_before(&ctx.pool, cutoff).await?;
+        tracing::info!(resolved, "Pruned resolved sync conflicts");
+
+        Ok(())
+    }
+}
+
+/// Queue the next pruning run
+pub async fn schedule_next(pool: &db_layer::DbPool) -> anyhow::Result<()> {
+    let task = CreateTask::new(task_types::PRUNE_SYNC_TOMBSTONES, serde_json::json!({}))
+        .scheduled_at(Utc::now() + Duration::hours(PRUNE_INTERVAL_HOURS));
+    TaskQueries::create(pool, &task).await?;
+
+    Ok(())
+}
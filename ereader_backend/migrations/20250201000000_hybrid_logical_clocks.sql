@@ -0,0 +1,21 @@
+-- Migration: Hybrid logical clocks for sync conflict resolution
+-- Each synced row stores the HLC (physical ms, logical counter, device) of the write
+-- that produced it, so merges no longer depend on device or database wall clocks.
+
+ALTER TABLE reading_states
+    ADD COLUMN hlc_physical BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN hlc_logical INT NOT NULL DEFAULT 0,
+    ADD COLUMN hlc_device_id UUID NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000';
+
+ALTER TABLE annotations
+    ADD COLUMN hlc_physical BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN hlc_logical INT NOT NULL DEFAULT 0,
+    ADD COLUMN hlc_device_id UUID NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000';
+
+-- Backfill existing rows from their last update time
+UPDATE reading_states SET
+    hlc_physical = (EXTRACT(EPOCH FROM updated_at) * 1000)::BIGINT,
+    hlc_device_id = device_id;
+
+UPDATE annotations SET
+    hlc_physical = (EXTRACT(EPOCH FROM updated_at) * 1000)::BIGINT;
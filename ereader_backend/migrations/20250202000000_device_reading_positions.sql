@@ -0,0 +1,33 @@
+-- Migration: Per-device reading positions and position resolution policy
+-- reading_states keeps the canonical position; device_reading_states keeps the last position
+-- reported by each device so clients can show "where am I on each device".
+
+CREATE TYPE reading_position_policy AS ENUM ('latest_write', 'furthest_progress', 'ask_user');
+
+CREATE TABLE device_reading_states (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id TEXT NOT NULL,
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    device_id UUID NOT NULL REFERENCES devices(id) ON DELETE CASCADE,
+    location JSONB NOT NULL,
+    hlc_physical BIGINT NOT NULL DEFAULT 0,
+    hlc_logical INT NOT NULL DEFAULT 0,
+    hlc_device_id UUID NOT NULL DEFAULT '00000000-0000-0000-0000-000000000000',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    UNIQUE(user_id, book_id, device_id)
+);
+
+CREATE INDEX idx_device_reading_states_user_book ON device_reading_states(user_id, book_id);
+
+-- Seed from the canonical positions so existing devices keep their last known place
+INSERT INTO device_reading_states (user_id, book_id, device_id, location,
+                                   hlc_physical, hlc_logical, hlc_device_id, updated_at)
+SELECT user_id, book_id, device_id, location,
+       hlc_physical, hlc_logical, hlc_device_id, updated_at
+FROM reading_states;
+
+CREATE TABLE sync_settings (
+    user_id TEXT PRIMARY KEY,
+    reading_position_policy reading_position_policy NOT NULL DEFAULT 'latest_write',
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
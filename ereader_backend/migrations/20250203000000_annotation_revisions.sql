@@ -0,0 +1,20 @@
+-- Migration: Annotation revisions for three-way merges
+-- Every write to an annotation bumps its revision and snapshots the mergeable fields, so a
+-- client edit based on an older revision can be merged against the version it started from.
+
+ALTER TABLE annotations ADD COLUMN revision INT NOT NULL DEFAULT 1;
+
+CREATE TABLE annotation_revisions (
+    annotation_id UUID NOT NULL REFERENCES annotations(id) ON DELETE CASCADE,
+    revision INT NOT NULL,
+    location_end TEXT,
+    content TEXT,
+    style TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (annotation_id, revision)
+);
+
+-- Existing annotations become their own base revision
+INSERT INTO annotation_revisions (annotation_id, revision, location_end, content, style)
+SELECT id, revision, location_end, content, style
+FROM annotations;
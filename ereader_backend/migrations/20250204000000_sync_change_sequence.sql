@@ -0,0 +1,74 @@
+-- Migration: Per-user change sequence for sync cursors
+-- Every write to a synced row takes the next value of the owning user's change counter.
+-- The counter row is locked until the writing transaction commits, so per user, sequence
+-- order matches commit order and a client resuming from a sequence number never skips a row.
+
+CREATE TABLE sync_change_counters (
+    user_id TEXT PRIMARY KEY,
+    last_seq BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE OR REPLACE FUNCTION next_sync_change_seq(p_user_id TEXT)
+RETURNS BIGINT AS $$
+DECLARE
+    seq BIGINT;
+BEGIN
+    INSERT INTO sync_change_counters (user_id, last_seq)
+    VALUES (p_user_id, 1)
+    ON CONFLICT (user_id) DO UPDATE SET last_seq = sync_change_counters.last_seq + 1
+    RETURNING last_seq INTO seq;
+    RETURN seq;
+END;
+$$ language 'plpgsql';
+
+CREATE OR REPLACE FUNCTION assign_sync_change_seq()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.change_seq = next_sync_change_seq(NEW.user_id);
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+ALTER TABLE reading_states ADD COLUMN change_seq BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE annotations ADD COLUMN change_seq BIGINT NOT NULL DEFAULT 0;
+
+-- Number existing rows per user in update order, across both tables
+CREATE TEMPORARY TABLE existing_changes AS
+SELECT kind, id, user_id,
+       ROW_NUMBER() OVER (PARTITION BY user_id ORDER BY updated_at, id) AS seq
+FROM (
+    SELECT 'reading_state' AS kind, id, user_id, updated_at FROM reading_states
+    UNION ALL
+    SELECT 'annotation' AS kind, id, user_id, updated_at FROM annotations
+) changes;
+
+UPDATE reading_states rs SET change_seq = ec.seq
+FROM existing_changes ec
+WHERE ec.kind = 'reading_state' AND ec.id = rs.id;
+
+-- Keep updated_at as-is while backfilling
+ALTER TABLE annotations DISABLE TRIGGER update_annotations_updated_at;
+
+UPDATE annotations a SET change_seq = ec.seq
+FROM existing_changes ec
+WHERE ec.kind = 'annotation' AND ec.id = a.id;
+
+ALTER TABLE annotations ENABLE TRIGGER update_annotations_updated_at;
+
+INSERT INTO sync_change_counters (user_id, last_seq)
+SELECT user_id, MAX(seq) FROM existing_changes GROUP BY user_id;
+
+DROP TABLE existing_changes;
+
+CREATE TRIGGER assign_reading_states_change_seq
+    BEFORE INSERT OR UPDATE ON reading_states
+    FOR EACH ROW
+    EXECUTE FUNCTION assign_sync_change_seq();
+
+CREATE TRIGGER assign_annotations_change_seq
+    BEFORE INSERT OR UPDATE ON annotations
+    FOR EACH ROW
+    EXECUTE FUNCTION assign_sync_change_seq();
+
+CREATE INDEX idx_reading_states_change_seq ON reading_states(user_id, change_seq);
+CREATE INDEX idx_annotations_change_seq ON annotations(user_id, change_seq);
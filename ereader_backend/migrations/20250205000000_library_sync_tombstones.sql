@@ -0,0 +1,109 @@
+-- Migration: Library changes in the sync feed
+-- Books, collections and collection membership join the per-user change sequence, and
+-- deletes leave tombstones so devices can drop what no longer exists. Tombstones are pruned
+-- after a retention period; sync_change_counters.pruned_seq records the newest pruned change
+-- so clients with an older token know to resync from scratch.
+
+CREATE TYPE sync_entity_type AS ENUM ('book', 'collection', 'collection_book');
+
+ALTER TABLE sync_change_counters ADD COLUMN pruned_seq BIGINT NOT NULL DEFAULT 0;
+
+CREATE TABLE sync_tombstones (
+    id BIGSERIAL PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    entity_type sync_entity_type NOT NULL,
+    entity_id UUID NOT NULL,
+    -- Collection for collection_book tombstones (entity_id is then the book)
+    parent_id UUID,
+    change_seq BIGINT NOT NULL,
+    deleted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_sync_tombstones_change_seq ON sync_tombstones(user_id, change_seq);
+CREATE INDEX idx_sync_tombstones_deleted_at ON sync_tombstones(deleted_at);
+
+ALTER TABLE books ADD COLUMN change_seq BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE collections ADD COLUMN change_seq BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE collection_books ADD COLUMN change_seq BIGINT NOT NULL DEFAULT 0;
+
+-- Membership rows carry no user_id, so take it from the collection
+CREATE OR REPLACE FUNCTION assign_collection_book_change_seq()
+RETURNS TRIGGER AS $$
+BEGIN
+    NEW.change_seq = next_sync_change_seq(
+        (SELECT user_id FROM collections WHERE id = NEW.collection_id)
+    );
+    RETURN NEW;
+END;
+$$ language 'plpgsql';
+
+CREATE OR REPLACE FUNCTION record_sync_tombstone()
+RETURNS TRIGGER AS $$
+BEGIN
+    INSERT INTO sync_tombstones (user_id, entity_type, entity_id, change_seq)
+    VALUES (OLD.user_id, TG_ARGV[0]::sync_entity_type, OLD.id, next_sync_change_seq(OLD.user_id));
+    RETURN OLD;
+END;
+$$ language 'plpgsql';
+
+CREATE OR REPLACE FUNCTION record_collection_book_tombstone()
+RETURNS TRIGGER AS $$
+DECLARE
+    owner TEXT;
+BEGIN
+    -- When the whole collection is being deleted its own tombstone covers the membership
+    SELECT user_id INTO owner FROM collections WHERE id = OLD.collection_id;
+    IF owner IS NOT NULL THEN
+        INSERT INTO sync_tombstones (user_id, entity_type, entity_id, parent_id, change_seq)
+        VALUES (owner, 'collection_book', OLD.book_id, OLD.collection_id, next_sync_change_seq(owner));
+    END IF;
+    RETURN OLD;
+END;
+$$ language 'plpgsql';
+
+-- Give existing library rows a place in the feed, leaving updated_at untouched
+ALTER TABLE books DISABLE TRIGGER update_books_updated_at;
+ALTER TABLE collections DISABLE TRIGGER update_collections_updated_at;
+
+UPDATE books SET change_seq = next_sync_change_seq(user_id);
+UPDATE collections SET change_seq = next_sync_change_seq(user_id);
+UPDATE collection_books cb SET change_seq = next_sync_change_seq(c.user_id)
+FROM collections c
+WHERE c.id = cb.collection_id;
+
+ALTER TABLE books ENABLE TRIGGER update_books_updated_at;
+ALTER TABLE collections ENABLE TRIGGER update_collections_updated_at;
+
+CREATE TRIGGER assign_books_change_seq
+    BEFORE INSERT OR UPDATE ON books
+    FOR EACH ROW
+    EXECUTE FUNCTION assign_sync_change_seq();
+
+CREATE TRIGGER assign_collections_change_seq
+    BEFORE INSERT OR UPDATE ON collections
+    FOR EACH ROW
+    EXECUTE FUNCTION assign_sync_change_seq();
+
+CREATE TRIGGER assign_collection_books_change_seq
+    BEFORE INSERT OR UPDATE ON collection_books
+    FOR EACH ROW
+    EXECUTE FUNCTION assign_collection_book_change_seq();
+
+CREATE TRIGGER record_books_tombstone
+    AFTER DELETE ON books
+    FOR EACH ROW
+    EXECUTE FUNCTION record_sync_tombstone('book');
+
+CREATE TRIGGER record_collections_tombstone
+    AFTER DELETE ON collections
+    FOR EACH ROW
+    EXECUTE FUNCTION record_sync_tombstone('collection');
+
+CREATE TRIGGER record_collection_books_tombstone
+    AFTER DELETE ON collection_books
+    FOR EACH ROW
+    EXECUTE FUNCTION record_collection_book_tombstone();
+
+CREATE INDEX idx_books_change_seq ON books(user_id, change_seq);
+CREATE INDEX idx_collections_change_seq ON collections(user_id, change_seq);
+CREATE INDEX idx_collection_books_change_seq ON collection_books(change_seq);
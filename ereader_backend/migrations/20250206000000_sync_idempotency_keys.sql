@@ -0,0 +1,16 @@
+-- Migration: Idempotency keys for sync requests
+-- A sync request may carry a client-chosen key. The response is stored with the key inside
+-- the sync transaction, so a retried request gets the same response without being applied
+-- twice. Keys expire after a day and are pruned by the worker.
+
+CREATE TABLE sync_idempotency_keys (
+    user_id TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    -- Hash of the request body, so a key reused for a different request is rejected
+    request_hash TEXT NOT NULL,
+    response JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, idempotency_key)
+);
+
+CREATE INDEX idx_sync_idempotency_keys_created_at ON sync_idempotency_keys(created_at);
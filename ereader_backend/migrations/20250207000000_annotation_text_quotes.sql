@@ -0,0 +1,15 @@
+-- Migration: Text-quote selectors for annotations
+-- Annotations keep the text they cover (plus surrounding context) next to their CFIs, so
+-- they can be found again when the book's file is replaced. anchor_status records whether
+-- the locators were moved to a new file or could not be placed.
+
+CREATE TYPE annotation_anchor_status AS ENUM ('anchored', 'reanchored', 'orphaned');
+
+ALTER TABLE annotations
+    ADD COLUMN quote_exact TEXT,
+    ADD COLUMN quote_prefix TEXT,
+    ADD COLUMN quote_suffix TEXT,
+    ADD COLUMN anchor_status annotation_anchor_status NOT NULL DEFAULT 'anchored';
+
+CREATE INDEX idx_annotations_book_anchor ON annotations(book_id, anchor_status)
+    WHERE deleted_at IS NULL;
@@ -0,0 +1,30 @@
+-- Migration: Persisted sync conflicts
+-- Annotation conflicts found while merging a sync batch are logged with both the device's
+-- version and the server's version at the time, so the user can review them later and pick
+-- a version or restore the losing note.
+
+CREATE TYPE conflict_resolution AS ENUM ('server_wins', 'client_wins', 'merged', 'kept_both');
+CREATE TYPE conflict_choice AS ENUM ('server', 'local', 'both');
+
+CREATE TABLE sync_conflicts (
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    -- Device whose version lost; kept after the device is removed
+    device_id UUID REFERENCES devices(id) ON DELETE SET NULL,
+    entity_type TEXT NOT NULL,
+    entity_id UUID NOT NULL,
+    -- How the merge resolved the conflict automatically
+    resolution conflict_resolution NOT NULL,
+    local_version JSONB NOT NULL,
+    server_version JSONB NOT NULL,
+    -- Annotation holding the device's version, for kept_both conflicts
+    copy_id UUID,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ,
+    resolved_with conflict_choice
+);
+
+CREATE INDEX idx_sync_conflicts_unresolved ON sync_conflicts(user_id, created_at)
+    WHERE resolved_at IS NULL;
+CREATE INDEX idx_sync_conflicts_resolved_at ON sync_conflicts(resolved_at)
+    WHERE resolved_at IS NOT NULL;
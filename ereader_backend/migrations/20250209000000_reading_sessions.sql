@@ -0,0 +1,23 @@
+-- Migration: Reading sessions
+-- Devices upload each stretch of reading as a session alongside their sync batch. Sessions
+-- are append-only and identified by a client-chosen id, so a retried upload is ignored.
+-- Reading-time statistics are computed from them.
+
+CREATE TABLE reading_sessions (
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    -- Device that recorded the session; kept after the device is removed
+    device_id UUID REFERENCES devices(id) ON DELETE SET NULL,
+    started_at TIMESTAMPTZ NOT NULL,
+    ended_at TIMESTAMPTZ NOT NULL,
+    start_locator TEXT NOT NULL,
+    end_locator TEXT NOT NULL,
+    -- Change in progress (0.0-1.0 scale) over the session; negative after paging back
+    progress_delta REAL NOT NULL DEFAULT 0,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (ended_at >= started_at)
+);
+
+CREATE INDEX idx_reading_sessions_user_started ON reading_sessions(user_id, started_at);
+CREATE INDEX idx_reading_sessions_book ON reading_sessions(user_id, book_id);
@@ -0,0 +1,15 @@
+-- Migration: App tokens
+-- Long-lived tokens for clients that can't sign in with Clerk, such as OPDS catalog
+-- readers. Only a SHA-256 hash of each token is stored; the token itself is shown once
+-- when it is created.
+
+CREATE TABLE app_tokens (
+    id UUID PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ
+);
+
+CREATE INDEX idx_app_tokens_user ON app_tokens(user_id);
@@ -0,0 +1,20 @@
+-- Migration: KOReader progress sync
+-- KOReader's sync plugin identifies documents by a partial MD5 of the file, which is
+-- stored with each book's file. KOReader devices are registered as regular devices, keyed
+-- by the device id KOReader reports. Each user can have one kosync account; KOReader only
+-- sends the MD5 of its password, and a SHA-256 hash of that is stored.
+
+ALTER TABLE books ADD COLUMN partial_md5 TEXT;
+CREATE INDEX idx_books_partial_md5 ON books(user_id, partial_md5) WHERE partial_md5 IS NOT NULL;
+
+ALTER TABLE devices ADD COLUMN external_id TEXT;
+CREATE UNIQUE INDEX idx_devices_external_id ON devices(user_id, external_id)
+    WHERE external_id IS NOT NULL;
+
+CREATE TABLE kosync_accounts (
+    user_id TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    key_hash TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,19 @@
+-- Migration: Calibre import
+-- Metadata Calibre keeps that books had no place for: the sort name of the authors, a
+-- rating in half stars (1-10, Calibre's own scale) and identifiers keyed by scheme such
+-- as `isbn`, `goodreads` or `amazon`. Imported books are recorded by their id in the
+-- source library, so an interrupted import resumes where it stopped.
+
+ALTER TABLE books ADD COLUMN author_sort TEXT;
+ALTER TABLE books ADD COLUMN rating SMALLINT CHECK (rating BETWEEN 1 AND 10);
+ALTER TABLE books ADD COLUMN identifiers JSONB NOT NULL DEFAULT '{}';
+
+CREATE TABLE book_imports (
+    user_id TEXT NOT NULL,
+    -- Library imported from, such as `calibre:{library uuid}`
+    source TEXT NOT NULL,
+    source_id TEXT NOT NULL,
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    imported_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, source, source_id)
+);
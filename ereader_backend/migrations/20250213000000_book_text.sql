@@ -0,0 +1,18 @@
+-- Migration: Full-text search over book contents
+-- The indexer stores the plain text of each spine document of a book's file, with a
+-- tsvector built by the text search configuration for the book's language. Searches parse
+-- the query with every configuration and match each chapter against the query parsed
+-- the way its text was, so stemming works per language.
+
+CREATE TABLE book_text (
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    spine_index INTEGER NOT NULL,
+    -- Title from the table of contents
+    title TEXT,
+    content TEXT NOT NULL,
+    config REGCONFIG NOT NULL DEFAULT 'simple',
+    search_vector TSVECTOR GENERATED ALWAYS AS (to_tsvector(config, content)) STORED,
+    PRIMARY KEY (book_id, spine_index)
+);
+
+CREATE INDEX idx_book_text_search ON book_text USING GIN (search_vector);
@@ -0,0 +1,15 @@
+-- Migration: Typo-tolerant book search
+-- Trigram indexes on titles, author names and series let searches match misspellings
+-- such as "tolkein" by word similarity, and serve substring LIKE matches as well.
+-- Authors are indexed as one string per book through an immutable wrapper, because
+-- array_to_string is only stable and can't be used in an index expression.
+
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE FUNCTION book_authors_text(authors TEXT[]) RETURNS TEXT
+    LANGUAGE sql IMMUTABLE PARALLEL SAFE
+    AS $$ SELECT array_to_string(authors, ' ') $$;
+
+CREATE INDEX idx_books_title_trgm ON books USING gin (LOWER(title) gin_trgm_ops);
+CREATE INDEX idx_books_authors_trgm ON books USING gin (LOWER(book_authors_text(authors)) gin_trgm_ops);
+CREATE INDEX idx_books_series_trgm ON books USING gin (LOWER(COALESCE(series_name, '')) gin_trgm_ops);
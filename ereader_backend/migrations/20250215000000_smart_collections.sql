@@ -0,0 +1,10 @@
+-- Migration: Smart collections
+-- A smart collection stores a book search query instead of a hand-picked list, and
+-- contains whichever books match it at the time. Its collection_books rows are a copy,
+-- refreshed when the collection or the owner's books change and periodically by the
+-- worker, so devices receive it like any other collection.
+
+ALTER TABLE collections ADD COLUMN query TEXT;
+
+ALTER TABLE collections ADD CONSTRAINT collections_smart_query
+    CHECK ((collection_type = 'smart') = (query IS NOT NULL));
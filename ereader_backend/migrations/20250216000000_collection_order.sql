@@ -0,0 +1,21 @@
+-- Migration: Collection reading order
+-- Books added to a collection now go to the end of its order, so give existing
+-- memberships positions in the order they were listed before: explicit positions
+-- first, then by title. Smart collections are ordered by their query instead.
+
+UPDATE collection_books cb
+SET sort_order = ordered.position
+FROM (
+    SELECT cb.collection_id, cb.book_id,
+           (ROW_NUMBER() OVER (
+               PARTITION BY cb.collection_id
+               ORDER BY COALESCE(cb.sort_order, 0), b.title, b.id
+           ) - 1)::int AS position
+    FROM collection_books cb
+    JOIN books b ON b.id = cb.book_id
+    JOIN collections c ON c.id = cb.collection_id
+    WHERE c.query IS NULL
+) ordered
+WHERE cb.collection_id = ordered.collection_id
+  AND cb.book_id = ordered.book_id
+  AND cb.sort_order IS DISTINCT FROM ordered.position;
@@ -0,0 +1,43 @@
+-- Migration: Nested collections
+-- A collection may sit inside a parent collection of the same user, so shelves can be
+-- organised into folders such as "Work > Rust > Async". Deleting a collection moves its
+-- children to the top level. A trigger rejects parents that would form a cycle.
+
+ALTER TABLE collections ADD COLUMN parent_id UUID REFERENCES collections(id) ON DELETE SET NULL;
+
+ALTER TABLE collections ADD CONSTRAINT collections_parent_not_self CHECK (parent_id <> id);
+
+CREATE INDEX idx_collections_parent_id ON collections(parent_id);
+
+CREATE OR REPLACE FUNCTION check_collection_parent()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF NEW.parent_id IS NULL THEN
+        RETURN NEW;
+    END IF;
+
+    IF NOT EXISTS (SELECT 1 FROM collections WHERE id = NEW.parent_id AND user_id = NEW.user_id) THEN
+        RAISE EXCEPTION 'parent collection % not found', NEW.parent_id
+            USING ERRCODE = 'foreign_key_violation';
+    END IF;
+
+    IF EXISTS (
+        WITH RECURSIVE ancestors(id, parent_id) AS (
+            SELECT id, parent_id FROM collections WHERE id = NEW.parent_id
+            UNION
+            SELECT c.id, c.parent_id FROM collections c JOIN ancestors a ON c.id = a.parent_id
+        )
+        SELECT 1 FROM ancestors WHERE id = NEW.id
+    ) THEN
+        RAISE EXCEPTION 'collection % cannot be nested inside itself', NEW.id
+            USING ERRCODE = 'check_violation';
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER check_collections_parent
+    BEFORE INSERT OR UPDATE OF parent_id ON collections
+    FOR EACH ROW
+    EXECUTE FUNCTION check_collection_parent();
@@ -0,0 +1,68 @@
+-- Migration: Series
+-- Series become rows of their own, linked from books by series_id, so they can be listed,
+-- described and merged. Books keep series_name as the name they show; a trigger links
+-- each book to the owner's series of that name, ignoring case and surrounding spaces,
+-- and creates the series if there is none.
+
+CREATE TABLE series (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    description TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_series_user_name ON series(user_id, LOWER(name));
+
+CREATE TRIGGER update_series_updated_at
+    BEFORE UPDATE ON series
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+ALTER TABLE books ADD COLUMN series_id UUID REFERENCES series(id) ON DELETE SET NULL;
+
+CREATE INDEX idx_books_series_id ON books(series_id);
+
+CREATE OR REPLACE FUNCTION link_book_series()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF BTRIM(COALESCE(NEW.series_name, '')) = '' THEN
+        NEW.series_id := NULL;
+        RETURN NEW;
+    END IF;
+
+    INSERT INTO series (user_id, name)
+    VALUES (NEW.user_id, BTRIM(NEW.series_name))
+    ON CONFLICT (user_id, LOWER(name)) DO NOTHING;
+
+    SELECT id INTO NEW.series_id
+    FROM series
+    WHERE user_id = NEW.user_id AND LOWER(name) = LOWER(BTRIM(NEW.series_name));
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Create series from existing names, keeping the spelling of the oldest book
+INSERT INTO series (user_id, name)
+SELECT DISTINCT ON (user_id, LOWER(BTRIM(series_name))) user_id, BTRIM(series_name)
+FROM books
+WHERE BTRIM(COALESCE(series_name, '')) <> ''
+ORDER BY user_id, LOWER(BTRIM(series_name)), created_at;
+
+-- Link existing books without moving them in the sync change feed
+ALTER TABLE books DISABLE TRIGGER update_books_updated_at;
+ALTER TABLE books DISABLE TRIGGER assign_books_change_seq;
+
+UPDATE books b SET series_id = s.id
+FROM series s
+WHERE s.user_id = b.user_id AND LOWER(s.name) = LOWER(BTRIM(b.series_name));
+
+ALTER TABLE books ENABLE TRIGGER update_books_updated_at;
+ALTER TABLE books ENABLE TRIGGER assign_books_change_seq;
+
+CREATE TRIGGER link_books_series
+    BEFORE INSERT OR UPDATE OF series_name ON books
+    FOR EACH ROW
+    EXECUTE FUNCTION link_book_series();
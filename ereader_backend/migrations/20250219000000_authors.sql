@@ -0,0 +1,141 @@
+-- Migration: Authors
+-- Authors become rows of their own, linked to books through book_authors, so spellings
+-- of one name can be recognised as the same person and duplicates merged. Books keep
+-- authors as the names they show; a trigger links each book to the owner's authors of
+-- those names and creates the authors there are none of yet.
+--
+-- Names are matched by a key that ignores case, spaces and punctuation and reads
+-- "Last, First" as "First Last", so "J. R. R. Tolkien", "J.R.R. Tolkien" and
+-- "Tolkien, J. R. R." are one author. Aliases are other names an author is matched by,
+-- such as those of authors merged into it.
+
+-- Key two spellings of an author's name match by
+CREATE OR REPLACE FUNCTION author_name_key(name TEXT)
+RETURNS TEXT AS $$
+    SELECT LOWER(REGEXP_REPLACE(
+        CASE
+            WHEN name ~ '^[^,]+,[^,]+$' THEN SPLIT_PART(name, ',', 2) || ' ' || SPLIT_PART(name, ',', 1)
+            ELSE name
+        END,
+        '[[:space:][:punct:]]+', '', 'g'
+    ))
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Keys of an author's name and aliases
+CREATE OR REPLACE FUNCTION author_match_keys(name TEXT, aliases TEXT[])
+RETURNS TEXT[] AS $$
+    SELECT ARRAY(SELECT DISTINCT author_name_key(n) FROM UNNEST(ARRAY_PREPEND(name, aliases)) AS n)
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Sort name of an author without one set: "Ursula K. Le Guin" files as "Guin, Ursula K. Le",
+-- names already in "Last, First" form and single names as themselves
+CREATE OR REPLACE FUNCTION author_default_sort_name(name TEXT)
+RETURNS TEXT AS $$
+    SELECT CASE
+        WHEN name LIKE '%,%' THEN BTRIM(name)
+        ELSE REGEXP_REPLACE(BTRIM(name), '^(.*\S)\s+(\S+)$', '\2, \1')
+    END
+$$ LANGUAGE sql IMMUTABLE;
+
+CREATE TABLE authors (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id TEXT NOT NULL,
+    name TEXT NOT NULL,
+    -- Name the author files under, or NULL to derive it from the name
+    sort_name TEXT,
+    aliases TEXT[] NOT NULL DEFAULT '{}',
+    match_keys TEXT[] GENERATED ALWAYS AS (author_match_keys(name, aliases)) STORED,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE UNIQUE INDEX idx_authors_user_name_key ON authors(user_id, author_name_key(name));
+CREATE INDEX idx_authors_match_keys ON authors USING GIN (match_keys);
+
+CREATE TRIGGER update_authors_updated_at
+    BEFORE UPDATE ON authors
+    FOR EACH ROW
+    EXECUTE FUNCTION update_updated_at_column();
+
+CREATE TABLE book_authors (
+    book_id UUID NOT NULL REFERENCES books(id) ON DELETE CASCADE,
+    author_id UUID NOT NULL REFERENCES authors(id) ON DELETE CASCADE,
+    -- Place of the author in the book's author list, from 1
+    position INTEGER NOT NULL,
+    PRIMARY KEY (book_id, author_id)
+);
+
+CREATE INDEX idx_book_authors_author_id ON book_authors(author_id);
+
+-- Id of the user's author matching a name, creating the author if there is none, or NULL
+-- for a blank name
+CREATE OR REPLACE FUNCTION find_or_create_author(p_user_id TEXT, p_name TEXT)
+RETURNS UUID AS $$
+DECLARE
+    key TEXT := author_name_key(p_name);
+    found UUID;
+BEGIN
+    IF key = '' THEN
+        RETURN NULL;
+    END IF;
+
+    -- Prefer an author of that name over one merely aliased to it
+    SELECT id INTO found
+    FROM authors
+    WHERE user_id = p_user_id AND match_keys @> ARRAY[key]
+    ORDER BY author_name_key(name) = key DESC, created_at
+    LIMIT 1;
+
+    IF found IS NULL THEN
+        INSERT INTO authors (user_id, name)
+        VALUES (p_user_id, BTRIM(p_name))
+        ON CONFLICT (user_id, author_name_key(name)) DO NOTHING
+        RETURNING id INTO found;
+    END IF;
+    IF found IS NULL THEN
+        SELECT id INTO found FROM authors WHERE user_id = p_user_id AND author_name_key(name) = key;
+    END IF;
+
+    RETURN found;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION link_book_authors()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE' AND OLD.authors IS NOT DISTINCT FROM NEW.authors THEN
+        RETURN NULL;
+    END IF;
+
+    DELETE FROM book_authors WHERE book_id = NEW.id;
+
+    INSERT INTO book_authors (book_id, author_id, position)
+    SELECT NEW.id, author_id, MIN(position)
+    FROM (
+        SELECT find_or_create_author(NEW.user_id, name) AS author_id, position
+        FROM UNNEST(NEW.authors) WITH ORDINALITY AS a(name, position)
+    ) linked
+    WHERE author_id IS NOT NULL
+    GROUP BY author_id;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+-- Create authors from existing names, keeping the spelling of the oldest book
+INSERT INTO authors (user_id, name)
+SELECT DISTINCT ON (b.user_id, author_name_key(a.name)) b.user_id, BTRIM(a.name)
+FROM books b, UNNEST(b.authors) AS a(name)
+WHERE author_name_key(a.name) <> ''
+ORDER BY b.user_id, author_name_key(a.name), b.created_at;
+
+INSERT INTO book_authors (book_id, author_id, position)
+SELECT b.id, au.id, MIN(a.position)
+FROM books b, UNNEST(b.authors) WITH ORDINALITY AS a(name, position), authors au
+WHERE au.user_id = b.user_id AND author_name_key(au.name) = author_name_key(a.name)
+GROUP BY b.id, au.id;
+
+CREATE TRIGGER link_books_authors
+    AFTER INSERT OR UPDATE OF authors ON books
+    FOR EACH ROW
+    EXECUTE FUNCTION link_book_authors();
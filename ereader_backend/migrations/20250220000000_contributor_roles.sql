@@ -0,0 +1,48 @@
+-- Migration: Contributor roles
+-- Books credit people in roles besides author, such as translators, editors, illustrators
+-- and narrators. Books keep authors as the names of their authors and list everyone else
+-- in contributors, as {"name", "role"} objects with the role a MARC relator code.
+-- book_authors links a book to each person it credits, once per role.
+
+ALTER TABLE books ADD COLUMN contributors JSONB NOT NULL DEFAULT '[]'
+    CHECK (jsonb_typeof(contributors) = 'array');
+
+ALTER TABLE book_authors ADD COLUMN role TEXT NOT NULL DEFAULT 'aut';
+ALTER TABLE book_authors ALTER COLUMN role DROP DEFAULT;
+ALTER TABLE book_authors DROP CONSTRAINT book_authors_pkey;
+ALTER TABLE book_authors ADD PRIMARY KEY (book_id, author_id, role);
+
+CREATE OR REPLACE FUNCTION link_book_authors()
+RETURNS TRIGGER AS $$
+BEGIN
+    IF TG_OP = 'UPDATE'
+        AND OLD.authors IS NOT DISTINCT FROM NEW.authors
+        AND OLD.contributors IS NOT DISTINCT FROM NEW.contributors
+    THEN
+        RETURN NULL;
+    END IF;
+
+    DELETE FROM book_authors WHERE book_id = NEW.id;
+
+    INSERT INTO book_authors (book_id, author_id, role, position)
+    SELECT NEW.id, author_id, role, MIN(position)
+    FROM (
+        SELECT find_or_create_author(NEW.user_id, a.name) AS author_id, 'aut' AS role, a.position
+        FROM UNNEST(NEW.authors) WITH ORDINALITY AS a(name, position)
+        UNION ALL
+        SELECT find_or_create_author(NEW.user_id, c.value->>'name'), c.value->>'role', c.position
+        FROM JSONB_ARRAY_ELEMENTS(NEW.contributors) WITH ORDINALITY AS c(value, position)
+    ) linked
+    WHERE author_id IS NOT NULL AND role IS NOT NULL
+    GROUP BY author_id, role;
+
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER link_books_authors ON books;
+
+CREATE TRIGGER link_books_authors
+    AFTER INSERT OR UPDATE OF authors, contributors ON books
+    FOR EACH ROW
+    EXECUTE FUNCTION link_book_authors();
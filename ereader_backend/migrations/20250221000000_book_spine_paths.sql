@@ -0,0 +1,7 @@
+-- Migration: Book spine paths
+-- Kobo bookmarks name the archive path of a book's content document, so translating
+-- positions needs the paths of its spine. They are recorded when the book's text is indexed,
+-- instead of reading the file whenever a position is synced. NULL until then, and again
+-- once the file is replaced.
+
+ALTER TABLE books ADD COLUMN spine_paths TEXT[];
@@ -0,0 +1,47 @@
+-- Migration: Series aliases
+-- Aliases are other names a series is matched by, such as those of series merged into it,
+-- so books added later under a merged series' name join the series it was merged into.
+-- Like series names, aliases are matched ignoring case and surrounding spaces.
+
+-- Keys of a series' name and aliases
+CREATE OR REPLACE FUNCTION series_match_keys(name TEXT, aliases TEXT[])
+RETURNS TEXT[] AS $$
+    SELECT ARRAY(SELECT DISTINCT LOWER(BTRIM(n)) FROM UNNEST(ARRAY_PREPEND(name, aliases)) AS n)
+$$ LANGUAGE sql IMMUTABLE;
+
+ALTER TABLE series ADD COLUMN aliases TEXT[] NOT NULL DEFAULT '{}';
+ALTER TABLE series
+    ADD COLUMN match_keys TEXT[] GENERATED ALWAYS AS (series_match_keys(name, aliases)) STORED;
+
+CREATE INDEX idx_series_match_keys ON series USING GIN (match_keys);
+
+CREATE OR REPLACE FUNCTION link_book_series()
+RETURNS TRIGGER AS $$
+DECLARE
+    key TEXT := LOWER(BTRIM(COALESCE(NEW.series_name, '')));
+BEGIN
+    IF key = '' THEN
+        NEW.series_id := NULL;
+        RETURN NEW;
+    END IF;
+
+    -- Prefer a series of that name over one merely aliased to it
+    SELECT id INTO NEW.series_id
+    FROM series
+    WHERE user_id = NEW.user_id AND match_keys @> ARRAY[key]
+    ORDER BY LOWER(name) = key DESC, created_at
+    LIMIT 1;
+
+    IF NEW.series_id IS NULL THEN
+        INSERT INTO series (user_id, name)
+        VALUES (NEW.user_id, BTRIM(NEW.series_name))
+        ON CONFLICT (user_id, LOWER(name)) DO NOTHING;
+
+        SELECT id INTO NEW.series_id
+        FROM series
+        WHERE user_id = NEW.user_id AND LOWER(name) = key;
+    END IF;
+
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;